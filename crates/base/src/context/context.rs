@@ -45,8 +45,12 @@ impl SjsContext {
         };
 
         let config = Arc::new(SchemeJsConfig::new(config_file.clone())?);
-        let file_descriptor_manager = Arc::new(FileDescriptorManager::new(
+        let file_descriptor_manager = Arc::new(FileDescriptorManager::new_with_mapped_bytes(
             config.process.max_file_descriptors_in_cache,
+            match config.process.max_mapped_bytes {
+                0 => None,
+                max_mapped_bytes => Some(max_mapped_bytes),
+            },
         ));
 
         let data_path = if cfg!(test) {
@@ -0,0 +1,33 @@
+use crate::manager::task::Task;
+use crate::manager::task_duration::TaskDuration;
+use schemajs_engine::with_query_manager;
+use std::cell::LazyCell;
+use std::time::Duration;
+
+// Share of a shard's data that must belong to deleted rows before it is worth rewriting.
+const MIN_DELETED_RATIO: f64 = 0.3;
+
+/// Rewrites past data shards without the data of their deleted and superseded rows.
+pub const COMPACT_SHARDS_TASK: LazyCell<Task> = LazyCell::new(|| {
+    Task::new(
+        "3".to_string(),
+        Box::new(move |rt| {
+            let engine = rt.read();
+            for db in engine.databases.iter() {
+                with_query_manager!(db.query_manager, query_manager => {
+                    for table in query_manager.table_names.read().unwrap().iter() {
+                        if let Some(table) = query_manager.tables.get(table) {
+                            table
+                                .data
+                                .read()
+                                .compact(MIN_DELETED_RATIO)
+                                .map_err(|_| ())?;
+                        }
+                    }
+                });
+            }
+            Ok(())
+        }),
+        TaskDuration::Defined(Duration::from_secs(30)),
+    )
+});
@@ -0,0 +1,26 @@
+use crate::manager::task::Task;
+use crate::manager::task_duration::TaskDuration;
+use schemajs_engine::with_query_manager;
+use std::cell::LazyCell;
+use std::time::Duration;
+
+/// Tombstones the rows of tables with a TTL once they expire, along with their index entries.
+pub const EXPIRE_ROWS_TASK: LazyCell<Task> = LazyCell::new(|| {
+    Task::new(
+        "5".to_string(),
+        Box::new(move |rt| {
+            let engine = rt.read();
+            for db in engine.databases.iter() {
+                with_query_manager!(db.query_manager, query_manager => {
+                    for table in query_manager.table_names.read().unwrap().iter() {
+                        if let Some(table) = query_manager.tables.get(table) {
+                            table.purge_expired().map_err(|_| ())?;
+                        }
+                    }
+                });
+            }
+            Ok(())
+        }),
+        TaskDuration::Defined(Duration::from_secs(1)),
+    )
+});
@@ -0,0 +1,26 @@
+use crate::manager::task::Task;
+use crate::manager::task_duration::TaskDuration;
+use schemajs_engine::with_query_manager;
+use std::cell::LazyCell;
+use std::time::Duration;
+
+/// Merges the sorted runs indexes are kept in, so lookups do not have to search too many of them.
+pub const MERGE_INDEXES_TASK: LazyCell<Task> = LazyCell::new(|| {
+    Task::new(
+        "6".to_string(),
+        Box::new(move |rt| {
+            let engine = rt.read();
+            for db in engine.databases.iter() {
+                with_query_manager!(db.query_manager, query_manager => {
+                    for table in query_manager.table_names.read().unwrap().iter() {
+                        if let Some(table) = query_manager.tables.get(table) {
+                            table.merge_indexes().map_err(|_| ())?;
+                        }
+                    }
+                });
+            }
+            Ok(())
+        }),
+        TaskDuration::Defined(Duration::from_secs(30)),
+    )
+});
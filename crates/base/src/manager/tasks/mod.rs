@@ -1,8 +1,25 @@
 use crate::manager::task::Task;
+use crate::manager::tasks::compaction_task::COMPACT_SHARDS_TASK;
+use crate::manager::tasks::expiry_task::EXPIRE_ROWS_TASK;
+use crate::manager::tasks::index_merge_task::MERGE_INDEXES_TASK;
 use crate::manager::tasks::reconcile_task::RECONCILE_DB_TASK;
+use crate::manager::tasks::reencryption_task::REENCRYPT_SHARDS_TASK;
+use crate::manager::tasks::sync_task::SYNC_DIRTY_FILES_TASK;
 
+mod compaction_task;
+mod expiry_task;
+mod index_merge_task;
 mod reconcile_task;
+mod reencryption_task;
+mod sync_task;
 
 pub fn get_all_internal_tasks() -> Vec<Task> {
-    vec![(*RECONCILE_DB_TASK).clone()]
+    vec![
+        (*RECONCILE_DB_TASK).clone(),
+        (*SYNC_DIRTY_FILES_TASK).clone(),
+        (*COMPACT_SHARDS_TASK).clone(),
+        (*REENCRYPT_SHARDS_TASK).clone(),
+        (*EXPIRE_ROWS_TASK).clone(),
+        (*MERGE_INDEXES_TASK).clone(),
+    ]
 }
@@ -1,22 +1,27 @@
 use crate::manager::task::Task;
 use crate::manager::task_duration::TaskDuration;
+use schemajs_engine::with_query_manager;
 use std::cell::LazyCell;
 use std::time::Duration;
 
+/// Reconciles the temporary shards of the tables that reached any of the thresholds of their reconcile policy.
+/// Tables are checked one by one, so a table being reconciled does not block the rest of the engine.
 pub const RECONCILE_DB_TASK: LazyCell<Task> = LazyCell::new(|| {
     Task::new(
         "1".to_string(),
         Box::new(move |rt| {
-            let engine = rt.write();
+            let engine = rt.read();
             for db in engine.databases.iter() {
-                let query_manager = &db.query_manager;
-                for table in query_manager.table_names.read().unwrap().iter() {
-                    let table = query_manager.tables.get(table).unwrap();
-                    table.temps.reconcile_all();
-                }
+                with_query_manager!(db.query_manager, query_manager => {
+                    for table in query_manager.table_names.read().unwrap().iter() {
+                        if let Some(table) = query_manager.tables.get(table) {
+                            table.reconcile_if_due().map_err(|_| ())?;
+                        }
+                    }
+                });
             }
             Ok(())
         }),
-        TaskDuration::Defined(Duration::from_millis(250)),
+        TaskDuration::Defined(Duration::from_millis(50)),
     )
 });
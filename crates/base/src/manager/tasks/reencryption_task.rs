@@ -0,0 +1,27 @@
+use crate::manager::task::Task;
+use crate::manager::task_duration::TaskDuration;
+use schemajs_engine::with_query_manager;
+use std::cell::LazyCell;
+use std::time::Duration;
+
+/// Rewrites the shards that are not encrypted with the current key of their database, one at a time,
+/// so a rotated key ends up being the only one needed.
+pub const REENCRYPT_SHARDS_TASK: LazyCell<Task> = LazyCell::new(|| {
+    Task::new(
+        "4".to_string(),
+        Box::new(move |rt| {
+            let engine = rt.read();
+            for db in engine.databases.iter() {
+                with_query_manager!(db.query_manager, query_manager => {
+                    for table in query_manager.table_names.read().unwrap().iter() {
+                        if let Some(table) = query_manager.tables.get(table) {
+                            while table.reencrypt().map_err(|_| ())? {}
+                        }
+                    }
+                });
+            }
+            Ok(())
+        }),
+        TaskDuration::Defined(Duration::from_secs(60)),
+    )
+});
@@ -0,0 +1,16 @@
+use crate::manager::task::Task;
+use crate::manager::task_duration::TaskDuration;
+use std::cell::LazyCell;
+use std::time::Duration;
+
+/// Flushes the files written under `Durability::Batch` once their sync interval has elapsed.
+pub const SYNC_DIRTY_FILES_TASK: LazyCell<Task> = LazyCell::new(|| {
+    Task::new(
+        "2".to_string(),
+        Box::new(move |rt| {
+            let fdm = rt.read().file_descriptor_manager.clone();
+            fdm.sync_dirty().map(|_| ()).map_err(|_| ())
+        }),
+        TaskDuration::Defined(Duration::from_millis(100)),
+    )
+});
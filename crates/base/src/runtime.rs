@@ -105,7 +105,7 @@ impl SchemeJsRuntime {
 
         {
             if !context.is_loaded() {
-                context.internal_manager.init();
+                context.internal_manager.init()?;
             }
         }
 
@@ -175,7 +175,7 @@ impl SchemeJsRuntime {
             }
 
             if !ctx.is_loaded() {
-                engine.register_tables(scheme_name.as_str(), tables);
+                engine.register_tables(scheme_name.as_str(), tables)?;
             }
         }
 
@@ -441,10 +441,12 @@ mod test {
     use crate::manager::SchemeJsManager;
     use crate::runtime::SchemeJsRuntime;
     use deno_core::{located_script_name, serde_json, v8};
+    use schemajs_engine::with_query_manager;
     use schemajs_helpers::create_helper_channel;
     use schemajs_helpers::helper::{Helper, HelperCall, HelperDbContext, HelperType};
+    use schemajs_primitives::column::types::DataValue;
+    use schemajs_query::ops::query_ops::{QueryOps, QueryVal};
     use schemajs_query::row::Row;
-    use schemajs_query::row_json::RowJson;
     use serde_json::json;
     use std::path::PathBuf;
     use std::sync::Arc;
@@ -577,17 +579,24 @@ mod test {
             let engine = last_rt.ctx.engine.clone();
             let reader = engine.read();
             let db = reader.find_by_name_ref("public").unwrap();
-            let table = db.query_manager.tables.get("users").unwrap();
-            let table_read = table.data.read();
-            let header_reader = table_read.current_master_shard.header.read();
-            println!("{}", header_reader.get_next_available_index().unwrap());
-            println!("{}", header_reader.get_last_offset_index());
-
-            (
-                header_reader.get_last_offset_index(),
-                header_reader.get_next_available_index().unwrap(),
-                table_read.get_element(2000).is_err(),
-            )
+            with_query_manager!(db.query_manager, query_manager => {
+                let table = query_manager.tables.get("users").unwrap();
+                let table_read = table.data.read();
+                let header_reader = table_read
+                    .current_master_shard
+                    .as_file()
+                    .unwrap()
+                    .header
+                    .read();
+                println!("{}", header_reader.get_next_available_index().unwrap());
+                println!("{}", header_reader.get_last_offset_index());
+
+                (
+                    header_reader.get_last_offset_index(),
+                    header_reader.get_next_available_index().unwrap(),
+                    table_read.get_element(2000).is_err(),
+                )
+            })
         };
 
         std::fs::remove_dir_all(data_path).unwrap();
@@ -671,22 +680,20 @@ mod test {
 
             let engine = context.engine.read();
             let db = engine.find_by_name_ref("public").unwrap();
-            let mut row = RowJson::from_json(
-                serde_json::json!({
-                    "id": "999",
-                    "username": "Luis",
-                    "password": "abcd",
-                    "enabled": true
-                }),
-                db.query_manager.get_table("users").unwrap(),
-            )
-            .unwrap();
-
-            let raw_insert = db
-                .query_manager
-                .raw_insert(&mut [row], true)
-                .unwrap()
+            with_query_manager!(db.query_manager, query_manager => {
+                let row = Row::from_json(
+                    serde_json::json!({
+                        "id": "999",
+                        "username": "Luis",
+                        "password": "abcd",
+                        "enabled": true
+                    }),
+                    query_manager.get_table("users").unwrap(),
+                )
                 .unwrap();
+
+                query_manager.raw_insert(&mut [row], true).unwrap().unwrap();
+            });
             let mut unbounded = unbounded_channel();
             let helper_call = HelperCall::CustomQuery {
                 identifier: "searchRowLuis".to_string(),
@@ -705,6 +712,73 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    pub async fn test_runtime_update() {
+        let (tx, rx) = create_helper_channel(1);
+        let context =
+            Arc::new(SjsContext::new(PathBuf::from("./test_cases/default-db"), None, tx).unwrap());
+        let mut rt = SchemeJsRuntime::new(context.clone()).await.unwrap();
+
+        let query_manager = {
+            let engine = context.engine.read();
+            engine
+                .find_by_name_ref("public")
+                .unwrap()
+                .query_manager
+                .clone()
+        };
+        let id = Uuid::new_v4().to_string();
+        with_query_manager!(query_manager, query_manager => {
+            let row = Row::from_json(
+                serde_json::json!({
+                    "id": id,
+                    "username": "Luis",
+                    "password": "abcd",
+                    "enabled": true
+                }),
+                query_manager.get_table("users").unwrap(),
+            )
+            .unwrap();
+            query_manager.raw_insert(&mut [row], true).unwrap();
+        });
+
+        let promise = rt
+            .js_runtime
+            .execute_script(
+                located_script_name!(),
+                format!(
+                    r#"globalThis.SchemeJS.update(globalThis.SchemeJS.QueryBuilder.where("public", "users", "id", "=", "{}"), {{ "username": "Carlos" }});"#,
+                    id
+                ),
+            )
+            .unwrap();
+        let updated = rt.js_runtime.resolve(promise).await.unwrap();
+        let updated = {
+            let scope = &mut rt.js_runtime.handle_scope();
+            v8::Local::new(scope, updated).number_value(scope).unwrap()
+        };
+        assert_eq!(updated, 1.0);
+
+        let rows = with_query_manager!(query_manager, query_manager => {
+            let rows = query_manager
+                .search_manager
+                .search(
+                    "users",
+                    &QueryOps::Condition(QueryVal {
+                        key: "id".to_string(),
+                        filter_type: "=".to_string(),
+                        value: DataValue::String(id),
+                    }),
+                )
+                .unwrap();
+            rows.iter()
+                .map(|row| row.to_json().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("username").unwrap(), &json!("Carlos"));
+    }
+
     #[tokio::test]
     pub async fn test_runtime_insert_with_manager() -> anyhow::Result<()> {
         let (tx, rx) = create_helper_channel(1);
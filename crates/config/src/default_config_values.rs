@@ -23,6 +23,7 @@ config_constants! {
     const MAX_ROWS_PER_TEMP_SHARD: u64 = 1000;
     const MAX_ROWS_PER_SHARD: u64 = 2_500_000;
     const MAX_RECORDS_PER_HASH_INDEX_SHARD: u64 = 10_000_000;
+    const DEFAULT_INDEX_BLOOM_FALSE_POSITIVE_RATE: f64 = 0.01;
     const DEFAULT_SCHEME_NAME: &'static str = "public";
 
     const DEFAULT_ROOT_USER: &'static str = "admin";
@@ -32,4 +33,13 @@ config_constants! {
     const DEFAULT_CUSTOM_QUERY_TIMEOUT: u64 = 30;
 
     const DEFAULT_MAX_FILE_DESCRIPTORS: usize = 2500;
+    const DEFAULT_MAX_MAPPED_BYTES: usize = 0;
+
+    const DEFAULT_DURABILITY_SYNC_INTERVAL_MS: u64 = 1000;
+
+    const DEFAULT_RECONCILE_MAX_ROWS: u64 = 1000;
+    const DEFAULT_RECONCILE_MAX_BYTES: u64 = 4_194_304;
+    const DEFAULT_RECONCILE_MAX_AGE_MS: u64 = 250;
+
+    const DEFAULT_BLOB_THRESHOLD_BYTES: u64 = 65_536;
 }
@@ -1,14 +1,17 @@
 mod default_config_values;
 
 use crate::default_config_values::{
-    get_DefaultCustomQueryTimeout, get_DefaultMaxFileDescriptors, get_MaxRecordsPerHashIndexShard,
+    get_DefaultBlobThresholdBytes, get_DefaultCustomQueryTimeout,
+    get_DefaultDurabilitySyncIntervalMs, get_DefaultIndexBloomFalsePositiveRate,
+    get_DefaultMaxFileDescriptors, get_DefaultMaxMappedBytes, get_DefaultReconcileMaxAgeMs,
+    get_DefaultReconcileMaxBytes, get_DefaultReconcileMaxRows, get_MaxRecordsPerHashIndexShard,
     get_MaxRowsPerShard, get_MaxRowsPerTempShard, get_MaxTemporaryShards, str_DefaultGrpcHost,
     str_DefaultRootPwd, str_DefaultRootUser, str_DefaultSchemeName,
 };
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct SchemeJsConfig {
@@ -34,10 +37,41 @@ pub struct GlobalConfig {
     pub max_rows_per_shard: u64,
     #[serde(default = "get_MaxRecordsPerHashIndexShard")]
     pub max_records_per_hash_index_shard: u64,
+    /// Share of the lookups of keys missing from an index shard that its Bloom filter lets through anyway.
+    /// Lower rates make filters larger. Zero disables them.
+    #[serde(default = "get_DefaultIndexBloomFalsePositiveRate")]
+    pub index_bloom_false_positive_rate: f64,
     #[serde(default)]
     pub default_auth: AuthConfig,
     #[serde(default = "str_DefaultSchemeName")]
     pub default_scheme: String,
+    #[serde(default)]
+    pub durability: DurabilityMode,
+    #[serde(default = "get_DefaultDurabilitySyncIntervalMs")]
+    pub durability_sync_interval_ms: u64,
+    #[serde(default)]
+    pub compression: CompressionCodec,
+    #[serde(default)]
+    pub row_format: RowFormat,
+    /// Size from which string values are kept in the blob store of their table instead of inline in the row.
+    /// Zero keeps every value inline.
+    #[serde(default = "get_DefaultBlobThresholdBytes")]
+    pub blob_threshold_bytes: u64,
+    /// File holding the key rows and indexes are encrypted with. Encryption is disabled if it is not set.
+    #[serde(default)]
+    pub encryption_key_file: Option<PathBuf>,
+    /// Keys used before the current one, kept until every shard has been re-encrypted with the current key.
+    #[serde(default)]
+    pub previous_encryption_key_files: Vec<PathBuf>,
+    /// Pending rows a table accumulates in its temporary shards before they are reconciled. Zero disables it.
+    #[serde(default = "get_DefaultReconcileMaxRows")]
+    pub reconcile_max_rows: u64,
+    /// Pending bytes a table accumulates in its temporary shards before they are reconciled. Zero disables it.
+    #[serde(default = "get_DefaultReconcileMaxBytes")]
+    pub reconcile_max_bytes: u64,
+    /// How long a row may wait in a temporary shard before it is reconciled. Zero disables it.
+    #[serde(default = "get_DefaultReconcileMaxAgeMs")]
+    pub reconcile_max_age_ms: u64,
 }
 
 impl Default for GlobalConfig {
@@ -47,8 +81,19 @@ impl Default for GlobalConfig {
             max_rows_per_temp_shard: get_MaxRowsPerTempShard(),
             max_rows_per_shard: get_MaxRowsPerShard(),
             max_records_per_hash_index_shard: get_MaxRecordsPerHashIndexShard(),
+            index_bloom_false_positive_rate: get_DefaultIndexBloomFalsePositiveRate(),
             default_auth: Default::default(),
             default_scheme: str_DefaultSchemeName(),
+            durability: Default::default(),
+            durability_sync_interval_ms: get_DefaultDurabilitySyncIntervalMs(),
+            compression: Default::default(),
+            row_format: Default::default(),
+            blob_threshold_bytes: get_DefaultBlobThresholdBytes(),
+            encryption_key_file: None,
+            previous_encryption_key_files: vec![],
+            reconcile_max_rows: get_DefaultReconcileMaxRows(),
+            reconcile_max_bytes: get_DefaultReconcileMaxBytes(),
+            reconcile_max_age_ms: get_DefaultReconcileMaxAgeMs(),
         }
     }
 }
@@ -59,8 +104,24 @@ pub struct DatabaseConfig {
     pub max_rows_per_temp_shard: u64,
     pub max_rows_per_shard: u64,
     pub max_records_per_hash_index_shard: u64,
+    pub index_bloom_false_positive_rate: f64,
     pub custom_query_timeout: u64,
     pub default_auth: AuthConfig,
+    pub durability: DurabilityMode,
+    pub durability_sync_interval_ms: u64,
+    pub compression: CompressionCodec,
+    pub row_format: RowFormat,
+    pub blob_threshold_bytes: u64,
+    pub encryption_key_file: Option<PathBuf>,
+    pub previous_encryption_key_files: Vec<PathBuf>,
+    pub reconcile_max_rows: u64,
+    pub reconcile_max_bytes: u64,
+    pub reconcile_max_age_ms: u64,
+    /// Keeps the rows of the database in memory instead of in files. They are lost when the process exits,
+    /// which suits caches and tests. Indexes are still kept in files.
+    pub memory: bool,
+    /// Settings overriding the ones of the database for specific tables.
+    pub tables: HashMap<String, TableConfig>,
 }
 
 impl Default for DatabaseConfig {
@@ -70,8 +131,21 @@ impl Default for DatabaseConfig {
             max_rows_per_temp_shard: get_MaxRowsPerTempShard(),
             max_rows_per_shard: get_MaxRowsPerShard(),
             max_records_per_hash_index_shard: get_MaxRecordsPerHashIndexShard(),
+            index_bloom_false_positive_rate: get_DefaultIndexBloomFalsePositiveRate(),
             custom_query_timeout: get_DefaultCustomQueryTimeout(),
             default_auth: Default::default(),
+            durability: Default::default(),
+            durability_sync_interval_ms: get_DefaultDurabilitySyncIntervalMs(),
+            compression: Default::default(),
+            row_format: Default::default(),
+            blob_threshold_bytes: get_DefaultBlobThresholdBytes(),
+            encryption_key_file: None,
+            previous_encryption_key_files: vec![],
+            reconcile_max_rows: get_DefaultReconcileMaxRows(),
+            reconcile_max_bytes: get_DefaultReconcileMaxBytes(),
+            reconcile_max_age_ms: get_DefaultReconcileMaxAgeMs(),
+            memory: false,
+            tables: HashMap::new(),
         }
     }
 }
@@ -83,12 +157,71 @@ impl DatabaseConfig {
             max_rows_per_temp_shard: global_config.max_rows_per_temp_shard,
             max_rows_per_shard: global_config.max_rows_per_shard,
             max_records_per_hash_index_shard: global_config.max_records_per_hash_index_shard,
+            index_bloom_false_positive_rate: global_config.index_bloom_false_positive_rate,
             custom_query_timeout: grpc.custom_query_timeout,
             default_auth: global_config.default_auth.clone(),
+            durability: global_config.durability,
+            durability_sync_interval_ms: global_config.durability_sync_interval_ms,
+            compression: global_config.compression,
+            row_format: global_config.row_format,
+            blob_threshold_bytes: global_config.blob_threshold_bytes,
+            encryption_key_file: global_config.encryption_key_file.clone(),
+            previous_encryption_key_files: global_config.previous_encryption_key_files.clone(),
+            reconcile_max_rows: global_config.reconcile_max_rows,
+            reconcile_max_bytes: global_config.reconcile_max_bytes,
+            reconcile_max_age_ms: global_config.reconcile_max_age_ms,
+            memory: false,
+            tables: HashMap::new(),
         }
     }
 }
 
+/// Per-table settings. Those left unset fall back to the ones of the database.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TableConfig {
+    pub reconcile_max_rows: Option<u64>,
+    pub reconcile_max_bytes: Option<u64>,
+    pub reconcile_max_age_ms: Option<u64>,
+}
+
+/// Determines when written data is flushed to disk.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DurabilityMode {
+    /// Never sync explicitly, leave it to the operating system.
+    None,
+    /// Group writes and sync them every `durability_sync_interval_ms`.
+    #[default]
+    Batch,
+    /// Sync every write before acknowledging it.
+    Strict,
+}
+
+/// Codec used to compress the rows stored in data shards.
+/// Shards keep the codec they were created with, so changing it only affects new shards.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionCodec {
+    /// Rows are stored uncompressed.
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+/// Encoding used to store the rows of the tables of a database, picking the `Row` implementation of its query manager.
+/// Rows already stored keep their encoding. Binary databases still read the rows stored as JSON before they switched,
+/// but JSON databases cannot read binary rows.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RowFormat {
+    /// Rows are stored as JSON objects keyed by column name.
+    #[default]
+    Json,
+    /// Rows are stored as their values alone, laid out following the columns of the table (`RowBinary`).
+    Binary,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct AuthConfig {
     #[serde(default = "str_DefaultRootUser")]
@@ -127,12 +260,17 @@ impl Default for GrpcConfig {
 pub struct ProcessConfig {
     #[serde(default = "get_DefaultMaxFileDescriptors")]
     pub max_file_descriptors_in_cache: usize,
+    /// Bytes of shard files kept memory-mapped at once. The least recently read files are unmapped past it.
+    /// Zero leaves it unlimited.
+    #[serde(default = "get_DefaultMaxMappedBytes")]
+    pub max_mapped_bytes: usize,
 }
 
 impl Default for ProcessConfig {
     fn default() -> Self {
         Self {
             max_file_descriptors_in_cache: get_DefaultMaxFileDescriptors(),
+            max_mapped_bytes: get_DefaultMaxMappedBytes(),
         }
     }
 }
@@ -161,8 +299,21 @@ impl SchemeJsConfig {
             pub max_rows_per_temp_shard: Option<u64>,
             pub max_rows_per_shard: Option<u64>,
             pub max_records_per_hash_index_shard: Option<u64>,
+            pub index_bloom_false_positive_rate: Option<f64>,
             pub custom_query_timeout: Option<u64>,
             pub default_auth: Option<AuthConfig>,
+            pub durability: Option<DurabilityMode>,
+            pub durability_sync_interval_ms: Option<u64>,
+            pub compression: Option<CompressionCodec>,
+            pub row_format: Option<RowFormat>,
+            pub blob_threshold_bytes: Option<u64>,
+            pub encryption_key_file: Option<PathBuf>,
+            pub previous_encryption_key_files: Option<Vec<PathBuf>>,
+            pub reconcile_max_rows: Option<u64>,
+            pub reconcile_max_bytes: Option<u64>,
+            pub reconcile_max_age_ms: Option<u64>,
+            pub memory: Option<bool>,
+            pub tables: Option<HashMap<String, TableConfig>>,
         }
 
         #[derive(Deserialize, Default)]
@@ -192,12 +343,41 @@ impl SchemeJsConfig {
                         max_records_per_hash_index_shard: val
                             .max_records_per_hash_index_shard
                             .unwrap_or_else(|| global.global.max_records_per_hash_index_shard),
+                        index_bloom_false_positive_rate: val
+                            .index_bloom_false_positive_rate
+                            .unwrap_or(global.global.index_bloom_false_positive_rate),
                         custom_query_timeout: val
                             .custom_query_timeout
                             .unwrap_or_else(|| global.grpc.custom_query_timeout),
                         default_auth: val
                             .default_auth
                             .unwrap_or_else(|| global.global.default_auth.clone()),
+                        durability: val.durability.unwrap_or(global.global.durability),
+                        durability_sync_interval_ms: val
+                            .durability_sync_interval_ms
+                            .unwrap_or(global.global.durability_sync_interval_ms),
+                        compression: val.compression.unwrap_or(global.global.compression),
+                        row_format: val.row_format.unwrap_or(global.global.row_format),
+                        blob_threshold_bytes: val
+                            .blob_threshold_bytes
+                            .unwrap_or(global.global.blob_threshold_bytes),
+                        encryption_key_file: val
+                            .encryption_key_file
+                            .or_else(|| global.global.encryption_key_file.clone()),
+                        previous_encryption_key_files: val
+                            .previous_encryption_key_files
+                            .unwrap_or_else(|| global.global.previous_encryption_key_files.clone()),
+                        reconcile_max_rows: val
+                            .reconcile_max_rows
+                            .unwrap_or(global.global.reconcile_max_rows),
+                        reconcile_max_bytes: val
+                            .reconcile_max_bytes
+                            .unwrap_or(global.global.reconcile_max_bytes),
+                        reconcile_max_age_ms: val
+                            .reconcile_max_age_ms
+                            .unwrap_or(global.global.reconcile_max_age_ms),
+                        memory: val.memory.unwrap_or_default(),
+                        tables: val.tables.unwrap_or_default(),
                     },
                 );
             }
@@ -214,9 +394,37 @@ impl SchemeJsConfig {
 
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         // Read the TOML file to a string
-        let toml = std::fs::read_to_string(path)?;
+        let toml = std::fs::read_to_string(path.as_ref())?;
+
+        let mut config = Self::from_str(toml.as_str())?;
+        if let Some(folder) = path.as_ref().parent() {
+            config.resolve_key_files(folder);
+        }
+
+        Ok(config)
+    }
 
-        Self::from_str(toml.as_str())
+    /// Makes the relative paths of the encryption key files relative to `folder`, where the config file lives.
+    fn resolve_key_files(&mut self, folder: &Path) {
+        let resolve = |file: &mut PathBuf| {
+            if file.is_relative() {
+                *file = folder.join(&file);
+            }
+        };
+
+        let global = &mut self.global;
+        global.encryption_key_file.iter_mut().for_each(resolve);
+        global
+            .previous_encryption_key_files
+            .iter_mut()
+            .for_each(resolve);
+
+        for db in self.db.values_mut() {
+            db.encryption_key_file.iter_mut().for_each(resolve);
+            db.previous_encryption_key_files
+                .iter_mut()
+                .for_each(resolve);
+        }
     }
 
     pub fn global_config(&self) -> &GlobalConfig {
@@ -237,7 +445,8 @@ impl SchemeJsConfig {
 #[cfg(test)]
 mod tests {
     use crate::default_config_values::{get_DefaultRootPwd, get_MaxTemporaryShards};
-    use crate::SchemeJsConfig;
+    use crate::{CompressionCodec, DurabilityMode, RowFormat, SchemeJsConfig};
+    use std::path::PathBuf;
 
     #[test]
     fn test_toml_config() {
@@ -260,4 +469,222 @@ mod tests {
         assert_eq!(db.default_auth.username, "lion");
         assert_eq!(db.default_auth.password, get_DefaultRootPwd());
     }
+
+    #[test]
+    fn test_toml_durability_config() {
+        let config: SchemeJsConfig = SchemeJsConfig::from_str(
+            r#"
+  [global]
+  durability = "none"
+  [db.audit]
+  durability = "strict"
+  [db.cache]
+  durability_sync_interval_ms = 50
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.global.durability, DurabilityMode::None);
+
+        let audit = config.db.get("audit").unwrap();
+        assert_eq!(audit.durability, DurabilityMode::Strict);
+
+        let cache = config.db.get("cache").unwrap();
+        assert_eq!(cache.durability, DurabilityMode::None);
+        assert_eq!(cache.durability_sync_interval_ms, 50);
+
+        assert_eq!(
+            SchemeJsConfig::default().global.durability,
+            DurabilityMode::Batch
+        );
+    }
+
+    #[test]
+    fn test_toml_compression_config() {
+        let config: SchemeJsConfig = SchemeJsConfig::from_str(
+            r#"
+  [global]
+  compression = "lz4"
+  [db.logs]
+  compression = "zstd"
+  [db.public]
+  custom_query_timeout = 1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.global.compression, CompressionCodec::Lz4);
+        assert_eq!(
+            config.db.get("logs").unwrap().compression,
+            CompressionCodec::Zstd
+        );
+        assert_eq!(
+            config.db.get("public").unwrap().compression,
+            CompressionCodec::Lz4
+        );
+        assert_eq!(
+            SchemeJsConfig::default().global.compression,
+            CompressionCodec::None
+        );
+    }
+
+    #[test]
+    fn test_toml_encryption_config() {
+        let folder = tempfile::tempdir().unwrap();
+        let config_path = folder.path().join("SchemeJS.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+  [db.payments]
+  encryption_key_file = "keys/current.key"
+  previous_encryption_key_files = ["/etc/schemajs/old.key"]
+  [db.public]
+  custom_query_timeout = 1
+"#,
+        )
+        .unwrap();
+
+        let config = SchemeJsConfig::new(&config_path).unwrap();
+
+        let payments = config.db.get("payments").unwrap();
+        assert_eq!(
+            payments.encryption_key_file,
+            Some(folder.path().join("keys/current.key"))
+        );
+        assert_eq!(
+            payments.previous_encryption_key_files,
+            vec![PathBuf::from("/etc/schemajs/old.key")]
+        );
+
+        // Encryption is enabled per database
+        let public = config.db.get("public").unwrap();
+        assert_eq!(public.encryption_key_file, None);
+        assert!(public.previous_encryption_key_files.is_empty());
+        assert_eq!(config.db_config("other").encryption_key_file, None);
+    }
+
+    #[test]
+    fn test_toml_reconcile_config() {
+        let config: SchemeJsConfig = SchemeJsConfig::from_str(
+            r#"
+  [global]
+  reconcile_max_rows = 500
+  [db.logs]
+  reconcile_max_age_ms = 0
+  [db.logs.tables.events]
+  reconcile_max_rows = 10
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.global.reconcile_max_rows, 500);
+        assert_eq!(config.global.reconcile_max_age_ms, 250);
+
+        let logs = config.db.get("logs").unwrap();
+        assert_eq!(logs.reconcile_max_rows, 500);
+        assert_eq!(logs.reconcile_max_age_ms, 0);
+        let events = logs.tables.get("events").unwrap();
+        assert_eq!(events.reconcile_max_rows, Some(10));
+        assert_eq!(events.reconcile_max_bytes, None);
+
+        let other = config.db_config("other");
+        assert_eq!(other.reconcile_max_rows, 500);
+        assert!(other.tables.is_empty());
+    }
+
+    #[test]
+    fn test_toml_process_config() {
+        let config: SchemeJsConfig = SchemeJsConfig::from_str(
+            r#"
+  [process]
+  max_mapped_bytes = 1073741824
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.process.max_mapped_bytes, 1_073_741_824);
+        assert_eq!(config.process.max_file_descriptors_in_cache, 2500);
+        assert_eq!(SchemeJsConfig::default().process.max_mapped_bytes, 0);
+    }
+
+    #[test]
+    fn test_toml_memory_config() {
+        let config: SchemeJsConfig = SchemeJsConfig::from_str(
+            r#"
+  [db.cache]
+  memory = true
+  [db.public]
+  custom_query_timeout = 1
+"#,
+        )
+        .unwrap();
+
+        assert!(config.db.get("cache").unwrap().memory);
+        assert!(!config.db.get("public").unwrap().memory);
+    }
+
+    #[test]
+    fn test_toml_row_format_config() {
+        let config: SchemeJsConfig = SchemeJsConfig::from_str(
+            r#"
+  [db.events]
+  row_format = "binary"
+  blob_threshold_bytes = 1024
+  [db.public]
+  custom_query_timeout = 1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.global.row_format, RowFormat::Json);
+        assert_eq!(
+            config.db.get("events").unwrap().row_format,
+            RowFormat::Binary
+        );
+        assert_eq!(config.db.get("public").unwrap().row_format, RowFormat::Json);
+        assert_eq!(config.db.get("events").unwrap().blob_threshold_bytes, 1024);
+        assert_eq!(
+            config.db.get("public").unwrap().blob_threshold_bytes,
+            65_536
+        );
+    }
+
+    #[test]
+    fn test_toml_index_bloom_config() {
+        let config: SchemeJsConfig = SchemeJsConfig::from_str(
+            r#"
+  [global]
+  index_bloom_false_positive_rate = 0.001
+  [db.auth]
+  index_bloom_false_positive_rate = 0.0
+  [db.public]
+  custom_query_timeout = 1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.global.index_bloom_false_positive_rate, 0.001);
+        assert_eq!(
+            config
+                .db
+                .get("auth")
+                .unwrap()
+                .index_bloom_false_positive_rate,
+            0.0
+        );
+        assert_eq!(
+            config
+                .db
+                .get("public")
+                .unwrap()
+                .index_bloom_false_positive_rate,
+            0.001
+        );
+        assert_eq!(
+            SchemeJsConfig::default()
+                .global
+                .index_bloom_false_positive_rate,
+            0.01
+        );
+    }
 }
@@ -1,5 +1,5 @@
 import * as SJsPrimitives from "ext:sjs_primitives/src/js/index.ts"
-import { insertRow, searchRows } from "ext:sjs_engine/src/js/ops.ts";
+import { insertRow, searchRows, deleteRows, updateRows } from "ext:sjs_engine/src/js/ops.ts";
 import { QueryBuilder } from "ext:sjs_engine/src/js/query.ts";
 const core = globalThis.Deno.core;
 class SchemeJS {
@@ -54,6 +54,26 @@ class SchemeJS {
         }
     }
 
+    static get delete() {
+        return (q: QueryBuilder) => {
+            if(!(q instanceof QueryBuilder)) {
+                throw new Error("Deletions must be performed with SchemeJS.QueryBuilder");
+            } else {
+                return deleteRows(q.dbName, q.tableName, q.build())
+            }
+        }
+    }
+
+    static get update() {
+        return (q: QueryBuilder, patch: any) => {
+            if(!(q instanceof QueryBuilder)) {
+                throw new Error("Updates must be performed with SchemeJS.QueryBuilder");
+            } else {
+                return updateRows(q.dbName, q.tableName, q.build(), patch)
+            }
+        }
+    }
+
     static print(msg: string) {
         core.ops.sjs_op_print(msg);
     }
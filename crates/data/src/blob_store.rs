@@ -0,0 +1,142 @@
+use crate::encryption::{KeyId, Keyring};
+use crate::errors::ShardErrors;
+use crate::shard::format::replace_file;
+use crate::utils::checksum::{verify_checksum, with_checksum};
+use crate::utils::fs::list_files_with_prefix;
+use crate::U64_SIZE;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+const BLOB_PREFIX: &str = "blob_";
+
+/// Keeps values too large to be stored inline in a row, one file per value.
+/// Rows only hold the id of their blobs, which are read when the value is needed.
+///
+/// A blob file holds the id of the key it was encrypted with, the (encrypted) value and its checksum.
+#[derive(Debug)]
+pub struct BlobStore {
+    folder: PathBuf,
+    keyring: Keyring,
+}
+
+impl BlobStore {
+    pub fn new(folder: PathBuf, keyring: Keyring) -> Self {
+        if !folder.exists() {
+            std::fs::create_dir_all(&folder).expect("Failed to create blob folder");
+        }
+
+        Self { folder, keyring }
+    }
+
+    fn path(&self, id: &Uuid) -> PathBuf {
+        self.folder.join(format!("{}{}", BLOB_PREFIX, id))
+    }
+
+    fn write(&self, id: &Uuid, data: &[u8]) -> Result<(), ShardErrors> {
+        let key_id = self.keyring.current_id();
+        let mut contents = key_id.to_le_bytes().to_vec();
+        contents.extend_from_slice(&self.keyring.encrypt(key_id, data)?);
+
+        replace_file(&self.path(id), &with_checksum(&contents))
+    }
+
+    fn read(&self, id: &Uuid) -> Result<(KeyId, Vec<u8>), ShardErrors> {
+        let stored = std::fs::read(self.path(id)).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => ShardErrors::UnknownBlob,
+            _ => ShardErrors::ErrorReadingByteRange,
+        })?;
+        let contents = verify_checksum(&stored)?;
+        if contents.len() < U64_SIZE {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        let (key_id, data) = contents.split_at(U64_SIZE);
+        let key_id = KeyId::from_le_bytes(key_id.try_into().unwrap());
+
+        Ok((key_id, self.keyring.decrypt(key_id, data.to_vec())?))
+    }
+
+    /// Stores `data`, returning the id it can be read with.
+    pub fn put(&self, data: &[u8]) -> Result<Uuid, ShardErrors> {
+        let id = Uuid::new_v4();
+        self.write(&id, data)?;
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<Vec<u8>, ShardErrors> {
+        self.read(id).map(|(_, data)| data)
+    }
+
+    /// Removes the blob. Removing a blob that does not exist is not an error.
+    pub fn remove(&self, id: &Uuid) -> Result<(), ShardErrors> {
+        match std::fs::remove_file(self.path(id)) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(_) => Err(ShardErrors::FlushingError),
+        }
+    }
+
+    /// Rewrites the first blob encrypted with a key other than the current one, returning whether it did.
+    pub fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        let paths = list_files_with_prefix(&self.folder, BLOB_PREFIX)
+            .map_err(|_| ShardErrors::ErrorReadingByteRange)?;
+
+        for path in paths {
+            let id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(BLOB_PREFIX))
+                .and_then(|id| Uuid::parse_str(id).ok());
+
+            if let Some(id) = id {
+                let (key_id, data) = self.read(&id)?;
+                if key_id != self.keyring.current_id() {
+                    self.write(&id, &data)?;
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blob_store::BlobStore;
+    use crate::encryption::{EncryptionKey, Keyring, KEY_SIZE};
+    use crate::errors::ShardErrors;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_blob_store() {
+        let folder = tempdir().unwrap();
+        let old_key = EncryptionKey::new(&[1u8; KEY_SIZE]).unwrap();
+        let new_key = EncryptionKey::new(&[2u8; KEY_SIZE]).unwrap();
+        let value = vec![7u8; 1 << 16];
+
+        let id = {
+            let store = BlobStore::new(
+                folder.path().join("blobs"),
+                Keyring::new(Some(old_key.clone()), vec![]),
+            );
+            let id = store.put(&value).unwrap();
+            assert_eq!(store.get(&id).unwrap(), value);
+            id
+        };
+
+        let store = BlobStore::new(
+            folder.path().join("blobs"),
+            Keyring::new(Some(new_key), vec![old_key]),
+        );
+        assert_eq!(store.get(&id).unwrap(), value);
+        assert!(store.reencrypt().unwrap());
+        assert!(!store.reencrypt().unwrap());
+        assert_eq!(store.get(&id).unwrap(), value);
+
+        store.remove(&id).unwrap();
+        assert!(matches!(store.get(&id), Err(ShardErrors::UnknownBlob)));
+        store.remove(&id).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use crate::errors::ShardErrors;
+use std::borrow::Cow;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Codec used to compress the items stored in a shard.
+/// It is recorded in the header of the shard, so a shard is always read with the codec it was written with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Items are stored as they are.
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn compress<'a>(&self, data: &'a [u8]) -> Result<Cow<'a, [u8]>, ShardErrors> {
+        match self {
+            Compression::None => Ok(Cow::Borrowed(data)),
+            Compression::Lz4 => Ok(Cow::Owned(lz4_flex::compress_prepend_size(data))),
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+                .map(Cow::Owned)
+                .map_err(|_| ShardErrors::CompressionError),
+        }
+    }
+
+    pub fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>, ShardErrors> {
+        match self {
+            Compression::None => Ok(data),
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(&data)
+                .map_err(|_| ShardErrors::CompressionError),
+            Compression::Zstd => {
+                zstd::stream::decode_all(data.as_slice()).map_err(|_| ShardErrors::CompressionError)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::Compression;
+
+    #[test]
+    fn test_compression_round_trip() {
+        let data = "Cats are cute. ".repeat(100).into_bytes();
+
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let compressed = compression.compress(&data).unwrap().into_owned();
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len());
+            }
+
+            assert_eq!(compression.decompress(compressed).unwrap(), data);
+            assert_eq!(
+                Compression::from_byte(compression.to_byte()),
+                Some(compression)
+            );
+        }
+
+        assert!(Compression::Lz4.decompress(vec![1, 2, 3]).is_err());
+        assert_eq!(Compression::from_byte(9), None);
+    }
+}
@@ -1,29 +1,38 @@
+use crate::durability::Durability;
 use crate::fdm::FileDescriptorManager;
 use memmap2::Mmap;
 use parking_lot::RwLock;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{Error, ErrorKind, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 #[derive(Debug)]
 pub struct DataHandler {
     pub path: PathBuf,
     fdm: Arc<FileDescriptorManager>,
-    mmap: Mmap,
+    // Empty while the file is unmapped to keep within the mapped bytes budget of the FDM.
+    // It is mapped again the next time it is read.
+    mmap: OnceLock<Mmap>,
+    len: usize,
+    durability: Durability,
 }
 
 impl DataHandler {
     unsafe fn new_from_path<P: AsRef<Path> + Clone>(
         path: P,
         fdm: Arc<FileDescriptorManager>,
+        durability: Durability,
     ) -> std::io::Result<Self> {
         if let Some(descriptor) = fdm.pop_insert(&path) {
             let file = descriptor.file.read();
+            let mmap = Self::mmap(&file)?;
             Ok(Self {
                 path: path.as_ref().to_path_buf(),
                 fdm,
-                mmap: Self::mmap(&file)?,
+                len: mmap.len(),
+                mmap: OnceLock::from(mmap),
+                durability,
             })
         } else {
             Err(Error::new(ErrorKind::Other, "Too many files open in FDM"))
@@ -35,19 +44,72 @@ impl DataHandler {
     }
 
     #[cfg(test)]
-    pub unsafe fn access_map(&self) -> &Mmap {
-        &self.mmap
+    pub unsafe fn access_map(&self) -> Option<&Mmap> {
+        self.mmap.get()
     }
 
     pub unsafe fn new<P: AsRef<Path> + Clone>(
         path: P,
         fdm: Arc<FileDescriptorManager>,
-    ) -> std::io::Result<RwLock<Self>> {
-        Ok(RwLock::new(Self::new_from_path(path, fdm)?))
+        durability: Durability,
+    ) -> std::io::Result<Arc<RwLock<Self>>> {
+        let handler = Arc::new(RwLock::new(Self::new_from_path(
+            path,
+            fdm.clone(),
+            durability,
+        )?));
+        {
+            let reader = handler.read();
+            fdm.track_mapping(&reader.path, Arc::downgrade(&handler));
+            fdm.record_mapping(&reader.path, reader.len);
+        }
+
+        Ok(handler)
+    }
+
+    /// Replaces the memory map of the file, keeping the FDM aware of how many bytes it maps.
+    fn set_mmap(&mut self, mmap: Mmap) {
+        self.len = mmap.len();
+        self.mmap = OnceLock::from(mmap);
+        self.fdm.record_mapping(&self.path, self.len);
+    }
+
+    /// Drops the memory map of the file. It is mapped again the next time it is read.
+    pub(crate) fn unmap(&mut self) {
+        self.mmap = OnceLock::new();
+    }
+
+    /// Opens the file again, picking up its contents after it has been replaced on disk.
+    pub fn reload(&mut self) -> std::io::Result<()> {
+        let descriptor = self
+            .fdm
+            .pop_insert(&self.path)
+            .ok_or_else(|| Error::other("Too many files open in FDM"))?;
+        let file = descriptor.file.read();
+        let mmap = unsafe { Self::mmap(&file)? };
+        self.set_mmap(mmap);
+
+        Ok(())
+    }
+
+    /// Returns the memory map of the file, mapping it again if it was unmapped.
+    fn mapped(&self) -> Option<&Mmap> {
+        if let Some(mmap) = self.mmap.get() {
+            self.fdm.touch_mapping(&self.path);
+            return Some(mmap);
+        }
+
+        let descriptor = self.fdm.pop_insert(&self.path)?;
+        let mmap = unsafe { Self::mmap(&descriptor.file.read()).ok()? };
+        // Another reader may have mapped it in the meantime, in which case this map is dropped.
+        let _ = self.mmap.set(mmap);
+        self.fdm.record_mapping(&self.path, self.len);
+
+        self.mmap.get()
     }
 
     pub fn len(&self) -> usize {
-        self.mmap.len()
+        self.len
     }
 
     pub fn is_empty(&self) -> bool {
@@ -55,7 +117,7 @@ impl DataHandler {
     }
 
     pub fn get_bytes(&self, from: usize, to: usize) -> Option<&[u8]> {
-        self.mmap.get(from..to)
+        self.mapped()?.get(from..to)
     }
 
     pub fn read_pointer(&self, start: u64, max_bytes: usize) -> Option<Vec<u8>> {
@@ -63,6 +125,18 @@ impl DataHandler {
             .map(|i| i.to_vec())
     }
 
+    /// Flushes the writes made to the file to disk, whatever the durability it was opened with.
+    pub fn sync(&self) -> std::io::Result<()> {
+        match self.fdm.get(&self.path) {
+            Some(descriptor) => descriptor.file.read().sync_data(),
+            None => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&self.path)?
+                .sync_data(),
+        }
+    }
+
     pub fn operate<F, R>(&mut self, callback: F) -> std::io::Result<R>
     where
         F: FnOnce(&mut File) -> std::io::Result<R>,
@@ -71,8 +145,15 @@ impl DataHandler {
         if let Some(fd) = fdm.get(&self.path) {
             let mut writer = fd.file.write();
             let cb = callback(&mut writer)?;
-            let new_mmap = unsafe { Self::mmap(&writer) };
-            self.mmap = new_mmap?;
+
+            match self.durability {
+                Durability::None => {}
+                Durability::Batch(interval) => fdm.mark_dirty(&self.path, interval),
+                Durability::Strict => writer.sync_data()?,
+            }
+
+            let new_mmap = unsafe { Self::mmap(&writer) }?;
+            self.set_mmap(new_mmap);
 
             Ok(cb)
         } else {
@@ -80,3 +161,9 @@ impl DataHandler {
         }
     }
 }
+
+impl Drop for DataHandler {
+    fn drop(&mut self) {
+        self.fdm.forget_mapping(&self.path);
+    }
+}
@@ -0,0 +1,14 @@
+use std::time::Duration;
+
+/// Determines when the bytes written to a shard are flushed to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Writes are never explicitly synced, the operating system decides when they reach disk.
+    #[default]
+    None,
+    /// Writes are grouped and synced by `FileDescriptorManager::sync_dirty`
+    /// no later than the given interval after they happened.
+    Batch(Duration),
+    /// Every write is synced before it is acknowledged.
+    Strict,
+}
@@ -0,0 +1,203 @@
+use crate::errors::ShardErrors;
+use crate::U64_SIZE;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+
+pub const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// Bytes added to every encrypted item: the nonce it was encrypted with and its authentication tag.
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Identifies the key a shard was encrypted with. It is recorded in the header of the shard.
+pub type KeyId = u64;
+
+/// Key id of the shards that are not encrypted.
+pub const NO_KEY: KeyId = 0;
+
+/// AES-256-GCM key used to encrypt the items stored in shards.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: KeyId,
+    cipher: Aes256Gcm,
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptionKey {
+    pub fn new(key: &[u8]) -> Result<Self, ShardErrors> {
+        let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| ShardErrors::EncryptionError)?;
+
+        // The id is derived from the key, so the same key always gets the same id
+        let digest = Sha256::digest(key);
+        let id = u64::from_le_bytes(digest[0..U64_SIZE].try_into().unwrap()).max(NO_KEY + 1);
+
+        Ok(Self { id, cipher })
+    }
+
+    /// Reads a key file holding either the 32 raw bytes of the key or their 64 hex characters.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ShardErrors> {
+        let contents = std::fs::read(path).map_err(|_| ShardErrors::EncryptionError)?;
+        if contents.len() == KEY_SIZE {
+            return Self::new(&contents);
+        }
+
+        let hex = std::str::from_utf8(&contents)
+            .map_err(|_| ShardErrors::EncryptionError)?
+            .trim();
+        if hex.len() != KEY_SIZE * 2 {
+            return Err(ShardErrors::EncryptionError);
+        }
+
+        let key = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ShardErrors::EncryptionError)?;
+
+        Self::new(&key)
+    }
+
+    pub fn id(&self) -> KeyId {
+        self.id
+    }
+
+    /// Returns the nonce followed by the encrypted `data` and its authentication tag.
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, ShardErrors> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let encrypted = self
+            .cipher
+            .encrypt(&nonce, data)
+            .map_err(|_| ShardErrors::EncryptionError)?;
+
+        let mut buffer = Vec::with_capacity(NONCE_SIZE + encrypted.len());
+        buffer.extend_from_slice(&nonce);
+        buffer.extend_from_slice(&encrypted);
+        Ok(buffer)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, ShardErrors> {
+        if data.len() < ENCRYPTION_OVERHEAD {
+            return Err(ShardErrors::EncryptionError);
+        }
+
+        let (nonce, encrypted) = data.split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| ShardErrors::EncryptionError)
+    }
+}
+
+/// Keys available to the shards of a database.
+/// New shards are encrypted with the current key, the previous ones are kept so shards that
+/// have not been re-encrypted yet can still be read.
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    current: Option<EncryptionKey>,
+    previous: Vec<EncryptionKey>,
+}
+
+impl Keyring {
+    pub fn new(current: Option<EncryptionKey>, previous: Vec<EncryptionKey>) -> Self {
+        Self { current, previous }
+    }
+
+    /// Id of the key new data is written with, `NO_KEY` if encryption is disabled.
+    pub fn current_id(&self) -> KeyId {
+        self.current.as_ref().map_or(NO_KEY, |key| key.id())
+    }
+
+    /// Returns the key whose id is `id`, or `None` if it is `NO_KEY`.
+    pub fn get(&self, id: KeyId) -> Result<Option<&EncryptionKey>, ShardErrors> {
+        if id == NO_KEY {
+            return Ok(None);
+        }
+
+        self.current
+            .iter()
+            .chain(self.previous.iter())
+            .find(|key| key.id() == id)
+            .map(Some)
+            .ok_or(ShardErrors::UnknownEncryptionKey)
+    }
+
+    pub fn encrypt<'a>(&self, id: KeyId, data: &'a [u8]) -> Result<Cow<'a, [u8]>, ShardErrors> {
+        match self.get(id)? {
+            None => Ok(Cow::Borrowed(data)),
+            Some(key) => key.encrypt(data).map(Cow::Owned),
+        }
+    }
+
+    pub fn decrypt(&self, id: KeyId, data: Vec<u8>) -> Result<Vec<u8>, ShardErrors> {
+        match self.get(id)? {
+            None => Ok(data),
+            Some(key) => key.decrypt(&data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encryption::{EncryptionKey, Keyring, ENCRYPTION_OVERHEAD, KEY_SIZE, NO_KEY};
+    use crate::errors::ShardErrors;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let key = EncryptionKey::new(&[7u8; KEY_SIZE]).unwrap();
+        let other_key = EncryptionKey::new(&[8u8; KEY_SIZE]).unwrap();
+        assert_ne!(key.id(), other_key.id());
+        assert!(EncryptionKey::new(&[7u8; 16]).is_err());
+
+        let encrypted = key.encrypt(b"Hello World").unwrap();
+        assert_eq!(encrypted.len(), b"Hello World".len() + ENCRYPTION_OVERHEAD);
+        assert_eq!(key.decrypt(&encrypted).unwrap(), b"Hello World".to_vec());
+        assert!(other_key.decrypt(&encrypted).is_err());
+
+        // Every item gets its own nonce
+        assert_ne!(key.encrypt(b"Hello World").unwrap(), encrypted);
+
+        let keyring = Keyring::new(Some(other_key.clone()), vec![key.clone()]);
+        assert_eq!(keyring.current_id(), other_key.id());
+        assert_eq!(
+            keyring.decrypt(key.id(), encrypted).unwrap(),
+            b"Hello World".to_vec()
+        );
+        assert_eq!(
+            keyring.encrypt(NO_KEY, b"Hello World").unwrap().as_ref(),
+            b"Hello World"
+        );
+        assert!(matches!(
+            Keyring::default().encrypt(key.id(), b"Hello World"),
+            Err(ShardErrors::UnknownEncryptionKey)
+        ));
+    }
+
+    #[test]
+    fn test_encryption_key_from_file() {
+        let temp_dir = tempdir().unwrap();
+        let raw_path = temp_dir.path().join("raw.key");
+        let hex_path = temp_dir.path().join("hex.key");
+
+        std::fs::write(&raw_path, [0xABu8; KEY_SIZE]).unwrap();
+        std::fs::write(&hex_path, format!("{}\n", "ab".repeat(KEY_SIZE))).unwrap();
+
+        let raw = EncryptionKey::from_file(&raw_path).unwrap();
+        let hex = EncryptionKey::from_file(&hex_path).unwrap();
+        assert_eq!(raw.id(), hex.id());
+
+        std::fs::write(&hex_path, "ab").unwrap();
+        assert!(EncryptionKey::from_file(&hex_path).is_err());
+    }
+}
@@ -26,4 +26,22 @@ pub enum ShardErrors {
     UnknownShard,
     #[error("Invalid locking detected")]
     InvalidLocking,
+    #[error("Could not write to the write-ahead log")]
+    WalError,
+    #[error("Item has been deleted")]
+    DeletedItem,
+    #[error("Could not compress or decompress item")]
+    CompressionError,
+    #[error("Shard is corrupted")]
+    Corrupted,
+    #[error("Shard was written in an unsupported format version")]
+    UnsupportedVersion,
+    #[error("Could not encrypt or decrypt item")]
+    EncryptionError,
+    #[error("Shard was encrypted with a key that is not available")]
+    UnknownEncryptionKey,
+    #[error("Blob does not exist")]
+    UnknownBlob,
+    #[error("Reconciled rows could not be processed")]
+    ReconcileError,
 }
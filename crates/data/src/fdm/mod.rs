@@ -1,25 +1,61 @@
 mod file_descriptor;
 
+use crate::data_handler::DataHandler;
 use crate::fdm::file_descriptor::FileDescriptor;
 use lru::LruCache;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct MappedFile {
+    handler: Weak<RwLock<DataHandler>>,
+    bytes: usize,
+}
+
+/// Files memory-mapped by a `DataHandler`, from the most to the least recently read.
+#[derive(Debug)]
+struct Mappings {
+    files: LruCache<PathBuf, MappedFile>,
+    bytes: usize,
+}
 
 #[derive(Debug)]
 pub struct FileDescriptorManager {
     cache: Arc<RwLock<LruCache<PathBuf, Arc<FileDescriptor>>>>,
     max_size: usize,
+    // Files with unsynced writes and the deadline by which they must be synced.
+    dirty: Mutex<HashMap<PathBuf, Instant>>,
+    // Only tracked if there is a budget of mapped bytes.
+    mappings: Mutex<Mappings>,
+    max_mapped_bytes: Option<usize>,
 }
 
 impl FileDescriptorManager {
     pub fn new(max_size: usize) -> Self {
+        Self::new_with_mapped_bytes(max_size, None)
+    }
+
+    /// Creates a manager that also keeps the bytes memory-mapped by the shard files within `max_mapped_bytes`,
+    /// unmapping the least recently read files once it is exceeded. Unmapped files are mapped again
+    /// the next time they are read. Files being read or written are never unmapped, so the budget may be
+    /// exceeded while they are in use.
+    pub fn new_with_mapped_bytes(max_size: usize, max_mapped_bytes: Option<usize>) -> Self {
         Self {
             cache: Arc::new(RwLock::new(LruCache::new(
                 NonZeroUsize::new(max_size).unwrap(),
             ))),
             max_size,
+            dirty: Mutex::new(HashMap::new()),
+            mappings: Mutex::new(Mappings {
+                files: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            max_mapped_bytes,
         }
     }
 
@@ -80,6 +116,155 @@ impl FileDescriptorManager {
         }
     }
 
+    /// Flags `path` as having unsynced writes which must reach disk within `interval`.
+    /// If the file was already dirty, its earlier deadline is kept.
+    pub fn mark_dirty(&self, path: &Path, interval: Duration) {
+        let deadline = Instant::now() + interval;
+        self.dirty
+            .lock()
+            .entry(path.to_path_buf())
+            .or_insert(deadline);
+    }
+
+    /// Syncs every dirty file whose deadline has passed, returning how many were synced.
+    /// Files that were evicted from the cache are reopened so their writes are still flushed.
+    pub fn sync_dirty(&self) -> std::io::Result<usize> {
+        let now = Instant::now();
+        let due: Vec<(PathBuf, Instant)> = {
+            let mut dirty = self.dirty.lock();
+            let paths: Vec<PathBuf> = dirty
+                .iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(path, _)| path.clone())
+                .collect();
+
+            paths
+                .into_iter()
+                .filter_map(|path| dirty.remove_entry(&path))
+                .collect()
+        };
+
+        let mut synced = 0;
+        let mut result = Ok(());
+
+        for (path, deadline) in due {
+            let op = match self.get(&path) {
+                Some(descriptor) => descriptor.file.read().sync_data(),
+                None => match OpenOptions::new().read(true).write(true).open(&path) {
+                    Ok(file) => file.sync_data(),
+                    // The file is gone, there is nothing left to sync.
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    Err(e) => Err(e),
+                },
+            };
+
+            match op {
+                Ok(_) => synced += 1,
+                Err(e) => {
+                    // Keep it around so it is retried in the next pass.
+                    self.dirty.lock().entry(path).or_insert(deadline);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result.map(|_| synced)
+    }
+
+    /// Starts accounting the bytes `handler` maps for `path` against the budget of mapped bytes.
+    pub(crate) fn track_mapping(&self, path: &Path, handler: Weak<RwLock<DataHandler>>) {
+        if self.max_mapped_bytes.is_none() {
+            return;
+        }
+
+        let mut mappings = self.mappings.lock();
+        if let Some(previous) = mappings
+            .files
+            .put(path.to_path_buf(), MappedFile { handler, bytes: 0 })
+        {
+            mappings.bytes -= previous.bytes;
+        }
+    }
+
+    /// Records that `path` was (re)mapped with `bytes` bytes, unmapping the least recently read files
+    /// if that goes over the budget.
+    pub(crate) fn record_mapping(&self, path: &Path, bytes: usize) {
+        let Some(max_mapped_bytes) = self.max_mapped_bytes else {
+            return;
+        };
+
+        // Handlers are only dropped once the lock on the mappings is released, since dropping them forgets them.
+        let mut unmapped = vec![];
+        let mut mappings = self.mappings.lock();
+
+        let Some(file) = mappings.files.get_mut(path) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut file.bytes, bytes);
+        mappings.bytes = mappings.bytes - previous + bytes;
+
+        let candidates: Vec<PathBuf> = mappings
+            .files
+            .iter()
+            .rev()
+            .filter(|(candidate, file)| file.bytes > 0 && candidate.as_path() != path)
+            .map(|(candidate, _)| candidate.clone())
+            .collect();
+
+        for candidate in candidates {
+            if mappings.bytes <= max_mapped_bytes {
+                break;
+            }
+
+            let Some(file) = mappings.files.peek_mut(&candidate) else {
+                continue;
+            };
+            let Some(handler) = file.handler.upgrade() else {
+                continue;
+            };
+
+            // Files being read or written are skipped
+            if let Some(mut writer) = handler.try_write() {
+                writer.unmap();
+                let freed = std::mem::take(&mut file.bytes);
+                mappings.bytes -= freed;
+            }
+
+            unmapped.push(handler);
+        }
+    }
+
+    /// Marks `path` as the most recently read file.
+    pub(crate) fn touch_mapping(&self, path: &Path) {
+        if self.max_mapped_bytes.is_some() {
+            self.mappings.lock().files.promote(path);
+        }
+    }
+
+    /// Stops accounting `path` once its handler is gone.
+    pub(crate) fn forget_mapping(&self, path: &Path) {
+        if self.max_mapped_bytes.is_none() {
+            return;
+        }
+
+        let mut mappings = self.mappings.lock();
+        // A newer handler of the same file may have taken its place.
+        let is_gone = mappings
+            .files
+            .peek(path)
+            .is_some_and(|file| file.handler.strong_count() == 0);
+        if is_gone {
+            if let Some(file) = mappings.files.pop(path) {
+                mappings.bytes -= file.bytes;
+            }
+        }
+    }
+
+    /// Bytes currently mapped by the files accounted against the budget of mapped bytes.
+    pub fn mapped_bytes(&self) -> usize {
+        self.mappings.lock().bytes
+    }
+
     pub fn remove_paths(&self, paths: Vec<PathBuf>) {
         let fdm = self.cache.clone();
         if !paths.is_empty() && self.max_size >= { fdm.read().len() } {
@@ -95,7 +280,10 @@ impl FileDescriptorManager {
 
 #[cfg(test)]
 mod fdm_tests {
+    use crate::data_handler::DataHandler;
+    use crate::durability::Durability;
     use crate::fdm::FileDescriptorManager;
+    use std::io::Write;
     use std::sync::Arc;
     use std::time::Duration;
     use tempfile::tempdir;
@@ -138,4 +326,65 @@ mod fdm_tests {
         assert!(get_fdm_2.is_some());
         assert!(!get_fdm_2.unwrap().file.is_locked_exclusive());
     }
+
+    #[tokio::test]
+    async fn test_fdm_sync_dirty() {
+        let fdm = Arc::new(FileDescriptorManager::new(1));
+        let temp_dir = tempdir().unwrap();
+
+        let fs_1 = temp_dir.path().join("1.data");
+        let fs_2 = temp_dir.path().join("2.data");
+
+        let descriptor = fdm.pop_insert(&fs_1).unwrap();
+        descriptor.file.write().write_all(b"Hello World").unwrap();
+        fdm.mark_dirty(&fs_1, Duration::from_millis(100));
+
+        // Not due yet
+        assert_eq!(fdm.sync_dirty().unwrap(), 0);
+
+        // Evicts fs_1 from the cache, it must still be synced
+        assert!(fdm.pop_insert(&fs_2).is_some());
+        assert!(fdm.get(&fs_1).is_none());
+        fdm.mark_dirty(&fs_2, Duration::ZERO);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(fdm.sync_dirty().unwrap(), 2);
+        assert_eq!(fdm.sync_dirty().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fdm_mapped_bytes_budget() {
+        let fdm = Arc::new(FileDescriptorManager::new_with_mapped_bytes(10, Some(100)));
+        let temp_dir = tempdir().unwrap();
+
+        let handlers: Vec<_> = (0..3u8)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("{}.data", i));
+                let handler =
+                    unsafe { DataHandler::new(path, fdm.clone(), Durability::None) }.unwrap();
+                handler
+                    .write()
+                    .operate(|file| file.write_all(&[i; 60]))
+                    .unwrap();
+                handler
+            })
+            .collect();
+
+        // Only the last file written fits in the budget
+        assert_eq!(fdm.mapped_bytes(), 60);
+        assert!(unsafe { handlers[0].read().access_map() }.is_none());
+        assert!(unsafe { handlers[2].read().access_map() }.is_some());
+
+        // Reading an unmapped file maps it again, unmapping the least recently read one
+        assert_eq!(
+            handlers[0].read().get_bytes(0, 60),
+            Some([0u8; 60].as_slice())
+        );
+        assert_eq!(fdm.mapped_bytes(), 60);
+        assert!(unsafe { handlers[2].read().access_map() }.is_none());
+        assert_eq!(handlers[2].read().len(), 60);
+
+        drop(handlers);
+        assert_eq!(fdm.mapped_bytes(), 0);
+    }
 }
@@ -1,9 +1,15 @@
+pub mod blob_store;
+pub mod compression;
 pub mod data_handler;
+pub mod durability;
+pub mod encryption;
 pub mod errors;
 pub mod fdm;
+pub mod reconcile_policy;
 pub mod shard;
 pub mod temp_offset_types;
 pub mod utils;
+pub mod wal;
 
 // https://doc.rust-lang.org/std/mem/fn.size_of.html
 pub const U64_SIZE: usize = size_of::<u64>();
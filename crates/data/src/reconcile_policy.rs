@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+/// Rows inserted in temporary shards that have not been reconciled into their parent shard yet.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PendingRows {
+    pub rows: u64,
+    pub bytes: u64,
+    /// When the oldest of the rows was inserted.
+    pub oldest: Option<Instant>,
+}
+
+impl PendingRows {
+    pub fn record(&mut self, data: &[&[u8]]) {
+        self.rows += data.len() as u64;
+        self.bytes += data.iter().map(|row| row.len() as u64).sum::<u64>();
+        self.oldest.get_or_insert_with(Instant::now);
+    }
+
+    pub fn merge(&mut self, other: &PendingRows) {
+        self.rows += other.rows;
+        self.bytes += other.bytes;
+        self.oldest = match (self.oldest, other.oldest) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// Thresholds deciding when the rows pending in temporary shards are reconciled.
+/// Reaching any of them triggers the reconciliation. A threshold set to zero is disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconcilePolicy {
+    pub max_rows: u64,
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+impl ReconcilePolicy {
+    pub fn is_due(&self, pending: &PendingRows) -> bool {
+        if pending.rows == 0 {
+            return false;
+        }
+
+        let over = |value: u64, max: u64| max > 0 && value >= max;
+        let too_old = !self.max_age.is_zero()
+            && pending
+                .oldest
+                .is_some_and(|oldest| oldest.elapsed() >= self.max_age);
+
+        over(pending.rows, self.max_rows) || over(pending.bytes, self.max_bytes) || too_old
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::reconcile_policy::{PendingRows, ReconcilePolicy};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_reconcile_policy() {
+        let policy = ReconcilePolicy {
+            max_rows: 3,
+            max_bytes: 10,
+            max_age: Duration::ZERO,
+        };
+
+        let mut pending = PendingRows::default();
+        assert!(!policy.is_due(&pending));
+
+        pending.record(&[b"ab", b"cd"]);
+        assert!(!policy.is_due(&pending));
+
+        let mut other = PendingRows::default();
+        other.record(&[b"ef"]);
+        pending.merge(&other);
+        assert_eq!(pending.rows, 3);
+        assert!(policy.is_due(&pending));
+
+        let mut large = PendingRows::default();
+        large.record(&[b"0123456789"]);
+        assert!(policy.is_due(&large));
+
+        let aged = ReconcilePolicy {
+            max_rows: 0,
+            max_bytes: 0,
+            max_age: Duration::from_millis(20),
+        };
+        assert!(!aged.is_due(&other));
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(aged.is_due(&other));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::errors::ShardErrors;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+// Every shard file starts with a preamble: magic bytes telling which kind of shard it holds,
+// followed by the version of the layout the rest of the file was written with.
+pub const MAGIC_SIZE: usize = 4;
+pub const VERSION_SIZE: usize = size_of::<u16>();
+pub const PREAMBLE_SIZE: usize = MAGIC_SIZE + VERSION_SIZE;
+
+pub const DATA_SHARD_MAGIC: &[u8; MAGIC_SIZE] = b"SJSD";
+pub const KV_SHARD_MAGIC: &[u8; MAGIC_SIZE] = b"SJSK";
+
+/// Version of the files written before the preamble existed.
+/// They are not readable as they are, they need to go through an upgrade first.
+pub const LEGACY_FORMAT_VERSION: u16 = 0;
+
+/// First version with a preamble. Its headers lack the id of the key the shard is encrypted with.
+pub const V1_FORMAT_VERSION: u16 = 1;
+
+/// Version of the layout written by this release.
+pub const FORMAT_VERSION: u16 = 2;
+
+// Upgraded shards are written with this prefix before replacing the original file,
+// so they are never picked up as shards.
+const UPGRADE_PREFIX: &str = "upgrading_";
+
+pub fn preamble(magic: &[u8; MAGIC_SIZE]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(PREAMBLE_SIZE);
+    buffer.extend_from_slice(magic);
+    buffer.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    buffer
+}
+
+/// Returns the format version of a file starting with `bytes`.
+/// Files not starting with `magic` are considered to be in the legacy format.
+pub fn read_version(magic: &[u8; MAGIC_SIZE], bytes: &[u8]) -> u16 {
+    match bytes.get(0..PREAMBLE_SIZE) {
+        Some(preamble) if preamble.starts_with(magic) => {
+            u16::from_le_bytes(preamble[MAGIC_SIZE..].try_into().unwrap())
+        }
+        _ => LEGACY_FORMAT_VERSION,
+    }
+}
+
+/// Returns the format version of the file at `path`, or `None` if it is empty or does not exist.
+pub fn read_file_version(magic: &[u8; MAGIC_SIZE], path: &Path) -> Option<u16> {
+    let mut bytes = Vec::with_capacity(PREAMBLE_SIZE);
+    File::open(path)
+        .ok()?
+        .take(PREAMBLE_SIZE as u64)
+        .read_to_end(&mut bytes)
+        .ok()?;
+
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(read_version(magic, &bytes))
+    }
+}
+
+/// Replaces the file at `path` with `contents`.
+/// They are written to a sibling file which is then renamed, so a crash never leaves a half written shard behind.
+pub fn replace_file(path: &Path, contents: &[u8]) -> Result<(), ShardErrors> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let upgrade_path = path.with_file_name(format!("{}{}", UPGRADE_PREFIX, file_name));
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&upgrade_path)
+        .map_err(|_| ShardErrors::FlushingError)?;
+    file.write_all(contents)
+        .map_err(|_| ShardErrors::FlushingError)?;
+    file.sync_all().map_err(|_| ShardErrors::FlushingError)?;
+
+    std::fs::rename(&upgrade_path, path).map_err(|_| ShardErrors::FlushingError)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::shard::format::{
+        preamble, read_version, DATA_SHARD_MAGIC, FORMAT_VERSION, KV_SHARD_MAGIC,
+        LEGACY_FORMAT_VERSION,
+    };
+
+    #[test]
+    pub fn test_read_version() {
+        let mut bytes = preamble(DATA_SHARD_MAGIC);
+        bytes.extend_from_slice(&10u64.to_le_bytes());
+
+        assert_eq!(read_version(DATA_SHARD_MAGIC, &bytes), FORMAT_VERSION);
+        // A kv shard is never read as a data shard, nor the other way around
+        assert_eq!(read_version(KV_SHARD_MAGIC, &bytes), LEGACY_FORMAT_VERSION);
+        assert_eq!(
+            read_version(DATA_SHARD_MAGIC, &10u64.to_le_bytes()),
+            LEGACY_FORMAT_VERSION
+        );
+
+        bytes[DATA_SHARD_MAGIC.len()] = 7;
+        assert_eq!(read_version(DATA_SHARD_MAGIC, &bytes), 7);
+    }
+}
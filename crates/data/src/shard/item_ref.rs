@@ -0,0 +1,47 @@
+use crate::data_handler::DataHandler;
+use parking_lot::{ArcRwLockReadGuard, RawRwLock};
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, Range};
+
+/// Item read from a shard, borrowed from where the shard keeps it whenever it is stored as it is read.
+///
+/// Borrowed items keep the shard read-locked until they are dropped, so writes to the shard wait for them.
+/// They are meant to be decoded right away rather than kept around.
+pub enum ItemRef {
+    /// Bytes of the memory map of a shard file.
+    Mapped {
+        data: ArcRwLockReadGuard<RawRwLock, DataHandler>,
+        range: Range<usize>,
+    },
+    /// Item of a shard kept in memory.
+    Memory {
+        items: ArcRwLockReadGuard<RawRwLock, Vec<Option<Vec<u8>>>>,
+        index: usize,
+    },
+    /// Copy of an item that had to be decoded (decrypted or decompressed) to be read.
+    Owned(Vec<u8>),
+}
+
+impl Deref for ItemRef {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Ranges and indexes are checked when the item is read, so they always point to the item.
+        match self {
+            ItemRef::Mapped { data, range } => {
+                data.get_bytes(range.start, range.end).unwrap_or_default()
+            }
+            ItemRef::Memory { items, index } => items
+                .get(*index)
+                .and_then(|i| i.as_deref())
+                .unwrap_or_default(),
+            ItemRef::Owned(item) => item,
+        }
+    }
+}
+
+impl Debug for ItemRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ItemRef").field(&self.deref()).finish()
+    }
+}
@@ -1,6 +1,7 @@
 use crate::errors::ShardErrors;
 use crate::fdm::FileDescriptorManager;
-use crate::shard::{AvailableSpace, Shard, ShardConfig};
+use crate::shard::item_ref::ItemRef;
+use crate::shard::{AvailableSpace, CompactableShard, Shard, ShardConfig};
 use crate::utils::fs::list_files_with_prefix;
 use indexmap::IndexMap;
 use parking_lot::RwLock;
@@ -8,6 +9,9 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use uuid::Uuid;
 
+// Shards being compacted are written with this prefix so they are never picked up as data shards.
+const COMPACTION_PREFIX: &str = "compacting_";
+
 #[derive(Debug)]
 pub struct MapShard<S: Shard<Opts>, Opts: ShardConfig> {
     pub current_master_shard: S,
@@ -26,7 +30,8 @@ impl<S: Shard<Opts>, Opts: ShardConfig> MapShard<S, Opts> {
         fdm: Arc<FileDescriptorManager>,
     ) -> Self {
         let shards_folder = shards_folder.as_ref().to_path_buf();
-        let shard_files = list_files_with_prefix(&shards_folder, shard_prefix).unwrap();
+        // Shards that are not kept in files (such as `MemoryShard`) do not need the folder to exist.
+        let shard_files = list_files_with_prefix(&shards_folder, shard_prefix).unwrap_or_default();
         let mut sorted_files: Vec<(usize, String, PathBuf)> = Vec::new();
 
         for path in shard_files {
@@ -108,11 +113,21 @@ impl<S: Shard<Opts>, Opts: ShardConfig> MapShard<S, Opts> {
         Some((number, uuid, path))
     }
 
+    /// Inserts the rows, returning the global position of the last one.
     pub fn insert_rows(&mut self, data: &[&[u8]]) -> usize {
         self.raw_insert_rows(data, false)
+            .last()
+            .copied()
+            .unwrap_or_default()
     }
 
-    pub fn raw_insert_rows(&mut self, data: &[&[u8]], create_new_shard: bool) -> usize {
+    /// Inserts the rows with as few writes as possible, one per shard they are spread across,
+    /// returning the global position given to each of them.
+    pub fn raw_insert_rows(&mut self, data: &[&[u8]], create_new_shard: bool) -> Vec<usize> {
+        if data.is_empty() {
+            return vec![];
+        }
+
         if create_new_shard {
             let (shard_number, _, _) =
                 Self::extract_shard_signature(self.current_master_shard.get_path().clone())
@@ -160,27 +175,26 @@ impl<S: Shard<Opts>, Opts: ShardConfig> MapShard<S, Opts> {
 
         let insert_data = &data[0..up_to];
 
-        let items_pos = {
-            let local_index = self.current_master_shard.insert_item(insert_data).unwrap();
-            let breaking_point = self.breaking_point();
-            match breaking_point {
-                None => local_index as usize,
+        let mut items_pos = {
+            let first_local_index = (self.current_master_shard.get_last_index() + 1) as usize;
+            self.current_master_shard.insert_item(insert_data).unwrap();
+            let curr_items = match self.breaking_point() {
+                None => 0,
                 Some(breaking_point) => {
-                    let reader = self.past_master_shards.read();
-                    let curr_items = reader.len() * breaking_point as usize;
-                    curr_items + local_index as usize
+                    self.past_master_shards.read().len() * breaking_point as usize
                 }
-            }
+            };
+            let first_pos = curr_items + first_local_index;
+            (first_pos..first_pos + insert_data.len()).collect::<Vec<_>>()
         };
 
         if data.len() > up_to {
             // Some data didn't fit, insert remaining data into a new shard
             let remaining_data = &data[up_to..];
-            let remaining_index = self.raw_insert_rows(remaining_data, true);
-            items_pos + remaining_index
-        } else {
-            items_pos
+            items_pos.extend(self.raw_insert_rows(remaining_data, true));
         }
+
+        items_pos
     }
 
     fn breaking_point(&self) -> Option<u64> {
@@ -199,51 +213,262 @@ impl<S: Shard<Opts>, Opts: ShardConfig> MapShard<S, Opts> {
         self.get_element_from_specific(&self.current_master_shard, index)
     }
 
-    pub fn get_element(&self, index: usize) -> Result<Vec<u8>, ShardErrors> {
+    /// Finds the shard holding the global `index` and calls `f` with it and the local index within it.
+    fn with_element_shard<R>(
+        &self,
+        index: usize,
+        f: impl FnOnce(&S, usize) -> Result<R, ShardErrors>,
+    ) -> Result<R, ShardErrors> {
         let breaking_point = self.breaking_point();
 
         match breaking_point {
-            None => self.get_element_from_master(index),
+            None => f(&self.current_master_shard, index),
             Some(breaking_point) => {
                 let breaking_point_usize = breaking_point as usize;
 
-                let reader = self.past_master_shards.read();
-                let shard_reversed = {
-                    let mut combined_shards: Vec<&S> = reader.values().rev().collect();
-                    combined_shards.push(&self.current_master_shard);
-                    combined_shards
-                };
-
-                // Calculate the total number of shards
-                let num_shards = shard_reversed.len();
-                // Determine which shard the index belongs to
+                // Determine which shard the index belongs to.
+                // Past shards are kept from the oldest to the newest, followed by the current master.
                 let shard_index = index / breaking_point_usize;
+                // Calculate the local index within the selected shard
+                let local_index = index % breaking_point_usize;
 
-                if shard_index >= num_shards {
-                    return Err(ShardErrors::OutOfRange);
+                let reader = self.past_master_shards.read();
+                match shard_index.cmp(&reader.len()) {
+                    std::cmp::Ordering::Less => {
+                        let (_, shard) = reader
+                            .get_index(shard_index)
+                            .ok_or(ShardErrors::OutOfRange)?;
+                        f(shard, local_index)
+                    }
+                    std::cmp::Ordering::Equal => f(&self.current_master_shard, local_index),
+                    std::cmp::Ordering::Greater => Err(ShardErrors::OutOfRange),
                 }
+            }
+        }
+    }
 
-                // Calculate the local index within the selected shard
-                let local_index = index % breaking_point_usize;
+    /// Number of positions taken in the collection, deleted rows included.
+    /// Positions go from `0` to `len() - 1`, past master shards first.
+    pub fn len(&self) -> usize {
+        let master_len = (self.current_master_shard.get_last_index() + 1) as usize;
+        match self.breaking_point() {
+            None => master_len,
+            Some(breaking_point) => {
+                self.past_master_shards.read().len() * breaking_point as usize + master_len
+            }
+        }
+    }
 
-                println!(
-                    "Getting from shard {} with local index {}",
-                    shard_index, local_index
-                );
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Flushes every shard holding positions from `position` onwards to disk.
+    pub fn sync_from(&self, position: usize) -> Result<(), ShardErrors> {
+        if let Some(breaking_point) = self.breaking_point() {
+            let reader = self.past_master_shards.read();
+            for shard in reader.values().skip(position / breaking_point as usize) {
+                shard.sync()?;
+            }
+        }
+
+        self.current_master_shard.sync()
+    }
+
+    /// Lazily walks the rows from the global position `start` up to `end` (exclusive, or the last row if `None`),
+    /// yielding their global position along with their bytes. Deleted rows are skipped.
+    ///
+    /// Past master shards are walked in the order they were filled, followed by the current master shard.
+    pub fn scan(&self, start: usize, end: Option<usize>) -> MapShardScan<'_, S, Opts> {
+        MapShardScan {
+            map: self,
+            cursor: ScanCursor::new(start, end),
+        }
+    }
+
+    pub fn get_element(&self, index: usize) -> Result<Vec<u8>, ShardErrors> {
+        self.with_element_shard(index, |shard, local_index| {
+            self.get_element_from_specific(shard, local_index)
+        })
+    }
+
+    /// Reads the element at the global `index` without copying it when its shard can lend it.
+    /// See `Shard::read_item_ref`.
+    pub fn get_element_ref(&self, index: usize) -> Result<ItemRef, ShardErrors> {
+        self.with_element_shard(index, |shard, local_index| shard.read_item_ref(local_index))
+    }
+
+    /// Deletes the element at the global `index`. See `Shard::delete_item`.
+    pub fn delete_element(&self, index: usize) -> Result<(), ShardErrors> {
+        self.with_element_shard(index, |shard, local_index| shard.delete_item(local_index))
+    }
+
+    /// Verifies every shard of the collection, returning the path of the damaged ones along with the error found.
+    pub fn verify(&self) -> Vec<(PathBuf, ShardErrors)> {
+        let reader = self.past_master_shards.read();
+        reader
+            .values()
+            .chain(std::iter::once(&self.current_master_shard))
+            .filter_map(|shard| shard.verify().err().map(|e| (shard.get_path(), e)))
+            .collect()
+    }
+
+    /// Rewrites the first shard, past ones before the current master, written with an encryption key other
+    /// than the current one. Returns whether a shard was rewritten, so it can be called until it returns `false`.
+    pub fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        let reader = self.past_master_shards.read();
+        for shard in reader
+            .values()
+            .chain(std::iter::once(&self.current_master_shard))
+        {
+            if shard.reencrypt()? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl<S: CompactableShard<Opts>, Opts: ShardConfig> MapShard<S, Opts> {
+    /// Number of rows in the collection, leaving out the deleted ones.
+    pub fn row_count(&self) -> usize {
+        let deleted: usize = self
+            .past_master_shards
+            .read()
+            .values()
+            .chain(std::iter::once(&self.current_master_shard))
+            .map(|shard| shard.deleted_items().0)
+            .sum();
+
+        self.len().saturating_sub(deleted)
+    }
+
+    /// Rewrites the past master shard whose deleted rows take the largest share of its data, if that
+    /// share is at least `min_deleted_ratio`, reclaiming the space taken by deleted and superseded rows.
+    ///
+    /// The copy is written while only holding read locks, so readers are not blocked during the rewrite.
+    /// It is then swapped in by atomically renaming it over the original shard. Rows keep their positions,
+    /// so the indexes pointing to them stay valid.
+    ///
+    /// Returns whether a shard was compacted.
+    pub fn compact(&self, min_deleted_ratio: f64) -> Result<bool, ShardErrors> {
+        let (shard_id, deleted_rows, compacted_path) = {
+            let reader = self.past_master_shards.read();
+            let candidate = reader
+                .iter()
+                .filter_map(|(shard_id, shard)| {
+                    let (deleted_rows, deleted_bytes) = shard.deleted_items();
+                    let ratio = deleted_bytes as f64 / shard.data_size().max(1) as f64;
+                    (deleted_bytes > 0 && ratio >= min_deleted_ratio).then_some((
+                        shard_id,
+                        shard,
+                        deleted_rows,
+                        ratio,
+                    ))
+                })
+                .max_by(|a, b| a.3.total_cmp(&b.3));
+
+            let Some((shard_id, shard, deleted_rows, _)) = candidate else {
+                return Ok(false);
+            };
+
+            let compacted_path = Self::get_compacted_path(&shard.get_path());
+            shard.write_compacted(&compacted_path)?;
+
+            (shard_id.clone(), deleted_rows, compacted_path)
+        };
+
+        let mut writer = self.past_master_shards.write();
+        let (path, id) = {
+            let shard = writer.get(&shard_id).ok_or(ShardErrors::UnknownShard)?;
+            if shard.deleted_items().0 != deleted_rows {
+                // Rows were deleted while the copy was written, it will be retried in the next run.
+                let _ = std::fs::remove_file(&compacted_path);
+                return Ok(false);
+            }
+
+            let id = Uuid::parse_str(&shard.get_id()).map_err(|_| ShardErrors::UnknownShard)?;
+            (shard.get_path(), id)
+        };
+
+        std::fs::rename(&compacted_path, &path).map_err(|_| ShardErrors::FlushingError)?;
+        let compacted_shard = S::new(path, self.config.clone(), Some(id), self.fdm.clone());
+        writer.insert(shard_id, compacted_shard);
+
+        Ok(true)
+    }
+
+    fn get_compacted_path(path: &Path) -> PathBuf {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}{}", COMPACTION_PREFIX, file_name))
+    }
+}
+
+/// Position of a scan over a `MapShard`.
+/// It does not borrow the collection, so it can be advanced under a lock taken for each row.
+#[derive(Debug, Clone)]
+pub struct ScanCursor {
+    position: usize,
+    end: Option<usize>,
+}
 
-                self.get_element_from_specific(shard_reversed[shard_index], local_index)
+impl ScanCursor {
+    pub fn new(start: usize, end: Option<usize>) -> Self {
+        Self {
+            position: start,
+            end,
+        }
+    }
+
+    /// Returns the next row of `map` that has not been deleted, along with its global position.
+    pub fn next_in<S: Shard<Opts>, Opts: ShardConfig>(
+        &mut self,
+        map: &MapShard<S, Opts>,
+    ) -> Option<Result<(usize, Vec<u8>), ShardErrors>> {
+        let len = map.len();
+        let end = self.end.map_or(len, |end| end.min(len));
+
+        while self.position < end {
+            let position = self.position;
+            self.position += 1;
+
+            match map.get_element(position) {
+                Ok(item) => return Some(Ok((position, item))),
+                Err(ShardErrors::DeletedItem) => continue,
+                Err(e) => return Some(Err(e)),
             }
         }
+
+        None
+    }
+}
+
+/// Iterator returned by `MapShard::scan`.
+pub struct MapShardScan<'a, S: Shard<Opts>, Opts: ShardConfig> {
+    map: &'a MapShard<S, Opts>,
+    cursor: ScanCursor,
+}
+
+impl<S: Shard<Opts>, Opts: ShardConfig> Iterator for MapShardScan<'_, S, Opts> {
+    type Item = Result<(usize, Vec<u8>), ShardErrors>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next_in(self.map)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::compression::Compression;
+    use crate::durability::Durability;
+    use crate::encryption::Keyring;
     use crate::fdm::FileDescriptorManager;
     use crate::shard::map_shard::MapShard;
     use crate::shard::shards::data_shard::config::DataShardConfig;
     use crate::shard::shards::data_shard::shard::DataShard;
     use crate::shard::Shard;
+    use crate::utils::checksum::CHECKSUM_SIZE;
     use parking_lot::RwLock;
     use std::sync::Arc;
     use uuid::Uuid;
@@ -257,7 +482,12 @@ mod test {
         let context = MapShard::<DataShard, DataShardConfig>::new(
             fake_empty_table_path,
             "data_",
-            DataShardConfig { max_offsets: None },
+            DataShardConfig {
+                max_offsets: None,
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
+            },
             Arc::new(FileDescriptorManager::new(2500)),
         );
 
@@ -282,7 +512,12 @@ mod test {
         let context = MapShard::<DataShard, DataShardConfig>::new(
             fake_partial_folder_path,
             "data_",
-            DataShardConfig { max_offsets: None },
+            DataShardConfig {
+                max_offsets: None,
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
+            },
             Arc::new(FileDescriptorManager::new(2500)),
         );
         assert!(!context.past_master_shards.read().is_empty());
@@ -312,6 +547,9 @@ mod test {
             "data_",
             DataShardConfig {
                 max_offsets: Some(1),
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
             },
             Arc::new(FileDescriptorManager::new(2500)),
         );
@@ -370,6 +608,9 @@ mod test {
             "data_",
             DataShardConfig {
                 max_offsets: Some(1),
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
             },
             Arc::new(FileDescriptorManager::new(2500)),
         );
@@ -383,4 +624,139 @@ mod test {
 
         context.get_element(3).unwrap();
     }
+
+    #[tokio::test]
+    pub async fn test_scan() {
+        let fake_partial_folder_path = std::env::current_dir()
+            .unwrap()
+            .join(format!("./test_cases/fake-db-folder/{}", Uuid::new_v4()));
+        std::fs::create_dir(&fake_partial_folder_path).unwrap();
+
+        let mut context = MapShard::<DataShard, DataShardConfig>::new(
+            fake_partial_folder_path.clone(),
+            "data_",
+            DataShardConfig {
+                max_offsets: Some(2),
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
+            },
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+
+        assert!(context.is_empty());
+        assert_eq!(context.scan(0, None).count(), 0);
+
+        let rows: Vec<Vec<u8>> = (0..7).map(|i| format!("row {}", i).into_bytes()).collect();
+        let rows: Vec<&[u8]> = rows.iter().map(|row| row.as_slice()).collect();
+        // The rows are spread across four shards
+        assert_eq!(
+            context.raw_insert_rows(&rows, false),
+            (0..7).collect::<Vec<_>>()
+        );
+        context.delete_element(3).unwrap();
+
+        assert_eq!(context.past_master_shards.read().len(), 3);
+        assert_eq!(context.len(), 7);
+        assert_eq!(context.row_count(), 6);
+
+        // Every row is found at the position it was given when inserted
+        let scanned: Vec<(usize, Vec<u8>)> =
+            context.scan(0, None).map(|row| row.unwrap()).collect();
+        let expected: Vec<(usize, Vec<u8>)> = rows
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| *position != 3)
+            .map(|(position, row)| (position, row.to_vec()))
+            .collect();
+        assert_eq!(scanned, expected);
+
+        for (position, row) in &scanned {
+            assert_eq!(&context.get_element(*position).unwrap(), row);
+        }
+
+        let positions: Vec<usize> = context.scan(2, Some(5)).map(|row| row.unwrap().0).collect();
+        assert_eq!(positions, vec![2, 4]);
+        assert_eq!(context.scan(6, Some(100)).count(), 1);
+        assert_eq!(context.scan(7, None).count(), 0);
+
+        std::fs::remove_dir_all(fake_partial_folder_path).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_compaction() {
+        let fake_partial_folder_path = std::env::current_dir()
+            .unwrap()
+            .join(format!("./test_cases/fake-db-folder/{}", Uuid::new_v4()));
+        std::fs::create_dir(&fake_partial_folder_path).unwrap();
+
+        let config = DataShardConfig {
+            max_offsets: Some(3),
+            durability: Durability::None,
+            compression: Compression::None,
+            keyring: Keyring::default(),
+        };
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+
+        {
+            let mut context = MapShard::<DataShard, DataShardConfig>::new(
+                fake_partial_folder_path.clone(),
+                "data_",
+                config.clone(),
+                fdm.clone(),
+            );
+
+            context.insert_rows(&[
+                b"Hello World".as_slice(),
+                b"Cats are cute",
+                b"Venezuela",
+                b"Roses",
+            ]);
+
+            // Nothing to reclaim yet
+            assert!(!context.compact(0.0).unwrap());
+
+            context.delete_element(0).unwrap();
+            context.delete_element(1).unwrap();
+
+            let past_size = |context: &MapShard<DataShard, DataShardConfig>| {
+                let reader = context.past_master_shards.read();
+                let shard = reader.values().next().unwrap();
+                std::fs::metadata(&shard.path).unwrap().len()
+            };
+            let size_before = past_size(&context);
+
+            // Not enough deleted data
+            assert!(!context.compact(0.9).unwrap());
+            assert!(context.compact(0.5).unwrap());
+            assert_eq!(
+                past_size(&context),
+                size_before
+                    - (b"Hello World".len() + b"Cats are cute".len() + 2 * CHECKSUM_SIZE) as u64
+            );
+
+            // Rows keep their position
+            assert!(context.get_element(0).unwrap_err().is_deleted_item());
+            assert!(context.get_element(1).unwrap_err().is_deleted_item());
+            assert_eq!(context.get_element(2).unwrap(), b"Venezuela".to_vec());
+            assert_eq!(context.get_element(3).unwrap(), b"Roses".to_vec());
+            assert!(context.verify().is_empty());
+
+            // Already compacted
+            assert!(!context.compact(0.0).unwrap());
+        }
+
+        let context = MapShard::<DataShard, DataShardConfig>::new(
+            fake_partial_folder_path.clone(),
+            "data_",
+            config,
+            fdm,
+        );
+        assert_eq!(context.past_master_shards.read().len(), 1);
+        assert!(context.get_element(1).unwrap_err().is_deleted_item());
+        assert_eq!(context.get_element(2).unwrap(), b"Venezuela".to_vec());
+        assert_eq!(context.get_element(3).unwrap(), b"Roses".to_vec());
+
+        std::fs::remove_dir_all(fake_partial_folder_path).unwrap();
+    }
 }
@@ -1,9 +1,12 @@
 use crate::errors::ShardErrors;
 use crate::fdm::FileDescriptorManager;
-use std::path::PathBuf;
+use crate::shard::item_ref::ItemRef;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use uuid::Uuid;
 
+pub mod format;
+pub mod item_ref;
 pub mod map_shard;
 pub mod shards;
 pub mod temp_collection;
@@ -88,13 +91,49 @@ pub trait Shard<Opts: ShardConfig> {
 
     fn read_item_from_index(&self, index: usize) -> Result<Vec<u8>, ShardErrors>;
 
+    /// Reads the item stored at `index` like `read_item_from_index`, without copying it if the shard can lend it.
+    /// See `ItemRef`.
+    fn read_item_ref(&self, index: usize) -> Result<ItemRef, ShardErrors> {
+        self.read_item_from_index(index).map(ItemRef::Owned)
+    }
+
     fn available_space(&self) -> AvailableSpace;
 
     fn insert_item(&self, data: &[&[u8]]) -> Result<u64, ShardErrors>;
 
+    /// Deletes the item stored at `index`.
+    ///
+    /// Shards whose positions are referenced from outside (such as `DataShard`, pointed by indexes)
+    /// keep the slot and only flag it as deleted, so reading it returns `ShardErrors::DeletedItem`.
+    /// Shards that are kept sorted (such as `KvShard`) remove the item and shift the following ones.
+    fn delete_item(&self, index: usize) -> Result<(), ShardErrors>;
+
+    /// Checks the header and every item of the shard against their checksums,
+    /// failing with `ShardErrors::Corrupted` on the first mismatch.
+    fn verify(&self) -> Result<(), ShardErrors>;
+
+    /// Rewrites the shard with the current encryption key if it was written with another one,
+    /// returning whether it did.
+    fn reencrypt(&self) -> Result<bool, ShardErrors>;
+
+    /// Flushes the items written to the shard to disk, whatever the durability it was created with.
+    fn sync(&self) -> Result<(), ShardErrors>;
+
     fn get_id(&self) -> String;
 }
 
+/// Shards whose deleted items keep taking space until the shard is rewritten by `MapShard::compact`.
+pub trait CompactableShard<Opts: ShardConfig>: Shard<Opts> {
+    /// Returns how many items have been deleted and how many bytes their data still takes.
+    fn deleted_items(&self) -> (usize, u64);
+
+    /// Returns how many bytes are taken by items, deleted or not.
+    fn data_size(&self) -> u64;
+
+    /// Writes a copy of the shard to `path` leaving out the data of the deleted items.
+    fn write_compacted(&self, path: &Path) -> Result<(), ShardErrors>;
+}
+
 pub trait TempShardConfig<Opts: ShardConfig>: Clone {
     fn to_config(&self) -> Opts;
 }
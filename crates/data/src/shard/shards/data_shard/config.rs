@@ -1,9 +1,15 @@
+use crate::compression::Compression;
+use crate::durability::Durability;
+use crate::encryption::Keyring;
 use crate::shard::{ShardConfig, TempShardConfig};
 use crate::temp_offset_types::TempOffsetTypes;
 
 #[derive(Clone, Debug)]
 pub struct DataShardConfig {
     pub max_offsets: Option<u64>,
+    pub durability: Durability,
+    pub compression: Compression,
+    pub keyring: Keyring,
 }
 
 impl ShardConfig for DataShardConfig {}
@@ -11,12 +17,18 @@ impl ShardConfig for DataShardConfig {}
 #[derive(Debug, Clone)]
 pub struct TempDataShardConfig {
     pub max_offsets: TempOffsetTypes,
+    pub durability: Durability,
+    pub keyring: Keyring,
 }
 
 impl TempShardConfig<DataShardConfig> for TempDataShardConfig {
     fn to_config(&self) -> DataShardConfig {
         DataShardConfig {
             max_offsets: self.max_offsets.get_real_offset(),
+            durability: self.durability,
+            // Temporary shards are short-lived, their rows get compressed once they are reconciled.
+            compression: Compression::None,
+            keyring: self.keyring.clone(),
         }
     }
 }
@@ -1,14 +1,21 @@
+use crate::compression::Compression;
 use crate::data_handler::DataHandler;
+use crate::encryption::{Keyring, NO_KEY};
 use crate::errors::ShardErrors;
 use crate::fdm::FileDescriptorManager;
+use crate::shard::format::{replace_file, LEGACY_FORMAT_VERSION, V1_FORMAT_VERSION};
+use crate::shard::item_ref::ItemRef;
 use crate::shard::shards::data_shard::config::DataShardConfig;
 use crate::shard::shards::data_shard::shard_header::DataShardHeader;
-use crate::shard::{AvailableSpace, Shard};
+use crate::shard::{AvailableSpace, CompactableShard, Shard};
+use crate::utils::checksum::{verify_checksum, with_checksum};
 use crate::utils::flatten;
+use crate::utils::fs::write_at;
 use crate::U64_SIZE;
 use parking_lot::RwLock;
+use std::fs::OpenOptions;
 use std::io::{Error, ErrorKind, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -18,50 +25,351 @@ pub struct DataShard {
     pub header: RwLock<DataShardHeader>,
     pub data: Arc<RwLock<DataHandler>>,
     pub id: Uuid,
+    keyring: Keyring,
 }
 
 impl DataShard {
     /// Reads data of type T from the given position to the next position in offsets
     pub fn read_item(&self, offset_position_in_header: usize) -> Result<Vec<u8>, ShardErrors> {
         let header_read = self.header.read();
+        header_read.check_usable()?;
 
-        let item_pos =
-            { header_read.get_offset_value_from_offset_header(offset_position_in_header) };
+        if header_read.is_tombstone(offset_position_in_header) {
+            return Err(ShardErrors::DeletedItem);
+        }
+
+        let item = self.read_item_with_header(&header_read, offset_position_in_header)?;
+        let item = verify_checksum(&item)?;
+        let item = self
+            .keyring
+            .decrypt(header_read.get_encryption_key(), item.to_vec())?;
+        header_read.get_compression().decompress(item)
+    }
+
+    /// Reads the item like `read_item`, borrowing it from the memory map when it is stored as it is read,
+    /// that is neither compressed nor encrypted.
+    pub fn read_item_borrowed(
+        &self,
+        offset_position_in_header: usize,
+    ) -> Result<ItemRef, ShardErrors> {
+        let header_read = self.header.read();
+        header_read.check_usable()?;
+
+        if header_read.is_tombstone(offset_position_in_header) {
+            return Err(ShardErrors::DeletedItem);
+        }
+
+        if header_read.get_compression() != Compression::None
+            || header_read.get_encryption_key() != NO_KEY
+        {
+            drop(header_read);
+            return self
+                .read_item(offset_position_in_header)
+                .map(ItemRef::Owned);
+        }
+
+        let (start_pos, end_pos) = self.item_range(&header_read, offset_position_in_header)?;
+        let data = self.data.read_arc();
+        let item_len = {
+            let stored = data
+                .get_bytes(start_pos as usize, end_pos as usize)
+                .ok_or(ShardErrors::ErrorReadingByteRange)?;
+            verify_checksum(stored)?.len()
+        };
+
+        Ok(ItemRef::Mapped {
+            data,
+            range: start_pos as usize..start_pos as usize + item_len,
+        })
+    }
+
+    /// Reads the item as it is stored, without verifying, decrypting nor decompressing it.
+    fn read_item_with_header(
+        &self,
+        header: &DataShardHeader,
+        offset_position_in_header: usize,
+    ) -> Result<Vec<u8>, ShardErrors> {
+        let (start_pos, end_pos) = self.item_range(header, offset_position_in_header)?;
+        let length = (end_pos - start_pos) as usize;
+
+        let read_bytes = self.data.read().read_pointer(start_pos, length);
+        match read_bytes {
+            None => Err(ShardErrors::ErrorReadingByteRange),
+            Some(b) => Ok(b),
+        }
+    }
+
+    /// Returns the start and end position in the file of the item whose offset lives at `offset_position_in_header`.
+    fn item_range(
+        &self,
+        header: &DataShardHeader,
+        offset_position_in_header: usize,
+    ) -> Result<(u64, u64), ShardErrors> {
+        let item_pos = { header.get_offset_value_from_offset_header(offset_position_in_header) };
 
         match item_pos {
             None => Err(ShardErrors::UnknownOffset),
             Some(start_pos) => {
-                let data_reader = self.data.read();
+                let data_len = self.data.read().len();
                 let end_pos = {
                     let next_offset_pos = offset_position_in_header + U64_SIZE;
-                    assert!(next_offset_pos <= header_read.max_offset_positions);
+                    assert!(next_offset_pos <= header.max_offset_positions);
                     let mut no_more_positions = false;
 
-                    if header_read.max_offset_positions == next_offset_pos {
+                    if header.max_offset_positions == next_offset_pos {
                         no_more_positions = true;
                     }
 
                     let end_reading = if no_more_positions {
-                        data_reader.len()
+                        data_len
                     } else {
-                        header_read
+                        header
                             .get_offset_value_from_offset_header(next_offset_pos)
                             .map(|e| e as usize)
-                            .unwrap_or_else(|| data_reader.len())
+                            .unwrap_or(data_len)
                     };
 
                     end_reading as u64
                 };
 
-                let length = (end_pos - start_pos) as usize;
+                // Offsets pointing outside of the file can only come from a damaged header
+                if start_pos > end_pos || end_pos as usize > data_len {
+                    return Err(ShardErrors::Corrupted);
+                }
+
+                Ok((start_pos, end_pos))
+            }
+        }
+    }
+
+    /// Returns how many rows in this shard have been deleted and how many bytes their data still takes.
+    pub fn deleted_items(&self) -> (usize, u64) {
+        let header = self.header.read();
+        let mut rows = 0;
+        let mut bytes = 0;
+
+        for index in 0..(header.get_last_offset_index() + 1) as usize {
+            if let Some(pos) = header.get_offset_pos_by_index(index) {
+                if header.is_tombstone(pos) {
+                    rows += 1;
+                    if let Ok((start, end)) = self.item_range(&header, pos) {
+                        bytes += end - start;
+                    }
+                }
+            }
+        }
+
+        (rows, bytes)
+    }
+
+    /// Returns how many bytes are taken by rows (deleted or not), leaving the header out.
+    pub fn data_size(&self) -> u64 {
+        let header_size = self.header.read().get_header_size();
+        self.data.read().len().saturating_sub(header_size) as u64
+    }
+
+    /// Writes a copy of this shard to `path` leaving out the data of the deleted rows.
+    /// Deleted rows keep their slot, empty and still flagged as deleted, so every row keeps its index.
+    pub fn write_compacted(&self, path: &Path) -> Result<(), ShardErrors> {
+        let header = self.header.read();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|_| ShardErrors::FlushingError)?;
+
+        let mut end_of_file = header.get_header_size() as u64;
+        file.seek(SeekFrom::Start(end_of_file))
+            .map_err(|_| ShardErrors::FlushingError)?;
+
+        let mut offsets = vec![];
+        for index in 0..(header.get_last_offset_index() + 1) as usize {
+            let pos = header
+                .get_offset_pos_by_index(index)
+                .ok_or(ShardErrors::UnknownOffset)?;
+
+            if header.is_tombstone(pos) {
+                offsets.push((end_of_file, true));
+                continue;
+            }
+
+            let item = self.read_item_with_header(&header, pos)?;
+            file.write_all(&item)
+                .map_err(|_| ShardErrors::FlushingError)?;
+            offsets.push((end_of_file, false));
+            end_of_file += item.len() as u64;
+        }
+
+        write_at(&mut file, &header.encode(&offsets), 0).map_err(|_| ShardErrors::FlushingError)?;
+        file.sync_all().map_err(|_| ShardErrors::FlushingError)
+    }
 
-                let read_bytes = data_reader.read_pointer(start_pos, length);
-                match read_bytes {
-                    None => Err(ShardErrors::ErrorReadingByteRange),
-                    Some(b) => Ok(b),
+    /// Rewrites this shard in the current format if it was written in an older one, returning whether it did.
+    /// Items of legacy shards are kept uncompressed, as they were, and gain the checksum they lacked.
+    /// Items of v1 shards are copied as they are. Either way they are left unencrypted until the next `reencrypt`.
+    pub fn upgrade(&self) -> Result<bool, ShardErrors> {
+        let mut header = self.header.write();
+
+        let (max_offsets, id, compression, contents) = {
+            let reader = self.data.read();
+            let bytes = reader
+                .get_bytes(0, reader.len())
+                .ok_or(ShardErrors::ErrorReadingByteRange)?;
+
+            let (max_offsets, id, compression, items) = match header.get_version() {
+                LEGACY_FORMAT_VERSION => {
+                    let (max_offsets, id, legacy_offsets) = DataShardHeader::decode_legacy(bytes)?;
+                    let offsets: Vec<(u64, bool)> =
+                        legacy_offsets.into_iter().map(|e| (e, false)).collect();
+                    let items: Vec<(Vec<u8>, bool)> = Self::split_items(bytes, &offsets)?
+                        .into_iter()
+                        .map(|(item, deleted)| (with_checksum(item), deleted))
+                        .collect();
+
+                    (max_offsets, id, Compression::None, items)
                 }
+                V1_FORMAT_VERSION => {
+                    let decoded = DataShardHeader::decode_v1(bytes)?;
+                    let items: Vec<(Vec<u8>, bool)> = Self::split_items(bytes, &decoded.offsets)?
+                        .into_iter()
+                        .map(|(item, deleted)| (item.to_vec(), deleted))
+                        .collect();
+
+                    (decoded.max_offsets, decoded.id, decoded.compression, items)
+                }
+                _ => return Ok(false),
+            };
+
+            let upgraded = DataShardHeader::new(
+                max_offsets,
+                Some(id),
+                compression,
+                NO_KEY,
+                self.data.clone(),
+            );
+
+            (
+                max_offsets,
+                id,
+                compression,
+                Self::encode_with_items(&upgraded, &items),
+            )
+        };
+
+        self.replace_contents(&contents)?;
+        *header = DataShardHeader::new_from_file(
+            self.data.clone(),
+            Some(max_offsets),
+            Some(id),
+            compression,
+            NO_KEY,
+        );
+
+        Ok(true)
+    }
+
+    /// Rewrites this shard with the current key of the keyring if its items were written with another one,
+    /// returning whether it did. Deleted rows keep their slot, left empty.
+    pub fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        let mut header = self.header.write();
+        header.check_usable()?;
+
+        let previous_key = header.get_encryption_key();
+        let current_key = self.keyring.current_id();
+        if previous_key == current_key {
+            return Ok(false);
+        }
+
+        let max_offsets = header.get_max_offsets();
+        let compression = header.get_compression();
+        let id = header.id;
+
+        let mut items = vec![];
+        for index in 0..(header.get_last_offset_index() + 1) as usize {
+            let pos = header
+                .get_offset_pos_by_index(index)
+                .ok_or(ShardErrors::UnknownOffset)?;
+
+            if header.is_tombstone(pos) {
+                items.push((vec![], true));
+                continue;
             }
+
+            let item = self.read_item_with_header(&header, pos)?;
+            let item = self
+                .keyring
+                .decrypt(previous_key, verify_checksum(&item)?.to_vec())?;
+            let item = self.keyring.encrypt(current_key, &item)?;
+            items.push((with_checksum(&item), false));
         }
+
+        let reencrypted = DataShardHeader::new(
+            max_offsets,
+            Some(id),
+            compression,
+            current_key,
+            self.data.clone(),
+        );
+        self.replace_contents(&Self::encode_with_items(&reencrypted, &items))?;
+
+        *header = DataShardHeader::new_from_file(
+            self.data.clone(),
+            Some(max_offsets),
+            Some(id),
+            compression,
+            current_key,
+        );
+
+        Ok(true)
+    }
+
+    /// Splits the items of a whole file given the offsets they start at. Each item ends where the next one starts.
+    fn split_items<'a>(
+        bytes: &'a [u8],
+        offsets: &[(u64, bool)],
+    ) -> Result<Vec<(&'a [u8], bool)>, ShardErrors> {
+        offsets
+            .iter()
+            .enumerate()
+            .map(|(index, (start, deleted))| {
+                let end = offsets
+                    .get(index + 1)
+                    .map(|(end, _)| *end as usize)
+                    .unwrap_or(bytes.len());
+
+                bytes
+                    .get(*start as usize..end)
+                    .map(|item| (item, *deleted))
+                    .ok_or(ShardErrors::Corrupted)
+            })
+            .collect()
+    }
+
+    /// Serializes `header` followed by `items`, as they are to be stored, flagging the deleted ones.
+    fn encode_with_items(header: &DataShardHeader, items: &[(Vec<u8>, bool)]) -> Vec<u8> {
+        let mut end_of_file = header.get_header_size() as u64;
+        let mut offsets = vec![];
+        for (item, deleted) in items {
+            offsets.push((end_of_file, *deleted));
+            end_of_file += item.len() as u64;
+        }
+
+        let mut contents = header.encode(&offsets);
+        for (item, _) in items {
+            contents.extend_from_slice(item);
+        }
+
+        contents
+    }
+
+    /// Atomically replaces the file of this shard and remaps it.
+    fn replace_contents(&self, contents: &[u8]) -> Result<(), ShardErrors> {
+        replace_file(&self.path, contents)?;
+        self.data
+            .write()
+            .reload()
+            .map_err(|_| ShardErrors::FlushingError)
     }
 }
 
@@ -72,16 +380,30 @@ impl Shard<DataShardConfig> for DataShard {
         uuid: Option<Uuid>,
         fdm: Arc<FileDescriptorManager>,
     ) -> Self {
-        let data_handler = unsafe { DataHandler::new(path.clone(), fdm) }.unwrap();
-        let arc_dh = Arc::new(data_handler);
-        let header = DataShardHeader::new_from_file(arc_dh.clone(), opts.max_offsets, uuid);
+        let arc_dh = unsafe { DataHandler::new(path.clone(), fdm, opts.durability) }.unwrap();
+        let header = DataShardHeader::new_from_file(
+            arc_dh.clone(),
+            opts.max_offsets,
+            uuid,
+            opts.compression,
+            opts.keyring.current_id(),
+        );
 
-        DataShard {
+        let mut shard = DataShard {
             path: path.clone(),
             data: arc_dh.clone(),
             id: header.id,
             header: RwLock::new(header),
+            keyring: opts.keyring,
+        };
+
+        // Shards written in an older format are brought up to date as they are opened.
+        // If that fails, every operation on the shard reports `ShardErrors::UnsupportedVersion`.
+        if let Ok(true) = shard.upgrade() {
+            shard.id = shard.header.read().id;
         }
+
+        shard
     }
 
     fn has_space(&self) -> bool {
@@ -112,6 +434,14 @@ impl Shard<DataShardConfig> for DataShard {
         }
     }
 
+    fn read_item_ref(&self, index: usize) -> Result<ItemRef, ShardErrors> {
+        let offset_pos_in_header = self.header.read().get_offset_pos_by_index(index);
+        match offset_pos_in_header {
+            None => Err(ShardErrors::UnknownOffset),
+            Some(pos_in_header) => self.read_item_borrowed(pos_in_header),
+        }
+    }
+
     fn available_space(&self) -> AvailableSpace {
         let header = self.header.read();
 
@@ -120,8 +450,22 @@ impl Shard<DataShardConfig> for DataShard {
 
     fn insert_item(&self, data: &[&[u8]]) -> Result<u64, ShardErrors> {
         let mut header_write = self.header.write();
+        header_write.check_usable()?;
+
+        let compression = header_write.get_compression();
+        let encryption_key = header_write.get_encryption_key();
+        let stored = data
+            .iter()
+            .map(|item| {
+                let item = compression.compress(item)?;
+                let item = self.keyring.encrypt(encryption_key, &item)?;
+                Ok(with_checksum(&item))
+            })
+            .collect::<Result<Vec<_>, ShardErrors>>()?;
+        let data: Vec<&[u8]> = stored.iter().map(|item| item.as_slice()).collect();
+
         let op = self.data.write().operate(|file| {
-            let write_data = flatten(data);
+            let write_data = flatten(&data);
 
             // Calculate the current end of the file
             let end_of_file = file
@@ -134,7 +478,7 @@ impl Shard<DataShardConfig> for DataShard {
 
             let mut curr_offset = end_of_file;
 
-            for item in data {
+            for item in data.iter() {
                 header_write
                     .add_next_offset(curr_offset, file)
                     .map_err(|_| Error::new(ErrorKind::OutOfMemory, "Out of position"))?;
@@ -155,15 +499,71 @@ impl Shard<DataShardConfig> for DataShard {
         }
     }
 
+    fn delete_item(&self, index: usize) -> Result<(), ShardErrors> {
+        let header_write = self.header.write();
+        header_write.check_usable()?;
+
+        let (pos, tombstone) = header_write.get_tombstone_for_index(index)?;
+
+        self.data
+            .write()
+            .operate(|file| write_at(file, &tombstone.to_le_bytes(), pos as u64))
+            .map(|_| ())
+            .map_err(|_| ShardErrors::FlushingError)
+    }
+
+    fn verify(&self) -> Result<(), ShardErrors> {
+        self.header.read().check_usable()?;
+
+        for index in 0..(self.get_last_index() + 1) as usize {
+            match self.read_item_from_index(index) {
+                Ok(_) | Err(ShardErrors::DeletedItem) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        DataShard::reencrypt(self)
+    }
+
+    fn sync(&self) -> Result<(), ShardErrors> {
+        self.data
+            .read()
+            .sync()
+            .map_err(|_| ShardErrors::FlushingError)
+    }
+
     fn get_id(&self) -> String {
         self.id.to_string()
     }
 }
 
+impl CompactableShard<DataShardConfig> for DataShard {
+    fn deleted_items(&self) -> (usize, u64) {
+        DataShard::deleted_items(self)
+    }
+
+    fn data_size(&self) -> u64 {
+        DataShard::data_size(self)
+    }
+
+    fn write_compacted(&self, path: &Path) -> Result<(), ShardErrors> {
+        DataShard::write_compacted(self, path)
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use crate::compression::Compression;
+    use crate::durability::Durability;
+    use crate::encryption::{EncryptionKey, Keyring, KEY_SIZE, NO_KEY};
     use crate::errors::ShardErrors;
     use crate::fdm::FileDescriptorManager;
+    use crate::shard::format::{DATA_SHARD_MAGIC, FORMAT_VERSION, PREAMBLE_SIZE};
+    use crate::shard::item_ref::ItemRef;
     use crate::shard::shards::data_shard::config::DataShardConfig;
     use crate::shard::shards::data_shard::shard::DataShard;
     use crate::shard::Shard;
@@ -183,6 +583,9 @@ mod test {
 
         let config = DataShardConfig {
             max_offsets: Some(10),
+            durability: Durability::None,
+            compression: Compression::None,
+            keyring: Keyring::default(),
         };
 
         let data_shard = DataShard::new(
@@ -233,6 +636,413 @@ mod test {
         // assert_eq!(res, shards.header.read().unwrap().offsets);
     }
 
+    #[tokio::test]
+    pub async fn test_read_item_ref() {
+        let temp_dir = tempdir().unwrap();
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let new_shard = |compression: Compression| {
+            DataShard::new(
+                temp_dir.path().join(format!("{}.bin", Uuid::new_v4())),
+                DataShardConfig {
+                    max_offsets: Some(10),
+                    durability: Durability::None,
+                    compression,
+                    keyring: Keyring::default(),
+                },
+                None,
+                fdm.clone(),
+            )
+        };
+
+        let data_shard = new_shard(Compression::None);
+        data_shard
+            .insert_item(&[b"Hello World", b"Cats are cute"])
+            .unwrap();
+        data_shard.delete_item(0).unwrap();
+
+        let item = data_shard.read_item_ref(1).unwrap();
+        assert!(matches!(item, ItemRef::Mapped { .. }));
+        assert_eq!(&*item, b"Cats are cute");
+        drop(item);
+        assert!(matches!(
+            data_shard.read_item_ref(0),
+            Err(ShardErrors::DeletedItem)
+        ));
+
+        // Compressed items are decoded into a copy
+        let data_shard = new_shard(Compression::Lz4);
+        data_shard.insert_item(&[b"Hello World"]).unwrap();
+        let item = data_shard.read_item_ref(0).unwrap();
+        assert!(matches!(item, ItemRef::Owned(_)));
+        assert_eq!(&*item, b"Hello World");
+    }
+
+    #[tokio::test]
+    pub async fn test_data_shard_tombstones() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir
+            .path()
+            .join(format!("{}.bin", Uuid::new_v4().to_string()));
+
+        let config = DataShardConfig {
+            max_offsets: Some(10),
+            durability: Durability::None,
+            compression: Compression::None,
+            keyring: Keyring::default(),
+        };
+
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        {
+            let data_shard = DataShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            data_shard
+                .insert_item(&[b"Hello World", b"Cats are cute", b"Venezuela"])
+                .unwrap();
+
+            data_shard.delete_item(1).unwrap();
+            assert!(matches!(
+                data_shard.read_item_from_index(1),
+                Err(ShardErrors::DeletedItem)
+            ));
+
+            // Neighbours are not affected by the tombstone
+            assert_eq!(
+                data_shard.read_item_from_index(0).unwrap(),
+                b"Hello World".to_vec()
+            );
+            assert_eq!(
+                data_shard.read_item_from_index(2).unwrap(),
+                b"Venezuela".to_vec()
+            );
+
+            // Out of the written range
+            assert!(data_shard.delete_item(3).is_err());
+        }
+
+        // Tombstones are persisted
+        let data_shard = DataShard::new(file_path, config, None, fdm);
+        assert!(data_shard
+            .read_item_from_index(1)
+            .unwrap_err()
+            .is_deleted_item());
+        data_shard.insert_item(&[b"Roses"]).unwrap();
+        assert_eq!(
+            data_shard.read_item_from_index(3).unwrap(),
+            b"Roses".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_data_shard_compression() {
+        let temp_dir = tempdir().unwrap();
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let item = "Cats are cute. ".repeat(100).into_bytes();
+
+        let file_size = |compression: Compression| {
+            let file_path = temp_dir.path().join(format!("{}.bin", Uuid::new_v4()));
+            let config = DataShardConfig {
+                max_offsets: Some(10),
+                durability: Durability::None,
+                compression,
+                keyring: Keyring::default(),
+            };
+
+            {
+                let data_shard =
+                    DataShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+                data_shard
+                    .insert_item(&[item.as_slice(), b"Venezuela"])
+                    .unwrap();
+                assert_eq!(data_shard.read_item_from_index(0).unwrap(), item);
+                assert_eq!(
+                    data_shard.read_item_from_index(1).unwrap(),
+                    b"Venezuela".to_vec()
+                );
+            }
+
+            // The codec in the header wins over the one in the config
+            let data_shard = DataShard::new(
+                file_path.clone(),
+                DataShardConfig {
+                    compression: Compression::None,
+                    ..config
+                },
+                None,
+                fdm.clone(),
+            );
+            assert_eq!(data_shard.header.read().get_compression(), compression);
+            assert_eq!(data_shard.read_item_from_index(0).unwrap(), item);
+
+            std::fs::metadata(&file_path).unwrap().len()
+        };
+
+        let uncompressed = file_size(Compression::None);
+        assert!(file_size(Compression::Lz4) < uncompressed);
+        assert!(file_size(Compression::Zstd) < uncompressed);
+    }
+
+    #[tokio::test]
+    pub async fn test_data_shard_encryption() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join(format!("{}.bin", Uuid::new_v4()));
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let old_key = EncryptionKey::new(&[1u8; KEY_SIZE]).unwrap();
+        let new_key = EncryptionKey::new(&[2u8; KEY_SIZE]).unwrap();
+        let config = |keyring: Keyring| DataShardConfig {
+            max_offsets: Some(10),
+            durability: Durability::None,
+            compression: Compression::Lz4,
+            keyring,
+        };
+
+        {
+            let data_shard = DataShard::new(
+                file_path.clone(),
+                config(Keyring::new(Some(old_key.clone()), vec![])),
+                None,
+                fdm.clone(),
+            );
+            assert_eq!(data_shard.header.read().get_encryption_key(), old_key.id());
+            data_shard
+                .insert_item(&[b"Hello World", b"Cats are cute"])
+                .unwrap();
+            data_shard.delete_item(1).unwrap();
+            assert_eq!(
+                data_shard.read_item_from_index(0).unwrap(),
+                b"Hello World".to_vec()
+            );
+        }
+
+        let contains = |needle: &[u8]| {
+            std::fs::read(&file_path)
+                .unwrap()
+                .windows(needle.len())
+                .any(|window| window == needle)
+        };
+        assert!(!contains(b"Hello World"));
+
+        // The shard cannot be read without its key
+        let data_shard = DataShard::new(
+            file_path.clone(),
+            config(Keyring::default()),
+            None,
+            fdm.clone(),
+        );
+        assert!(matches!(
+            data_shard.read_item_from_index(0),
+            Err(ShardErrors::UnknownEncryptionKey)
+        ));
+        assert!(matches!(
+            data_shard.reencrypt(),
+            Err(ShardErrors::UnknownEncryptionKey)
+        ));
+        drop(data_shard);
+
+        // Rotating the key keeps the old one around until the shard is re-encrypted
+        let data_shard = DataShard::new(
+            file_path.clone(),
+            config(Keyring::new(Some(new_key.clone()), vec![old_key.clone()])),
+            None,
+            fdm.clone(),
+        );
+        assert_eq!(
+            data_shard.read_item_from_index(0).unwrap(),
+            b"Hello World".to_vec()
+        );
+        assert!(data_shard.reencrypt().unwrap());
+        assert!(!data_shard.reencrypt().unwrap());
+        assert_eq!(data_shard.header.read().get_encryption_key(), new_key.id());
+        assert!(data_shard
+            .read_item_from_index(1)
+            .unwrap_err()
+            .is_deleted_item());
+        data_shard.insert_item(&[b"Venezuela"]).unwrap();
+        drop(data_shard);
+
+        let data_shard = DataShard::new(
+            file_path.clone(),
+            config(Keyring::new(Some(new_key.clone()), vec![])),
+            None,
+            fdm.clone(),
+        );
+        assert_eq!(
+            data_shard.read_item_from_index(0).unwrap(),
+            b"Hello World".to_vec()
+        );
+        assert_eq!(
+            data_shard.read_item_from_index(2).unwrap(),
+            b"Venezuela".to_vec()
+        );
+        assert!(data_shard.verify().is_ok());
+
+        assert!(!contains(b"Venezuela"));
+        drop(data_shard);
+
+        // Disabling encryption decrypts the shard on the next pass
+        let data_shard = DataShard::new(
+            file_path.clone(),
+            config(Keyring::new(None, vec![new_key])),
+            None,
+            fdm,
+        );
+        assert!(data_shard.reencrypt().unwrap());
+        assert_eq!(data_shard.header.read().get_encryption_key(), NO_KEY);
+        assert_eq!(
+            data_shard.read_item_from_index(2).unwrap(),
+            b"Venezuela".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_data_shard_corruption() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join(format!("{}.bin", Uuid::new_v4()));
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let config = DataShardConfig {
+            max_offsets: Some(10),
+            durability: Durability::None,
+            compression: Compression::None,
+            keyring: Keyring::default(),
+        };
+
+        let flip_byte = |pos: u64| {
+            let mut bytes = std::fs::read(&file_path).unwrap();
+            bytes[pos as usize] ^= 0xFF;
+            std::fs::write(&file_path, bytes).unwrap();
+        };
+
+        {
+            let data_shard = DataShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            data_shard
+                .insert_item(&[b"Hello World", b"Cats are cute"])
+                .unwrap();
+            assert!(data_shard.verify().is_ok());
+        }
+
+        // The last byte of the file belongs to the last item
+        flip_byte(std::fs::metadata(&file_path).unwrap().len() - 1);
+
+        {
+            let data_shard = DataShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            assert_eq!(
+                data_shard.read_item_from_index(0).unwrap(),
+                b"Hello World".to_vec()
+            );
+            assert!(matches!(
+                data_shard.read_item_from_index(1),
+                Err(ShardErrors::Corrupted)
+            ));
+            assert!(matches!(data_shard.verify(), Err(ShardErrors::Corrupted)));
+        }
+
+        // Damaging the header makes the whole shard unusable
+        flip_byte(PREAMBLE_SIZE as u64);
+
+        let data_shard = DataShard::new(file_path.clone(), config, None, fdm);
+        assert!(data_shard.header.read().corrupted);
+        assert!(matches!(
+            data_shard.read_item_from_index(0),
+            Err(ShardErrors::Corrupted)
+        ));
+        assert!(matches!(
+            data_shard.insert_item(&[b"Venezuela"]),
+            Err(ShardErrors::Corrupted)
+        ));
+        assert!(matches!(data_shard.verify(), Err(ShardErrors::Corrupted)));
+    }
+
+    #[tokio::test]
+    pub async fn test_data_shard_upgrade() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join(format!("{}.bin", Uuid::new_v4()));
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let config = DataShardConfig {
+            max_offsets: Some(10),
+            durability: Durability::None,
+            compression: Compression::None,
+            keyring: Keyring::default(),
+        };
+
+        // Legacy layout: max_offsets | last_offset_index | id | offsets | items
+        let id = Uuid::new_v4();
+        let header_size = 8 + 8 + 16 + 10 * 8;
+        let mut legacy = vec![];
+        legacy.extend_from_slice(&10u64.to_le_bytes());
+        legacy.extend_from_slice(&1i64.to_le_bytes());
+        legacy.extend_from_slice(&id.to_bytes_le());
+        legacy.extend_from_slice(&(header_size as u64).to_le_bytes());
+        legacy.extend_from_slice(&(header_size as u64 + 11).to_le_bytes());
+        legacy.resize(header_size, 0);
+        legacy.extend_from_slice(b"Hello World");
+        legacy.extend_from_slice(b"Cats are cute");
+        std::fs::write(&file_path, legacy).unwrap();
+
+        {
+            let data_shard = DataShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            assert_eq!(data_shard.id, id);
+            assert_eq!(data_shard.header.read().get_version(), FORMAT_VERSION);
+            assert_eq!(
+                data_shard.read_item_from_index(0).unwrap(),
+                b"Hello World".to_vec()
+            );
+            assert_eq!(
+                data_shard.read_item_from_index(1).unwrap(),
+                b"Cats are cute".to_vec()
+            );
+            assert!(data_shard.verify().is_ok());
+            assert!(!data_shard.upgrade().unwrap());
+
+            data_shard.insert_item(&[b"Venezuela"]).unwrap();
+        }
+
+        assert!(std::fs::read(&file_path)
+            .unwrap()
+            .starts_with(DATA_SHARD_MAGIC));
+
+        let data_shard = DataShard::new(file_path.clone(), config, None, fdm);
+        assert_eq!(data_shard.id, id);
+        assert_eq!(data_shard.get_last_index(), 2);
+        assert_eq!(
+            data_shard.read_item_from_index(2).unwrap(),
+            b"Venezuela".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_data_shard_unsupported_version() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join(format!("{}.bin", Uuid::new_v4()));
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let config = DataShardConfig {
+            max_offsets: Some(10),
+            durability: Durability::None,
+            compression: Compression::None,
+            keyring: Keyring::default(),
+        };
+
+        {
+            let data_shard = DataShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            data_shard.insert_item(&[b"Hello World"]).unwrap();
+        }
+
+        // A file written by a newer release
+        let mut bytes = std::fs::read(&file_path).unwrap();
+        bytes[DATA_SHARD_MAGIC.len()..PREAMBLE_SIZE]
+            .copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&file_path, &bytes).unwrap();
+
+        let data_shard = DataShard::new(file_path.clone(), config, None, fdm);
+        assert!(matches!(
+            data_shard.read_item_from_index(0),
+            Err(ShardErrors::UnsupportedVersion)
+        ));
+        assert!(matches!(
+            data_shard.verify(),
+            Err(ShardErrors::UnsupportedVersion)
+        ));
+        // The file is left as it was
+        assert_eq!(std::fs::read(&file_path).unwrap(), bytes);
+    }
+
     #[tokio::test]
     pub async fn test_data_shard_from_file() {
         let temp_dir = tempdir().unwrap();
@@ -242,6 +1052,9 @@ mod test {
 
         let config = DataShardConfig {
             max_offsets: Some(10),
+            durability: Durability::None,
+            compression: Compression::None,
+            keyring: Keyring::default(),
         };
 
         let data_shard = DataShard::new(
@@ -274,6 +1087,9 @@ mod test {
             file_path.clone(),
             DataShardConfig {
                 max_offsets: Some(10),
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
             },
             None,
             Arc::new(FileDescriptorManager::new(2500)),
@@ -293,6 +1109,9 @@ mod test {
             file_path,
             DataShardConfig {
                 max_offsets: Some(2),
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
             },
             None,
             Arc::new(FileDescriptorManager::new(2500)),
@@ -335,6 +1154,9 @@ mod test {
             file_path,
             DataShardConfig {
                 max_offsets: Some(2),
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
             },
             None,
             Arc::new(FileDescriptorManager::new(2500)),
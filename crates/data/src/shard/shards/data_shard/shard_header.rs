@@ -1,18 +1,29 @@
+use crate::compression::Compression;
 use crate::data_handler::DataHandler;
+use crate::encryption::KeyId;
 use crate::errors::ShardErrors;
+use crate::shard::format::{
+    preamble, read_version, DATA_SHARD_MAGIC, FORMAT_VERSION, PREAMBLE_SIZE,
+};
 use crate::shard::shards::UUID_BYTE_LEN;
 use crate::{I64_SIZE, U64_SIZE};
 use parking_lot::RwLock;
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
 
+use crate::utils::checksum::{checksum, CHECKSUM_SIZE};
 use crate::utils::fs::write_at;
 use std::sync::Arc;
 use uuid::Uuid;
 
 pub const DEFAULT_MAX_OFFSETS: u64 = 100;
 
-// TODO: Header version
+const COMPRESSION_SIZE: usize = 1;
+const ENCRYPTION_KEY_SIZE: usize = U64_SIZE;
+
+// Offsets are file positions, so the highest bit is never used by a real offset.
+// It is set on the offset of a row that has been deleted.
+const TOMBSTONE_FLAG: u64 = 1 << 63;
 
 #[derive(Debug)]
 pub struct DataShardHeader {
@@ -20,23 +31,37 @@ pub struct DataShardHeader {
     last_offset_index: i64, // Even though this is realistically a u64, we use i64 because if everything is empty, it will be -1 which can't be with u64
     pub max_offset_positions: usize,
     pub id: Uuid,
+    compression: Compression,
+    encryption_key: KeyId,
+    version: u16,
+    // Whether the header on disk did not match its checksum.
+    // Every operation on a corrupted shard fails with `ShardErrors::Corrupted`.
+    pub corrupted: bool,
     data: Arc<RwLock<DataHandler>>,
     zero_offset: usize,
     header_size: usize,
-    offset_space_size: usize,
 }
 
 impl DataShardHeader {
-    pub fn new(max_offsets: u64, uuid: Option<Uuid>, data: Arc<RwLock<DataHandler>>) -> Self {
+    pub fn new(
+        max_offsets: u64,
+        uuid: Option<Uuid>,
+        compression: Compression,
+        encryption_key: KeyId,
+        data: Arc<RwLock<DataHandler>>,
+    ) -> Self {
         Self {
             max_offsets,
             last_offset_index: -1,
             id: uuid.unwrap_or_else(Uuid::new_v4),
+            compression,
+            encryption_key,
+            version: FORMAT_VERSION,
+            corrupted: false,
             max_offset_positions: Self::calculate_offset_pos(max_offsets as usize),
             data,
             zero_offset: Self::calculate_offset_pos(0),
             header_size: Self::calculate_header_size(max_offsets),
-            offset_space_size: Self::calculate_offset_space_size(max_offsets),
         }
     }
 
@@ -50,28 +75,72 @@ impl DataShardHeader {
         let offsets_size = Self::calculate_offset_space_size(max_offsets);
         let id_len = UUID_BYTE_LEN as usize;
 
-        max_offsets_size + last_offset_index_size + offsets_size + id_len
+        PREAMBLE_SIZE
+            + max_offsets_size
+            + last_offset_index_size
+            + CHECKSUM_SIZE
+            + offsets_size
+            + id_len
+            + COMPRESSION_SIZE
+            + ENCRYPTION_KEY_SIZE
     }
 
     pub fn get_max_offsets(&self) -> u64 {
         self.max_offsets
     }
 
+    pub fn get_compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Id of the key the items of this shard are encrypted with, `NO_KEY` if they are not.
+    pub fn get_encryption_key(&self) -> KeyId {
+        self.encryption_key
+    }
+
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    /// Fails if the shard cannot be operated on, either because it was written in a format
+    /// this release cannot read or because its header is corrupted.
+    pub fn check_usable(&self) -> Result<(), ShardErrors> {
+        if self.version != FORMAT_VERSION {
+            Err(ShardErrors::UnsupportedVersion)
+        } else if self.corrupted {
+            Err(ShardErrors::Corrupted)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn new_from_file(
         file: Arc<RwLock<DataHandler>>,
         max_offsets: Option<u64>,
         uuid: Option<Uuid>,
+        compression: Compression,
+        encryption_key: KeyId,
     ) -> Self {
         let mut header = DataShardHeader::new(
             max_offsets.unwrap_or(DEFAULT_MAX_OFFSETS),
             uuid,
+            compression,
+            encryption_key,
             file.clone(),
         );
 
         if file.read().len() == 0 {
             header.initialize_empty_file();
         } else {
-            header.read_header();
+            header.version = read_version(
+                DATA_SHARD_MAGIC,
+                file.read().get_bytes(0, PREAMBLE_SIZE).unwrap_or_default(),
+            );
+
+            // Only the current layout can be read, older ones go through `DataShard::upgrade` first
+            if header.version == FORMAT_VERSION {
+                header.corrupted = header.read_header().is_err();
+            }
         }
 
         header
@@ -85,32 +154,7 @@ impl DataShardHeader {
                 file.seek(SeekFrom::Start(0))
                     .expect("Failed to seek to start of file");
 
-                // Create a buffer for the header
-                let mut buffer = Vec::with_capacity(self.header_size);
-
-                {
-                    // Write max_offsets to the buffer
-                    let max_offsets_bytes = (self.max_offsets).to_le_bytes();
-                    buffer.extend_from_slice(&max_offsets_bytes);
-                }
-
-                {
-                    // Write last_used_offset to the buffer
-                    let last_offset_index = (self.last_offset_index).to_le_bytes();
-                    buffer.extend_from_slice(&last_offset_index);
-                }
-
-                {
-                    // Write shard id
-                    let id_bytes = self.id.to_bytes_le();
-                    buffer.extend_from_slice(&id_bytes);
-                }
-
-                {
-                    // Pre-allocate space for offsets by writing zeroed bytes
-                    let zero_bytes = vec![0u8; self.offset_space_size];
-                    buffer.extend_from_slice(&zero_bytes);
-                }
+                let buffer = self.encode(&[]);
 
                 // Write the buffer to the file
                 file.write_all(&buffer).expect("Failed to write header");
@@ -120,30 +164,157 @@ impl DataShardHeader {
             .unwrap()
     }
 
-    /// Reads the header (max_offsets and offsets) from the file
-    fn read_header(&mut self) {
-        let reader = self.data.read();
+    /// Serializes this header with the given row offsets, flagging the ones marked as deleted.
+    /// Offsets not provided are zeroed.
+    pub fn encode(&self, offsets: &[(u64, bool)]) -> Vec<u8> {
+        // Create a buffer for the header, starting with the preamble
+        let mut buffer = Vec::with_capacity(self.header_size);
+        buffer.extend_from_slice(&preamble(DATA_SHARD_MAGIC));
+
         {
-            let max_offset_bytes = reader.get_bytes(0, U64_SIZE).unwrap();
-            let max_offset_bytes: [u8; 8] = max_offset_bytes.try_into().unwrap();
-            self.max_offsets = u64::from_le_bytes(max_offset_bytes);
+            // Write max_offsets to the buffer
+            let max_offsets_bytes = (self.max_offsets).to_le_bytes();
+            buffer.extend_from_slice(&max_offsets_bytes);
         }
 
-        self.max_offset_positions = Self::calculate_offset_pos(self.max_offsets as usize);
+        {
+            // Write last_used_offset to the buffer
+            let last_offset_index = offsets.len() as i64 - 1;
+            buffer.extend_from_slice(&last_offset_index.to_le_bytes());
+
+            // Followed by the checksum of the header
+            buffer.extend_from_slice(&self.checksum(last_offset_index).to_le_bytes());
+        }
+
+        {
+            // Write shard id
+            let id_bytes = self.id.to_bytes_le();
+            buffer.extend_from_slice(&id_bytes);
+        }
+
+        {
+            // Write the compression codec
+            buffer.push(self.compression.to_byte());
+        }
+
+        {
+            // Write the id of the encryption key
+            buffer.extend_from_slice(&self.encryption_key.to_le_bytes());
+        }
 
         {
-            let last_offset_index_bytes = reader.read_pointer(U64_SIZE as u64, I64_SIZE).unwrap();
-            let last_offset_index_bytes: [u8; 8] = last_offset_index_bytes.try_into().unwrap();
-            self.last_offset_index = i64::from_le_bytes(last_offset_index_bytes);
+            for (offset, deleted) in offsets {
+                let value = if *deleted {
+                    offset | TOMBSTONE_FLAG
+                } else {
+                    *offset
+                };
+                buffer.extend_from_slice(&value.to_le_bytes());
+            }
+
+            // Pre-allocate space for the remaining offsets by writing zeroed bytes
+            buffer.resize(self.header_size, 0);
         }
 
+        buffer
+    }
+
+    pub fn get_header_size(&self) -> usize {
+        self.header_size
+    }
+
+    /// Checksum of every field of the header but the offsets, which are covered by the checksum of their item.
+    fn checksum(&self, last_offset_index: i64) -> u32 {
+        let mut bytes = Self::v1_checksum_bytes(
+            self.max_offsets,
+            last_offset_index,
+            self.id,
+            self.compression,
+        );
+        bytes.extend_from_slice(&self.encryption_key.to_le_bytes());
+        checksum(&bytes)
+    }
+
+    fn v1_checksum_bytes(
+        max_offsets: u64,
+        last_offset_index: i64,
+        id: Uuid,
+        compression: Compression,
+    ) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            U64_SIZE + I64_SIZE + UUID_BYTE_LEN as usize + COMPRESSION_SIZE + ENCRYPTION_KEY_SIZE,
+        );
+        bytes.extend_from_slice(&max_offsets.to_le_bytes());
+        bytes.extend_from_slice(&last_offset_index.to_le_bytes());
+        bytes.extend_from_slice(&id.to_bytes_le());
+        bytes.push(compression.to_byte());
+        bytes
+    }
+
+    /// Reads the header (max_offsets and offsets) from the file
+    /// Fails with `ShardErrors::Corrupted` if it is incomplete or does not match its checksum,
+    /// in which case the header is left untouched.
+    fn read_header(&mut self) -> Result<(), ShardErrors> {
+        let reader = self.data.read();
+        let read_u64 = |pos: usize| -> Result<[u8; 8], ShardErrors> {
+            reader
+                .get_bytes(pos, pos + U64_SIZE)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or(ShardErrors::Corrupted)
+        };
+
+        let max_offsets = u64::from_le_bytes(read_u64(PREAMBLE_SIZE)?);
+        let last_offset_index = i64::from_le_bytes(read_u64(PREAMBLE_SIZE + U64_SIZE)?);
+
+        let checksum_pos = PREAMBLE_SIZE + U64_SIZE + I64_SIZE;
+        let stored_checksum = reader
+            .get_bytes(checksum_pos, checksum_pos + CHECKSUM_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let id_pos = checksum_pos + CHECKSUM_SIZE;
+        let id = reader
+            .get_bytes(id_pos, id_pos + UUID_BYTE_LEN as usize)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Uuid::from_bytes_le)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        // Existing shards keep the codec they were written with
+        let compression_pos = id_pos + UUID_BYTE_LEN as usize;
+        let compression = reader
+            .get_bytes(compression_pos, compression_pos + COMPRESSION_SIZE)
+            .and_then(|bytes| Compression::from_byte(bytes[0]))
+            .ok_or(ShardErrors::Corrupted)?;
+
+        // As well as the key they were encrypted with
+        let encryption_key_pos = compression_pos + COMPRESSION_SIZE;
+        let encryption_key = u64::from_le_bytes(read_u64(encryption_key_pos)?);
+
+        let header = Self {
+            max_offsets,
+            last_offset_index,
+            max_offset_positions: Self::calculate_offset_pos(max_offsets as usize),
+            id,
+            compression,
+            encryption_key,
+            version: self.version,
+            corrupted: false,
+            data: self.data.clone(),
+            zero_offset: self.zero_offset,
+            header_size: Self::calculate_header_size(max_offsets),
+        };
+
+        if header.checksum(last_offset_index) != stored_checksum
+            || reader.len() < header.header_size
         {
-            let id_bytes = reader
-                .read_pointer((U64_SIZE + I64_SIZE) as u64, UUID_BYTE_LEN as usize)
-                .unwrap();
-            let id_bytes = id_bytes.try_into().unwrap();
-            self.id = Uuid::from_bytes_le(id_bytes);
+            return Err(ShardErrors::Corrupted);
         }
+
+        drop(reader);
+        *self = header;
+
+        Ok(())
     }
 
     fn calculate_offset_pos(index: usize) -> usize {
@@ -152,7 +323,14 @@ impl DataShardHeader {
         let id_len = UUID_BYTE_LEN as usize;
         let offsets_from_pos = index * U64_SIZE;
 
-        max_offsets + last_used_offset + id_len + offsets_from_pos
+        PREAMBLE_SIZE
+            + max_offsets
+            + last_used_offset
+            + CHECKSUM_SIZE
+            + id_len
+            + COMPRESSION_SIZE
+            + ENCRYPTION_KEY_SIZE
+            + offsets_from_pos
     }
 
     pub fn add_next_offset(&mut self, value: u64, file: &mut File) -> Result<(), ShardErrors> {
@@ -165,8 +343,16 @@ impl DataShardHeader {
                     let offset_bytes = value.to_le_bytes();
                     write_at(file, &offset_bytes, pos as u64)
                         .expect("Failed to write offset to file");
-                    write_at(file, &available_index.to_le_bytes(), U64_SIZE as u64)
-                        .map_err(|_| ShardErrors::ErrorAddingHeaderOffset)?;
+                    // The index and the checksum are contiguous, so they are written at once
+                    let mut index_with_checksum = (available_index as i64).to_le_bytes().to_vec();
+                    index_with_checksum
+                        .extend_from_slice(&self.checksum(available_index as i64).to_le_bytes());
+                    write_at(
+                        file,
+                        &index_with_checksum,
+                        (PREAMBLE_SIZE + U64_SIZE) as u64,
+                    )
+                    .map_err(|_| ShardErrors::ErrorAddingHeaderOffset)?;
                     self.last_offset_index = available_index as i64;
                     Ok(())
                 }
@@ -211,12 +397,9 @@ impl DataShardHeader {
         }
     }
 
-    pub fn get_offset_value_from_offset_header(&self, offset: usize) -> Option<u64> {
+    fn read_raw_offset(&self, offset: usize) -> Option<u64> {
         // Read the pointer
-        let bytes = match self.data.read().read_pointer(offset as u64, U64_SIZE) {
-            Some(bytes) => bytes,
-            None => return None,
-        };
+        let bytes = self.data.read().read_pointer(offset as u64, U64_SIZE)?;
 
         // Convert Vec<u8> to [u8; 8]
         let arr: [u8; 8] = match bytes.try_into() {
@@ -224,7 +407,11 @@ impl DataShardHeader {
             Err(_) => return None,
         };
 
-        let val = u64::from_le_bytes(arr);
+        Some(u64::from_le_bytes(arr))
+    }
+
+    pub fn get_offset_value_from_offset_header(&self, offset: usize) -> Option<u64> {
+        let val = self.read_raw_offset(offset)? & !TOMBSTONE_FLAG;
 
         if offset > self.zero_offset && val == 0 {
             None
@@ -237,6 +424,31 @@ impl DataShardHeader {
         }
     }
 
+    /// Whether the row whose offset lives at `offset` (position in the header) was deleted.
+    pub fn is_tombstone(&self, offset: usize) -> bool {
+        self.read_raw_offset(offset)
+            .map(|val| val & TOMBSTONE_FLAG != 0)
+            .unwrap_or(false)
+    }
+
+    /// Returns the position in the header of the offset of the row at `index`,
+    /// along with the value that flags that row as deleted.
+    /// The row keeps its position, so the positions of every other row (and the indexes pointing to them) stay valid.
+    pub fn get_tombstone_for_index(&self, index: usize) -> Result<(usize, u64), ShardErrors> {
+        if index as i64 > self.last_offset_index {
+            return Err(ShardErrors::UnknownOffset);
+        }
+
+        let pos = self
+            .get_offset_pos_by_index(index)
+            .ok_or(ShardErrors::UnknownOffset)?;
+        let val = self
+            .read_raw_offset(pos)
+            .ok_or(ShardErrors::UnknownOffset)?;
+
+        Ok((pos, val | TOMBSTONE_FLAG))
+    }
+
     pub fn get_offset_pos_by_index(&self, index: usize) -> Option<usize> {
         let pos = Self::calculate_offset_pos(index);
         if self.max_offset_positions > pos {
@@ -249,4 +461,128 @@ impl DataShardHeader {
     pub fn get_last_offset_index(&self) -> i64 {
         self.last_offset_index
     }
+
+    /// Reads the header of a shard written in the legacy format, returning its max offsets, id
+    /// and the offsets of its items.
+    /// That layout had neither preamble, checksum nor compression: `max_offsets | last_offset_index | id | offsets`.
+    pub fn decode_legacy(bytes: &[u8]) -> Result<(u64, Uuid, Vec<u64>), ShardErrors> {
+        let read_u64 = |pos: usize| -> Result<[u8; 8], ShardErrors> {
+            bytes
+                .get(pos..pos + U64_SIZE)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or(ShardErrors::Corrupted)
+        };
+
+        let max_offsets = u64::from_le_bytes(read_u64(0)?);
+        let last_offset_index = i64::from_le_bytes(read_u64(U64_SIZE)?);
+
+        let id_pos = U64_SIZE + I64_SIZE;
+        let id = bytes
+            .get(id_pos..id_pos + UUID_BYTE_LEN as usize)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Uuid::from_bytes_le)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let offsets_pos = id_pos + UUID_BYTE_LEN as usize;
+        let header_end = (max_offsets as usize)
+            .checked_mul(U64_SIZE)
+            .and_then(|size| size.checked_add(offsets_pos))
+            .ok_or(ShardErrors::Corrupted)?;
+
+        if header_end > bytes.len() || last_offset_index >= max_offsets as i64 {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        let offsets = (0..(last_offset_index + 1) as usize)
+            .map(|index| read_u64(offsets_pos + index * U64_SIZE).map(u64::from_le_bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Items are appended after the header, one after the other
+        let mut previous = header_end as u64;
+        for offset in offsets.iter() {
+            if *offset < previous || *offset > bytes.len() as u64 {
+                return Err(ShardErrors::Corrupted);
+            }
+            previous = *offset;
+        }
+
+        Ok((max_offsets, id, offsets))
+    }
+
+    /// Reads the header of a shard written in the first version of the format, returning its max offsets, id, codec,
+    /// the offsets of its items flagging the deleted ones, and the size of the header.
+    /// That layout lacked the id of the encryption key: `preamble | max_offsets | last_offset_index | checksum | id | compression | offsets`.
+    pub fn decode_v1(bytes: &[u8]) -> Result<DecodedV1Header, ShardErrors> {
+        let read_u64 = |pos: usize| -> Result<[u8; 8], ShardErrors> {
+            bytes
+                .get(pos..pos + U64_SIZE)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or(ShardErrors::Corrupted)
+        };
+
+        let max_offsets = u64::from_le_bytes(read_u64(PREAMBLE_SIZE)?);
+        let last_offset_index = i64::from_le_bytes(read_u64(PREAMBLE_SIZE + U64_SIZE)?);
+
+        let checksum_pos = PREAMBLE_SIZE + U64_SIZE + I64_SIZE;
+        let stored_checksum = bytes
+            .get(checksum_pos..checksum_pos + CHECKSUM_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let id_pos = checksum_pos + CHECKSUM_SIZE;
+        let id = bytes
+            .get(id_pos..id_pos + UUID_BYTE_LEN as usize)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Uuid::from_bytes_le)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let compression_pos = id_pos + UUID_BYTE_LEN as usize;
+        let compression = bytes
+            .get(compression_pos)
+            .and_then(|byte| Compression::from_byte(*byte))
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let v1_checksum = checksum(&Self::v1_checksum_bytes(
+            max_offsets,
+            last_offset_index,
+            id,
+            compression,
+        ));
+        if v1_checksum != stored_checksum || last_offset_index >= max_offsets as i64 {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        let offsets_pos = compression_pos + COMPRESSION_SIZE;
+        let header_size = (max_offsets as usize)
+            .checked_mul(U64_SIZE)
+            .and_then(|size| size.checked_add(offsets_pos))
+            .filter(|size| *size <= bytes.len())
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let offsets = (0..(last_offset_index + 1) as usize)
+            .map(|index| {
+                read_u64(offsets_pos + index * U64_SIZE)
+                    .map(u64::from_le_bytes)
+                    .map(|offset| (offset & !TOMBSTONE_FLAG, offset & TOMBSTONE_FLAG != 0))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(DecodedV1Header {
+            max_offsets,
+            id,
+            compression,
+            offsets,
+            header_size,
+        })
+    }
+}
+
+/// Header of a shard written in the first version of the format. See `DataShardHeader::decode_v1`.
+pub struct DecodedV1Header {
+    pub max_offsets: u64,
+    pub id: Uuid,
+    pub compression: Compression,
+    pub offsets: Vec<(u64, bool)>,
+    pub header_size: usize,
 }
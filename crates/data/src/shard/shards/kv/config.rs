@@ -1,9 +1,13 @@
+use crate::durability::Durability;
+use crate::encryption::Keyring;
 use crate::shard::ShardConfig;
 
 #[derive(Debug, Clone)]
 pub struct KvShardConfig {
     pub value_size: usize,
     pub max_capacity: Option<u64>,
+    pub durability: Durability,
+    pub keyring: Keyring,
 }
 
 impl ShardConfig for KvShardConfig {}
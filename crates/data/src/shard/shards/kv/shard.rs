@@ -1,10 +1,13 @@
 use crate::data_handler::DataHandler;
+use crate::encryption::{Keyring, NO_KEY};
 use crate::errors::ShardErrors;
 use crate::fdm::FileDescriptorManager;
+use crate::shard::format::{replace_file, LEGACY_FORMAT_VERSION, V1_FORMAT_VERSION};
 use crate::shard::shards::kv::config::KvShardConfig;
 use crate::shard::shards::kv::shard_header::KvShardHeader;
 use crate::shard::shards::kv::util::get_element_offset;
 use crate::shard::{AvailableSpace, Shard};
+use crate::utils::checksum::{verify_checksum, with_checksum};
 use crate::utils::flatten;
 use crate::utils::fs::write_at;
 use parking_lot::RwLock;
@@ -22,13 +25,42 @@ pub struct KvShard {
     pub value_size: usize,
     max_capacity: usize,
     id: Uuid,
+    keyring: Keyring,
 }
 
 impl KvShard {
     pub fn get_element(&self, index: usize) -> Option<Vec<u8>> {
+        self.read_entry(index).ok()
+    }
+
+    /// Reads the value at `index`, verifying it against the checksum stored next to it.
+    pub fn read_entry(&self, index: usize) -> Result<Vec<u8>, ShardErrors> {
+        let (entry_size, encryption_key) = {
+            let header = self.header.read();
+            header.check_usable()?;
+            (header.entry_size(), header.encryption_key)
+        };
+
         let reader = self.data.read();
-        let starting_point = Self::get_element_offset(index, self.value_size) as u64;
-        reader.read_pointer(starting_point, self.value_size)
+        let starting_point = Self::get_element_offset(index, entry_size);
+        let entry = reader
+            .get_bytes(starting_point, starting_point + entry_size)
+            .ok_or(ShardErrors::UnknownEntry)?;
+
+        let value = verify_checksum(entry)?;
+        self.keyring.decrypt(encryption_key, value.to_vec())
+    }
+
+    /// Size taken by each value in the file, including its checksum.
+    fn entry_size(&self) -> usize {
+        self.header.read().entry_size()
+    }
+
+    /// Encrypts `value` with the key of this shard and appends its checksum, as it is stored in the file.
+    fn encode_entry(&self, value: &[u8]) -> Result<Vec<u8>, ShardErrors> {
+        let encryption_key = self.header.read().encryption_key;
+        let value = self.keyring.encrypt(encryption_key, value)?;
+        Ok(with_checksum(&value))
     }
 
     fn get_element_offset(index: usize, value_size: usize) -> usize {
@@ -42,18 +74,148 @@ impl KvShard {
         first_element: &[u8],
         second_element: &[u8],
     ) -> Result<(), std::io::Error> {
+        let first_entry = self
+            .encode_entry(first_element)
+            .map_err(std::io::Error::other)?;
+        let second_entry = self
+            .encode_entry(second_element)
+            .map_err(std::io::Error::other)?;
+
         write_at(
             file,
-            second_element,
-            Self::get_element_offset(i, self.value_size) as u64,
+            &second_entry,
+            Self::get_element_offset(i, self.entry_size()) as u64,
         )?;
         write_at(
             file,
-            first_element,
-            Self::get_element_offset(i - 1, self.value_size) as u64,
+            &first_entry,
+            Self::get_element_offset(i - 1, self.entry_size()) as u64,
         )?;
         Ok(())
     }
+
+    /// Rewrites this shard in the current format if it was written in an older one, returning whether it did.
+    /// Entries of legacy shards gain the checksum they lacked, the ones of v1 shards are copied as they are.
+    /// Either way they are left unencrypted until the next `reencrypt`.
+    pub fn upgrade(&self) -> Result<bool, ShardErrors> {
+        let mut header = self.header.write();
+
+        let (max_capacity, value_size, contents) = {
+            let reader = self.data.read();
+            let bytes = reader
+                .get_bytes(0, reader.len())
+                .ok_or(ShardErrors::ErrorReadingByteRange)?;
+
+            let (max_capacity, items_len, value_size, id) = match header.get_version() {
+                LEGACY_FORMAT_VERSION => KvShardHeader::decode_legacy(bytes)?,
+                V1_FORMAT_VERSION => KvShardHeader::decode_v1(bytes)?,
+                _ => return Ok(false),
+            };
+
+            let upgraded = KvShardHeader::new(
+                items_len,
+                Some(max_capacity),
+                value_size,
+                Some(id),
+                NO_KEY,
+                self.data.clone(),
+            );
+            let mut contents = upgraded.encode();
+
+            if header.get_version() == LEGACY_FORMAT_VERSION {
+                let entries_pos = KvShardHeader::legacy_header_size();
+                for index in 0..items_len as usize {
+                    let start = entries_pos + index * value_size as usize;
+                    let entry = bytes
+                        .get(start..start + value_size as usize)
+                        .ok_or(ShardErrors::Corrupted)?;
+                    contents.extend_from_slice(&with_checksum(entry));
+                }
+            } else {
+                contents.extend_from_slice(&bytes[KvShardHeader::v1_header_size()..]);
+            }
+
+            (max_capacity, value_size, contents)
+        };
+
+        self.replace_contents(&contents)?;
+        *header = KvShardHeader::new_from_file(
+            self.data.clone(),
+            None,
+            None,
+            Some(max_capacity),
+            value_size,
+            NO_KEY,
+        );
+
+        Ok(true)
+    }
+
+    /// Rewrites this shard with the current key of the keyring if its entries were written with another one,
+    /// returning whether it did.
+    pub fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        let mut header = self.header.write();
+        header.check_usable()?;
+
+        let previous_key = header.encryption_key;
+        let current_key = self.keyring.current_id();
+        if previous_key == current_key {
+            return Ok(false);
+        }
+
+        let max_capacity = header.max_capacity;
+        let value_size = header.value_size;
+
+        let contents = {
+            let reencrypted = KvShardHeader::new(
+                header.items_len,
+                max_capacity,
+                value_size,
+                Some(header.id),
+                current_key,
+                self.data.clone(),
+            );
+            let mut contents = reencrypted.encode();
+
+            let reader = self.data.read();
+            let entry_size = header.entry_size();
+            for index in 0..header.items_len as usize {
+                let start = Self::get_element_offset(index, entry_size);
+                let entry = reader
+                    .get_bytes(start, start + entry_size)
+                    .ok_or(ShardErrors::UnknownEntry)?;
+
+                let value = self
+                    .keyring
+                    .decrypt(previous_key, verify_checksum(entry)?.to_vec())?;
+                let value = self.keyring.encrypt(current_key, &value)?;
+                contents.extend_from_slice(&with_checksum(&value));
+            }
+
+            contents
+        };
+
+        self.replace_contents(&contents)?;
+        *header = KvShardHeader::new_from_file(
+            self.data.clone(),
+            None,
+            None,
+            max_capacity,
+            value_size,
+            current_key,
+        );
+
+        Ok(true)
+    }
+
+    /// Atomically replaces the file of this shard and remaps it.
+    fn replace_contents(&self, contents: &[u8]) -> Result<(), ShardErrors> {
+        replace_file(&self.path, contents)?;
+        self.data
+            .write()
+            .reload()
+            .map_err(|_| ShardErrors::FlushingError)
+    }
 }
 
 impl Shard<KvShardConfig> for KvShard {
@@ -63,8 +225,7 @@ impl Shard<KvShardConfig> for KvShard {
         uuid: Option<Uuid>,
         fdm: Arc<FileDescriptorManager>,
     ) -> Self {
-        let data = unsafe { DataHandler::new(path.clone(), fdm).unwrap() };
-        let data = Arc::new(data);
+        let data = unsafe { DataHandler::new(path.clone(), fdm, opts.durability).unwrap() };
 
         let header = KvShardHeader::new_from_file(
             data.clone(),
@@ -72,16 +233,32 @@ impl Shard<KvShardConfig> for KvShard {
             Some(0),
             opts.max_capacity,
             opts.value_size as u64,
+            opts.keyring.current_id(),
         );
 
-        Self {
+        let mut shard = Self {
             path,
             data: data.clone(),
             max_capacity: header.max_capacity.unwrap_or(0) as usize,
             value_size: header.value_size as usize,
             id: header.id,
             header: RwLock::new(header),
+            keyring: opts.keyring,
+        };
+
+        // Shards written in an older format are brought up to date as they are opened.
+        // If that fails, every operation on the shard reports `ShardErrors::UnsupportedVersion`.
+        if let Ok(true) = shard.upgrade() {
+            let (max_capacity, value_size, id) = {
+                let header = shard.header.read();
+                (header.max_capacity, header.value_size, header.id)
+            };
+            shard.max_capacity = max_capacity.unwrap_or(0) as usize;
+            shard.value_size = value_size as usize;
+            shard.id = id;
         }
+
+        shard
     }
 
     fn has_space(&self) -> bool {
@@ -113,10 +290,7 @@ impl Shard<KvShardConfig> for KvShard {
     }
 
     fn read_item_from_index(&self, index: usize) -> Result<Vec<u8>, ShardErrors> {
-        match self.get_element(index) {
-            None => Err(ShardErrors::UnknownEntry),
-            Some(v) => Ok(v),
-        }
+        self.read_entry(index)
     }
 
     fn available_space(&self) -> AvailableSpace {
@@ -129,6 +303,14 @@ impl Shard<KvShardConfig> for KvShard {
     }
 
     fn insert_item(&self, data: &[&[u8]]) -> Result<u64, ShardErrors> {
+        self.header.read().check_usable()?;
+
+        let entries = data
+            .iter()
+            .map(|item| self.encode_entry(item))
+            .collect::<Result<Vec<_>, _>>()?;
+        let entries: Vec<&[u8]> = entries.iter().map(|entry| entry.as_slice()).collect();
+
         let mut writer = self.data.write();
         writer
             .operate(|file| {
@@ -136,7 +318,7 @@ impl Shard<KvShardConfig> for KvShard {
                     .seek(SeekFrom::End(0))
                     .expect("Failed to seek to end of file");
 
-                let flat_items = flatten(data);
+                let flat_items = flatten(&entries);
 
                 file.write_all(&flat_items)
                     .expect("Failed to write item to file");
@@ -155,6 +337,61 @@ impl Shard<KvShardConfig> for KvShard {
             .map_err(|_| ShardErrors::ErrorAddingEntry)
     }
 
+    fn delete_item(&self, index: usize) -> Result<(), ShardErrors> {
+        self.header.read().check_usable()?;
+
+        let last_index = self.get_last_index();
+        if index as i64 > last_index {
+            return Err(ShardErrors::UnknownEntry);
+        }
+
+        // Entries after `index` are moved one slot back so the shard stays contiguous and sorted.
+        let start = Self::get_element_offset(index + 1, self.entry_size());
+        let end = Self::get_element_offset(last_index as usize + 1, self.entry_size());
+        let following = self
+            .data
+            .read()
+            .get_bytes(start, end)
+            .ok_or(ShardErrors::UnknownEntry)?
+            .to_vec();
+
+        let mut writer = self.data.write();
+        writer
+            .operate(|file| {
+                let new_end = Self::get_element_offset(last_index as usize, self.entry_size());
+                write_at(
+                    file,
+                    &following,
+                    Self::get_element_offset(index, self.entry_size()) as u64,
+                )?;
+                file.set_len(new_end as u64)?;
+                self.header.write().decrement_len(file);
+                Ok(())
+            })
+            .map_err(|_| ShardErrors::FlushingError)
+    }
+
+    fn verify(&self) -> Result<(), ShardErrors> {
+        self.header.read().check_usable()?;
+
+        for index in 0..(self.get_last_index() + 1) as usize {
+            self.read_entry(index)?;
+        }
+
+        Ok(())
+    }
+
+    fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        KvShard::reencrypt(self)
+    }
+
+    fn sync(&self) -> Result<(), ShardErrors> {
+        self.data
+            .read()
+            .sync()
+            .map_err(|_| ShardErrors::FlushingError)
+    }
+
     fn get_id(&self) -> String {
         self.id.to_string()
     }
@@ -162,7 +399,11 @@ impl Shard<KvShardConfig> for KvShard {
 
 #[cfg(test)]
 mod test {
+    use crate::durability::Durability;
+    use crate::encryption::{EncryptionKey, Keyring, KEY_SIZE};
+    use crate::errors::ShardErrors;
     use crate::fdm::FileDescriptorManager;
+    use crate::shard::format::{FORMAT_VERSION, KV_SHARD_MAGIC, PREAMBLE_SIZE};
     use crate::shard::shards::kv::config::KvShardConfig;
     use crate::shard::shards::kv::shard::KvShard;
     use crate::shard::Shard;
@@ -182,6 +423,8 @@ mod test {
             KvShardConfig {
                 value_size: 1,
                 max_capacity: None,
+                durability: Durability::None,
+                keyring: Keyring::default(),
             },
             None,
             Arc::new(FileDescriptorManager::new(2500)),
@@ -212,4 +455,185 @@ mod test {
 
         assert!(kv_shard.get_element(3).is_none(),);
     }
+
+    #[tokio::test]
+    pub async fn test_kv_shard_delete() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir
+            .path()
+            .join(format!("{}.index", Uuid::new_v4().to_string()));
+
+        let kv_shard = KvShard::new(
+            file_path.clone(),
+            KvShardConfig {
+                value_size: 1,
+                max_capacity: None,
+                durability: Durability::None,
+                keyring: Keyring::default(),
+            },
+            None,
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+
+        kv_shard.insert_item(&[b"a", b"b", b"c"]).unwrap();
+        kv_shard.delete_item(1).unwrap();
+
+        assert_eq!(kv_shard.get_last_index(), 1);
+        assert_eq!(kv_shard.get_element(0).unwrap(), b"a".to_vec());
+        assert_eq!(kv_shard.get_element(1).unwrap(), b"c".to_vec());
+        assert!(kv_shard.get_element(2).is_none());
+        assert!(kv_shard.delete_item(2).is_err());
+
+        // New items are appended after the remaining ones
+        kv_shard.insert_item(&[b"d"]).unwrap();
+        assert_eq!(kv_shard.get_element(2).unwrap(), b"d".to_vec());
+    }
+
+    #[tokio::test]
+    pub async fn test_kv_shard_encryption() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join(format!("{}.index", Uuid::new_v4()));
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let old_key = EncryptionKey::new(&[1u8; KEY_SIZE]).unwrap();
+        let new_key = EncryptionKey::new(&[2u8; KEY_SIZE]).unwrap();
+        let config = |keyring: Keyring| KvShardConfig {
+            value_size: 5,
+            max_capacity: None,
+            durability: Durability::None,
+            keyring,
+        };
+
+        {
+            let kv_shard = KvShard::new(
+                file_path.clone(),
+                config(Keyring::new(Some(old_key.clone()), vec![])),
+                None,
+                fdm.clone(),
+            );
+            kv_shard
+                .insert_item(&[b"apple", b"berry", b"cocoa"])
+                .unwrap();
+            kv_shard.delete_item(0).unwrap();
+            assert_eq!(kv_shard.get_element(0).unwrap(), b"berry".to_vec());
+            assert_eq!(kv_shard.get_element(1).unwrap(), b"cocoa".to_vec());
+        }
+
+        let bytes = std::fs::read(&file_path).unwrap();
+        assert!(!bytes.windows(5).any(|window| window == b"berry"));
+
+        let kv_shard = KvShard::new(
+            file_path.clone(),
+            config(Keyring::new(Some(new_key.clone()), vec![old_key])),
+            None,
+            fdm.clone(),
+        );
+        assert!(kv_shard.reencrypt().unwrap());
+        assert!(!kv_shard.reencrypt().unwrap());
+        assert_eq!(kv_shard.header.read().encryption_key, new_key.id());
+        kv_shard.insert_item(&[b"dates"]).unwrap();
+        drop(kv_shard);
+
+        let kv_shard = KvShard::new(
+            file_path,
+            config(Keyring::new(Some(new_key), vec![])),
+            None,
+            fdm,
+        );
+        assert_eq!(kv_shard.get_last_index(), 2);
+        assert_eq!(kv_shard.get_element(1).unwrap(), b"cocoa".to_vec());
+        assert_eq!(kv_shard.get_element(2).unwrap(), b"dates".to_vec());
+        assert!(kv_shard.verify().is_ok());
+    }
+
+    #[tokio::test]
+    pub async fn test_kv_shard_corruption() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join(format!("{}.index", Uuid::new_v4()));
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let config = KvShardConfig {
+            value_size: 1,
+            max_capacity: None,
+            durability: Durability::None,
+            keyring: Keyring::default(),
+        };
+
+        let flip_byte = |pos: u64| {
+            let mut bytes = std::fs::read(&file_path).unwrap();
+            bytes[pos as usize] ^= 0xFF;
+            std::fs::write(&file_path, bytes).unwrap();
+        };
+
+        {
+            let kv_shard = KvShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            kv_shard.insert_item(&[b"a", b"b", b"c"]).unwrap();
+            assert!(kv_shard.verify().is_ok());
+        }
+
+        // The last byte of the file belongs to the checksum of the last entry
+        flip_byte(std::fs::metadata(&file_path).unwrap().len() - 1);
+
+        {
+            let kv_shard = KvShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            assert_eq!(kv_shard.get_element(1).unwrap(), b"b".to_vec());
+            assert!(kv_shard.get_element(2).is_none());
+            assert!(matches!(
+                kv_shard.read_entry(2),
+                Err(ShardErrors::Corrupted)
+            ));
+            assert!(matches!(kv_shard.verify(), Err(ShardErrors::Corrupted)));
+        }
+
+        flip_byte(PREAMBLE_SIZE as u64);
+
+        let kv_shard = KvShard::new(file_path.clone(), config, None, fdm);
+        assert!(kv_shard.header.read().corrupted);
+        assert!(matches!(kv_shard.verify(), Err(ShardErrors::Corrupted)));
+        assert!(matches!(
+            kv_shard.insert_item(&[b"d"]),
+            Err(ShardErrors::Corrupted)
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn test_kv_shard_upgrade() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join(format!("{}.index", Uuid::new_v4()));
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let config = KvShardConfig {
+            value_size: 1,
+            max_capacity: None,
+            durability: Durability::None,
+            keyring: Keyring::default(),
+        };
+
+        // Legacy layout: max_capacity | items_len | value_size | id | entries
+        let id = Uuid::new_v4();
+        let mut legacy = vec![];
+        legacy.extend_from_slice(&0u64.to_le_bytes());
+        legacy.extend_from_slice(&3u64.to_le_bytes());
+        legacy.extend_from_slice(&1u64.to_le_bytes());
+        legacy.extend_from_slice(&id.to_bytes_le());
+        legacy.extend_from_slice(b"abc");
+        std::fs::write(&file_path, legacy).unwrap();
+
+        {
+            let kv_shard = KvShard::new(file_path.clone(), config.clone(), None, fdm.clone());
+            assert_eq!(kv_shard.get_id(), id.to_string());
+            assert_eq!(kv_shard.header.read().get_version(), FORMAT_VERSION);
+            assert_eq!(kv_shard.get_last_index(), 2);
+            assert_eq!(kv_shard.get_element(0).unwrap(), b"a".to_vec());
+            assert_eq!(kv_shard.get_element(2).unwrap(), b"c".to_vec());
+            assert!(kv_shard.verify().is_ok());
+
+            kv_shard.insert_item(&[b"d"]).unwrap();
+        }
+
+        assert!(std::fs::read(&file_path)
+            .unwrap()
+            .starts_with(KV_SHARD_MAGIC));
+
+        let kv_shard = KvShard::new(file_path.clone(), config, None, fdm);
+        assert_eq!(kv_shard.get_id(), id.to_string());
+        assert_eq!(kv_shard.get_element(3).unwrap(), b"d".to_vec());
+    }
 }
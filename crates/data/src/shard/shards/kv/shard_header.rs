@@ -1,5 +1,9 @@
 use crate::data_handler::DataHandler;
+use crate::encryption::{KeyId, ENCRYPTION_OVERHEAD, NO_KEY};
+use crate::errors::ShardErrors;
+use crate::shard::format::{preamble, read_version, FORMAT_VERSION, KV_SHARD_MAGIC, PREAMBLE_SIZE};
 use crate::shard::shards::UUID_BYTE_LEN;
+use crate::utils::checksum::{checksum, CHECKSUM_SIZE};
 use crate::utils::fs::write_at;
 use crate::U64_SIZE;
 use parking_lot::RwLock;
@@ -8,12 +12,20 @@ use std::io::{Seek, SeekFrom, Write};
 use std::sync::Arc;
 use uuid::Uuid;
 
+const ENCRYPTION_KEY_SIZE: usize = U64_SIZE;
+
 #[derive(Debug)]
 pub struct KvShardHeader {
     pub max_capacity: Option<u64>,
     pub items_len: u64,
     pub value_size: u64,
     pub id: Uuid,
+    // Id of the key the entries are encrypted with, `NO_KEY` if they are not.
+    pub encryption_key: KeyId,
+    version: u16,
+    // Whether the header on disk did not match its checksum.
+    // Every operation on a corrupted shard fails with `ShardErrors::Corrupted`.
+    pub corrupted: bool,
     data: Arc<RwLock<DataHandler>>,
 }
 
@@ -23,6 +35,7 @@ impl KvShardHeader {
         max_capacity: Option<u64>,
         value_size: u64,
         uuid: Option<Uuid>,
+        encryption_key: KeyId,
         data: Arc<RwLock<DataHandler>>,
     ) -> Self {
         Self {
@@ -31,6 +44,9 @@ impl KvShardHeader {
             data,
             id: uuid.unwrap_or_else(|| Uuid::new_v4()),
             value_size,
+            encryption_key,
+            version: FORMAT_VERSION,
+            corrupted: false,
         }
     }
 
@@ -40,12 +56,14 @@ impl KvShardHeader {
         items_len: Option<u64>,
         max_capacity: Option<u64>,
         value_size: u64,
+        encryption_key: KeyId,
     ) -> Self {
         let mut header = KvShardHeader::new(
             items_len.unwrap_or(0),
             max_capacity,
             value_size,
             uuid,
+            encryption_key,
             file.clone(),
         );
 
@@ -54,18 +72,59 @@ impl KvShardHeader {
         if file_len == 0 {
             header.initialize_empty_file();
         } else {
-            header.read_header();
+            header.version = read_version(
+                KV_SHARD_MAGIC,
+                file.read().get_bytes(0, PREAMBLE_SIZE).unwrap_or_default(),
+            );
+
+            // Only the current layout can be read, older ones go through `KvShard::upgrade` first
+            if header.version == FORMAT_VERSION {
+                header.corrupted = header.read_header().is_err();
+            }
         }
 
         header
     }
 
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    /// Fails if the shard cannot be operated on, either because it was written in a format
+    /// this release cannot read or because its header is corrupted.
+    pub fn check_usable(&self) -> Result<(), ShardErrors> {
+        if self.version != FORMAT_VERSION {
+            Err(ShardErrors::UnsupportedVersion)
+        } else if self.corrupted {
+            Err(ShardErrors::Corrupted)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn header_size() -> usize {
         let max_capacity_size = U64_SIZE;
         let items_len_size = U64_SIZE;
         let value_size = U64_SIZE;
         let id_len = UUID_BYTE_LEN as usize;
-        max_capacity_size + items_len_size + value_size + id_len
+        PREAMBLE_SIZE
+            + max_capacity_size
+            + items_len_size
+            + CHECKSUM_SIZE
+            + value_size
+            + id_len
+            + ENCRYPTION_KEY_SIZE
+    }
+
+    /// Size taken by each entry in the file, including what encrypting it adds and its checksum.
+    pub fn entry_size(&self) -> usize {
+        let encryption_overhead = if self.encryption_key == NO_KEY {
+            0
+        } else {
+            ENCRYPTION_OVERHEAD
+        };
+
+        self.value_size as usize + encryption_overhead + CHECKSUM_SIZE
     }
 
     fn initialize_empty_file(&mut self) {
@@ -75,32 +134,7 @@ impl KvShardHeader {
                 file.seek(SeekFrom::Start(0))
                     .expect("Failed to seek to start of file");
 
-                // Create a buffer for the header
-                let mut buffer = Vec::with_capacity(Self::header_size());
-
-                {
-                    // Write max_offsets to the buffer
-                    let max_capacity_bytes = (self.max_capacity).unwrap_or(0).to_le_bytes();
-                    buffer.extend_from_slice(&max_capacity_bytes);
-                }
-
-                {
-                    // Write max_offsets to the buffer
-                    let items_len_bytes = (self.items_len).to_le_bytes();
-                    buffer.extend_from_slice(&items_len_bytes);
-                }
-
-                {
-                    // Write value_size to the buffer
-                    let value_size_bytes = (self.value_size).to_le_bytes();
-                    buffer.extend_from_slice(&value_size_bytes);
-                }
-
-                {
-                    // Write shard id
-                    let id_bytes = self.id.to_bytes_le();
-                    buffer.extend_from_slice(&id_bytes);
-                }
+                let buffer = self.encode();
 
                 // Write the buffer to the file
                 file.write_all(&buffer)
@@ -111,44 +145,232 @@ impl KvShardHeader {
             .unwrap();
     }
 
-    fn read_header(&mut self) {
-        let reader = self.data.read();
+    /// Serializes this header, starting with the preamble.
+    pub fn encode(&self) -> Vec<u8> {
+        // Create a buffer for the header
+        let mut buffer = Vec::with_capacity(Self::header_size());
+        buffer.extend_from_slice(&preamble(KV_SHARD_MAGIC));
+
+        {
+            // Write max_offsets to the buffer
+            let max_capacity_bytes = (self.max_capacity).unwrap_or(0).to_le_bytes();
+            buffer.extend_from_slice(&max_capacity_bytes);
+        }
+
+        {
+            // Write max_offsets to the buffer
+            let items_len_bytes = (self.items_len).to_le_bytes();
+            buffer.extend_from_slice(&items_len_bytes);
+        }
+
         {
-            let max_capacity_bytes = reader.get_bytes(0, U64_SIZE).unwrap();
-            let max_capacity_bytes: [u8; 8] = max_capacity_bytes.try_into().unwrap();
-            self.max_capacity = Some(u64::from_le_bytes(max_capacity_bytes));
+            // Followed by the checksum of the header
+            let checksum_bytes = self.checksum(self.items_len).to_le_bytes();
+            buffer.extend_from_slice(&checksum_bytes);
         }
 
         {
-            let items_len_bytes = reader.read_pointer(U64_SIZE as u64, U64_SIZE).unwrap();
-            let items_len_bytes: [u8; 8] = items_len_bytes.try_into().unwrap();
-            self.items_len = u64::from_le_bytes(items_len_bytes);
+            // Write value_size to the buffer
+            let value_size_bytes = (self.value_size).to_le_bytes();
+            buffer.extend_from_slice(&value_size_bytes);
         }
 
         {
-            let value_size_bytes = reader
-                .read_pointer(U64_SIZE as u64 + U64_SIZE as u64, U64_SIZE)
-                .unwrap();
-            let value_size_bytes: [u8; 8] = value_size_bytes.try_into().unwrap();
-            self.value_size = u64::from_le_bytes(value_size_bytes);
+            // Write shard id
+            let id_bytes = self.id.to_bytes_le();
+            buffer.extend_from_slice(&id_bytes);
         }
 
         {
-            let id_bytes = reader
-                .read_pointer(
-                    (U64_SIZE + U64_SIZE + U64_SIZE) as u64,
-                    UUID_BYTE_LEN as usize,
-                )
-                .unwrap();
-            let id_bytes = id_bytes.try_into().unwrap();
-            self.id = Uuid::from_bytes_le(id_bytes);
+            // Write the id of the encryption key
+            buffer.extend_from_slice(&self.encryption_key.to_le_bytes());
         }
+
+        buffer
+    }
+
+    fn checksum(&self, items_len: u64) -> u32 {
+        let mut bytes = Self::v1_checksum_bytes(
+            self.max_capacity.unwrap_or(0),
+            items_len,
+            self.value_size,
+            self.id,
+        );
+        bytes.extend_from_slice(&self.encryption_key.to_le_bytes());
+        checksum(&bytes)
+    }
+
+    fn v1_checksum_bytes(max_capacity: u64, items_len: u64, value_size: u64, id: Uuid) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::header_size());
+        bytes.extend_from_slice(&max_capacity.to_le_bytes());
+        bytes.extend_from_slice(&items_len.to_le_bytes());
+        bytes.extend_from_slice(&value_size.to_le_bytes());
+        bytes.extend_from_slice(&id.to_bytes_le());
+        bytes
+    }
+
+    /// Fails with `ShardErrors::Corrupted` if the header is incomplete or does not match its checksum,
+    /// in which case the header is left untouched.
+    fn read_header(&mut self) -> Result<(), ShardErrors> {
+        let reader = self.data.read();
+        let read_u64 = |pos: usize| -> Result<u64, ShardErrors> {
+            reader
+                .get_bytes(pos, pos + U64_SIZE)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or(ShardErrors::Corrupted)
+        };
+
+        let max_capacity = read_u64(PREAMBLE_SIZE)?;
+        let items_len = read_u64(PREAMBLE_SIZE + U64_SIZE)?;
+
+        let checksum_pos = PREAMBLE_SIZE + U64_SIZE + U64_SIZE;
+        let stored_checksum = reader
+            .get_bytes(checksum_pos, checksum_pos + CHECKSUM_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let value_size = read_u64(checksum_pos + CHECKSUM_SIZE)?;
+
+        let id_pos = checksum_pos + CHECKSUM_SIZE + U64_SIZE;
+        let id = reader
+            .get_bytes(id_pos, id_pos + UUID_BYTE_LEN as usize)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Uuid::from_bytes_le)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let encryption_key = read_u64(id_pos + UUID_BYTE_LEN as usize)?;
+
+        let header = Self {
+            max_capacity: Some(max_capacity),
+            items_len,
+            value_size,
+            id,
+            encryption_key,
+            version: self.version,
+            corrupted: false,
+            data: self.data.clone(),
+        };
+
+        if header.checksum(items_len) != stored_checksum {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        drop(reader);
+        *self = header;
+
+        Ok(())
     }
 
     pub fn increment_len(&mut self, len: Option<u64>, file: &mut File) -> u64 {
         self.items_len += len.unwrap_or(1);
-        write_at(file, &self.items_len.to_le_bytes(), U64_SIZE as u64).unwrap();
+        self.write_len(file);
+
+        self.items_len
+    }
+
+    pub fn decrement_len(&mut self, file: &mut File) -> u64 {
+        self.items_len = self.items_len.saturating_sub(1);
+        self.write_len(file);
 
         self.items_len
     }
+
+    // The length and the checksum are contiguous, so they are written at once
+    fn write_len(&self, file: &mut File) {
+        let mut len_with_checksum = self.items_len.to_le_bytes().to_vec();
+        len_with_checksum.extend_from_slice(&self.checksum(self.items_len).to_le_bytes());
+        write_at(file, &len_with_checksum, (PREAMBLE_SIZE + U64_SIZE) as u64).unwrap();
+    }
+
+    /// Reads the header of a shard written in the legacy format, returning its max capacity, items len, value size and id.
+    /// That layout had neither preamble nor checksum: `max_capacity | items_len | value_size | id`.
+    pub fn decode_legacy(bytes: &[u8]) -> Result<(u64, u64, u64, Uuid), ShardErrors> {
+        let read_u64 = |pos: usize| -> Result<u64, ShardErrors> {
+            bytes
+                .get(pos..pos + U64_SIZE)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or(ShardErrors::Corrupted)
+        };
+
+        let max_capacity = read_u64(0)?;
+        let items_len = read_u64(U64_SIZE)?;
+        let value_size = read_u64(U64_SIZE * 2)?;
+
+        let id_pos = U64_SIZE * 3;
+        let id = bytes
+            .get(id_pos..id_pos + UUID_BYTE_LEN as usize)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Uuid::from_bytes_le)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let entries_size = items_len.checked_mul(value_size);
+        let expected_size =
+            entries_size.and_then(|size| size.checked_add(Self::legacy_header_size() as u64));
+        if expected_size != Some(bytes.len() as u64) {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        Ok((max_capacity, items_len, value_size, id))
+    }
+
+    /// Size of the header in the legacy format.
+    pub fn legacy_header_size() -> usize {
+        U64_SIZE * 3 + UUID_BYTE_LEN as usize
+    }
+
+    /// Reads the header of a shard written in the first version of the format, returning its max capacity,
+    /// items len, value size and id.
+    /// That layout lacked the id of the encryption key: `preamble | max_capacity | items_len | checksum | value_size | id`.
+    pub fn decode_v1(bytes: &[u8]) -> Result<(u64, u64, u64, Uuid), ShardErrors> {
+        let read_u64 = |pos: usize| -> Result<u64, ShardErrors> {
+            bytes
+                .get(pos..pos + U64_SIZE)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or(ShardErrors::Corrupted)
+        };
+
+        let max_capacity = read_u64(PREAMBLE_SIZE)?;
+        let items_len = read_u64(PREAMBLE_SIZE + U64_SIZE)?;
+
+        let checksum_pos = PREAMBLE_SIZE + U64_SIZE * 2;
+        let stored_checksum = bytes
+            .get(checksum_pos..checksum_pos + CHECKSUM_SIZE)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let value_size = read_u64(checksum_pos + CHECKSUM_SIZE)?;
+
+        let id_pos = checksum_pos + CHECKSUM_SIZE + U64_SIZE;
+        let id = bytes
+            .get(id_pos..id_pos + UUID_BYTE_LEN as usize)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Uuid::from_bytes_le)
+            .ok_or(ShardErrors::Corrupted)?;
+
+        let v1_checksum = checksum(&Self::v1_checksum_bytes(
+            max_capacity,
+            items_len,
+            value_size,
+            id,
+        ));
+        let expected_size = value_size
+            .checked_add(CHECKSUM_SIZE as u64)
+            .and_then(|entry_size| entry_size.checked_mul(items_len))
+            .and_then(|size| size.checked_add(Self::v1_header_size() as u64));
+        if v1_checksum != stored_checksum || expected_size != Some(bytes.len() as u64) {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        Ok((max_capacity, items_len, value_size, id))
+    }
+
+    /// Size of the header in the first version of the format.
+    pub fn v1_header_size() -> usize {
+        Self::header_size() - ENCRYPTION_KEY_SIZE
+    }
 }
@@ -0,0 +1,22 @@
+use crate::shard::{ShardConfig, TempShardConfig};
+use crate::temp_offset_types::TempOffsetTypes;
+
+#[derive(Clone, Debug)]
+pub struct MemoryShardConfig {
+    pub max_offsets: Option<u64>,
+}
+
+impl ShardConfig for MemoryShardConfig {}
+
+#[derive(Debug, Clone)]
+pub struct TempMemoryShardConfig {
+    pub max_offsets: TempOffsetTypes,
+}
+
+impl TempShardConfig<MemoryShardConfig> for TempMemoryShardConfig {
+    fn to_config(&self) -> MemoryShardConfig {
+        MemoryShardConfig {
+            max_offsets: self.max_offsets.get_real_offset(),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::errors::ShardErrors;
+use crate::fdm::FileDescriptorManager;
+use crate::shard::item_ref::ItemRef;
+use crate::shard::shards::memory::config::MemoryShardConfig;
+use crate::shard::{AvailableSpace, CompactableShard, Shard};
+use parking_lot::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Shard keeping its items in memory, for databases whose data does not need to outlive the process.
+///
+/// Nothing is written to `path`, it only names the shard the same way the file backed ones are named.
+/// Items are neither compressed nor encrypted, and deleted ones free their data right away
+/// while keeping their slot, so the positions of the following items do not change.
+#[derive(Debug)]
+pub struct MemoryShard {
+    pub path: PathBuf,
+    pub id: Uuid,
+    max_offsets: Option<u64>,
+    items: Arc<RwLock<Vec<Option<Vec<u8>>>>>,
+}
+
+impl Shard<MemoryShardConfig> for MemoryShard {
+    fn new(
+        path: PathBuf,
+        opts: MemoryShardConfig,
+        uuid: Option<Uuid>,
+        _fdm: Arc<FileDescriptorManager>,
+    ) -> Self {
+        MemoryShard {
+            path,
+            id: uuid.unwrap_or_else(Uuid::new_v4),
+            max_offsets: opts.max_offsets,
+            items: Arc::new(RwLock::new(vec![])),
+        }
+    }
+
+    fn has_space(&self) -> bool {
+        match self.available_space() {
+            AvailableSpace::Fixed(space) => space > 0,
+            AvailableSpace::Unlimited => true,
+        }
+    }
+
+    fn breaking_point(&self) -> Option<u64> {
+        self.max_offsets
+    }
+
+    fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    fn get_last_index(&self) -> i64 {
+        self.items.read().len() as i64 - 1
+    }
+
+    fn read_item_from_index(&self, index: usize) -> Result<Vec<u8>, ShardErrors> {
+        match self.items.read().get(index) {
+            None => Err(ShardErrors::UnknownOffset),
+            Some(None) => Err(ShardErrors::DeletedItem),
+            Some(Some(item)) => Ok(item.clone()),
+        }
+    }
+
+    fn read_item_ref(&self, index: usize) -> Result<ItemRef, ShardErrors> {
+        let items = self.items.read_arc();
+        match items.get(index) {
+            None => Err(ShardErrors::UnknownOffset),
+            Some(None) => Err(ShardErrors::DeletedItem),
+            Some(Some(_)) => Ok(ItemRef::Memory { items, index }),
+        }
+    }
+
+    fn available_space(&self) -> AvailableSpace {
+        match self.max_offsets {
+            None => AvailableSpace::Unlimited,
+            Some(max_offsets) => AvailableSpace::Fixed(
+                (max_offsets as usize).saturating_sub(self.items.read().len()),
+            ),
+        }
+    }
+
+    fn insert_item(&self, data: &[&[u8]]) -> Result<u64, ShardErrors> {
+        let mut items = self.items.write();
+        if let Some(max_offsets) = self.max_offsets {
+            if items.len() + data.len() > max_offsets as usize {
+                return Err(ShardErrors::OutOfPositions);
+            }
+        }
+
+        items.extend(data.iter().map(|item| Some(item.to_vec())));
+
+        Ok(items.len() as u64 - 1)
+    }
+
+    fn delete_item(&self, index: usize) -> Result<(), ShardErrors> {
+        let mut items = self.items.write();
+        let item = items.get_mut(index).ok_or(ShardErrors::UnknownOffset)?;
+        *item = None;
+
+        Ok(())
+    }
+
+    fn verify(&self) -> Result<(), ShardErrors> {
+        Ok(())
+    }
+
+    fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        Ok(false)
+    }
+
+    fn sync(&self) -> Result<(), ShardErrors> {
+        Ok(())
+    }
+
+    fn get_id(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl CompactableShard<MemoryShardConfig> for MemoryShard {
+    fn deleted_items(&self) -> (usize, u64) {
+        // Deleted items free their data right away, so there is never anything to reclaim.
+        let deleted = self.items.read().iter().filter(|i| i.is_none()).count();
+        (deleted, 0)
+    }
+
+    fn data_size(&self) -> u64 {
+        self.items
+            .read()
+            .iter()
+            .flatten()
+            .map(|i| i.len() as u64)
+            .sum()
+    }
+
+    fn write_compacted(&self, _path: &Path) -> Result<(), ShardErrors> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::ShardErrors;
+    use crate::fdm::FileDescriptorManager;
+    use crate::shard::map_shard::MapShard;
+    use crate::shard::shards::memory::config::MemoryShardConfig;
+    use crate::shard::shards::memory::shard::MemoryShard;
+    use crate::shard::Shard;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    pub fn test_memory_shard() {
+        let shard = MemoryShard::new(
+            PathBuf::from("memory_shard"),
+            MemoryShardConfig {
+                max_offsets: Some(3),
+            },
+            None,
+            Arc::new(FileDescriptorManager::new(1)),
+        );
+
+        assert_eq!(shard.get_last_index(), -1);
+        assert_eq!(shard.insert_item(&[b"a", b"b"]).unwrap(), 1);
+        assert!(matches!(
+            shard.insert_item(&[b"c", b"d"]),
+            Err(ShardErrors::OutOfPositions)
+        ));
+        assert_eq!(shard.insert_item(&[b"c"]).unwrap(), 2);
+        assert!(!shard.has_space());
+
+        shard.delete_item(1).unwrap();
+        assert_eq!(shard.read_item_from_index(0).unwrap(), b"a".to_vec());
+        assert!(matches!(
+            shard.read_item_from_index(1),
+            Err(ShardErrors::DeletedItem)
+        ));
+        assert_eq!(shard.read_item_from_index(2).unwrap(), b"c".to_vec());
+        assert_eq!(&*shard.read_item_ref(2).unwrap(), b"c");
+        assert!(matches!(
+            shard.read_item_ref(1),
+            Err(ShardErrors::DeletedItem)
+        ));
+        assert!(matches!(
+            shard.read_item_from_index(3),
+            Err(ShardErrors::UnknownOffset)
+        ));
+    }
+
+    #[test]
+    pub fn test_memory_map_shard() {
+        // The folder does not exist, memory shards never touch it.
+        let mut map = MapShard::<MemoryShard, MemoryShardConfig>::new(
+            PathBuf::from("./test_cases/memory-map-shard"),
+            "data_",
+            MemoryShardConfig {
+                max_offsets: Some(2),
+            },
+            Arc::new(FileDescriptorManager::new(1)),
+        );
+
+        let positions = map.raw_insert_rows(&[b"0", b"1", b"2", b"3", b"4"], false);
+        assert_eq!(positions, vec![0, 1, 2, 3, 4]);
+        assert_eq!(map.past_master_shards.read().len(), 2);
+        assert_eq!(map.get_element(3).unwrap(), b"3".to_vec());
+
+        map.delete_element(3).unwrap();
+        assert_eq!(map.row_count(), 4);
+        assert!(!map.compact(0.0).unwrap());
+        assert!(!PathBuf::from("./test_cases/memory-map-shard").exists());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod data_shard;
 pub mod kv;
+pub mod memory;
+pub mod storage;
 
 pub const UUID_BYTE_LEN: u64 = 16;
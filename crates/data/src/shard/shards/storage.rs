@@ -0,0 +1,140 @@
+use crate::errors::ShardErrors;
+use crate::fdm::FileDescriptorManager;
+use crate::shard::item_ref::ItemRef;
+use crate::shard::shards::data_shard::config::{DataShardConfig, TempDataShardConfig};
+use crate::shard::shards::data_shard::shard::DataShard;
+use crate::shard::shards::memory::config::{MemoryShardConfig, TempMemoryShardConfig};
+use crate::shard::shards::memory::shard::MemoryShard;
+use crate::shard::{AvailableSpace, CompactableShard, Shard, ShardConfig, TempShardConfig};
+use enum_as_inner::EnumAsInner;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Shard of a table, kept in a file or in memory depending on the configuration it was created with.
+#[derive(Debug, EnumAsInner)]
+pub enum StorageShard {
+    File(Box<DataShard>),
+    Memory(MemoryShard),
+}
+
+#[derive(Clone, Debug)]
+pub enum StorageShardConfig {
+    File(Box<DataShardConfig>),
+    Memory(MemoryShardConfig),
+}
+
+impl ShardConfig for StorageShardConfig {}
+
+#[derive(Clone, Debug)]
+pub enum TempStorageShardConfig {
+    File(Box<TempDataShardConfig>),
+    Memory(TempMemoryShardConfig),
+}
+
+impl TempShardConfig<StorageShardConfig> for TempStorageShardConfig {
+    fn to_config(&self) -> StorageShardConfig {
+        match self {
+            TempStorageShardConfig::File(opts) => {
+                StorageShardConfig::File(Box::new(opts.to_config()))
+            }
+            TempStorageShardConfig::Memory(opts) => StorageShardConfig::Memory(opts.to_config()),
+        }
+    }
+}
+
+macro_rules! dispatch {
+    ($self:ident, $shard:ident => $call:expr) => {
+        match $self {
+            StorageShard::File($shard) => {
+                let $shard = $shard.as_ref();
+                $call
+            }
+            StorageShard::Memory($shard) => $call,
+        }
+    };
+}
+
+impl Shard<StorageShardConfig> for StorageShard {
+    fn new(
+        path: PathBuf,
+        opts: StorageShardConfig,
+        uuid: Option<Uuid>,
+        fdm: Arc<FileDescriptorManager>,
+    ) -> Self {
+        match opts {
+            StorageShardConfig::File(opts) => {
+                StorageShard::File(Box::new(DataShard::new(path, *opts, uuid, fdm)))
+            }
+            StorageShardConfig::Memory(opts) => {
+                StorageShard::Memory(MemoryShard::new(path, opts, uuid, fdm))
+            }
+        }
+    }
+
+    fn has_space(&self) -> bool {
+        dispatch!(self, shard => shard.has_space())
+    }
+
+    fn breaking_point(&self) -> Option<u64> {
+        dispatch!(self, shard => shard.breaking_point())
+    }
+
+    fn get_path(&self) -> PathBuf {
+        dispatch!(self, shard => shard.get_path())
+    }
+
+    fn get_last_index(&self) -> i64 {
+        dispatch!(self, shard => shard.get_last_index())
+    }
+
+    fn read_item_from_index(&self, index: usize) -> Result<Vec<u8>, ShardErrors> {
+        dispatch!(self, shard => shard.read_item_from_index(index))
+    }
+
+    fn read_item_ref(&self, index: usize) -> Result<ItemRef, ShardErrors> {
+        dispatch!(self, shard => Shard::read_item_ref(shard, index))
+    }
+
+    fn available_space(&self) -> AvailableSpace {
+        dispatch!(self, shard => shard.available_space())
+    }
+
+    fn insert_item(&self, data: &[&[u8]]) -> Result<u64, ShardErrors> {
+        dispatch!(self, shard => shard.insert_item(data))
+    }
+
+    fn delete_item(&self, index: usize) -> Result<(), ShardErrors> {
+        dispatch!(self, shard => shard.delete_item(index))
+    }
+
+    fn verify(&self) -> Result<(), ShardErrors> {
+        dispatch!(self, shard => shard.verify())
+    }
+
+    fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        dispatch!(self, shard => Shard::reencrypt(shard))
+    }
+
+    fn sync(&self) -> Result<(), ShardErrors> {
+        dispatch!(self, shard => Shard::sync(shard))
+    }
+
+    fn get_id(&self) -> String {
+        dispatch!(self, shard => shard.get_id())
+    }
+}
+
+impl CompactableShard<StorageShardConfig> for StorageShard {
+    fn deleted_items(&self) -> (usize, u64) {
+        dispatch!(self, shard => CompactableShard::deleted_items(shard))
+    }
+
+    fn data_size(&self) -> u64 {
+        dispatch!(self, shard => CompactableShard::data_size(shard))
+    }
+
+    fn write_compacted(&self, path: &Path) -> Result<(), ShardErrors> {
+        dispatch!(self, shard => CompactableShard::write_compacted(shard, path))
+    }
+}
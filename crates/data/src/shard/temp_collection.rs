@@ -1,8 +1,11 @@
 use crate::errors::ShardErrors;
 use crate::fdm::FileDescriptorManager;
+use crate::reconcile_policy::{PendingRows, ReconcilePolicy};
 use crate::shard::map_shard::MapShard;
-use crate::shard::temp_map_shard::TempMapShard;
+use crate::shard::temp_map_shard::{DataWithIndex, TempMapShard};
 use crate::shard::{Shard, ShardConfig, TempShardConfig};
+use crate::utils::fs::list_files_with_prefix;
+use crate::wal::WriteAheadLog;
 use parking_lot::RwLock;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -13,6 +16,7 @@ pub struct TempCollection<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShard
     pub target_shard: Arc<RwLock<MapShard<S, Opts>>>,
     pub temps: Arc<Vec<RwLock<TempMapShard<S, Opts, TempOpts>>>>,
     counter: AtomicUsize,
+    wal: Option<Arc<WriteAheadLog>>,
 }
 
 impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
@@ -25,7 +29,18 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
         prefix: &str,
         temp_config: TempOpts,
         fdm: Arc<FileDescriptorManager>,
+        wal: Option<Arc<WriteAheadLog>>,
     ) -> Self {
+        if wal.is_some() {
+            // Temporary shards are never reopened. Whatever they held before a restart
+            // is still pending in the write-ahead log and gets replayed through `recover`.
+            if let Ok(stale_shards) = list_files_with_prefix(&folder, prefix) {
+                for stale_shard in stale_shards {
+                    let _ = std::fs::remove_file(stale_shard);
+                }
+            }
+        }
+
         let mut temps = vec![];
 
         for _ in 0..capacity {
@@ -35,6 +50,7 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
                 target_shard.clone(),
                 temp_config.clone(),
                 fdm.clone(),
+                wal.clone(),
             )));
         }
 
@@ -42,6 +58,7 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
             target_shard,
             temps: Arc::new(temps),
             counter: AtomicUsize::new(0),
+            wal,
         }
     }
 
@@ -50,10 +67,67 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
         &self.temps[index]
     }
 
-    pub fn reconcile_all(&self) {
+    /// Reconciles every temporary shard. A shard failing to reconcile does not stop the rest,
+    /// the first error found is returned once they are all done.
+    pub fn reconcile_all(&self) -> Result<(), ShardErrors> {
+        let mut result = Ok(());
+        for temp in self.temps.iter() {
+            let reconciled = temp.write().reconcile_all();
+            if result.is_ok() {
+                result = reconciled;
+            }
+        }
+
+        result
+    }
+
+    /// Rows inserted across all the temporary shards that have not been reconciled yet.
+    pub fn pending_rows(&self) -> PendingRows {
+        let mut pending = PendingRows::default();
+        for temp in self.temps.iter() {
+            pending.merge(&temp.read().pending_rows());
+        }
+
+        pending
+    }
+
+    /// Reconciles every temporary shard if the pending rows reached any of the thresholds of `policy`,
+    /// returning whether it did.
+    pub fn reconcile_if_due(&self, policy: &ReconcilePolicy) -> Result<bool, ShardErrors> {
+        if !policy.is_due(&self.pending_rows()) {
+            return Ok(false);
+        }
+
+        self.reconcile_all()?;
+        Ok(true)
+    }
+
+    /// Verifies the temporary shards, returning the path of the damaged ones along with the error found.
+    pub fn verify(&self) -> Vec<(PathBuf, ShardErrors)> {
+        let mut damaged = vec![];
         for temp in self.temps.iter() {
-            temp.write().reconcile_all()
+            for shard in temp.read().temp_shards.iter() {
+                if let Err(e) = shard.verify() {
+                    damaged.push((shard.get_path(), e));
+                }
+            }
         }
+
+        damaged
+    }
+
+    /// Rewrites the first temporary shard written with an encryption key other than the current one,
+    /// returning whether it did.
+    pub fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        for temp in self.temps.iter() {
+            for shard in temp.read().temp_shards.iter() {
+                if shard.reencrypt()? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
     }
 
     pub fn insert(&self, data: &[&[u8]]) -> Result<u64, ShardErrors> {
@@ -61,4 +135,246 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
 
         next_shard.raw_insert_rows(data)
     }
+
+    /// Replays the rows that were logged but never reconciled (e.g. because the process was killed)
+    /// and reconciles them into the target shard.
+    /// Rows a reconciliation that was cut short already moved into the target shard are not inserted again,
+    /// they are only handed to the reconciliation callbacks.
+    /// It must be called once the reconciliation callbacks are set, so indexes are rebuilt as well.
+    pub fn recover(&self) -> Result<usize, ShardErrors> {
+        let wal = match &self.wal {
+            None => return Ok(0),
+            Some(wal) => wal,
+        };
+
+        let pending = wal.pending()?;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        let replayed = pending.len();
+
+        let mut reconciled = vec![];
+        let mut reconciled_seqs = vec![];
+        let mut unreconciled = vec![];
+        {
+            let target = self.target_shard.read();
+            for record in pending {
+                let Some(position) = record.position else {
+                    unreconciled.push(record);
+                    continue;
+                };
+
+                match target.get_element(position as usize) {
+                    Ok(row) if row == record.data => {
+                        reconciled_seqs.push(record.seq);
+                        reconciled.push(DataWithIndex {
+                            data: record.data,
+                            index: position,
+                        });
+                    }
+                    // Deleted after it was reconciled, there is nothing left to process.
+                    Err(ShardErrors::DeletedItem) => reconciled_seqs.push(record.seq),
+                    _ => unreconciled.push(record),
+                }
+            }
+        }
+
+        {
+            let mut temp_shard = self.get_next_shard().write();
+            temp_shard.finish_reconciled(reconciled, &reconciled_seqs)?;
+            for record in unreconciled.iter() {
+                temp_shard.insert_logged_rows(&[record.data.as_slice()], vec![record.seq])?;
+            }
+        }
+
+        self.reconcile_all()?;
+
+        Ok(replayed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compression::Compression;
+    use crate::durability::Durability;
+    use crate::encryption::Keyring;
+    use crate::errors::ShardErrors;
+    use crate::fdm::FileDescriptorManager;
+    use crate::reconcile_policy::ReconcilePolicy;
+    use crate::shard::map_shard::MapShard;
+    use crate::shard::shards::data_shard::config::{DataShardConfig, TempDataShardConfig};
+    use crate::shard::shards::data_shard::shard::DataShard;
+    use crate::shard::temp_collection::TempCollection;
+    use crate::shard::Shard;
+    use crate::temp_offset_types::TempOffsetTypes;
+    use crate::wal::WriteAheadLog;
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn create_collection(
+        folder: std::path::PathBuf,
+        fdm: Arc<FileDescriptorManager>,
+    ) -> TempCollection<DataShard, DataShardConfig, TempDataShardConfig> {
+        let master = MapShard::<DataShard, DataShardConfig>::new(
+            folder.clone(),
+            "data_",
+            DataShardConfig {
+                max_offsets: None,
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
+            },
+            fdm.clone(),
+        );
+
+        let temps_folder = folder.join("temps");
+        if !temps_folder.exists() {
+            std::fs::create_dir(&temps_folder).unwrap();
+        }
+
+        TempCollection::new(
+            Arc::new(RwLock::new(master)),
+            2,
+            temps_folder,
+            "temp_",
+            TempDataShardConfig {
+                max_offsets: TempOffsetTypes::Custom(Some(10)),
+                durability: Durability::None,
+                keyring: Keyring::default(),
+            },
+            fdm.clone(),
+            Some(Arc::new(
+                WriteAheadLog::new(folder.join("table.wal"), Durability::Strict, None, fdm)
+                    .unwrap(),
+            )),
+        )
+    }
+
+    #[tokio::test]
+    pub async fn test_recover_unreconciled_rows() {
+        let temp_dir = tempdir().unwrap();
+        let folder = temp_dir.path().to_path_buf();
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+
+        {
+            let collection = create_collection(folder.clone(), fdm.clone());
+            collection.insert(&[b"0:Hello world"]).unwrap();
+            collection.insert(&[b"1:Hello Cats"]).unwrap();
+            // Dropped without reconciling, as if the process was killed.
+        }
+
+        let collection = create_collection(folder.clone(), fdm.clone());
+        assert_eq!(
+            collection
+                .target_shard
+                .read()
+                .current_master_shard
+                .get_last_index(),
+            -1
+        );
+
+        assert_eq!(collection.recover().unwrap(), 2);
+
+        let master = collection.target_shard.read();
+        let mut items = vec![
+            master.get_element(0).unwrap(),
+            master.get_element(1).unwrap(),
+        ];
+        items.sort();
+        assert_eq!(
+            items,
+            vec![b"0:Hello world".to_vec(), b"1:Hello Cats".to_vec()]
+        );
+
+        // Everything was reconciled, nothing is left to replay.
+        assert_eq!(collection.recover().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_recover_rows_already_reconciled() {
+        let temp_dir = tempdir().unwrap();
+        let folder = temp_dir.path().to_path_buf();
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+        let rows = vec![b"0:Hello world".to_vec(), b"1:Hello Cats".to_vec()];
+
+        {
+            let collection = create_collection(folder.clone(), fdm.clone());
+            for temp in collection.temps.iter() {
+                temp.write().set_on_reconcile(Box::new(|_| Err(())));
+            }
+            for row in rows.iter() {
+                collection.insert(&[row.as_slice()]).unwrap();
+            }
+
+            // The rows reach the master shard but are not checkpointed, since the callback failed
+            assert!(matches!(
+                collection.reconcile_all(),
+                Err(ShardErrors::ReconcileError)
+            ));
+            assert_eq!(collection.target_shard.read().len(), 2);
+            assert_eq!(collection.pending_rows().rows, 0);
+            collection.reconcile_all().unwrap();
+            assert_eq!(collection.target_shard.read().len(), 2);
+        }
+
+        let collection = create_collection(folder.clone(), fdm.clone());
+        let reconciled = Arc::new(parking_lot::Mutex::new(vec![]));
+        for temp in collection.temps.iter() {
+            let reconciled = reconciled.clone();
+            temp.write().set_on_reconcile(Box::new(move |items| {
+                reconciled
+                    .lock()
+                    .extend(items.into_iter().map(|item| (item.index, item.data)));
+                Ok(())
+            }));
+        }
+
+        // Replaying them hands them to the callback again, without inserting them twice
+        assert_eq!(collection.recover().unwrap(), 2);
+        assert_eq!(collection.target_shard.read().len(), 2);
+        let mut reconciled = reconciled.lock().clone();
+        reconciled.sort();
+        let master = collection.target_shard.read();
+        for (position, row) in reconciled.iter() {
+            assert_eq!(master.get_element(*position as usize).unwrap(), *row);
+        }
+        let mut reconciled_rows: Vec<Vec<u8>> =
+            reconciled.into_iter().map(|(_, row)| row).collect();
+        reconciled_rows.sort();
+        assert_eq!(reconciled_rows, rows);
+        drop(master);
+
+        assert_eq!(collection.recover().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_reconcile_if_due() {
+        let temp_dir = tempdir().unwrap();
+        let folder = temp_dir.path().to_path_buf();
+        let collection = create_collection(folder, Arc::new(FileDescriptorManager::new(2500)));
+        let policy = ReconcilePolicy {
+            max_rows: 3,
+            max_bytes: 0,
+            max_age: Duration::ZERO,
+        };
+        let reconciled_rows = || collection.target_shard.read().row_count();
+
+        // Nothing to reconcile
+        assert!(!collection.reconcile_if_due(&policy).unwrap());
+
+        collection.insert(&[b"0:Hello world"]).unwrap();
+        collection.insert(&[b"1:Hello Cats"]).unwrap();
+        assert_eq!(collection.pending_rows().rows, 2);
+        assert_eq!(collection.pending_rows().bytes, 25);
+        assert!(!collection.reconcile_if_due(&policy).unwrap());
+        assert_eq!(reconciled_rows(), 0);
+
+        collection.insert(&[b"2:Hello Dogs"]).unwrap();
+        assert!(collection.reconcile_if_due(&policy).unwrap());
+        assert_eq!(reconciled_rows(), 3);
+        assert_eq!(collection.pending_rows().rows, 0);
+        assert!(collection.pending_rows().oldest.is_none());
+    }
 }
@@ -1,7 +1,9 @@
 use crate::errors::ShardErrors;
 use crate::fdm::FileDescriptorManager;
+use crate::reconcile_policy::PendingRows;
 use crate::shard::map_shard::MapShard;
 use crate::shard::{Shard, ShardConfig, TempShardConfig};
+use crate::wal::WriteAheadLog;
 use parking_lot::RwLock;
 use std::fmt::{Debug, Formatter};
 use std::ops::Range;
@@ -30,9 +32,14 @@ pub struct TempMapShard<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardCo
     prefix: String,
     parent_shard: Arc<RwLock<MapShard<S, Opts>>>,
     pub temp_shards: Vec<S>,
+    // Write-ahead log sequence numbers of the rows held by each shard in `temp_shards`.
+    logged_seqs: Vec<Vec<u64>>,
+    // Rows waiting in each shard in `temp_shards` to be reconciled.
+    pending: Vec<PendingRows>,
     temp_opts: TempOpts,
     on_reconcile: OnReconcileCb,
     fdm: Arc<FileDescriptorManager>,
+    wal: Option<Arc<WriteAheadLog>>,
 }
 
 impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
@@ -44,15 +51,19 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
         parent_shard: Arc<RwLock<MapShard<S, Opts>>>,
         temp_opts: TempOpts,
         fdm: Arc<FileDescriptorManager>,
+        wal: Option<Arc<WriteAheadLog>>,
     ) -> Self {
         TempMapShard {
             parent_shard,
             folder,
             prefix: prefix.to_string(),
             temp_shards: vec![],
+            logged_seqs: vec![],
+            pending: vec![],
             temp_opts,
             on_reconcile: OnReconcileCb { func: None },
             fdm,
+            wal,
         }
     }
 
@@ -77,24 +88,45 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
     }
 
     pub fn raw_insert_rows(&mut self, data: &[&[u8]]) -> Result<u64, ShardErrors> {
+        // Rows are logged before they are acknowledged so they survive a crash before reconciliation.
+        let seqs = match &self.wal {
+            None => vec![],
+            Some(wal) => wal.append(data)?,
+        };
+
+        self.insert_logged_rows(data, seqs)
+    }
+
+    pub(crate) fn insert_logged_rows(
+        &mut self,
+        data: &[&[u8]],
+        seqs: Vec<u64>,
+    ) -> Result<u64, ShardErrors> {
         let find_usable_shard = { self.temp_shards.iter().position(|i| i.has_space()) };
 
         let shard_index = match find_usable_shard {
             None => {
-                self.reconcile_specific(None);
+                self.reconcile_specific(None)?;
                 let shard = self.create_shard();
                 self.temp_shards.push(shard);
+                self.logged_seqs.push(vec![]);
+                self.pending.push(PendingRows::default());
                 self.temp_shards.len() - 1
             }
             Some(shard) => shard,
         };
 
-        {
+        let inserted = {
             self.temp_shards
                 .get(shard_index)
                 .ok_or(ShardErrors::UnknownShard)?
-                .insert_item(data)
-        }
+                .insert_item(data)?
+        };
+
+        self.logged_seqs[shard_index].extend(seqs);
+        self.pending[shard_index].record(data);
+
+        Ok(inserted)
     }
 
     fn get_reconciliation_data(shard: &S) -> (&S, Range<i64>) {
@@ -110,65 +142,166 @@ impl<S: Shard<Opts>, Opts: ShardConfig, TempOpts: TempShardConfig<Opts>>
         (shard, indexes)
     }
 
+    /// Reads the rows held by the temporary shards, which have not made it to the parent shard yet.
+    pub fn unreconciled_rows(&self) -> Result<Vec<Vec<u8>>, ShardErrors> {
+        let mut rows = vec![];
+        for shard in self.temp_shards.iter() {
+            let (shard, indexes) = Self::get_reconciliation_data(shard);
+            for item_index in indexes {
+                match shard.read_item_from_index(item_index as usize) {
+                    Ok(row) => rows.push(row),
+                    Err(ShardErrors::DeletedItem) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// Rows inserted in the temporary shards since they were last reconciled.
+    pub fn pending_rows(&self) -> PendingRows {
+        let mut pending = PendingRows::default();
+        for shard_pending in self.pending.iter() {
+            pending.merge(shard_pending);
+        }
+
+        pending
+    }
+
     // Maybe async?
-    fn call_on_reconcile(&self, data: Vec<DataWithIndex>) -> Result<(), ()> {
+    fn call_on_reconcile(&self, data: Vec<DataWithIndex>) -> Result<(), ShardErrors> {
         match &self.on_reconcile.func {
             None => Ok(()),
-            Some(cb) => cb(data),
+            Some(cb) => cb(data).map_err(|_| ShardErrors::ReconcileError),
         }
     }
 
-    fn reconcile(&self, from: &S, target: &mut MapShard<S, Opts>) {
-        let (shard, indexes) = Self::get_reconciliation_data(from);
-        let mut reconciling_items = vec![];
-        // TODO: What if the row is inserted `target.insert_rows` but, the reconciling (call_on_reconcile) fails?
-        for item_index in indexes {
-            let binary_item = shard.read_item_from_index(item_index as usize).unwrap();
-            let pos = target.insert_rows(&[&binary_item]);
-            reconciling_items.push(DataWithIndex {
-                data: binary_item,
-                index: pos as u64,
-            });
+    /// Moves the rows of the temporary shards at `shard_positions` into the parent shard with a single bulk insert,
+    /// and removes those shards.
+    ///
+    /// When rows are logged, the positions they are about to take are logged first, and the parent shard is synced
+    /// before they are checkpointed. Once the rows are in the parent shard their temporary shards are removed even if
+    /// something fails afterwards: the rows are left un-checkpointed, so `TempCollection::recover` finishes
+    /// their reconciliation when the table is opened again without inserting them twice.
+    fn reconcile(&mut self, shard_positions: &[usize]) -> Result<(), ShardErrors> {
+        let mut rows = vec![];
+        let mut dropped_seqs = vec![];
+        for &shard_position in shard_positions {
+            let (shard, indexes) = Self::get_reconciliation_data(&self.temp_shards[shard_position]);
+            let logged_seqs = &self.logged_seqs[shard_position];
+            for item_index in indexes {
+                let seq = logged_seqs.get(item_index as usize).copied();
+                match shard.read_item_from_index(item_index as usize) {
+                    Ok(row) => rows.push((row, seq)),
+                    // Rows deleted before being reconciled only need to be checkpointed.
+                    Err(ShardErrors::DeletedItem) => dropped_seqs.extend(seq),
+                    Err(e) => return Err(e),
+                }
+            }
         }
-        self.call_on_reconcile(reconciling_items).unwrap();
+
+        let parent_shard = self.parent_shard.clone();
+        let mut target = parent_shard.write();
+
+        // Rows take consecutive positions from the end of the parent shard.
+        let first_position = target.len();
+        if let Some(wal) = &self.wal {
+            let moves: Vec<(u64, u64)> = rows
+                .iter()
+                .zip(first_position as u64..)
+                .filter_map(|((_, seq), position)| seq.map(|seq| (seq, position)))
+                .collect();
+            wal.log_reconcile(&moves)?;
+        }
+
+        let data: Vec<&[u8]> = rows.iter().map(|(row, _)| row.as_slice()).collect();
+        let positions = target.raw_insert_rows(&data, false);
+        self.remove_shards(shard_positions);
+
+        // Whatever the durability of the parent shard, its rows must be on disk before they leave the log.
+        let synced = match self.wal {
+            None => Ok(()),
+            Some(_) => target.sync_from(first_position),
+        };
+
+        let mut seqs = dropped_seqs;
+        let reconciling_items = rows
+            .into_iter()
+            .zip(positions)
+            .map(|((data, seq), pos)| {
+                seqs.extend(seq);
+                DataWithIndex {
+                    data,
+                    index: pos as u64,
+                }
+            })
+            .collect();
+        self.call_on_reconcile(reconciling_items)?;
+        synced?;
+        self.checkpoint(&seqs)
+    }
+
+    /// Hands rows a reconciliation cut short already moved into the parent shard to the reconciliation callback again,
+    /// since it may not have run for them, and checkpoints them along with `seqs`.
+    pub(crate) fn finish_reconciled(
+        &self,
+        rows: Vec<DataWithIndex>,
+        seqs: &[u64],
+    ) -> Result<(), ShardErrors> {
+        if seqs.is_empty() {
+            return Ok(());
+        }
+
+        self.call_on_reconcile(rows)?;
+        self.checkpoint(seqs)
     }
 
-    pub fn reconcile_all(&mut self) {
-        let mut parent_writer = self.parent_shard.write();
+    fn remove_shards(&mut self, shard_positions: &[usize]) {
+        let mut shard_positions = shard_positions.to_vec();
+        shard_positions.sort_unstable();
 
-        for from_shard in self.temp_shards.iter() {
-            self.reconcile(from_shard, &mut parent_writer);
+        let mut paths = vec![];
+        for shard_position in shard_positions.into_iter().rev() {
+            paths.push(self.temp_shards.remove(shard_position).get_path());
+            self.logged_seqs.remove(shard_position);
+            self.pending.remove(shard_position);
         }
 
-        let paths: Vec<PathBuf> = self.temp_shards.iter().map(|i| i.get_path()).collect();
         self.fdm.remove_paths(paths);
+    }
 
-        self.temp_shards.clear()
+    fn checkpoint(&self, seqs: &[u64]) -> Result<(), ShardErrors> {
+        match &self.wal {
+            None => Ok(()),
+            Some(wal) => wal.checkpoint(seqs),
+        }
     }
 
-    pub fn reconcile_specific(&mut self, shard_position: Option<usize>) {
-        let pos = {
-            let index = shard_position.or_else(|| self.temp_shards.len().checked_sub(1));
-
-            if let Some(index) = index {
-                if let Some(shard) = self.temp_shards.get(index) {
-                    let mut parent_shard = self.parent_shard.write();
-                    self.reconcile(shard, &mut parent_shard);
-                    index
-                } else {
-                    return;
-                }
-            } else {
-                return;
-            }
-        };
+    pub fn reconcile_all(&mut self) -> Result<(), ShardErrors> {
+        let shard_positions: Vec<usize> = (0..self.temp_shards.len()).collect();
+        if shard_positions.is_empty() {
+            return Ok(());
+        }
+
+        self.reconcile(&shard_positions)
+    }
 
-        self.temp_shards.remove(pos);
+    pub fn reconcile_specific(&mut self, shard_position: Option<usize>) -> Result<(), ShardErrors> {
+        let index = shard_position.or_else(|| self.temp_shards.len().checked_sub(1));
+
+        match index {
+            Some(index) if index < self.temp_shards.len() => self.reconcile(&[index]),
+            _ => Ok(()),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::compression::Compression;
+    use crate::durability::Durability;
+    use crate::encryption::Keyring;
     use crate::fdm::FileDescriptorManager;
     use crate::shard::map_shard::MapShard;
     use crate::shard::shards::data_shard::config::{DataShardConfig, TempDataShardConfig};
@@ -195,7 +328,12 @@ mod test {
         let ctx = MapShard::<DataShard, DataShardConfig>::new(
             data_path.clone(),
             "localdata_",
-            DataShardConfig { max_offsets: None },
+            DataShardConfig {
+                max_offsets: None,
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
+            },
             Arc::new(FileDescriptorManager::new(2500)),
         );
 
@@ -207,8 +345,11 @@ mod test {
             parent_shard.clone(),
             TempDataShardConfig {
                 max_offsets: TempOffsetTypes::Custom(Some(2)),
+                durability: Durability::None,
+                keyring: Keyring::default(),
             },
             Arc::new(FileDescriptorManager::new(2500)),
+            None,
         );
 
         shard
@@ -280,4 +421,68 @@ mod test {
 
         std::fs::remove_dir_all(data_path).unwrap()
     }
+
+    #[tokio::test]
+    pub async fn test_reconcile_across_shards() {
+        let data_path = tempfile::tempdir().unwrap();
+        let data_path = data_path.path().to_path_buf();
+        let fdm = Arc::new(FileDescriptorManager::new(2500));
+
+        let parent_shard = Arc::new(RwLock::new(MapShard::<DataShard, DataShardConfig>::new(
+            data_path.clone(),
+            "localdata_",
+            DataShardConfig {
+                max_offsets: Some(2),
+                durability: Durability::None,
+                compression: Compression::None,
+                keyring: Keyring::default(),
+            },
+            fdm.clone(),
+        )));
+
+        let mut shard = TempMapShard::<DataShard, DataShardConfig, TempDataShardConfig>::new(
+            data_path,
+            "tempdata_",
+            parent_shard.clone(),
+            TempDataShardConfig {
+                max_offsets: TempOffsetTypes::Custom(Some(3)),
+                durability: Durability::None,
+                keyring: Keyring::default(),
+            },
+            fdm,
+            None,
+        );
+
+        let reconciled = Arc::new(parking_lot::Mutex::new(vec![]));
+        {
+            let reconciled = reconciled.clone();
+            shard.set_on_reconcile(Box::new(move |items| {
+                reconciled
+                    .lock()
+                    .extend(items.into_iter().map(|item| (item.index, item.data)));
+                Ok(())
+            }));
+        }
+
+        let rows: Vec<Vec<u8>> = (0..5).map(|i| format!("row {}", i).into_bytes()).collect();
+        for row in rows.iter() {
+            shard.raw_insert_rows(&[row.as_slice()]).unwrap();
+        }
+        shard.reconcile_all().unwrap();
+
+        // Every row gets its own position even though they were spread over three parent shards at once
+        let expected: Vec<(u64, Vec<u8>)> = rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, row)| (i as u64, row))
+            .collect();
+        assert_eq!(*reconciled.lock(), expected);
+        assert_eq!(parent_shard.read().past_master_shards.read().len(), 2);
+        for (position, row) in expected {
+            assert_eq!(
+                parent_shard.read().get_element(position as usize).unwrap(),
+                row
+            );
+        }
+    }
 }
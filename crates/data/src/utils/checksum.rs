@@ -0,0 +1,32 @@
+use crate::errors::ShardErrors;
+
+pub const CHECKSUM_SIZE: usize = size_of::<u32>();
+
+pub fn checksum(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+/// Returns `data` followed by its checksum.
+pub fn with_checksum(data: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(data.len() + CHECKSUM_SIZE);
+    buffer.extend_from_slice(data);
+    buffer.extend_from_slice(&checksum(data).to_le_bytes());
+    buffer
+}
+
+/// Strips the checksum of an item written through `with_checksum`,
+/// failing with `ShardErrors::Corrupted` if it does not match the item.
+pub fn verify_checksum(stored: &[u8]) -> Result<&[u8], ShardErrors> {
+    if stored.len() < CHECKSUM_SIZE {
+        return Err(ShardErrors::Corrupted);
+    }
+
+    let (data, stored_checksum) = stored.split_at(stored.len() - CHECKSUM_SIZE);
+    let stored_checksum = u32::from_le_bytes(stored_checksum.try_into().unwrap());
+
+    if checksum(data) == stored_checksum {
+        Ok(data)
+    } else {
+        Err(ShardErrors::Corrupted)
+    }
+}
@@ -1,3 +1,4 @@
+pub mod checksum;
 pub mod fs;
 pub mod hash;
 
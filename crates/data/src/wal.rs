@@ -0,0 +1,448 @@
+use crate::durability::Durability;
+use crate::encryption::{KeyId, Keyring, NO_KEY};
+use crate::errors::ShardErrors;
+use crate::fdm::FileDescriptorManager;
+use crate::U64_SIZE;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const WAL_INSERT_RECORD: u8 = 1;
+const WAL_CHECKPOINT_RECORD: u8 = 2;
+// Insert whose payload is the id of the key it was encrypted with followed by the encrypted row
+const WAL_ENCRYPTED_INSERT_RECORD: u8 = 3;
+// Positions logged rows are about to take in the master shard, as pairs of sequence number and position
+const WAL_RECONCILE_RECORD: u8 = 4;
+
+// Record kind + sequence number + payload length
+const WAL_RECORD_HEADER_SIZE: usize = 1 + U64_SIZE + U64_SIZE;
+
+// Record kind, sequence number and payload
+type RawWalRecord<'a> = (u8, u64, &'a [u8]);
+
+/// An insert that was logged but has not been reconciled into the master shard yet.
+#[derive(Debug, Clone)]
+pub struct WalRecord {
+    pub seq: u64,
+    pub data: Vec<u8>,
+    /// Position the row was given in the master shard by a reconciliation that did not finish.
+    /// The row may or may not have made it there.
+    pub position: Option<u64>,
+}
+
+#[derive(Debug)]
+struct WalState {
+    file: File,
+    next_seq: u64,
+    live: HashSet<u64>,
+    positions: HashMap<u64, u64>,
+}
+
+/// Append-only log of every row handed to the temporary shards of a table.
+///
+/// Each insert is written here before it is acknowledged, and synced according to `durability`. Once the temporary
+/// shard holding a row is reconciled into the master shard, its sequence numbers are
+/// checkpointed. Whatever is left un-checkpointed when the log is opened again is what
+/// needs to be replayed. The positions rows take in the master shard are logged before they are written there,
+/// so replaying a reconciliation that was cut short does not insert them twice.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    pub path: PathBuf,
+    state: Mutex<WalState>,
+    durability: Durability,
+    keyring: Keyring,
+    fdm: Arc<FileDescriptorManager>,
+}
+
+impl WriteAheadLog {
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        durability: Durability,
+        keyring: Option<Keyring>,
+        fdm: Arc<FileDescriptorManager>,
+    ) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+
+        let mut bytes = vec![];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut bytes)?;
+
+        let (records, valid_len) = Self::parse_records(&bytes);
+
+        let mut next_seq = 0;
+        let mut live = HashSet::new();
+        let mut positions = HashMap::new();
+
+        for (kind, seq, payload) in records {
+            match kind {
+                WAL_INSERT_RECORD | WAL_ENCRYPTED_INSERT_RECORD => {
+                    live.insert(seq);
+                    next_seq = next_seq.max(seq + 1);
+                }
+                WAL_RECONCILE_RECORD => {
+                    for pair in Self::decode_seqs(payload).chunks_exact(2) {
+                        positions.insert(pair[0], pair[1]);
+                    }
+                }
+                WAL_CHECKPOINT_RECORD => {
+                    for reconciled in Self::decode_seqs(payload) {
+                        live.remove(&reconciled);
+                        positions.remove(&reconciled);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if live.is_empty() {
+            // Nothing to replay, start from a clean log.
+            file.set_len(0)?;
+        } else if valid_len < bytes.len() {
+            // Drop a record that was torn by a crash in the middle of a write.
+            file.set_len(valid_len as u64)?;
+        }
+
+        Ok(Self {
+            path,
+            state: Mutex::new(WalState {
+                file,
+                next_seq,
+                live,
+                positions,
+            }),
+            durability,
+            keyring: keyring.unwrap_or_default(),
+            fdm,
+        })
+    }
+
+    fn sync(&self, file: &File) -> std::io::Result<()> {
+        match self.durability {
+            Durability::None => Ok(()),
+            Durability::Batch(interval) => {
+                self.fdm.mark_dirty(&self.path, interval);
+                Ok(())
+            }
+            Durability::Strict => file.sync_data(),
+        }
+    }
+
+    /// Returns the complete records in `bytes` and the length of the valid prefix.
+    fn parse_records(bytes: &[u8]) -> (Vec<RawWalRecord<'_>>, usize) {
+        let mut records = vec![];
+        let mut pos = 0;
+
+        while let Some(header) = bytes.get(pos..pos + WAL_RECORD_HEADER_SIZE) {
+            let kind = header[0];
+            let seq = u64::from_le_bytes(header[1..1 + U64_SIZE].try_into().unwrap());
+            let len = u64::from_le_bytes(header[1 + U64_SIZE..].try_into().unwrap()) as usize;
+
+            let payload_start = pos + WAL_RECORD_HEADER_SIZE;
+            match bytes.get(payload_start..payload_start + len) {
+                Some(payload) => {
+                    records.push((kind, seq, payload));
+                    pos = payload_start + len;
+                }
+                None => break,
+            }
+        }
+
+        (records, pos)
+    }
+
+    fn decode_seqs(payload: &[u8]) -> Vec<u64> {
+        payload
+            .chunks_exact(U64_SIZE)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    fn encode_record(buffer: &mut Vec<u8>, kind: u8, seq: u64, payload: &[u8]) {
+        buffer.push(kind);
+        buffer.extend_from_slice(&seq.to_le_bytes());
+        buffer.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(payload);
+    }
+
+    /// Logs `data` and returns the sequence number assigned to each item.
+    pub fn append(&self, data: &[&[u8]]) -> Result<Vec<u64>, ShardErrors> {
+        let mut state = self.state.lock();
+        let mut buffer = vec![];
+        let mut seqs = Vec::with_capacity(data.len());
+        let encryption_key = self.keyring.current_id();
+
+        for item in data {
+            let seq = state.next_seq + seqs.len() as u64;
+            if encryption_key == NO_KEY {
+                Self::encode_record(&mut buffer, WAL_INSERT_RECORD, seq, item);
+            } else {
+                let mut payload = encryption_key.to_le_bytes().to_vec();
+                payload.extend_from_slice(&self.keyring.encrypt(encryption_key, item)?);
+                Self::encode_record(&mut buffer, WAL_ENCRYPTED_INSERT_RECORD, seq, &payload);
+            }
+            seqs.push(seq);
+        }
+
+        state
+            .file
+            .write_all(&buffer)
+            .and_then(|_| self.sync(&state.file))
+            .map_err(|_| ShardErrors::WalError)?;
+
+        state.next_seq += seqs.len() as u64;
+        state.live.extend(seqs.iter());
+
+        Ok(seqs)
+    }
+
+    /// Logs the positions the rows logged as `(seq, position)` in `moves` are about to take in the master shard.
+    /// It is synced whatever the durability of the log, since it must reach disk before the rows do.
+    pub fn log_reconcile(&self, moves: &[(u64, u64)]) -> Result<(), ShardErrors> {
+        if moves.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock();
+        let payload: Vec<u8> = moves
+            .iter()
+            .flat_map(|(seq, position)| [seq.to_le_bytes(), position.to_le_bytes()])
+            .flatten()
+            .collect();
+        let mut buffer = Vec::with_capacity(WAL_RECORD_HEADER_SIZE + payload.len());
+        Self::encode_record(&mut buffer, WAL_RECONCILE_RECORD, 0, &payload);
+
+        state
+            .file
+            .write_all(&buffer)
+            .and_then(|_| state.file.sync_data())
+            .map_err(|_| ShardErrors::WalError)?;
+
+        state.positions.extend(moves.iter().copied());
+
+        Ok(())
+    }
+
+    /// Marks `seqs` as reconciled. When no logged row is pending anymore the log is truncated.
+    pub fn checkpoint(&self, seqs: &[u64]) -> Result<(), ShardErrors> {
+        if seqs.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock();
+
+        for seq in seqs {
+            state.live.remove(seq);
+            state.positions.remove(seq);
+        }
+
+        let op = if state.live.is_empty() {
+            state.file.set_len(0)
+        } else {
+            let payload: Vec<u8> = seqs.iter().flat_map(|seq| seq.to_le_bytes()).collect();
+            let mut buffer = Vec::with_capacity(WAL_RECORD_HEADER_SIZE + payload.len());
+            Self::encode_record(&mut buffer, WAL_CHECKPOINT_RECORD, 0, &payload);
+            state.file.write_all(&buffer)
+        };
+
+        op.and_then(|_| self.sync(&state.file))
+            .map_err(|_| ShardErrors::WalError)
+    }
+
+    /// Reads every logged insert that has not been checkpointed yet, in insertion order.
+    pub fn pending(&self) -> Result<Vec<WalRecord>, ShardErrors> {
+        let mut state = self.state.lock();
+        if state.live.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut bytes = vec![];
+        state
+            .file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| state.file.read_to_end(&mut bytes))
+            .map_err(|_| ShardErrors::WalError)?;
+
+        let (records, _) = Self::parse_records(&bytes);
+        let mut pending = records
+            .into_iter()
+            .filter(|(_, seq, _)| state.live.contains(seq))
+            .filter_map(|(kind, seq, payload)| {
+                let position = state.positions.get(&seq).copied();
+                match kind {
+                    WAL_INSERT_RECORD => Some(Ok(WalRecord {
+                        seq,
+                        data: payload.to_vec(),
+                        position,
+                    })),
+                    WAL_ENCRYPTED_INSERT_RECORD => {
+                        Some(self.decrypt_payload(payload).map(|data| WalRecord {
+                            seq,
+                            data,
+                            position,
+                        }))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        pending.sort_by_key(|record| record.seq);
+
+        Ok(pending)
+    }
+
+    fn decrypt_payload(&self, payload: &[u8]) -> Result<Vec<u8>, ShardErrors> {
+        if payload.len() < U64_SIZE {
+            return Err(ShardErrors::WalError);
+        }
+
+        let (key, data) = payload.split_at(U64_SIZE);
+        let key = KeyId::from_le_bytes(key.try_into().unwrap());
+        self.keyring.decrypt(key, data.to_vec())
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.state.lock().live.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::durability::Durability;
+    use crate::encryption::{EncryptionKey, Keyring, KEY_SIZE};
+    use crate::fdm::FileDescriptorManager;
+    use crate::wal::WriteAheadLog;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn open_wal(path: &Path) -> WriteAheadLog {
+        WriteAheadLog::new(
+            path,
+            Durability::Strict,
+            None,
+            Arc::new(FileDescriptorManager::new(2500)),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    pub async fn test_wal_replays_unreconciled_rows() {
+        let temp_dir = tempdir().unwrap();
+        let wal_path = temp_dir.path().join("table.wal");
+
+        {
+            let wal = open_wal(&wal_path);
+            let seqs = wal.append(&[b"1", b"2"]).unwrap();
+            assert_eq!(seqs, vec![0, 1]);
+            let seqs = wal.append(&[b"3"]).unwrap();
+            assert_eq!(seqs, vec![2]);
+
+            wal.checkpoint(&[0, 2]).unwrap();
+        }
+
+        let wal = open_wal(&wal_path);
+        let pending = wal.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].seq, 1);
+        assert_eq!(pending[0].data, b"2".to_vec());
+
+        // New inserts keep counting from the last logged sequence
+        assert_eq!(wal.append(&[b"4"]).unwrap(), vec![3]);
+
+        wal.checkpoint(&[1, 3]).unwrap();
+        assert!(!wal.has_pending());
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_wal_keeps_reconcile_positions() {
+        let temp_dir = tempdir().unwrap();
+        let wal_path = temp_dir.path().join("table.wal");
+
+        {
+            let wal = open_wal(&wal_path);
+            wal.append(&[b"1", b"2", b"3"]).unwrap();
+            wal.log_reconcile(&[(0, 10), (1, 11)]).unwrap();
+            wal.checkpoint(&[0]).unwrap();
+        }
+
+        let wal = open_wal(&wal_path);
+        let pending = wal.pending().unwrap();
+        let positions: Vec<(u64, Option<u64>)> = pending
+            .iter()
+            .map(|record| (record.seq, record.position))
+            .collect();
+        assert_eq!(positions, vec![(1, Some(11)), (2, None)]);
+    }
+
+    #[tokio::test]
+    pub async fn test_wal_ignores_torn_record() {
+        let temp_dir = tempdir().unwrap();
+        let wal_path = temp_dir.path().join("table.wal");
+
+        {
+            let wal = open_wal(&wal_path);
+            wal.append(&[b"Hello World"]).unwrap();
+        }
+
+        // Simulate a crash in the middle of the second write
+        let len = std::fs::metadata(&wal_path).unwrap().len();
+        {
+            let wal = open_wal(&wal_path);
+            wal.append(&[b"Cats are cute"]).unwrap();
+        }
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&wal_path)
+            .unwrap();
+        file.set_len(len + 4).unwrap();
+
+        let wal = open_wal(&wal_path);
+        let pending = wal.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].data, b"Hello World".to_vec());
+    }
+
+    #[tokio::test]
+    pub async fn test_wal_encryption() {
+        let temp_dir = tempdir().unwrap();
+        let wal_path = temp_dir.path().join("table.wal");
+        let key = EncryptionKey::new(&[1u8; KEY_SIZE]).unwrap();
+        let open_encrypted_wal = |keyring: Keyring| {
+            WriteAheadLog::new(
+                &wal_path,
+                Durability::Strict,
+                Some(keyring),
+                Arc::new(FileDescriptorManager::new(2500)),
+            )
+            .unwrap()
+        };
+
+        {
+            let wal = open_encrypted_wal(Keyring::new(Some(key.clone()), vec![]));
+            wal.append(&[b"Hello World"]).unwrap();
+        }
+
+        let bytes = std::fs::read(&wal_path).unwrap();
+        assert!(!bytes.windows(11).any(|window| window == b"Hello World"));
+
+        // Rows logged before the key was rotated can still be replayed
+        let new_key = EncryptionKey::new(&[2u8; KEY_SIZE]).unwrap();
+        let wal = open_encrypted_wal(Keyring::new(Some(new_key), vec![key]));
+        wal.append(&[b"Cats are cute"]).unwrap();
+
+        let pending = wal.pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].data, b"Hello World".to_vec());
+        assert_eq!(pending[1].data, b"Cats are cute".to_vec());
+    }
+}
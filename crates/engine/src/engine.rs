@@ -7,6 +7,7 @@ use schemajs_data::fdm::FileDescriptorManager;
 use schemajs_dirs::create_scheme_js_folder;
 use schemajs_helpers::helper::HelperCall;
 use schemajs_primitives::table::Table;
+use schemajs_query::errors::QueryError;
 use std::future::Future;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
@@ -18,7 +19,7 @@ pub struct SchemeJsEngine {
     pub data_path_dir: Option<PathBuf>,
     pub config: Arc<SchemeJsConfig>,
     pub helper_tx: Sender<HelperCall>,
-    file_descriptor_manager: Arc<FileDescriptorManager>,
+    pub file_descriptor_manager: Arc<FileDescriptorManager>,
 }
 
 impl SchemeJsEngine {
@@ -71,11 +72,17 @@ impl SchemeJsEngine {
         Ok((schema_name.to_string(), table_specifiers))
     }
 
-    pub fn register_tables(&self, schema_name: &str, loaded_tables: Vec<Table>) {
+    pub fn register_tables(
+        &self,
+        schema_name: &str,
+        loaded_tables: Vec<Table>,
+    ) -> Result<(), QueryError> {
         let mut db = self.find_by_name_ref(schema_name).unwrap();
         for table in loaded_tables {
-            db.add_table(table);
+            db.add_table(table)?;
         }
+
+        Ok(())
     }
 
     pub fn contains_db(&self, name: &str) -> bool {
@@ -100,6 +107,8 @@ impl SchemeJsEngine {
 #[cfg(test)]
 mod test {
     use crate::engine::SchemeJsEngine;
+    use crate::engine_db::DbQueryManager;
+    use crate::with_query_manager;
     use schemajs_config::SchemeJsConfig;
     use schemajs_data::fdm::FileDescriptorManager;
     use schemajs_data::shard::Shard;
@@ -151,6 +160,7 @@ mod test {
                         comment: None,
                         primary_key: false,
                         default_index: Some(true),
+                        unique: false,
                     },
                 );
 
@@ -164,6 +174,7 @@ mod test {
                         comment: None,
                         primary_key: false,
                         default_index: Some(false),
+                        unique: false,
                     },
                 );
 
@@ -172,12 +183,13 @@ mod test {
                     columns: cols,
                     indexes: vec![],
                     primary_key: "".to_string(),
+                    ttl: None,
                     metadata: Default::default(),
                 };
 
                 let mut writer = db_engine.write().unwrap();
                 let mut db = writer.find_by_name_ref("rust-test-random").unwrap();
-                db.add_table(table);
+                db.add_table(table).unwrap();
             }
         }
 
@@ -187,38 +199,42 @@ mod test {
         let thread_1 = thread::spawn(move || {
             let mut writer = ref_shard1.write().unwrap();
             let db = writer.find_by_name_ref("rust-test-random").unwrap();
-            let tbl = db.query_manager.get_table("users").unwrap();
-            db.query_manager
-                .insert(
-                    RowJson::from_json(
-                        json!({
-                            "_uid": "97ad4bba-98c5-4a9e-80d8-6bf6302fb883",
-                            "id": "1"
-                        }),
-                        tbl,
+            with_query_manager!(db.query_manager, query_manager => {
+                let tbl = query_manager.get_table("users").unwrap();
+                query_manager
+                    .insert(
+                        Row::from_json(
+                            json!({
+                                "_uid": "97ad4bba-98c5-4a9e-80d8-6bf6302fb883",
+                                "id": "1"
+                            }),
+                            tbl,
+                        )
+                        .unwrap(),
                     )
-                    .unwrap(),
-                )
-                .unwrap();
+                    .unwrap();
+            });
         });
 
         let ref_shard2 = Arc::clone(&arc);
         let thread_2 = thread::spawn(move || {
             let mut writer = ref_shard2.write().unwrap();
             let db = writer.find_by_name_ref("rust-test-random").unwrap();
-            let tbl = db.query_manager.get_table("users").unwrap();
-            db.query_manager
-                .insert(
-                    RowJson::from_json(
-                        json!({
-                            "_uid": "2ec92148-646d-4521-974f-b4a6d422c195",
-                            "id": "2"
-                        }),
-                        tbl,
+            with_query_manager!(db.query_manager, query_manager => {
+                let tbl = query_manager.get_table("users").unwrap();
+                query_manager
+                    .insert(
+                        Row::from_json(
+                            json!({
+                                "_uid": "2ec92148-646d-4521-974f-b4a6d422c195",
+                                "id": "2"
+                            }),
+                            tbl,
+                        )
+                        .unwrap(),
                     )
-                    .unwrap(),
-                )
-                .unwrap();
+                    .unwrap();
+            });
         });
 
         thread_1.join().unwrap();
@@ -228,14 +244,17 @@ mod test {
         {
             let mut reader = db_engine.write().unwrap();
             let mut db = reader.find_by_name_ref("rust-test-random").unwrap();
-            let tbl = db.query_manager.tables.get("users").unwrap();
-            tbl.temps.reconcile_all();
+            let DbQueryManager::Json(query_manager) = &db.query_manager else {
+                panic!("Databases store JSON rows by default");
+            };
+            let tbl = query_manager.tables.get("users").unwrap();
+            tbl.temps.reconcile_all().unwrap();
 
             let a = tbl.data.read().get_element(0).unwrap();
             let b = tbl.data.read().get_element(1).unwrap();
 
-            let a = RowJson::from_slice(a.as_slice(), tbl.table.clone());
-            let b = RowJson::from_slice(b.as_slice(), tbl.table.clone());
+            let a = RowJson::from_slice(a.as_slice(), tbl.table.clone()).unwrap();
+            let b = RowJson::from_slice(b.as_slice(), tbl.table.clone()).unwrap();
 
             let a_val = a
                 .get_value(tbl.table.get_column("id").unwrap())
@@ -1,19 +1,45 @@
-use schemajs_config::DatabaseConfig;
+use schemajs_config::{DatabaseConfig, RowFormat};
 use schemajs_data::fdm::FileDescriptorManager;
 use schemajs_dirs::create_scheme_js_db;
 use schemajs_helpers::helper::HelperCall;
 use schemajs_primitives::table::Table;
+use schemajs_query::errors::QueryError;
 use schemajs_query::managers::single::SingleQueryManager;
+use schemajs_query::row::Row;
+use schemajs_query::row_binary::RowBinary;
 use schemajs_query::row_json::RowJson;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::Sender;
 
+/// Query manager of a database, using the `Row` implementation its `row_format` asks for.
+#[derive(Debug, Clone)]
+pub enum DbQueryManager {
+    Json(Arc<SingleQueryManager<RowJson>>),
+    Binary(Arc<SingleQueryManager<RowBinary>>),
+}
+
+/// Evaluates `$body` with `$qm` bound to the `SingleQueryManager` held by the `DbQueryManager` `$manager`,
+/// whichever `Row` implementation it uses.
+///
+/// ```ignore
+/// let table = with_query_manager!(db.query_manager, qm => qm.get_table("users"));
+/// ```
+#[macro_export]
+macro_rules! with_query_manager {
+    ($manager:expr, $qm:ident => $body:expr) => {
+        match &$manager {
+            $crate::engine_db::DbQueryManager::Json($qm) => $body,
+            $crate::engine_db::DbQueryManager::Binary($qm) => $body,
+        }
+    };
+}
+
 #[derive(Debug)]
 pub struct EngineDb {
     pub db_folder: PathBuf,
-    pub query_manager: Arc<SingleQueryManager<RowJson>>,
+    pub query_manager: DbQueryManager,
     pub name: String,
     pub db_config: Arc<DatabaseConfig>,
     helper_tx: Sender<HelperCall>,
@@ -30,28 +56,54 @@ impl EngineDb {
         let db_folder = create_scheme_js_db(base_path.clone(), name);
         let db_config = Arc::new(db_config);
 
-        let mut query_manager = SingleQueryManager::new(
-            name.to_string(),
-            helper_tx.clone(),
-            db_config.clone(),
-            file_descriptor_manager.clone(),
-        );
-        query_manager.data_path = base_path.clone();
+        let query_manager = match db_config.row_format {
+            RowFormat::Json => DbQueryManager::Json(Arc::new(Self::new_query_manager(
+                base_path,
+                name,
+                &helper_tx,
+                &db_config,
+                file_descriptor_manager,
+            ))),
+            RowFormat::Binary => DbQueryManager::Binary(Arc::new(Self::new_query_manager(
+                base_path,
+                name,
+                &helper_tx,
+                &db_config,
+                file_descriptor_manager,
+            ))),
+        };
 
         EngineDb {
             name: name.to_string(),
             db_folder,
             db_config,
-            query_manager: Arc::new(query_manager),
+            query_manager,
             helper_tx,
         }
     }
 
+    fn new_query_manager<T: Row>(
+        base_path: Option<PathBuf>,
+        name: &str,
+        helper_tx: &Sender<HelperCall>,
+        db_config: &Arc<DatabaseConfig>,
+        file_descriptor_manager: Arc<FileDescriptorManager>,
+    ) -> SingleQueryManager<T> {
+        let mut query_manager = SingleQueryManager::new(
+            name.to_string(),
+            helper_tx.clone(),
+            db_config.clone(),
+            file_descriptor_manager,
+        );
+        query_manager.data_path = base_path;
+        query_manager
+    }
+
     pub async fn call_helper(&self, call: HelperCall) -> Result<(), SendError<HelperCall>> {
         self.helper_tx.send(call).await
     }
 
-    pub fn add_table(&self, table: Table) {
-        self.query_manager.register_table(table);
+    pub fn add_table(&self, table: Table) -> Result<(), QueryError> {
+        with_query_manager!(self.query_manager, qm => qm.register_table(table))
     }
 }
@@ -9,4 +9,12 @@ export const insertRow = async (dbName: string, tableName: string, data: any) =>
 
 export const searchRows = async (dbName: string, tableName: string, data: any) => {
     return await core.ops.op_engine_search_rows(dbName, tableName, data);
-}
\ No newline at end of file
+}
+
+export const deleteRows = async (dbName: string, tableName: string, data: any) => {
+    return await core.ops.op_engine_delete_rows(dbName, tableName, data);
+}
+
+export const updateRows = async (dbName: string, tableName: string, data: any, patch: any) => {
+    return await core.ops.op_engine_update_rows(dbName, tableName, data, patch);
+}
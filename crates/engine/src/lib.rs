@@ -1,5 +1,5 @@
 use crate::ops::insert::op_engine_insert_row;
-use crate::ops::query::op_engine_search_rows;
+use crate::ops::query::{op_engine_delete_rows, op_engine_search_rows, op_engine_update_rows};
 use deno_core::error::AnyError;
 use deno_core::{op2, OpState};
 
@@ -19,6 +19,12 @@ pub fn sjs_op_print(state: &mut OpState, #[string] msg: &str) -> Result<(), AnyE
 
 deno_core::extension!(
     sjs_engine,
-    ops = [op_engine_insert_row, op_engine_search_rows, sjs_op_print],
+    ops = [
+        op_engine_insert_row,
+        op_engine_search_rows,
+        op_engine_delete_rows,
+        op_engine_update_rows,
+        sjs_op_print
+    ],
     esm = ["src/js/ops.ts", "src/js/context.ts", "src/js/query.ts",]
 );
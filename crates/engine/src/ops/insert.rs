@@ -1,9 +1,9 @@
 use crate::engine::SchemeJsEngine;
+use crate::with_query_manager;
 use deno_core::{op2, serde_json, OpState};
 use parking_lot::RwLock;
 use schemajs_query::errors::QueryError;
 use schemajs_query::row::Row;
-use schemajs_query::row_json::{RowData, RowJson};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -28,11 +28,13 @@ pub async fn op_engine_insert_row(
         db.query_manager.clone()
     };
 
-    let table = query_manager.get_table(&table_name);
-    if let Some(table) = table {
-        return query_manager
-            .insert(RowJson::from_json(row, table).map_err(|_| QueryError::InvalidSerialization)?);
-    }
+    with_query_manager!(query_manager, query_manager => {
+        let table = query_manager.get_table(&table_name);
+        if let Some(table) = table {
+            return query_manager
+                .insert(Row::from_json(row, table).map_err(|_| QueryError::InvalidSerialization)?);
+        }
 
-    return Err(QueryError::InvalidInsertion);
+        Err(QueryError::InvalidInsertion)
+    })
 }
@@ -1,11 +1,14 @@
 use crate::engine::SchemeJsEngine;
+use crate::with_query_manager;
 use deno_core::{op2, OpState};
 use parking_lot::RwLock;
+use schemajs_primitives::column::types::DataValue;
 use schemajs_query::errors::QueryError;
 use schemajs_query::ops::query_ops::QueryOps;
 use schemajs_query::row::Row;
 use serde_json::Value;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -28,15 +31,83 @@ pub async fn op_engine_search_rows(
         db.query_manager.clone()
     };
 
-    let table = query_manager.get_table(&table_name);
-    if let Some(_) = table {
-        let s = query_manager
-            .search_manager
-            .search(&table_name, &args)
-            .map_err(|_| QueryError::InvalidQuerySearch(table_name.clone()))?;
-        let vals: Vec<Value> = s.iter().filter_map(|row| row.to_json().ok()).collect();
-        return Ok(vals);
-    }
-
-    Err(QueryError::InvalidQuerySearch(table_name))
+    with_query_manager!(query_manager, query_manager => {
+        let table = query_manager.get_table(&table_name);
+        if let Some(_) = table {
+            let s = query_manager
+                .search_manager
+                .search(&table_name, &args)
+                .map_err(|_| QueryError::InvalidQuerySearch(table_name.clone()))?;
+            let vals: Vec<Value> = s.iter().filter_map(|row| row.to_json().ok()).collect();
+            return Ok(vals);
+        }
+
+        Err(QueryError::InvalidQuerySearch(table_name))
+    })
+}
+
+#[op2(async)]
+#[number]
+pub async fn op_engine_delete_rows(
+    state: Rc<RefCell<OpState>>,
+    #[string] db_name: String,
+    #[string] table_name: String,
+    #[serde] args: QueryOps,
+) -> Result<u64, QueryError> {
+    let mut mut_state = state.borrow_mut();
+    let state = mut_state
+        .borrow_mut::<Arc<RwLock<SchemeJsEngine>>>()
+        .clone();
+
+    let query_manager = {
+        let read_engine = state.read();
+        let db = read_engine.find_by_name_ref(db_name.as_str()).unwrap();
+        db.query_manager.clone()
+    };
+
+    let deleted = with_query_manager!(query_manager, query_manager => {
+        query_manager.delete(&table_name, &args)?
+    });
+
+    Ok(deleted as u64)
+}
+
+#[op2(async)]
+#[number]
+pub async fn op_engine_update_rows(
+    state: Rc<RefCell<OpState>>,
+    #[string] db_name: String,
+    #[string] table_name: String,
+    #[serde] args: QueryOps,
+    #[serde] patch: Value,
+) -> Result<u64, QueryError> {
+    let mut mut_state = state.borrow_mut();
+    let state = mut_state
+        .borrow_mut::<Arc<RwLock<SchemeJsEngine>>>()
+        .clone();
+
+    let query_manager = {
+        let read_engine = state.read();
+        let db = read_engine.find_by_name_ref(db_name.as_str()).unwrap();
+        db.query_manager.clone()
+    };
+
+    let updated = with_query_manager!(query_manager, query_manager => {
+        let table = query_manager
+            .get_table(&table_name)
+            .ok_or_else(|| QueryError::InvalidTable(table_name.clone()))?;
+        let patch_obj = patch.as_object().ok_or(QueryError::InvalidSerialization)?;
+
+        let mut changes = HashMap::new();
+        for (col_name, val) in patch_obj {
+            let col = table
+                .get_column(col_name)
+                .ok_or_else(|| QueryError::UnknownColumn(col_name.clone()))?;
+            changes.insert(col_name.clone(), DataValue::from((col, val)));
+        }
+
+        query_manager.update(&table_name, &args, &changes)?
+    });
+
+    Ok(updated as u64)
 }
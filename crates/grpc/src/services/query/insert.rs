@@ -5,6 +5,7 @@ use crate::services::query::insert::insert_service::{
 use crate::services::shared::shared;
 use crate::services::shared::shared::data_value::ValueType;
 use crate::utils::common::convert_to_data_value;
+use schemajs_engine::with_query_manager;
 use schemajs_internal::auth::types::UserContext;
 use schemajs_primitives::column::types::DataValue;
 use serde::{Deserialize, Serialize};
@@ -50,9 +51,11 @@ define_sjs_grpc_service!(InsertService, {
             })
             .collect();
 
-        let insert = db.query_manager.insert_from_value_map(new_rows, false);
+        let inserted = with_query_manager!(db.query_manager, query_manager => {
+            query_manager.insert_from_value_map(new_rows, false).is_ok()
+        });
 
-        Ok(insert.is_ok())
+        Ok(inserted)
     }
 });
 
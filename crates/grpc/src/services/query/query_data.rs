@@ -6,6 +6,7 @@ use crate::services::shared::shared;
 use crate::services::shared::shared::data_value::ValueType;
 use crate::services::shared::shared::DataValue as GrpcDataValue;
 use crate::utils::common::{convert_to_grpc_value, find_database, from_grpc_ops_to_sjs_ops};
+use schemajs_engine::with_query_manager;
 use schemajs_internal::auth::types::UserContext;
 use schemajs_primitives::column::types::DataValue;
 use schemajs_query::row::Row;
@@ -28,16 +29,20 @@ define_sjs_grpc_service!(QueryService, {
         if let Some(op) = operation {
             let query_ops = from_grpc_ops_to_sjs_ops(op);
             if let Ok(qops) = query_ops {
-                let rows = db
-                    .query_manager
-                    .search_manager
-                    .search(&table_name, &qops)
-                    .map_err(|e| Status::internal("Query could not be completed"))?;
+                let rows = with_query_manager!(db.query_manager, query_manager => {
+                    query_manager
+                        .search_manager
+                        .search(&table_name, &qops)
+                        .map_err(|e| Status::internal("Query could not be completed"))?
+                        .iter()
+                        .map(|r| r.to_map())
+                        .collect::<Vec<_>>()
+                });
                 // Refactor closure to handle errors
                 let map_rows: Vec<HashMap<String, GrpcDataValue>> = rows
                     .into_iter()
                     .filter_map(|r| {
-                        match r.to_map() {
+                        match r {
                             Ok(val) => Some(
                                 val.iter()
                                     .map(|(col, val)| {
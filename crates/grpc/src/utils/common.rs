@@ -32,6 +32,7 @@ pub fn convert_to_grpc_value(val: &DataValue) -> ValueType {
         &DataValue::String(s) => ValueType::StringValue(s.clone()),
         &DataValue::Boolean(b) => ValueType::BoolValue(b.clone()),
         &DataValue::Number(n) => ValueType::NumberValue(n.as_f64().unwrap() as f32),
+        &DataValue::Blob(id) => ValueType::UuidValue(id.to_string()),
     }
 }
 
@@ -0,0 +1,174 @@
+use schemajs_data::encryption::{KeyId, Keyring};
+use schemajs_data::errors::ShardErrors;
+use schemajs_data::utils::checksum::{verify_checksum, with_checksum};
+use schemajs_data::utils::hash::to_sha256;
+use schemajs_data::U64_SIZE;
+use std::f64::consts::LN_2;
+use std::path::Path;
+
+const MAX_HASHES: u64 = 16;
+
+/// Bloom filter over the keys of an index run.
+///
+/// A key the filter does not contain is certainly not in the run, so searching the run can be skipped.
+/// A key it contains may still be missing from the run, in a share of lookups close to the false-positive rate
+/// the filter was built for.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u64,
+    /// Entries the run had when the filter was built.
+    pub items: u64,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized to hold `items` keys at `false_positive_rate`.
+    pub fn new(items: u64, false_positive_rate: f64) -> Self {
+        let capacity = items.max(1) as f64;
+        let bits = (-capacity * false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as u64;
+        let bits = bits.max(64);
+        let hashes = ((bits as f64 / capacity) * LN_2).round() as u64;
+
+        Self {
+            bits: vec![0; bits.div_ceil(8) as usize],
+            hashes: hashes.clamp(1, MAX_HASHES),
+            items,
+        }
+    }
+
+    /// Bits of the filter `key` maps to. They are derived from two halves of its SHA-256,
+    /// so filters stay valid across builds.
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = to_sha256(key.to_vec());
+        let h1 = u64::from_le_bytes(hash[0..U64_SIZE].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[U64_SIZE..U64_SIZE * 2].try_into().unwrap()) | 1;
+        let len = self.bits.len() as u64 * 8;
+
+        (0..self.hashes).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        for pos in self.positions(key).collect::<Vec<_>>() {
+            self.bits[pos / 8] |= 1 << (pos % 8);
+        }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.positions(key)
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(U64_SIZE * 2 + self.bits.len());
+        bytes.extend(self.items.to_le_bytes());
+        bytes.extend(self.hashes.to_le_bytes());
+        bytes.extend(&self.bits);
+        with_checksum(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ShardErrors> {
+        let bytes = verify_checksum(bytes)?;
+        if bytes.len() <= U64_SIZE * 2 {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        let (items, rest) = bytes.split_at(U64_SIZE);
+        let (hashes, bits) = rest.split_at(U64_SIZE);
+        let hashes = u64::from_le_bytes(hashes.try_into().unwrap());
+        if hashes == 0 || hashes > MAX_HASHES {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        Ok(Self {
+            bits: bits.to_vec(),
+            hashes,
+            items: u64::from_le_bytes(items.try_into().unwrap()),
+        })
+    }
+
+    /// Reads a filter written by `save`, decrypting it with the key of `keyring` it was written with.
+    pub fn load<P: AsRef<Path>>(path: P, keyring: &Keyring) -> Result<Self, ShardErrors> {
+        let stored = std::fs::read(path).map_err(|_| ShardErrors::UnknownShard)?;
+        let contents = verify_checksum(&stored)?;
+        if contents.len() < U64_SIZE {
+            return Err(ShardErrors::Corrupted);
+        }
+
+        let (key_id, data) = contents.split_at(U64_SIZE);
+        let key_id = KeyId::from_le_bytes(key_id.try_into().unwrap());
+
+        Self::from_bytes(&keyring.decrypt(key_id, data.to_vec())?)
+    }
+
+    /// Writes the filter encrypted with the current key of `keyring`, like the run it belongs to,
+    /// since its bits tell which keys the run holds.
+    pub fn save<P: AsRef<Path>>(&self, path: P, keyring: &Keyring) -> Result<(), ShardErrors> {
+        let key_id = keyring.current_id();
+        let mut contents = key_id.to_le_bytes().to_vec();
+        contents.extend_from_slice(&keyring.encrypt(key_id, &self.to_bytes())?);
+
+        std::fs::write(path, with_checksum(&contents)).map_err(|_| ShardErrors::FlushingError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::bloom_filter::BloomFilter;
+    use schemajs_data::encryption::{EncryptionKey, Keyring, KEY_SIZE};
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    pub async fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            filter.insert(format!("key{}", i).as_bytes());
+        }
+
+        assert!((0..1000).all(|i| filter.contains(format!("key{}", i).as_bytes())));
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.contains(format!("missing{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 300);
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("bloom");
+        filter.save(&path, &Keyring::default()).unwrap();
+
+        let loaded = BloomFilter::load(&path, &Keyring::default()).unwrap();
+        assert_eq!(loaded.items, 1000);
+        assert!((0..1000).all(|i| loaded.contains(format!("key{}", i).as_bytes())));
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[20] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(BloomFilter::load(&path, &Keyring::default()).is_err());
+
+        let mut bytes = filter.to_bytes();
+        bytes[20] ^= 1;
+        assert!(BloomFilter::from_bytes(&bytes).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_encrypted_bloom_filter() {
+        let mut filter = BloomFilter::new(100, 0.01);
+        for i in 0..100 {
+            filter.insert(format!("key{}", i).as_bytes());
+        }
+
+        let key = EncryptionKey::new(&[3u8; KEY_SIZE]).unwrap();
+        let keyring = Keyring::new(Some(key), vec![]);
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("bloom");
+        filter.save(&path, &keyring).unwrap();
+
+        // The bits of the filter are not written as they are
+        let stored = std::fs::read(&path).unwrap();
+        let plain = filter.to_bytes();
+        assert!(!stored.windows(plain.len()).any(|window| window == plain));
+        assert!(BloomFilter::load(&path, &Keyring::default()).is_err());
+
+        let loaded = BloomFilter::load(&path, &keyring).unwrap();
+        assert_eq!(loaded.to_bytes(), plain);
+    }
+}
@@ -1,30 +1,140 @@
+use crate::data::bloom_filter::BloomFilter;
 use crate::data::index_data_unit::IndexDataUnit;
 use crate::types::{IndexKey, IndexValue};
 use crate::utils::get_entry_size;
-use parking_lot::RwLock;
+use parking_lot::{RwLock, RwLockUpgradableReadGuard};
+use schemajs_data::durability::Durability;
+use schemajs_data::encryption::Keyring;
 use schemajs_data::errors::ShardErrors;
 use schemajs_data::fdm::FileDescriptorManager;
 use schemajs_data::shard::map_shard::MapShard;
 use schemajs_data::shard::shards::kv::config::KvShardConfig;
 use schemajs_data::shard::shards::kv::shard::KvShard;
 use schemajs_data::shard::Shard;
+use schemajs_data::utils::fs::list_files_with_prefix;
+use schemajs_data::wal::WriteAheadLog;
 use schemajs_data::U64_SIZE;
 use std::cmp::Ordering;
-use std::io::{Seek, Write};
-use std::marker::PhantomData;
-use std::path::Path;
+use std::collections::HashMap;
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// Entries kept in memory by default before they are written as a new run.
+pub const DEFAULT_INDEX_BUFFER_CAPACITY: usize = 1024;
+
+/// Runs an index can be made of before `IndexShard::merge_runs` merges them into one.
+pub const MAX_INDEX_RUNS: usize = 8;
+
+/// False-positive rate of the Bloom filters of the runs, unless another one is given.
+pub const DEFAULT_BLOOM_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+const WAL_INSERT_OP: u8 = 0;
+const WAL_REMOVE_OP: u8 = 1;
+const MERGE_PREFIX: &str = "merging_";
+const BLOOM_PREFIX: &str = "bloom_";
+
+/// Byte following the value of a tombstone entry. Live entries end with their value,
+/// so runs written before tombstones existed are still read the same way.
+const TOMBSTONE_MARKER: u8 = 1;
+const TOMBSTONE_MARKER_SIZE: usize = 1;
+
+/// An entry of the index. Tombstones record the removal of the entries older than them with the same key and value.
+#[derive(Debug, Clone)]
+struct IndexOp<K, V> {
+    key: K,
+    value: V,
+    tombstone: bool,
+}
+
+impl<K, V> IndexOp<K, V> {
+    fn new(key: K, value: V, tombstone: bool) -> Self {
+        Self {
+            key,
+            value,
+            tombstone,
+        }
+    }
+}
+
+/// Entries inserted or removed since the last run was written, sorted by key.
+#[derive(Debug)]
+struct IndexBuffer<K, V> {
+    entries: Vec<IndexOp<K, V>>,
+    /// Sequence numbers of the logged operations applied to `entries`, checkpointed once the buffer is written.
+    seqs: Vec<u64>,
+}
+
+impl<K: IndexKey, V: IndexValue> IndexBuffer<K, V> {
+    fn new() -> Self {
+        Self {
+            entries: vec![],
+            seqs: vec![],
+        }
+    }
+
+    /// Positions of the entries whose key is `target`.
+    fn find(&self, target: &K) -> Range<usize> {
+        let start = self.entries.partition_point(|op| &op.key < target);
+        let end = self.entries.partition_point(|op| &op.key <= target);
+        start..end
+    }
+
+    /// Adds sorted `data` to the entries. Entries sharing a key keep their insertion order,
+    /// so the latest operation on an entry is always the last one.
+    fn extend(&mut self, data: Vec<IndexOp<K, V>>) {
+        self.entries.extend(data);
+        // Both the entries and `data` are sorted, so this is a single merge of the two.
+        self.entries.sort_by(|a, b| a.key.cmp(&b.key));
+    }
+}
+
+fn value_bytes<V: IndexValue>(value: &V) -> Vec<u8> {
+    value.clone().into()
+}
+
+/// Applies `ops`, given from the oldest to the newest, returning the entries no later tombstone removed.
+fn live_entries<K: IndexKey, V: IndexValue>(
+    ops: impl IntoIterator<Item = IndexOp<K, V>>,
+) -> Vec<(K, V)> {
+    let mut order = vec![];
+    let mut latest: HashMap<(Vec<u8>, Vec<u8>), IndexOp<K, V>> = HashMap::new();
+    for op in ops {
+        let id = (op.key.clone().into(), value_bytes(&op.value));
+        if latest.insert(id.clone(), op).is_none() {
+            order.push(id);
+        }
+    }
+
+    order
+        .into_iter()
+        .filter_map(|id| latest.remove(&id))
+        .filter(|op| !op.tombstone)
+        .map(|op| (op.key, op.value))
+        .collect()
+}
+
+/// Index storage made of immutable sorted runs and an in-memory buffer.
+///
+/// Inserted entries are sorted into the buffer and logged to a write-ahead log, so they are not lost if the
+/// process stops before the buffer is written. Once the buffer is full it is written as a new run, in a single write.
+/// Removals go through the buffer the same way, as tombstones hiding the older entries they remove.
+/// Each shard of `data` is a run sorted on its own, so lookups search the buffer and every run.
+/// Every run has a Bloom filter, kept next to it and encrypted like it, so runs that cannot hold a key are not searched for it.
+/// Runs are merged into one by `merge_runs` once there are too many of them, which drops the removed entries.
 #[derive(Debug)]
 pub struct IndexShard<K: IndexKey, V: IndexValue> {
     pub data: RwLock<MapShard<KvShard, KvShardConfig>>,
-    binary_order: bool,
+    buffer: RwLock<IndexBuffer<K, V>>,
+    wal: WriteAheadLog,
+    buffer_capacity: usize,
+    bloom_false_positive_rate: f64,
+    /// Bloom filters of the runs, by the path of the run.
+    bloom_filters: RwLock<HashMap<PathBuf, BloomFilter>>,
+    config: KvShardConfig,
+    fdm: Arc<FileDescriptorManager>,
     key_size: usize,
     value_size: usize,
-
-    // Markers
-    _key_marker: PhantomData<K>,
-    _val_marker: PhantomData<V>,
 }
 
 pub type IndexEntry = (IndexDataUnit, IndexDataUnit, Vec<u8>);
@@ -36,34 +146,101 @@ impl<K: IndexKey, V: IndexValue> IndexShard<K, V> {
         key_size: usize,
         value_size: usize,
         max_capacity: Option<u64>,
-        binary_order: Option<bool>,
+        buffer_capacity: Option<usize>,
+        bloom_false_positive_rate: Option<f64>,
+        durability: Option<Durability>,
+        keyring: Option<Keyring>,
         fdm: Arc<FileDescriptorManager>,
     ) -> Self {
+        let shard_folder = shard_folder.as_ref().to_path_buf();
+        let shard_prefix = format!("indx{}_", index_name);
+        let config = KvShardConfig {
+            value_size: get_entry_size(key_size, value_size) + TOMBSTONE_MARKER_SIZE,
+            max_capacity,
+            durability: durability.unwrap_or_default(),
+            keyring: keyring.unwrap_or_default(),
+        };
+
+        // Runs left behind by a merge that did not finish. The runs they were merged from are still in place.
+        let merging_prefix = format!("{}{}", MERGE_PREFIX, shard_prefix);
+        for path in list_files_with_prefix(&shard_folder, &merging_prefix).unwrap_or_default() {
+            let _ = std::fs::remove_file(path);
+        }
+
+        let wal = WriteAheadLog::new(
+            shard_folder.join(format!("indx{}.wal", index_name)),
+            config.durability,
+            Some(config.keyring.clone()),
+            fdm.clone(),
+        )
+        .unwrap();
+
         let shard_collection = MapShard::new(
-            shard_folder.as_ref().to_path_buf(),
-            format!("indx{}_", index_name).as_str(),
-            KvShardConfig {
-                value_size: get_entry_size(key_size, value_size),
-                max_capacity: max_capacity.clone(),
-            },
-            fdm,
+            shard_folder,
+            shard_prefix.as_str(),
+            config.clone(),
+            fdm.clone(),
         );
 
-        Self {
+        let shard = Self {
             data: RwLock::new(shard_collection),
-            binary_order: binary_order.unwrap_or(false),
-            _key_marker: PhantomData,
-            _val_marker: PhantomData,
+            buffer: RwLock::new(IndexBuffer::new()),
+            wal,
+            buffer_capacity: buffer_capacity.unwrap_or(DEFAULT_INDEX_BUFFER_CAPACITY),
+            bloom_false_positive_rate: bloom_false_positive_rate
+                .unwrap_or(DEFAULT_BLOOM_FALSE_POSITIVE_RATE),
+            bloom_filters: RwLock::new(HashMap::new()),
+            config,
+            fdm,
             key_size,
             value_size,
+        };
+
+        shard.replay().unwrap();
+
+        shard
+    }
+
+    /// Applies the operations logged since the buffer was last written, bringing it back to where it was.
+    fn replay(&self) -> Result<(), ShardErrors> {
+        let mut buffer = self.buffer.write();
+        for record in self.wal.pending()? {
+            let (op, entry) = record.data.split_first().ok_or(ShardErrors::WalError)?;
+            let (key_unit, val_unit, el) = self
+                .build_entry_from_vec(entry.to_vec())
+                .ok_or(ShardErrors::WalError)?;
+            let (key, value, _) = self.build_kv(key_unit, val_unit, el);
+
+            buffer.extend(vec![IndexOp::new(key, value, *op == WAL_REMOVE_OP)]);
+            buffer.seqs.push(record.seq);
         }
+
+        Ok(())
+    }
+
+    /// Reads the entry at `index` of `shard`, telling whether it is a tombstone.
+    fn read_op(&self, shard: &KvShard, index: usize) -> Option<IndexOp<K, V>> {
+        let entry = self.get_entry_from_shard(shard, index).ok()?;
+        let (key_unit, val_unit, el) = self.build_entry_from_vec(entry)?;
+        let tombstone = Self::is_tombstone(&key_unit, &val_unit, &el);
+        let (key, value, _) = self.build_kv(key_unit, val_unit, el);
+        Some(IndexOp::new(key, value, tombstone))
+    }
+
+    fn is_tombstone(key_unit: &IndexDataUnit, val_unit: &IndexDataUnit, el: &[u8]) -> bool {
+        // The marker follows the value, inside the entry
+        let marker = U64_SIZE * 2 + (key_unit.item_size + val_unit.item_size) as usize;
+        IndexDataUnit::try_from(el)
+            .is_ok_and(|entry| entry.data.get(marker) == Some(&TOMBSTONE_MARKER))
     }
 
     pub fn build_entry_from_vec(&self, el: Vec<u8>) -> Option<IndexEntry> {
         let index_unit = IndexDataUnit::try_from(el.as_slice()).ok()?;
         let data = index_unit.data;
-        let key = IndexDataUnit::try_from(&data[0..(U64_SIZE + self.key_size)]).ok()?;
-        let value = IndexDataUnit::try_from(&data[(U64_SIZE + self.key_size)..]).ok()?;
+        // Keys may be shorter than `key_size`, the value starts right after the key
+        let key = IndexDataUnit::try_from(data.as_slice()).ok()?;
+        let value =
+            IndexDataUnit::try_from(data.get((U64_SIZE + key.item_size as usize)..)?).ok()?;
 
         Some((key, value, el))
     }
@@ -76,6 +253,7 @@ impl<K: IndexKey, V: IndexValue> IndexShard<K, V> {
         shard.read_item_from_index(index)
     }
 
+    /// Reads the entry at `index` of the runs. Entries still in the buffer are not reachable by position.
     pub fn get_entry(&self, index: usize, global: bool) -> Option<IndexEntry> {
         let get_el = if !global {
             self.data.read().get_element_from_master(index)
@@ -106,58 +284,386 @@ impl<K: IndexKey, V: IndexValue> IndexShard<K, V> {
         (K::from(key_unit), V::from(val_unit), el)
     }
 
-    pub fn raw_insert(&self, data: Vec<(K, V)>) {
-        let data_units: Vec<Vec<u8>> = data
-            .into_iter()
-            .map(|(k, v)| {
-                let key_vec: Vec<u8> = k.into();
-                let value_vec: Vec<u8> = v.into();
+    /// Adds the entries to the buffer, sorting them once, and logs them until the buffer is written.
+    /// The buffer is written as a new run once it holds as many entries as its capacity.
+    pub fn raw_insert(&self, mut data: Vec<(K, V)>) {
+        if data.is_empty() {
+            return;
+        }
 
-                let build_entry = self.build_entry(key_vec, value_vec);
+        data.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let full = {
+            let mut buffer = self.buffer.write();
+
+            let records: Vec<Vec<u8>> = data
+                .iter()
+                .map(|(k, v)| self.build_wal_record(WAL_INSERT_OP, k.clone(), v.clone()))
+                .collect();
+            let records: Vec<&[u8]> = records.iter().map(|i| i.as_slice()).collect();
+            let seqs = self.wal.append(&records).unwrap();
+
+            buffer.seqs.extend(seqs);
+            buffer.extend(
+                data.into_iter()
+                    .map(|(key, value)| IndexOp::new(key, value, false))
+                    .collect(),
+            );
+            buffer.entries.len() >= self.buffer_capacity
+        };
 
-                let vec: Vec<u8> = build_entry.into();
+        if full {
+            self.flush().unwrap();
+        }
+    }
 
-                vec
-            })
+    pub fn insert(&self, key: K, value: V) {
+        self.raw_insert(vec![(key, value)]);
+    }
+
+    /// Writes the buffer as a new run and checkpoints the logged operations it held.
+    pub fn flush(&self) -> Result<(), ShardErrors> {
+        let mut buffer = self.buffer.write();
+
+        if !buffer.entries.is_empty() {
+            let data_units: Vec<Vec<u8>> = buffer
+                .entries
+                .drain(..)
+                .map(|op| self.build_padded_entry(op.key, op.value, op.tombstone))
+                .collect();
+            let entries: Vec<&[u8]> = data_units.iter().map(|i| i.as_slice()).collect();
+
+            let mut writer = self.data.write();
+            // Runs are only sorted on their own, so a new one is started unless the current shard is empty.
+            // Empty shards made for smaller entries, before tombstones existed, are not written to either.
+            let new_run = writer.current_master_shard.get_last_index() >= 0
+                || writer.current_master_shard.value_size != self.config.value_size;
+            writer.raw_insert_rows(&entries, new_run);
+            self.build_bloom_filter(&writer.current_master_shard);
+        }
+
+        let seqs = std::mem::take(&mut buffer.seqs);
+        self.wal.checkpoint(&seqs)
+    }
+
+    /// Returns the value of every entry whose key is `target`, across the buffer and every run,
+    /// leaving out the ones removed by a tombstone.
+    pub fn binary_search_all(&self, target: K) -> Vec<V> {
+        let buffer = self.buffer.read();
+        let reader = self.data.read();
+
+        // From the oldest to the newest, so tombstones come after the entries they remove
+        let mut ops = vec![];
+        let _ = Self::for_each_run(&reader, |shard| {
+            let found = self.raw_binary_search_all(shard, target.clone());
+            ops.extend(found.into_iter().map(|(_, op)| op));
+            Ok(())
+        });
+        ops.extend(buffer.entries[buffer.find(&target)].iter().cloned());
+
+        Self::live_values(ops)
+    }
+
+    /// Returns the value of every entry whose key falls within `start` and `end`, across the buffer and every run.
+    ///
+    /// `cmp` compares the key of an entry against a bound. It does not need to be the `Ord` of the keys
+    /// (for example, it may only look at the first part of them), but it must agree with the order the entries are kept in.
+    pub fn range_search<F>(&self, start: Bound<&K>, end: Bound<&K>, cmp: F) -> Vec<V>
+    where
+        F: Fn(&K, &K) -> Ordering,
+    {
+        let before_start = |key: &K| match start {
+            Bound::Included(bound) => cmp(key, bound) == Ordering::Less,
+            Bound::Excluded(bound) => cmp(key, bound) != Ordering::Greater,
+            Bound::Unbounded => false,
+        };
+        let after_end = |key: &K| match end {
+            Bound::Included(bound) => cmp(key, bound) == Ordering::Greater,
+            Bound::Excluded(bound) => cmp(key, bound) != Ordering::Less,
+            Bound::Unbounded => false,
+        };
+
+        let buffer = self.buffer.read();
+        let reader = self.data.read();
+
+        // From the oldest to the newest, so tombstones come after the entries they remove
+        let mut ops = vec![];
+        let _ = Self::for_each_run(&reader, |shard| {
+            // First entry that is not before `start`
+            let mut left = 0;
+            let mut right = (shard.get_last_index() + 1) as usize;
+            while left < right {
+                let mid = left + (right - left) / 2;
+                match self.read_op(shard, mid) {
+                    Some(op) if before_start(&op.key) => left = mid + 1,
+                    _ => right = mid,
+                }
+            }
+
+            let mut index = left;
+            while let Some(op) = self.read_op(shard, index) {
+                if after_end(&op.key) {
+                    break;
+                }
+
+                ops.push(op);
+                index += 1;
+            }
+
+            Ok(())
+        });
+
+        let first = buffer.entries.partition_point(|op| before_start(&op.key));
+        ops.extend(
+            buffer.entries[first..]
+                .iter()
+                .take_while(|op| !after_end(&op.key))
+                .cloned(),
+        );
+
+        Self::live_values(ops)
+    }
+
+    /// Removes the entries whose key is `target` and whose value is `value`, returning whether any was found.
+    /// Other entries sharing the key are kept.
+    ///
+    /// Runs are not written to, the removal is logged and added to the buffer as a tombstone instead.
+    pub fn remove(&self, target: K, value: V) -> bool {
+        let bytes = value_bytes(&value);
+        let found = self
+            .binary_search_all(target.clone())
+            .iter()
+            .any(|entry_value| value_bytes(entry_value) == bytes);
+        if !found {
+            return false;
+        }
+
+        let full = {
+            let mut buffer = self.buffer.write();
+
+            let record = self.build_wal_record(WAL_REMOVE_OP, target.clone(), value.clone());
+            let seqs = self.wal.append(&[record.as_slice()]).unwrap();
+
+            buffer.seqs.extend(seqs);
+            buffer.extend(vec![IndexOp::new(target, value, true)]);
+            buffer.entries.len() >= self.buffer_capacity
+        };
+
+        if full {
+            self.flush().unwrap();
+        }
+
+        true
+    }
+
+    /// Merges every run into a single one, leaving out repeated entries, once there are more than `MAX_INDEX_RUNS`.
+    /// Entries removed by a tombstone are dropped along with their tombstones. Returns whether the runs were merged.
+    ///
+    /// The merged run is written under a temporary prefix while only holding a read lock, so lookups are not blocked
+    /// while it is written. It then replaces the runs it was merged from under the write lock.
+    pub fn merge_runs(&self) -> Result<bool, ShardErrors> {
+        let reader = self.data.upgradable_read();
+        let runs = Self::run_paths(&reader);
+
+        let mut ops = vec![];
+        let mut non_empty_runs = 0;
+        Self::for_each_run(&reader, |shard| {
+            let len = (shard.get_last_index() + 1) as usize;
+            non_empty_runs += (len > 0) as usize;
+            for index in 0..len {
+                ops.push(self.read_op(shard, index).ok_or(ShardErrors::Corrupted)?);
+            }
+
+            Ok(())
+        })?;
+
+        if non_empty_runs <= MAX_INDEX_RUNS {
+            return Ok(false);
+        }
+
+        // Every run is merged, so there are no older entries left for the tombstones to remove
+        let mut entries: Vec<(K, Vec<u8>)> = live_entries(ops)
+            .into_iter()
+            .map(|(key, value)| (key.clone(), self.build_padded_entry(key, value, false)))
             .collect();
+        entries.sort_by(|(a_key, a), (b_key, b)| a_key.cmp(b_key).then_with(|| a.cmp(b)));
+        let entries: Vec<&[u8]> = entries.iter().map(|(_, el)| el.as_slice()).collect();
+
+        let merged_runs = {
+            let mut merged = MapShard::<KvShard, KvShardConfig>::new(
+                reader.shards_folder.clone(),
+                format!("{}{}", MERGE_PREFIX, reader.shard_prefix).as_str(),
+                self.config.clone(),
+                self.fdm.clone(),
+            );
+            merged.raw_insert_rows(&entries, false);
+            Self::run_paths(&merged)
+        };
 
-        let entries: Vec<&[u8]> = data_units.iter().map(|i| i.as_slice()).collect();
+        let mut writer = RwLockUpgradableReadGuard::upgrade(reader);
 
-        self.data.write().insert_rows(&entries);
+        for path in merged_runs {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let run_path = path.with_file_name(file_name.trim_start_matches(MERGE_PREFIX));
+            std::fs::rename(&path, run_path).map_err(|_| ShardErrors::FlushingError)?;
+        }
 
-        if self.binary_order {
-            self.keep_binary_order();
+        // Filters go first, a run left without one gets it rebuilt.
+        {
+            let mut bloom_filters = self.bloom_filters.write();
+            for path in &runs {
+                bloom_filters.remove(path);
+                let _ = std::fs::remove_file(Self::bloom_filter_path(path));
+            }
         }
+
+        // Until these are removed both the old runs and the merged one are in place, which only repeats entries.
+        for path in runs {
+            std::fs::remove_file(path).map_err(|_| ShardErrors::FlushingError)?;
+        }
+
+        *writer = MapShard::new(
+            writer.shards_folder.clone(),
+            writer.shard_prefix.clone().as_str(),
+            self.config.clone(),
+            self.fdm.clone(),
+        );
+
+        Ok(true)
     }
 
-    pub fn insert(&self, key: K, value: V) {
-        self.raw_insert(vec![(key, value)]);
+    /// Calls `visit` with every run of `shards`, from the oldest to the newest.
+    fn for_each_run(
+        shards: &MapShard<KvShard, KvShardConfig>,
+        mut visit: impl FnMut(&KvShard) -> Result<(), ShardErrors>,
+    ) -> Result<(), ShardErrors> {
+        let past_master_shards = shards.past_master_shards.read();
+        for shard in past_master_shards
+            .values()
+            .chain(std::iter::once(&shards.current_master_shard))
+        {
+            visit(shard)?;
+        }
+
+        Ok(())
     }
 
-    pub fn binary_search(&self, target: K) -> Option<(u64, K, V)> {
-        let reader = self.data.read();
-        let breaking_point = reader.current_master_shard.breaking_point();
-        match breaking_point {
-            None => self.raw_binary_search(&reader.current_master_shard, target),
-            Some(_) => {
-                let past_master_shards = reader.past_master_shards.read();
-
-                let shards = {
-                    let mut shards = vec![&reader.current_master_shard];
-                    let combined_shards: Vec<&KvShard> = past_master_shards.values().collect();
-                    shards.extend(combined_shards);
-                    shards
-                };
-
-                for shard in shards {
-                    if let Some(found) = self.raw_binary_search(shard, target.clone()) {
-                        return Some(found);
-                    }
-                }
+    fn run_paths(shards: &MapShard<KvShard, KvShardConfig>) -> Vec<PathBuf> {
+        shards
+            .past_master_shards
+            .read()
+            .values()
+            .chain(std::iter::once(&shards.current_master_shard))
+            .map(|shard| shard.get_path())
+            .collect()
+    }
 
-                None
+    /// Values of the entries left by `ops`, given from the oldest to the newest.
+    /// Repeated entries, which are left behind when the process stops after a run is written
+    /// but before its entries are checkpointed, or in the middle of a merge, are only returned once.
+    fn live_values(ops: Vec<IndexOp<K, V>>) -> Vec<V> {
+        live_entries(ops)
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    fn bloom_filter_path(run_path: &Path) -> PathBuf {
+        let file_name = run_path.file_name().unwrap_or_default().to_string_lossy();
+        run_path.with_file_name(format!("{}{}", BLOOM_PREFIX, file_name))
+    }
+
+    /// Builds the Bloom filter of `shard` from its entries and saves it next to it.
+    /// Returns `None` if an entry cannot be read, leaving the run to be searched.
+    fn build_bloom_filter(&self, shard: &KvShard) -> Option<BloomFilter> {
+        let items = (shard.get_last_index() + 1) as u64;
+        let mut filter = BloomFilter::new(items, self.bloom_false_positive_rate);
+        for index in 0..items as usize {
+            let entry = self.get_entry_from_shard(shard, index).ok()?;
+            let (key_unit, _, _) = self.build_entry_from_vec(entry)?;
+            let key: Vec<u8> = K::from(key_unit).into();
+            filter.insert(&key);
+        }
+
+        let path = shard.get_path();
+        // The filter can always be rebuilt, so failing to save it only costs rebuilding it when the index is opened.
+        let _ = filter.save(Self::bloom_filter_path(&path), &self.config.keyring);
+        self.bloom_filters.write().insert(path, filter.clone());
+
+        Some(filter)
+    }
+
+    /// Returns whether `shard` may hold entries whose key is `target`, according to its Bloom filter.
+    ///
+    /// Runs are only written to while they are empty, so a filter built over fewer entries than its run has
+    /// is out of date and is rebuilt. Removed entries are left in filters, they only make them less precise.
+    fn may_contain(&self, shard: &KvShard, target: &K) -> bool {
+        if self.bloom_false_positive_rate <= 0.0 {
+            return true;
+        }
+
+        let items = (shard.get_last_index() + 1) as u64;
+        if items == 0 {
+            return false;
+        }
+
+        let key: Vec<u8> = target.clone().into();
+        let path = shard.get_path();
+
+        if let Some(filter) = self.bloom_filters.read().get(&path) {
+            if filter.items >= items {
+                return filter.contains(&key);
             }
         }
+
+        if let Ok(filter) = BloomFilter::load(Self::bloom_filter_path(&path), &self.config.keyring)
+        {
+            if filter.items >= items {
+                let contains = filter.contains(&key);
+                self.bloom_filters.write().insert(path, filter);
+                return contains;
+            }
+        }
+
+        self.build_bloom_filter(shard)
+            .is_none_or(|filter| filter.contains(&key))
+    }
+
+    /// Returns every entry of `shard` whose key is `target` along with its position.
+    /// Entries sharing a key are kept next to each other by the binary order,
+    /// so they are found by scanning around the match of the binary search.
+    /// Runs whose Bloom filter does not contain `target` are not searched.
+    fn raw_binary_search_all(&self, shard: &KvShard, target: K) -> Vec<(u64, IndexOp<K, V>)> {
+        if !self.may_contain(shard, &target) {
+            return vec![];
+        }
+
+        let found = match self.raw_binary_search(shard, target.clone()) {
+            None => return vec![],
+            Some((pos, _, _)) => pos as usize,
+        };
+
+        let mut first = found;
+        while first > 0
+            && self
+                .read_op(shard, first - 1)
+                .is_some_and(|op| op.key == target)
+        {
+            first -= 1;
+        }
+
+        let mut entries = vec![];
+        let mut index = first;
+        while let Some(op) = self.read_op(shard, index) {
+            if op.key != target {
+                break;
+            }
+
+            entries.push((index as u64, op));
+            index += 1;
+        }
+
+        entries
     }
 
     pub fn raw_binary_search(&self, shard: &KvShard, target: K) -> Option<(u64, K, V)> {
@@ -191,7 +697,7 @@ impl<K: IndexKey, V: IndexValue> IndexShard<K, V> {
         None
     }
 
-    fn build_entry(&self, key: Vec<u8>, value: Vec<u8>) -> IndexDataUnit {
+    fn build_entry(&self, key: Vec<u8>, value: Vec<u8>, tombstone: bool) -> IndexDataUnit {
         let build_entry = {
             let mut entry: Vec<u8> = Vec::new();
 
@@ -203,52 +709,42 @@ impl<K: IndexKey, V: IndexValue> IndexShard<K, V> {
 
             entry.extend(key_vec_val);
             entry.extend(value_vec_val);
+            if tombstone {
+                entry.push(TOMBSTONE_MARKER);
+            }
             entry
         };
 
         IndexDataUnit::new(build_entry)
     }
 
-    fn keep_binary_order(&self) {
-        let mut i = { self.data.read().current_master_shard.get_last_index() };
-
-        while i > 0 {
-            let (curr_index, _, curr_original_el) = self.get_kv(i as usize, false).unwrap();
-            let (prev_index, _, prev_original_el) = self.get_kv(i as usize - 1, false).unwrap();
+    fn build_padded_entry(&self, key: K, value: V, tombstone: bool) -> Vec<u8> {
+        let mut vec: Vec<u8> = self.build_entry(key.into(), value.into(), tombstone).into();
+        // Keys shorter than `key_size` are padded, since every entry of the shard takes the same space.
+        vec.resize(
+            get_entry_size(self.key_size, self.value_size) + TOMBSTONE_MARKER_SIZE,
+            0,
+        );
+        vec
+    }
 
-            match curr_index.cmp(&prev_index) {
-                Ordering::Less => {
-                    let mut writer = self.data.write();
-                    let mut curr_shard = writer.current_master_shard.data.write();
-                    curr_shard
-                        .operate(|file| {
-                            writer
-                                .current_master_shard
-                                .swap_elements(
-                                    file,
-                                    i as usize,
-                                    &curr_original_el,
-                                    &prev_original_el,
-                                )
-                                .unwrap();
-                            i -= 1;
-                            Ok(())
-                        })
-                        .unwrap();
-                }
-                _ => break,
-            }
-        }
+    fn build_wal_record(&self, op: u8, key: K, value: V) -> Vec<u8> {
+        let mut record = vec![op];
+        record.extend(self.build_padded_entry(key, value, false));
+        record
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::data::index_shard::IndexShard;
+    use crate::data::bloom_filter::BloomFilter;
+    use crate::data::index_shard::{IndexShard, MAX_INDEX_RUNS};
     use crate::keys::string_index::StringIndexKey;
     use crate::utils::get_entry_size;
     use crate::vals::raw_value::RawIndexValue;
+    use schemajs_data::encryption::{EncryptionKey, Keyring, KEY_SIZE};
     use schemajs_data::fdm::FileDescriptorManager;
+    use schemajs_data::shard::Shard;
     use std::sync::Arc;
     use tempfile::tempdir;
     use uuid::Uuid;
@@ -272,7 +768,10 @@ mod test {
             32,
             1024,
             None,
-            Some(true),
+            None,
+            None,
+            None,
+            None,
             Arc::new(FileDescriptorManager::new(2500)),
         );
 
@@ -290,6 +789,7 @@ mod test {
         index.insert(StringIndexKey("f".repeat(key_size)), vec![0u8; 1024].into());
         index.insert(StringIndexKey("g".repeat(key_size)), vec![0u8; 1024].into());
         index.insert(StringIndexKey("h".repeat(key_size)), vec![0u8; 1024].into());
+        index.flush().unwrap();
 
         {
             let entry = index.get_kv(0, true).unwrap();
@@ -324,7 +824,10 @@ mod test {
             32,
             1024,
             None,
-            Some(true),
+            None,
+            None,
+            None,
+            None,
             Arc::new(FileDescriptorManager::new(2500)),
         );
 
@@ -341,6 +844,7 @@ mod test {
         index.insert(StringIndexKey("b".repeat(key_size)), vec![0u8; 1024].into());
         index.insert(StringIndexKey("d".repeat(key_size)), vec![0u8; 1024].into());
         index.insert(StringIndexKey("e".repeat(key_size)), vec![0u8; 1024].into());
+        index.flush().unwrap();
 
         assert_eq!(index.get_kv(0, true).unwrap().0 .0, "b".repeat(key_size));
         assert_eq!(index.get_kv(1, true).unwrap().0 .0, "d".repeat(key_size));
@@ -366,7 +870,10 @@ mod test {
             32,
             1024,
             None,
-            Some(true),
+            None,
+            None,
+            None,
+            None,
             Arc::new(FileDescriptorManager::new(2500)),
         );
 
@@ -392,6 +899,7 @@ mod test {
             StringIndexKey(pad_key("string(a:1)")),
             vec![2u8; 1024].into(),
         );
+        index.flush().unwrap();
 
         // Assert the correct order
         assert_eq!(index.get_kv(0, true).unwrap().0 .0, pad_key("string(a:0)"));
@@ -405,4 +913,332 @@ mod test {
 
         std::fs::remove_dir_all(index_folder).unwrap();
     }
+
+    fn new_runs_index(
+        index_folder: &std::path::Path,
+        buffer_capacity: usize,
+    ) -> IndexShard<StringIndexKey, RawIndexValue> {
+        IndexShard::new(
+            index_folder,
+            "indx".to_string(),
+            32,
+            8,
+            None,
+            Some(buffer_capacity),
+            None,
+            None,
+            None,
+            Arc::new(FileDescriptorManager::new(2500)),
+        )
+    }
+
+    fn positions(values: Vec<RawIndexValue>) -> Vec<u64> {
+        let mut positions: Vec<u64> = values
+            .into_iter()
+            .map(|val| u64::from_le_bytes(val.0.as_slice().try_into().unwrap()))
+            .collect();
+        positions.sort();
+        positions
+    }
+
+    #[tokio::test]
+    pub async fn test_runs_and_merge() {
+        let temp_dir = tempdir().unwrap();
+        let index_folder = temp_dir.path().join("indx");
+        std::fs::create_dir(index_folder.clone()).unwrap();
+
+        // Every pair of entries is written as a run
+        let index = new_runs_index(&index_folder, 2);
+        let key = |n: u64| StringIndexKey(format!("key{}", n % 5));
+
+        let inserted = (MAX_INDEX_RUNS as u64 + 1) * 2 + 1;
+        for pos in (0..inserted).rev() {
+            index.insert(key(pos), pos.to_le_bytes().to_vec().into());
+        }
+
+        let runs = index.data.read().past_master_shards.read().len() + 1;
+        assert_eq!(runs, MAX_INDEX_RUNS + 1);
+
+        let expected = |n: u64| (0..inserted).filter(|pos| pos % 5 == n).collect::<Vec<_>>();
+        assert_eq!(positions(index.binary_search_all(key(3))), expected(3));
+
+        assert!(index.remove(key(0), 0u64.to_le_bytes().to_vec().into()));
+        assert!(index.merge_runs().unwrap());
+        assert!(!index.merge_runs().unwrap());
+        assert!(index.data.read().past_master_shards.read().is_empty());
+
+        // The merged run is sorted
+        let keys: Vec<String> = (0..inserted as usize - 2)
+            .map(|i| index.get_kv(i, true).unwrap().0 .0)
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        for n in 1..5 {
+            assert_eq!(positions(index.binary_search_all(key(n))), expected(n));
+        }
+        assert_eq!(
+            positions(index.binary_search_all(key(0))),
+            expected(0)[1..].to_vec()
+        );
+        assert_eq!(
+            positions(index.range_search(
+                std::ops::Bound::Included(&key(1)),
+                std::ops::Bound::Excluded(&key(3)),
+                |a, b| a.cmp(b)
+            )),
+            positions(
+                index
+                    .binary_search_all(key(1))
+                    .into_iter()
+                    .chain(index.binary_search_all(key(2)))
+                    .collect()
+            )
+        );
+
+        let leftovers = std::fs::read_dir(&index_folder)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("merging_")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_buffer_is_replayed() {
+        let temp_dir = tempdir().unwrap();
+        let index_folder = temp_dir.path().join("indx");
+        std::fs::create_dir(index_folder.clone()).unwrap();
+
+        {
+            let index = new_runs_index(&index_folder, 4);
+            for pos in 0..6u64 {
+                index.insert(
+                    StringIndexKey(format!("key{}", pos % 2)),
+                    pos.to_le_bytes().to_vec().into(),
+                );
+            }
+            assert!(index.remove(
+                StringIndexKey("key0".to_string()),
+                4u64.to_le_bytes().to_vec().into()
+            ));
+        }
+
+        // The first four entries were written as a run, the rest were only logged
+        let index = new_runs_index(&index_folder, 4);
+        assert_eq!(index.data.read().len(), 4);
+        assert_eq!(
+            positions(index.binary_search_all(StringIndexKey("key0".to_string()))),
+            vec![0, 2]
+        );
+        assert_eq!(
+            positions(index.binary_search_all(StringIndexKey("key1".to_string()))),
+            vec![1, 3, 5]
+        );
+
+        index.flush().unwrap();
+        drop(index);
+
+        // The replayed entries were written as a second run, along with the tombstone of the removal
+        let index = new_runs_index(&index_folder, 4);
+        assert_eq!(index.data.read().past_master_shards.read().len(), 1);
+        assert_eq!(index.data.read().current_master_shard.get_last_index(), 2);
+        assert_eq!(
+            positions(index.binary_search_all(StringIndexKey("key0".to_string()))),
+            vec![0, 2]
+        );
+        assert_eq!(
+            positions(index.binary_search_all(StringIndexKey("key1".to_string()))),
+            vec![1, 3, 5]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_removals_are_tombstones() {
+        let temp_dir = tempdir().unwrap();
+        let index_folder = temp_dir.path().join("indx");
+        std::fs::create_dir(index_folder.clone()).unwrap();
+
+        let index = new_runs_index(&index_folder, 2);
+        let key = |n: u64| StringIndexKey(format!("key{}", n % 2));
+        let value = |pos: u64| RawIndexValue::from(pos.to_le_bytes().to_vec());
+        for pos in 0..4u64 {
+            index.insert(key(pos), value(pos));
+        }
+
+        let run_lens = |index: &IndexShard<StringIndexKey, RawIndexValue>| {
+            let data = index.data.read();
+            let mut lens: Vec<i64> = data
+                .past_master_shards
+                .read()
+                .values()
+                .map(|shard| shard.get_last_index() + 1)
+                .collect();
+            lens.push(data.current_master_shard.get_last_index() + 1);
+            lens
+        };
+        let written = run_lens(&index);
+
+        // Removing an entry of a run leaves the run as it was
+        assert!(index.remove(key(0), value(0)));
+        assert!(!index.remove(key(0), value(0)));
+        assert_eq!(run_lens(&index), written);
+        assert_eq!(positions(index.binary_search_all(key(0))), vec![2]);
+        assert!(positions(index.range_search(
+            std::ops::Bound::Included(&key(0)),
+            std::ops::Bound::Included(&key(1)),
+            |a, b| a.cmp(b)
+        ))
+        .iter()
+        .all(|pos| *pos != 0));
+
+        // An entry inserted again after its removal is found
+        index.insert(key(0), value(0));
+        assert_eq!(positions(index.binary_search_all(key(0))), vec![0, 2]);
+        assert!(index.remove(key(0), value(0)));
+
+        while index.data.read().past_master_shards.read().len() < MAX_INDEX_RUNS {
+            index.insert(StringIndexKey("other".to_string()), value(9));
+        }
+        index.flush().unwrap();
+        assert!(index.merge_runs().unwrap());
+
+        // Merging drops the tombstones along with the entries they removed
+        // and writes the repeated entries once
+        assert_eq!(
+            index.data.read().current_master_shard.get_last_index() + 1,
+            4
+        );
+        assert_eq!(positions(index.binary_search_all(key(0))), vec![2]);
+        assert_eq!(positions(index.binary_search_all(key(1))), vec![1, 3]);
+    }
+
+    #[tokio::test]
+    pub async fn test_bloom_filters() {
+        let temp_dir = tempdir().unwrap();
+        let index_folder = temp_dir.path().join("indx");
+        std::fs::create_dir(index_folder.clone()).unwrap();
+
+        let bloom_files = || {
+            std::fs::read_dir(&index_folder)
+                .unwrap()
+                .filter(|entry| {
+                    entry
+                        .as_ref()
+                        .unwrap()
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with("bloom_")
+                })
+                .count()
+        };
+
+        {
+            let index = new_runs_index(&index_folder, 2);
+            for pos in 0..6u64 {
+                index.insert(
+                    StringIndexKey(format!("key{}", pos)),
+                    pos.to_le_bytes().to_vec().into(),
+                );
+            }
+
+            // A filter per run, written along with it
+            assert_eq!(bloom_files(), 3);
+            assert_eq!(index.bloom_filters.read().len(), 3);
+            assert!(index
+                .binary_search_all(StringIndexKey("missing".to_string()))
+                .is_empty());
+        }
+
+        // Filters are loaded back from their files
+        let index = new_runs_index(&index_folder, 2);
+        assert!(index.bloom_filters.read().is_empty());
+        for pos in 0..6u64 {
+            assert_eq!(
+                positions(index.binary_search_all(StringIndexKey(format!("key{}", pos)))),
+                vec![pos]
+            );
+        }
+        assert_eq!(index.bloom_filters.read().len(), 3);
+
+        // Filters of merged runs are dropped along with them
+        for pos in 6..(MAX_INDEX_RUNS as u64 + 1) * 2 {
+            index.insert(
+                StringIndexKey(format!("key{}", pos)),
+                pos.to_le_bytes().to_vec().into(),
+            );
+        }
+        assert_eq!(bloom_files(), MAX_INDEX_RUNS + 1);
+        assert!(index.merge_runs().unwrap());
+        assert_eq!(bloom_files(), 0);
+        assert_eq!(
+            positions(index.binary_search_all(StringIndexKey("key7".to_string()))),
+            vec![7]
+        );
+        assert_eq!(bloom_files(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_encrypted_bloom_filters() {
+        let temp_dir = tempdir().unwrap();
+        let index_folder = temp_dir.path().join("indx");
+        std::fs::create_dir(index_folder.clone()).unwrap();
+
+        let keyring = Keyring::new(Some(EncryptionKey::new(&[5u8; KEY_SIZE]).unwrap()), vec![]);
+        let open_index = || -> IndexShard<StringIndexKey, RawIndexValue> {
+            IndexShard::new(
+                &index_folder,
+                "indx".to_string(),
+                32,
+                8,
+                None,
+                Some(2),
+                None,
+                None,
+                Some(keyring.clone()),
+                Arc::new(FileDescriptorManager::new(2500)),
+            )
+        };
+
+        {
+            let index = open_index();
+            for pos in 0..6u64 {
+                index.insert(
+                    StringIndexKey(format!("key{}", pos)),
+                    pos.to_le_bytes().to_vec().into(),
+                );
+            }
+        }
+
+        // No filter can be read without the key of the table
+        let bloom_files: Vec<_> = std::fs::read_dir(&index_folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("bloom_")
+            })
+            .collect();
+        assert_eq!(bloom_files.len(), 3);
+        for path in &bloom_files {
+            assert!(BloomFilter::load(path, &Keyring::default()).is_err());
+            assert!(BloomFilter::from_bytes(&std::fs::read(path).unwrap()).is_err());
+        }
+
+        let index = open_index();
+        for pos in 0..6u64 {
+            assert_eq!(
+                positions(index.binary_search_all(StringIndexKey(format!("key{}", pos)))),
+                vec![pos]
+            );
+        }
+        assert_eq!(index.bloom_filters.read().len(), 3);
+    }
 }
@@ -0,0 +1,393 @@
+use crate::composite_key::CompositeKey;
+use crate::data::index_shard::IndexShard;
+use crate::implementations::btree::btree_index_header::{
+    BTREE_INDEX_KEY_SIZE, BTREE_INDEX_VALUE_SIZE,
+};
+use crate::index_keys::IndexKeyType;
+use crate::keys::ordered_key::{OrderedIndexKey, OrderedKeyValue};
+use crate::types::Index;
+use crate::vals::raw_value::RawIndexValue;
+use schemajs_data::durability::Durability;
+use schemajs_data::encryption::Keyring;
+use schemajs_data::errors::ShardErrors;
+use schemajs_data::fdm::FileDescriptorManager;
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Index keeping its keys in value order, so it can answer range (`>`, `<`, `>=`, `<=`) and prefix lookups
+/// besides equality ones.
+///
+/// String values longer than the key size are compared by their first bytes only,
+/// so lookups on them may also return rows sharing that start, which callers have to check again.
+#[derive(Debug)]
+pub struct BTreeIndex {
+    pub index: Arc<IndexShard<OrderedIndexKey, RawIndexValue>>,
+}
+
+impl BTreeIndex {
+    pub fn new_from_path<P: AsRef<Path> + Clone>(
+        path: P,
+        index_name: Option<String>,
+        capacity: Option<u64>,
+        bloom_false_positive_rate: Option<f64>,
+        durability: Option<Durability>,
+        keyring: Option<Keyring>,
+        fdm: Arc<FileDescriptorManager>,
+    ) -> Self {
+        let index_shard = IndexShard::new(
+            path,
+            index_name.unwrap_or_else(|| "btreeindx".to_string()),
+            BTREE_INDEX_KEY_SIZE,
+            BTREE_INDEX_VALUE_SIZE,
+            capacity,
+            None,
+            bloom_false_positive_rate,
+            durability,
+            keyring,
+            fdm,
+        );
+
+        Self {
+            index: Arc::new(index_shard),
+        }
+    }
+
+    fn to_position(val: RawIndexValue) -> u64 {
+        u64::from_le_bytes(val.0.as_slice().try_into().unwrap())
+    }
+
+    /// Returns `key` as it is stored in the index.
+    fn stored_key(key: &IndexKeyType) -> OrderedIndexKey {
+        let mut key = key.as_ordered().unwrap().clone();
+        key.truncate(BTREE_INDEX_KEY_SIZE);
+        key
+    }
+
+    /// Returns `bound` as it is compared against the stored keys. A truncated key also stands for every longer key
+    /// sharing its start, some of which are past the original one, so it can no longer be excluded.
+    fn stored_bound(bound: Bound<&IndexKeyType>) -> Bound<OrderedIndexKey> {
+        match bound {
+            Bound::Included(key) => Bound::Included(Self::stored_key(key)),
+            Bound::Excluded(key) => {
+                let stored = Self::stored_key(key);
+                if key.as_ordered() == Some(&stored) {
+                    Bound::Excluded(stored)
+                } else {
+                    Bound::Included(stored)
+                }
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+
+    fn search(
+        &self,
+        start: Bound<&OrderedIndexKey>,
+        end: Bound<&OrderedIndexKey>,
+        cmp: impl Fn(&OrderedIndexKey, &OrderedIndexKey) -> Ordering,
+    ) -> Vec<u64> {
+        self.index
+            .range_search(start, end, cmp)
+            .into_iter()
+            .map(Self::to_position)
+            .collect()
+    }
+
+    /// Returns the position of every row whose key starts with the values of `prefix`.
+    pub fn prefix(&self, prefix: &OrderedIndexKey) -> Vec<u64> {
+        self.search(
+            Bound::Included(prefix),
+            Bound::Included(prefix),
+            OrderedIndexKey::cmp_prefix,
+        )
+    }
+
+    /// Returns the position of every row whose key starts with the values of `prefix`,
+    /// except for the last one, which must be a string the value of the row starts with.
+    pub fn starts_with(&self, prefix: &OrderedIndexKey) -> Vec<u64> {
+        let (last, leading) = match prefix.0.split_last() {
+            Some((OrderedKeyValue::String(last), leading)) => (last, leading),
+            _ => return self.prefix(prefix),
+        };
+
+        self.search(
+            Bound::Included(prefix),
+            Bound::Included(prefix),
+            |key, bound| {
+                let leading_key =
+                    OrderedIndexKey(key.0.iter().take(leading.len()).cloned().collect());
+                match leading_key.cmp_prefix(&OrderedIndexKey(leading.to_vec())) {
+                    Ordering::Equal => match key.0.get(leading.len()) {
+                        Some(OrderedKeyValue::String(val)) if val.starts_with(last.as_str()) => {
+                            Ordering::Equal
+                        }
+                        _ => key.cmp_prefix(bound),
+                    },
+                    ord => ord,
+                }
+            },
+        )
+    }
+}
+
+impl Index for BTreeIndex {
+    /// Keys built from a `CompositeKey` keep every value as a string, use `to_ordered_key` to keep their types.
+    fn to_key(&self, key: CompositeKey) -> IndexKeyType {
+        IndexKeyType::Ordered(OrderedIndexKey(
+            key.0
+                .into_iter()
+                .map(|(_, val)| OrderedKeyValue::String(val))
+                .collect(),
+        ))
+    }
+
+    fn to_ordered_key(&self, key: OrderedIndexKey) -> Option<IndexKeyType> {
+        Some(IndexKeyType::Ordered(key))
+    }
+
+    fn bulk_insert(&self, data: Vec<(IndexKeyType, u64)>) {
+        self.index.raw_insert(
+            data.into_iter()
+                .map(|(key, pos)| (Self::stored_key(&key), pos.to_le_bytes().to_vec().into()))
+                .collect(),
+        )
+    }
+
+    fn insert(&self, key: IndexKeyType, row_position: u64) {
+        self.index.insert(
+            Self::stored_key(&key),
+            row_position.to_le_bytes().to_vec().into(),
+        );
+    }
+
+    fn get(&self, key: &IndexKeyType) -> Vec<u64> {
+        self.index
+            .binary_search_all(Self::stored_key(key))
+            .into_iter()
+            .map(Self::to_position)
+            .collect()
+    }
+
+    fn remove(&mut self, key: &IndexKeyType, row_position: u64) -> bool {
+        self.index.remove(
+            Self::stored_key(key),
+            row_position.to_le_bytes().to_vec().into(),
+        )
+    }
+
+    fn range(&self, start: Bound<&IndexKeyType>, end: Bound<&IndexKeyType>) -> Vec<u64> {
+        let start = Self::stored_bound(start);
+        let end = Self::stored_bound(end);
+
+        self.search(start.as_ref(), end.as_ref(), OrderedIndexKey::cmp_prefix)
+    }
+
+    fn supported_search_operators(&self) -> Vec<String> {
+        ["=", ">", "<", ">=", "<="]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn verify(&self) -> Vec<(PathBuf, ShardErrors)> {
+        self.index.data.read().verify()
+    }
+
+    fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        self.index.data.read().reencrypt()
+    }
+
+    fn merge(&self) -> Result<bool, ShardErrors> {
+        self.index.merge_runs()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::implementations::btree::btree_index::BTreeIndex;
+    use crate::index_keys::IndexKeyType;
+    use crate::keys::ordered_key::{OrderedIndexKey, OrderedKeyValue};
+    use crate::types::Index;
+    use schemajs_data::fdm::FileDescriptorManager;
+    use std::ops::Bound;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn new_index(capacity: Option<u64>) -> (tempfile::TempDir, BTreeIndex) {
+        let temp_dir = tempdir().unwrap();
+        let btreeindx = temp_dir.as_ref().to_path_buf().join("btreeindx");
+        std::fs::create_dir(btreeindx.clone()).unwrap();
+
+        let index = BTreeIndex::new_from_path(
+            btreeindx,
+            None,
+            capacity,
+            None,
+            None,
+            None,
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+
+        (temp_dir, index)
+    }
+
+    fn key(values: Vec<OrderedKeyValue>) -> IndexKeyType {
+        IndexKeyType::Ordered(OrderedIndexKey(values))
+    }
+
+    fn sorted(mut positions: Vec<u64>) -> Vec<u64> {
+        positions.sort();
+        positions
+    }
+
+    #[tokio::test]
+    pub async fn test_range_lookups() {
+        // A shard every three entries and some left in the buffer, so ranges span several of them
+        let (_dir, mut index) = new_index(Some(3));
+        let age = |n: f64| key(vec![OrderedKeyValue::Number(n)]);
+
+        for (pos, n) in [30.0, 9.0, 100.0, 10.0, 45.5, 30.0, -1.0]
+            .into_iter()
+            .enumerate()
+        {
+            index.insert(age(n), pos as u64);
+        }
+        index.index.flush().unwrap();
+        index.insert(key(vec![OrderedKeyValue::Null]), 7);
+        index.insert(key(vec![OrderedKeyValue::String("10".to_string())]), 8);
+
+        let range = |index: &BTreeIndex, start: Bound<&IndexKeyType>, end: Bound<&IndexKeyType>| {
+            sorted(index.range(start, end))
+        };
+
+        assert_eq!(
+            range(&index, Bound::Excluded(&age(10.0)), Bound::Unbounded),
+            vec![0, 2, 4, 5, 8]
+        );
+        assert_eq!(
+            range(
+                &index,
+                Bound::Included(&age(10.0)),
+                Bound::Excluded(&age(45.5))
+            ),
+            vec![0, 3, 5]
+        );
+        assert_eq!(
+            range(&index, Bound::Unbounded, Bound::Included(&age(9.0))),
+            vec![1, 6, 7]
+        );
+        assert_eq!(sorted(index.get(&age(30.0))), vec![0, 5]);
+
+        assert!(index.remove(&age(30.0), 5));
+        assert_eq!(
+            range(
+                &index,
+                Bound::Included(&age(30.0)),
+                Bound::Included(&age(100.0))
+            ),
+            vec![0, 2, 4]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_equal_numbers_share_a_key() {
+        let (_dir, index) = new_index(None);
+        let num = |n: f64| key(vec![OrderedKeyValue::Number(n)]);
+        let other_nan = f64::from_bits(f64::NAN.to_bits() | 1);
+
+        index.insert(num(-0.0), 0);
+        index.insert(num(f64::NAN), 1);
+        index.index.flush().unwrap();
+        index.insert(num(0.0), 2);
+        index.insert(num(other_nan), 3);
+
+        assert_eq!(sorted(index.get(&num(0.0))), vec![0, 2]);
+        assert_eq!(sorted(index.get(&num(-0.0))), vec![0, 2]);
+        assert_eq!(sorted(index.get(&num(-f64::NAN))), vec![1, 3]);
+        assert_eq!(
+            sorted(index.range(Bound::Excluded(&num(-0.0)), Bound::Included(&num(1.0)))),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_multi_column_and_prefix_lookups() {
+        let (_dir, index) = new_index(None);
+        let user = |country: &str, name: &str| {
+            key(vec![
+                OrderedKeyValue::String(country.to_string()),
+                OrderedKeyValue::String(name.to_string()),
+            ])
+        };
+
+        index.insert(user("VE", "Luis"), 0);
+        index.insert(user("AR", "Maria"), 1);
+        index.insert(user("VE", "Andres"), 2);
+        index.insert(user("VEN", "Ana"), 3);
+        index.insert(user("CO", "Andrea"), 4);
+        index.index.flush().unwrap();
+
+        let entries: Vec<String> = (0..5)
+            .map(|i| index.index.get_kv(i, true).unwrap().0.into())
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                r#"[String("AR"), String("Maria")]"#,
+                r#"[String("CO"), String("Andrea")]"#,
+                r#"[String("VE"), String("Andres")]"#,
+                r#"[String("VE"), String("Luis")]"#,
+                r#"[String("VEN"), String("Ana")]"#,
+            ]
+        );
+
+        let prefix = |values: Vec<&str>| {
+            OrderedIndexKey(
+                values
+                    .into_iter()
+                    .map(|v| OrderedKeyValue::String(v.to_string()))
+                    .collect(),
+            )
+        };
+        assert_eq!(sorted(index.prefix(&prefix(vec!["VE"]))), vec![0, 2]);
+        assert_eq!(
+            sorted(index.starts_with(&prefix(vec!["VE"]))),
+            vec![0, 2, 3]
+        );
+        assert_eq!(
+            sorted(index.starts_with(&prefix(vec!["VE", "An"]))),
+            vec![2]
+        );
+        assert_eq!(sorted(index.get(&user("VE", "Luis"))), vec![0]);
+        assert!(index.get(&user("VE", "Lu")).is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_long_string_keys() {
+        let (_dir, index) = new_index(None);
+        let long = |suffix: &str| {
+            key(vec![OrderedKeyValue::String(format!(
+                "{}{}",
+                "a".repeat(300),
+                suffix
+            ))])
+        };
+
+        index.insert(long("1"), 0);
+        index.insert(long("2"), 1);
+        index.insert(key(vec![OrderedKeyValue::String("b".to_string())]), 2);
+
+        // Both share the stored start of the key, so neither can be told apart nor excluded
+        assert_eq!(sorted(index.get(&long("1"))), vec![0, 1]);
+        assert_eq!(
+            sorted(index.range(Bound::Excluded(&long("1")), Bound::Unbounded)),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            sorted(index.range(Bound::Unbounded, Bound::Excluded(&long("2")))),
+            vec![0, 1]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+/// Keys are encoded in at most this many bytes, longer string values are truncated to fit.
+pub const BTREE_INDEX_KEY_SIZE: usize = 256;
+pub const BTREE_INDEX_VALUE_SIZE: usize = 8;
@@ -7,10 +7,13 @@ use crate::index_keys::IndexKeyType;
 use crate::keys::index_key_sha256::IndexKeySha256;
 use crate::types::Index;
 use crate::vals::raw_value::RawIndexValue;
+use schemajs_data::durability::Durability;
+use schemajs_data::encryption::Keyring;
+use schemajs_data::errors::ShardErrors;
 use schemajs_data::fdm::FileDescriptorManager;
 use std::fmt::Debug;
 use std::io::{Seek, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Debug)]
@@ -23,6 +26,9 @@ impl HashIndex {
         path: P,
         index_name: Option<String>,
         capacity: Option<u64>,
+        bloom_false_positive_rate: Option<f64>,
+        durability: Option<Durability>,
+        keyring: Option<Keyring>,
         fdm: Arc<FileDescriptorManager>,
     ) -> Self {
         let index_shard = IndexShard::new(
@@ -31,7 +37,10 @@ impl HashIndex {
             HASH_INDEX_KEY_SIZE,
             HASH_INDEX_VALUE_SIZE,
             capacity,
-            Some(true),
+            None,
+            bloom_false_positive_rate,
+            durability,
+            keyring,
             fdm,
         );
 
@@ -45,10 +54,15 @@ impl HashIndex {
     }
 
     pub fn find_index(&self, find: IndexKeySha256) -> Option<u64> {
-        match self.index.binary_search(find) {
-            None => return None,
-            Some((_, _, val)) => Some(u64::from_le_bytes(val.0.as_slice().try_into().unwrap())),
-        }
+        self.find_indexes(find).into_iter().next()
+    }
+
+    pub fn find_indexes(&self, find: IndexKeySha256) -> Vec<u64> {
+        self.index
+            .binary_search_all(find)
+            .into_iter()
+            .map(|val| u64::from_le_bytes(val.0.as_slice().try_into().unwrap()))
+            .collect()
     }
 }
 
@@ -76,17 +90,32 @@ impl Index for HashIndex {
             .insert(key, row_position.to_le_bytes().to_vec().into());
     }
 
-    fn get(&self, key: &IndexKeyType) -> Option<u64> {
-        self.find_index(key.clone().into_sha256().unwrap())
+    fn get(&self, key: &IndexKeyType) -> Vec<u64> {
+        self.find_indexes(key.clone().into_sha256().unwrap())
     }
 
-    fn remove(&mut self, key: &IndexKeyType) -> Option<u64> {
-        todo!()
+    fn remove(&mut self, key: &IndexKeyType, row_position: u64) -> bool {
+        self.index.remove(
+            key.clone().into_sha256().unwrap(),
+            row_position.to_le_bytes().to_vec().into(),
+        )
     }
 
     fn supported_search_operators(&self) -> Vec<String> {
         vec![String::from("=")]
     }
+
+    fn verify(&self) -> Vec<(PathBuf, ShardErrors)> {
+        self.index.data.read().verify()
+    }
+
+    fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        self.index.data.read().reencrypt()
+    }
+
+    fn merge(&self) -> Result<bool, ShardErrors> {
+        self.index.merge_runs()
+    }
 }
 
 #[cfg(test)]
@@ -112,6 +141,9 @@ mod test {
             hashindx.clone(),
             None,
             None,
+            None,
+            None,
+            None,
             Arc::new(FileDescriptorManager::new(2500)),
         );
 
@@ -132,6 +164,9 @@ mod test {
             hashindx.clone(),
             None,
             Some(2),
+            None,
+            None,
+            None,
             Arc::new(FileDescriptorManager::new(2500)),
         );
 
@@ -140,6 +175,99 @@ mod test {
         std::fs::remove_dir_all(hashindx).unwrap();
     }
 
+    #[tokio::test]
+    pub async fn test_remove_keys() {
+        let temp_dir = tempdir().unwrap();
+
+        let hashindx = temp_dir.as_ref().to_path_buf().join("hashindx");
+        std::fs::create_dir(hashindx.clone()).unwrap();
+
+        // Removals must work on past shards as well as on the current one
+        let mut index = HashIndex::new_from_path(
+            hashindx.clone(),
+            None,
+            Some(2),
+            None,
+            None,
+            None,
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+
+        let key =
+            |i: u64| index.to_key(CompositeKey(vec![(String::from("user_id"), i.to_string())]));
+        let keys: Vec<IndexKeyType> = (0..6).map(key).collect();
+
+        for (pos, key) in keys.iter().enumerate() {
+            index.insert(key.clone(), pos as u64);
+        }
+        index.index.flush().unwrap();
+
+        assert!(index.remove(&keys[1], 1));
+        assert!(index.remove(&keys[4], 4));
+        assert!(!index.remove(&keys[4], 4));
+        assert!(!index.remove(&keys[2], 3));
+
+        for (pos, key) in keys.iter().enumerate() {
+            if pos == 1 || pos == 4 {
+                assert!(index.get(key).is_empty());
+            } else {
+                assert_eq!(index.get(key), vec![pos as u64]);
+            }
+        }
+
+        std::fs::remove_dir_all(hashindx).unwrap();
+    }
+
+    #[tokio::test]
+    pub async fn test_duplicate_keys() {
+        let temp_dir = tempdir().unwrap();
+
+        let hashindx = temp_dir.as_ref().to_path_buf().join("hashindx");
+        std::fs::create_dir(hashindx.clone()).unwrap();
+
+        // Entries sharing a key end up in the same run, in different ones and in the buffer
+        let mut index = HashIndex::new_from_path(
+            hashindx.clone(),
+            None,
+            Some(3),
+            None,
+            None,
+            None,
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+
+        let key = |enabled: bool| {
+            index.to_key(CompositeKey(vec![(
+                String::from("enabled"),
+                enabled.to_string(),
+            )]))
+        };
+        let (enabled, disabled) = (key(true), key(false));
+
+        for pos in 0..8u64 {
+            let key = if pos % 3 == 0 { &disabled } else { &enabled };
+            index.insert(key.clone(), pos);
+
+            if pos % 3 == 2 {
+                index.index.flush().unwrap();
+            }
+        }
+
+        let sorted = |mut positions: Vec<u64>| {
+            positions.sort();
+            positions
+        };
+        assert_eq!(sorted(index.get(&enabled)), vec![1, 2, 4, 5, 7]);
+        assert_eq!(sorted(index.get(&disabled)), vec![0, 3, 6]);
+
+        assert!(index.remove(&enabled, 4));
+        assert!(!index.remove(&enabled, 3));
+        assert_eq!(sorted(index.get(&enabled)), vec![1, 2, 5, 7]);
+        assert_eq!(sorted(index.get(&disabled)), vec![0, 3, 6]);
+
+        std::fs::remove_dir_all(hashindx).unwrap();
+    }
+
     fn add_data(index: &mut HashIndex) {
         let usernames = vec![
             String::from("user1"),
@@ -207,6 +335,11 @@ mod test {
             println!("Adding {}", key.to_string());
 
             index.insert(IndexKeyType::Sha256(key), rand::random());
+
+            // Part of the entries are written as runs, the rest stay in the buffer
+            if i == 19 {
+                index.index.flush().unwrap();
+            }
         }
 
         println!("After loop");
@@ -1 +1,2 @@
+pub mod btree;
 pub mod hash;
@@ -1,4 +1,5 @@
 use crate::keys::index_key_sha256::IndexKeySha256;
+use crate::keys::ordered_key::OrderedIndexKey;
 use crate::keys::string_index::StringIndexKey;
 use enum_as_inner::EnumAsInner;
 
@@ -6,4 +7,5 @@ use enum_as_inner::EnumAsInner;
 pub enum IndexKeyType {
     Sha256(IndexKeySha256),
     String(StringIndexKey),
+    Ordered(OrderedIndexKey),
 }
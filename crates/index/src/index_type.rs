@@ -1,3 +1,4 @@
+use crate::implementations::btree::btree_index::BTreeIndex;
 use crate::implementations::hash::hash_index::HashIndex;
 use crate::types::{Index, IndexKey};
 use enum_as_inner::EnumAsInner;
@@ -6,17 +7,27 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, EnumAsInner, Clone, PartialEq, Serialize, Deserialize)]
 pub enum IndexType {
     Hash,
+    BTree,
 }
 
 #[derive(Debug)]
 pub enum IndexTypeValue {
     Hash(HashIndex),
+    BTree(BTreeIndex),
 }
 
 impl IndexTypeValue {
     pub fn as_index(&self) -> Box<&dyn Index> {
         match self {
             IndexTypeValue::Hash(indx) => Box::new(indx),
+            IndexTypeValue::BTree(indx) => Box::new(indx),
+        }
+    }
+
+    pub fn as_index_mut(&mut self) -> Box<&mut dyn Index> {
+        match self {
+            IndexTypeValue::Hash(indx) => Box::new(indx),
+            IndexTypeValue::BTree(indx) => Box::new(indx),
         }
     }
 }
@@ -1,2 +1,3 @@
 pub mod index_key_sha256;
+pub mod ordered_key;
 pub mod string_index;
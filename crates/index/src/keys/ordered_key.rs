@@ -0,0 +1,212 @@
+use crate::data::index_data_unit::IndexDataUnit;
+use crate::types::IndexKey;
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+const NULL_TAG: u8 = 0;
+const BOOLEAN_TAG: u8 = 1;
+const NUMBER_TAG: u8 = 2;
+const STRING_TAG: u8 = 3;
+const UUID_TAG: u8 = 4;
+
+/// Gives a single representation to numbers that are equal, so they share a key:
+/// `-0.0` becomes `0.0` and every NaN the same NaN.
+fn canonical_number(n: f64) -> f64 {
+    if n == 0.0 {
+        0.0
+    } else if n.is_nan() {
+        f64::NAN
+    } else {
+        n
+    }
+}
+
+/// Value of a column in an `OrderedIndexKey`.
+///
+/// Values are ordered the same way `DataValue` is in `schemajs_primitives`:
+/// nulls first, then booleans, numbers, strings and uuids, each compared by value.
+#[derive(Debug, Clone)]
+pub enum OrderedKeyValue {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Uuid(Uuid),
+}
+
+impl OrderedKeyValue {
+    /// Number value of a key, see `canonical_number`.
+    pub fn number(n: f64) -> Self {
+        OrderedKeyValue::Number(canonical_number(n))
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            OrderedKeyValue::Null => NULL_TAG,
+            OrderedKeyValue::Boolean(_) => BOOLEAN_TAG,
+            OrderedKeyValue::Number(_) => NUMBER_TAG,
+            OrderedKeyValue::String(_) => STRING_TAG,
+            OrderedKeyValue::Uuid(_) => UUID_TAG,
+        }
+    }
+
+    fn encoded_size(&self) -> usize {
+        1 + match self {
+            OrderedKeyValue::Null => 0,
+            OrderedKeyValue::Boolean(_) => 1,
+            OrderedKeyValue::Number(_) => 8,
+            OrderedKeyValue::String(s) => 4 + s.len(),
+            OrderedKeyValue::Uuid(_) => 16,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.tag());
+        match self {
+            OrderedKeyValue::Null => {}
+            OrderedKeyValue::Boolean(b) => buf.push(*b as u8),
+            OrderedKeyValue::Number(n) => buf.extend(canonical_number(*n).to_le_bytes()),
+            OrderedKeyValue::String(s) => {
+                buf.extend((s.len() as u32).to_le_bytes());
+                buf.extend(s.as_bytes());
+            }
+            OrderedKeyValue::Uuid(id) => buf.extend(id.as_bytes()),
+        }
+    }
+
+    /// Decodes the value at the start of `data`, returning it along with the remaining bytes.
+    fn decode(data: &[u8]) -> Option<(Self, &[u8])> {
+        let (tag, data) = data.split_first()?;
+        match *tag {
+            NULL_TAG => Some((OrderedKeyValue::Null, data)),
+            BOOLEAN_TAG => {
+                let (b, data) = data.split_first()?;
+                Some((OrderedKeyValue::Boolean(*b != 0), data))
+            }
+            NUMBER_TAG => {
+                let n = f64::from_le_bytes(data.get(0..8)?.try_into().ok()?);
+                Some((OrderedKeyValue::number(n), &data[8..]))
+            }
+            STRING_TAG => {
+                let len = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?) as usize;
+                let s = String::from_utf8(data.get(4..4 + len)?.to_vec()).ok()?;
+                Some((OrderedKeyValue::String(s), &data[4 + len..]))
+            }
+            UUID_TAG => {
+                let id = Uuid::from_slice(data.get(0..16)?).ok()?;
+                Some((OrderedKeyValue::Uuid(id), &data[16..]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Ord for OrderedKeyValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (OrderedKeyValue::Boolean(lhs), OrderedKeyValue::Boolean(rhs)) => lhs.cmp(rhs),
+            (OrderedKeyValue::Number(lhs), OrderedKeyValue::Number(rhs)) => {
+                canonical_number(*lhs).total_cmp(&canonical_number(*rhs))
+            }
+            (OrderedKeyValue::String(lhs), OrderedKeyValue::String(rhs)) => lhs.cmp(rhs),
+            (OrderedKeyValue::Uuid(lhs), OrderedKeyValue::Uuid(rhs)) => lhs.cmp(rhs),
+            _ => self.tag().cmp(&other.tag()),
+        }
+    }
+}
+
+impl PartialOrd for OrderedKeyValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for OrderedKeyValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OrderedKeyValue {}
+
+/// Key made of the values of the columns of an index, in the order of its members.
+///
+/// Keys are compared column by column, so rows are ordered by the first member of the index,
+/// then by the second one, and so on.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OrderedIndexKey(pub Vec<OrderedKeyValue>);
+
+impl OrderedIndexKey {
+    /// Compares the key against `prefix`, only looking at as many columns as `prefix` has.
+    /// A key starting with every value of `prefix` is `Equal` to it.
+    pub fn cmp_prefix(&self, prefix: &OrderedIndexKey) -> Ordering {
+        for (val, prefix_val) in self.0.iter().zip(prefix.0.iter()) {
+            match val.cmp(prefix_val) {
+                Ordering::Equal => continue,
+                ord => return ord,
+            }
+        }
+
+        self.0.len().min(prefix.0.len()).cmp(&prefix.0.len())
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        self.0.iter().map(|val| val.encoded_size()).sum()
+    }
+
+    /// Shortens the string values of the key, starting from the last one, until it is encoded in `max_size` bytes.
+    pub fn truncate(&mut self, max_size: usize) {
+        let mut excess = self.encoded_size().saturating_sub(max_size);
+        for val in self.0.iter_mut().rev() {
+            if excess == 0 {
+                return;
+            }
+
+            if let OrderedKeyValue::String(s) = val {
+                let mut len = s.len().saturating_sub(excess);
+                while !s.is_char_boundary(len) {
+                    len -= 1;
+                }
+                excess = excess.saturating_sub(s.len() - len);
+                s.truncate(len);
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for OrderedIndexKey {
+    fn from(value: Vec<u8>) -> Self {
+        let mut values = vec![];
+        let mut data = value.as_slice();
+        while let Some((val, rest)) = OrderedKeyValue::decode(data) {
+            values.push(val);
+            data = rest;
+        }
+
+        OrderedIndexKey(values)
+    }
+}
+
+impl From<OrderedIndexKey> for Vec<u8> {
+    fn from(value: OrderedIndexKey) -> Self {
+        let mut buf = Vec::with_capacity(value.encoded_size());
+        for val in &value.0 {
+            val.encode(&mut buf);
+        }
+        buf
+    }
+}
+
+impl From<OrderedIndexKey> for String {
+    fn from(value: OrderedIndexKey) -> Self {
+        format!("{:?}", value.0)
+    }
+}
+
+impl From<IndexDataUnit> for OrderedIndexKey {
+    fn from(value: IndexDataUnit) -> Self {
+        OrderedIndexKey::from(value.data)
+    }
+}
+
+impl IndexKey for OrderedIndexKey {}
@@ -1,7 +1,11 @@
 use crate::composite_key::CompositeKey;
 use crate::data::index_data_unit::IndexDataUnit;
 use crate::index_keys::IndexKeyType;
+use crate::keys::ordered_key::OrderedIndexKey;
+use schemajs_data::errors::ShardErrors;
 use std::fmt::Debug;
+use std::ops::Bound;
+use std::path::PathBuf;
 
 pub trait IndexKey:
     From<Vec<u8>> + Into<Vec<u8>> + Ord + Clone + Into<String> + From<IndexDataUnit>
@@ -13,13 +17,39 @@ pub trait IndexValue: From<Vec<u8>> + Into<Vec<u8>> + Clone + From<IndexDataUnit
 pub trait Index: Debug {
     fn to_key(&self, key: CompositeKey) -> IndexKeyType;
 
+    /// Builds the key of an index keeping its keys in value order.
+    /// Returns `None` for indexes whose keys are built by `to_key`.
+    fn to_ordered_key(&self, _key: OrderedIndexKey) -> Option<IndexKeyType> {
+        None
+    }
+
     fn bulk_insert(&self, data: Vec<(IndexKeyType, u64)>);
 
     fn insert(&self, key: IndexKeyType, row_position: u64);
 
-    fn get(&self, key: &IndexKeyType) -> Option<u64>;
+    /// Returns the position of every row indexed under `key`.
+    fn get(&self, key: &IndexKeyType) -> Vec<u64>;
+
+    /// Removes the entry of `key` pointing to `row_position`, returning whether it existed.
+    /// Other rows indexed under the same key keep their entries.
+    fn remove(&mut self, key: &IndexKeyType, row_position: u64) -> bool;
 
-    fn remove(&mut self, key: &IndexKeyType) -> Option<u64>;
+    /// Returns the position of every row whose key falls within `start` and `end`.
+    /// Bounds with fewer columns than the index match every key starting with them.
+    /// Only indexes reporting range operators in `supported_search_operators` return any row.
+    fn range(&self, _start: Bound<&IndexKeyType>, _end: Bound<&IndexKeyType>) -> Vec<u64> {
+        vec![]
+    }
 
     fn supported_search_operators(&self) -> Vec<String>;
+
+    /// Verifies the shards holding the index, returning the path of the damaged ones along with the error found.
+    fn verify(&self) -> Vec<(PathBuf, ShardErrors)>;
+
+    /// Rewrites the first shard of the index written with an encryption key other than the current one,
+    /// returning whether it did.
+    fn reencrypt(&self) -> Result<bool, ShardErrors>;
+
+    /// Merges the sorted runs the index is kept in once there are too many of them, returning whether it did.
+    fn merge(&self) -> Result<bool, ShardErrors>;
 }
@@ -4,6 +4,7 @@ use dashmap::DashMap;
 use parking_lot::RwLock;
 use schemajs_engine::engine::SchemeJsEngine;
 use schemajs_engine::engine_db::EngineDb;
+use schemajs_engine::with_query_manager;
 use schemajs_primitives::column::types::DataValue;
 use schemajs_query::ops::query_ops::{QueryOps, QueryVal};
 use schemajs_query::row::Row;
@@ -111,45 +112,42 @@ impl AuthManager {
         let search_users = Self::search_user(db, user);
 
         if search_users.is_none() {
-            let tbl = db
-                .query_manager
-                .get_table(INTERNAL_USER_TABLE_NAME)
+            with_query_manager!(db.query_manager, query_manager => {
+                let tbl = query_manager.get_table(INTERNAL_USER_TABLE_NAME).unwrap();
+                let user_row = Row::from_json(
+                    serde_json::to_value(create_user(
+                        user.clone(),
+                        pass.clone(),
+                        true,
+                        true,
+                        vec![],
+                        db_name.to_string(),
+                    ))
+                    .unwrap(),
+                    tbl,
+                )
                 .unwrap();
-            let user_row = RowJson::from_json(
-                serde_json::to_value(create_user(
-                    user.clone(),
-                    pass.clone(),
-                    true,
-                    true,
-                    vec![],
-                    db_name.to_string(),
-                ))
-                .unwrap(),
-                tbl,
-            )
-            .unwrap();
-            let _ = db.query_manager.raw_insert(&mut [user_row], true).unwrap();
+                let _ = query_manager.raw_insert(&mut [user_row], true).unwrap();
+            });
         }
     }
 
     fn search_user(db: &EngineDb, scheme_username: &String) -> Option<RowJson> {
-        let users = db
-            .query_manager
-            .search_manager
-            .search(
-                INTERNAL_USER_TABLE_NAME,
-                &QueryOps::And(vec![QueryOps::Condition(QueryVal {
-                    key: "identifier".to_string(),
-                    filter_type: "=".to_string(),
-                    value: DataValue::String(scheme_username.clone()),
-                })]),
-            )
-            .unwrap();
-
-        if !users.is_empty() {
-            Some(users[0].clone())
-        } else {
-            None
-        }
+        let query = QueryOps::And(vec![QueryOps::Condition(QueryVal {
+            key: "identifier".to_string(),
+            filter_type: "=".to_string(),
+            value: DataValue::String(scheme_username.clone()),
+        })]);
+
+        // Users are read back as JSON rows whatever format the database stores them in.
+        with_query_manager!(db.query_manager, query_manager => {
+            let users = query_manager
+                .search_manager
+                .search(INTERNAL_USER_TABLE_NAME, &query)
+                .unwrap();
+
+            let user = users.first()?;
+            RowJson::from_map(user.get_table(), user.to_map().ok()?).ok()
+        })
     }
 }
@@ -3,6 +3,7 @@ use crate::get_internal_tables;
 use parking_lot::RwLock;
 use schemajs_config::SchemeJsConfig;
 use schemajs_engine::engine::SchemeJsEngine;
+use schemajs_query::errors::QueryError;
 use std::sync::Arc;
 
 pub struct InternalManager {
@@ -22,7 +23,7 @@ impl InternalManager {
         self._engine.read().config.clone()
     }
 
-    pub fn init(&self) {
+    pub fn init(&self) -> Result<(), QueryError> {
         {
             let mut writer = self._engine.write();
             let default_scheme_name = writer.config.global.default_scheme.clone();
@@ -43,7 +44,7 @@ impl InternalManager {
                 .map(|e| e.name.clone())
                 .collect();
             for schema_name in &db_names {
-                read_engine.register_tables(schema_name, get_internal_tables());
+                read_engine.register_tables(schema_name, get_internal_tables())?;
             }
 
             db_names
@@ -54,6 +55,8 @@ impl InternalManager {
                 self.auth_manager.init_default_user(&db_name);
             }
         }
+
+        Ok(())
     }
 
     pub fn engine(&self) -> Arc<RwLock<SchemeJsEngine>> {
@@ -22,7 +22,11 @@ pub const INTERNAL_USER_TABLE_NAME: &str = "sjs_users";
 
 pub(crate) static INTERNAL_USER_TABLE: LazyLock<Table> = LazyLock::new(|| {
     let mut tbl = Table::new(INTERNAL_USER_TABLE_NAME)
-        .add_column(Column::new("identifier", DataTypes::String).set_required(true))
+        .add_column(
+            Column::new("identifier", DataTypes::String)
+                .set_required(true)
+                .set_unique(true),
+        )
         .add_column(
             Column::new("scheme", DataTypes::String)
                 .set_required(true)
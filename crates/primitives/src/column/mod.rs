@@ -12,6 +12,9 @@ pub struct Column {
     pub comment: Option<String>,
     pub primary_key: bool,
     pub default_index: Option<bool>,
+    /// Rejects inserting a row whose value of the column another row already has.
+    #[serde(default, rename = "isUnique")]
+    pub unique: bool,
 }
 
 impl Column {
@@ -24,6 +27,7 @@ impl Column {
             required: false,
             primary_key: false,
             default_index: Some(true),
+            unique: false,
         }
     }
 
@@ -51,4 +55,9 @@ impl Column {
         self.default_index = Some(default_index);
         self
     }
+
+    pub fn set_unique(mut self, unique: bool) -> Self {
+        self.unique = unique;
+        self
+    }
 }
@@ -1,5 +1,6 @@
 use crate::column::Column;
 use enum_as_inner::EnumAsInner;
+use schemajs_index::keys::ordered_key::OrderedKeyValue;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use serde_json::Value;
@@ -23,6 +24,9 @@ pub enum DataValue {
     String(String),
     Boolean(bool),
     Number(serde_json::Number),
+    /// Reference to a value too large to be kept inline, stored in the blob store of its table.
+    /// It is replaced by the actual value before the row is returned.
+    Blob(Uuid),
 }
 
 impl DataValue {
@@ -33,6 +37,7 @@ impl DataValue {
             DataValue::Boolean(_) => DataTypes::Boolean,
             DataValue::Number(_) => DataTypes::Number,
             DataValue::Uuid(_) => DataTypes::Uuid,
+            DataValue::Blob(_) => DataTypes::String,
         }
     }
 
@@ -43,6 +48,7 @@ impl DataValue {
             DataValue::Boolean(b) => Value::Bool(b.clone()),
             DataValue::Number(n) => Value::Number(n.clone()),
             DataValue::Uuid(val) => Value::String(val.to_string()),
+            DataValue::Blob(id) => Value::String(id.to_string()),
         }
     }
 
@@ -53,6 +59,7 @@ impl DataValue {
             DataValue::Boolean(b) => b.to_string(),
             DataValue::Number(n) => n.to_string().to_string(),
             DataValue::Uuid(val) => val.to_string(),
+            DataValue::Blob(id) => id.to_string(),
         }
     }
 }
@@ -86,6 +93,7 @@ impl PartialEq for DataValue {
             DataValue::String(val) => val == other.as_string().unwrap(),
             DataValue::Boolean(val) => val == other.as_boolean().unwrap(),
             DataValue::Number(n) => n == other.as_number().unwrap(),
+            DataValue::Blob(id) => other.as_blob() == Some(id),
         }
     }
 }
@@ -113,6 +121,10 @@ impl PartialOrd for DataValue {
             (_, DataValue::String(_)) => Some(Ordering::Greater),
 
             (DataValue::Uuid(lhs), DataValue::Uuid(rhs)) => lhs.partial_cmp(rhs),
+            (DataValue::Uuid(_), _) => Some(Ordering::Less),
+            (_, DataValue::Uuid(_)) => Some(Ordering::Greater),
+
+            (DataValue::Blob(lhs), DataValue::Blob(rhs)) => lhs.partial_cmp(rhs),
         }
     }
 }
@@ -123,6 +135,19 @@ impl Ord for DataValue {
     }
 }
 
+/// Index keys keep values in the same order as `DataValue`, so ordered indexes can answer range queries.
+impl From<&DataValue> for OrderedKeyValue {
+    fn from(value: &DataValue) -> Self {
+        match value {
+            DataValue::Null => OrderedKeyValue::Null,
+            DataValue::Boolean(b) => OrderedKeyValue::Boolean(*b),
+            DataValue::Number(n) => OrderedKeyValue::number(n.as_f64().unwrap_or_default()),
+            DataValue::String(s) => OrderedKeyValue::String(s.clone()),
+            DataValue::Uuid(id) | DataValue::Blob(id) => OrderedKeyValue::Uuid(*id),
+        }
+    }
+}
+
 macro_rules! data_value_from {
     ($variant:ident, $type:ty, $converter:expr) => {
         impl From<$type> for DataValue {
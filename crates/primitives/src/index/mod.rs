@@ -6,4 +6,8 @@ pub struct Index {
     pub name: String,
     pub members: Vec<String>,
     pub index_type: IndexType,
+    /// Rejects inserting a row whose values of the members another row already has.
+    /// Rows with a null in any member are not checked.
+    #[serde(default)]
+    pub unique: bool,
 }
@@ -8,6 +8,7 @@ export class Column {
     public required: boolean = false;
     public primaryKey: boolean = false;
     public defaultIndex: boolean = true;
+    public isUnique: boolean = false;
 
     constructor(name: string, dataType?: DataTypes) {
         this.name = name;
@@ -29,6 +30,11 @@ export class Column {
         return this;
     }
 
+    unique(data: boolean = true) {
+        this.isUnique = data;
+        return this;
+    }
+
     withComment(comment: string) {
         this.comment = comment;
         return this;
@@ -6,6 +6,7 @@ export class Table {
     public columns: Record<string, Column> = {};
     public indexes = [];
     public primary_key = "_uid";
+    public ttl_seconds?: number;
     public helpers: Helper[] = [];
 
     constructor(name: string) {
@@ -17,6 +18,11 @@ export class Table {
         return this;
     }
 
+    ttl(seconds: number) {
+        this.ttl_seconds = seconds;
+        return this;
+    }
+
     addQuery(name: string, cb: any) {
         this.helpers.push(new Helper(name, HelperType.CustomQuery, cb));
         return this;
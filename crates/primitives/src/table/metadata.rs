@@ -5,6 +5,11 @@ use serde::{Deserialize, Serialize};
 pub struct TableMetadata {
     pub module_id: Option<ModuleId>,
     pub internal: bool,
+    /// Sorted column names of every schema the table has had. Rows stored by position name the version
+    /// they were written with, so they keep decoding after columns are added.
+    pub schema_versions: Vec<Vec<String>>,
+    /// Version of the current schema within `schema_versions`.
+    pub schema_version: usize,
 }
 
 impl TableMetadata {
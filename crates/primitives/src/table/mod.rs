@@ -6,6 +6,7 @@ use crate::index::Index;
 use crate::table::metadata::TableMetadata;
 use schemajs_index::index_type::IndexType;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::sync::LazyLock;
 
@@ -15,6 +16,9 @@ pub struct Table {
     pub columns: HashMap<String, Column>,
     pub indexes: Vec<Index>,
     pub primary_key: String,
+    /// Seconds rows live after being inserted. Rows never expire if it is not set.
+    #[serde(default, rename = "ttl_seconds")]
+    pub ttl: Option<u64>,
     #[serde(skip_serializing, skip_deserializing)]
     pub metadata: TableMetadata,
 }
@@ -25,10 +29,23 @@ static UID_COL: LazyLock<Column> = LazyLock::new(|| {
         .set_primary_key(true)
 });
 
+// Holds the moment (in seconds since the Unix epoch) a row expires, for tables with a TTL.
+static EXPIRES_AT_COL: LazyLock<Column> =
+    LazyLock::new(|| Column::new("_expires_at", DataTypes::Number));
+
+// Keeps the rows of tables with a TTL ordered by the moment they expire, so expired rows are found without a scan.
+static EXPIRES_AT_INDEX: LazyLock<Index> = LazyLock::new(|| Index {
+    name: "_expires_at_indx".to_string(),
+    members: vec!["_expires_at".to_string()],
+    index_type: IndexType::BTree,
+    unique: false,
+});
+
 static UID_INDEX: LazyLock<Index> = LazyLock::new(|| Index {
     name: "uidindx".to_string(),
     members: vec!["_uid".to_string()],
     index_type: IndexType::Hash,
+    unique: false,
 });
 
 impl Table {
@@ -38,6 +55,7 @@ impl Table {
             columns: HashMap::from([("_uid".to_string(), Self::get_internal_uid().clone())]),
             metadata: Default::default(),
             primary_key: "_uid".to_string(),
+            ttl: None,
             indexes: vec![Self::get_internal_uid_index().clone()],
         }
     }
@@ -46,16 +64,22 @@ impl Table {
         self.columns
             .insert("_uid".to_string(), Self::get_internal_uid().clone());
 
+        if self.ttl.is_some() {
+            self.add_expires_at_column();
+        }
+
         for (col_name, col) in &self.columns {
             if col_name == "_uid" {
                 continue;
             }
 
-            if col.default_index.unwrap_or(false) {
+            // Unique columns are checked through their index, so they always get one.
+            if col.default_index.unwrap_or(false) || col.unique {
                 self.indexes.push(Index {
                     name: format!("{}_indx", col_name),
                     members: vec![col_name.to_string()],
                     index_type: IndexType::Hash,
+                    unique: col.unique,
                 });
             }
         }
@@ -67,10 +91,36 @@ impl Table {
         &*UID_COL
     }
 
+    pub fn get_internal_expires_at<'a>() -> &'a Column {
+        &EXPIRES_AT_COL
+    }
+
     fn get_internal_uid_index<'a>() -> &'a Index {
         &*UID_INDEX
     }
 
+    pub fn get_internal_expires_at_index<'a>() -> &'a Index {
+        &EXPIRES_AT_INDEX
+    }
+
+    fn add_expires_at_column(&mut self) {
+        let col = Self::get_internal_expires_at();
+        self.columns.insert(col.name.clone(), col.clone());
+
+        let index = Self::get_internal_expires_at_index();
+        if !self.indexes.iter().any(|i| i.name == index.name) {
+            self.indexes.push(index.clone());
+        }
+    }
+
+    /// Makes the rows of the table expire `seconds` after being inserted.
+    /// A row can still set its own expiry through the `_expires_at` column.
+    pub fn set_ttl(mut self, seconds: u64) -> Self {
+        self.ttl = Some(seconds);
+        self.add_expires_at_column();
+        self
+    }
+
     pub fn add_index(mut self, index: Index) -> Self {
         self.indexes.push(index);
         self
@@ -94,6 +144,43 @@ impl Table {
         self.columns.get(column_name)
     }
 
+    /// Names of the columns of the table, sorted.
+    pub fn sorted_column_names(&self) -> Vec<String> {
+        let mut columns: Vec<String> = self.columns.keys().cloned().collect();
+        columns.sort();
+        columns
+    }
+
+    /// Makes the current columns of the table its current schema version, adding them to
+    /// `metadata.schema_versions` unless a previous version had the same ones. Returns whether they were added.
+    pub fn register_schema_version(&mut self) -> bool {
+        let columns = self.sorted_column_names();
+        let versions = &mut self.metadata.schema_versions;
+        match versions.iter().position(|version| *version == columns) {
+            Some(version) => {
+                self.metadata.schema_version = version;
+                false
+            }
+            None => {
+                versions.push(columns);
+                self.metadata.schema_version = versions.len() - 1;
+                true
+            }
+        }
+    }
+
+    /// Sorted column names of `version` of the schema of the table.
+    /// Tables that never registered a version only know the current one, made of their current columns.
+    pub fn schema_version_columns(&self, version: usize) -> Option<Cow<'_, [String]>> {
+        match self.metadata.schema_versions.get(version) {
+            Some(columns) => Some(Cow::Borrowed(columns)),
+            None if version == self.metadata.schema_version => {
+                Some(Cow::Owned(self.sorted_column_names()))
+            }
+            None => None,
+        }
+    }
+
     pub fn list_columns(&self) -> Vec<&String> {
         self.columns.keys().collect()
     }
@@ -27,9 +27,21 @@ pub enum QueryError {
     #[error("Invalid Insertion")]
     InvalidInsertion,
 
+    #[error("Unknown column '{0}'")]
+    UnknownColumn(String),
+
+    #[error("Value of column '{0}' is already present in table")]
+    DuplicateValue(String),
+
     #[error("A Shard Error has occured")]
     ShardError(#[from] ShardErrors),
 
+    #[error("Rows could not be transferred: {0}")]
+    TransferError(String),
+
     #[error("Row could not be serialized")]
     SerializationError(#[from] RowSerializationError),
+
+    #[error("Invalid database configuration: {0}")]
+    ConfigError(String),
 }
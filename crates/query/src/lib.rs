@@ -5,8 +5,10 @@ pub mod errors;
 pub mod managers;
 pub mod ops;
 pub mod row;
+pub mod row_binary;
 pub mod row_json;
 mod search;
+pub mod transfer;
 
 #[derive(Debug, Error, Serialize, Deserialize, Clone)]
 pub enum RowSerializationError {
@@ -1,8 +1,11 @@
 pub mod table_shard;
 
 use crate::errors::QueryError;
-use crate::managers::single::table_shard::TableShard;
-use crate::row::Row;
+use crate::managers::single::table_shard::{
+    durability_from_config, keyring_from_config, TableShard,
+};
+use crate::ops::query_ops::{QueryOps, QueryVal};
+use crate::row::{unix_now, Row};
 use crate::search::search_manager::QuerySearchManager;
 use chashmap::CHashMap;
 use schemajs_config::DatabaseConfig;
@@ -12,12 +15,13 @@ use schemajs_data::shard::temp_map_shard::DataWithIndex;
 use schemajs_data::temp_offset_types::TempOffsetTypes;
 use schemajs_helpers::helper::HelperCall;
 use schemajs_primitives::column::types::DataValue;
+use schemajs_primitives::index::Index;
 use schemajs_primitives::table::Table;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use tokio::sync::mpsc::Sender;
 use uuid::Uuid;
 
@@ -48,6 +52,10 @@ pub struct SingleQueryManager<T: Row> {
     pub database_config: Arc<DatabaseConfig>,
 
     pub fdm: Arc<FileDescriptorManager>,
+
+    // Held while rows of tables with unique indexes are checked and written, by inserts and by updates
+    // of unique columns, so concurrent writes cannot both pass the checks with the same values.
+    unique_inserts: Mutex<()>,
 }
 
 /// `SingleQueryManager` is responsible for managing all query-related operations
@@ -96,6 +104,7 @@ impl<T: Row> SingleQueryManager<T> {
             helper_tx,
             database_config,
             fdm: file_descriptor_manager,
+            unique_inserts: Mutex::new(()),
         }
     }
 
@@ -111,28 +120,33 @@ impl<T: Row> SingleQueryManager<T> {
     /// use schemajs_query::row_json::RowJson;
     ///
     /// let query_manager: SingleQueryManager<RowJson> = SingleQueryManager::new("database-name".to_string());
-    /// query_manager.register_table(Table::new("users"));
+    /// query_manager.register_table(Table::new("users")).unwrap();
     /// ```
     ///
     /// Note `register_table` will panic due to `No such file or directory` due to the database must have a folder already created in system.
-    pub fn register_table(&self, table: Table) {
-        self.table_names.write().unwrap().push(table.name.clone());
-        self.tables.insert(
-            table.name.clone(),
-            TableShard::<T>::new(
-                table,
-                self.data_path.clone(),
-                self.scheme.as_str(),
-                TempDataShardConfig {
-                    max_offsets: TempOffsetTypes::Custom(Some(
-                        self.database_config.max_rows_per_temp_shard,
-                    )),
-                },
-                self.helper_tx.clone(),
-                &self.database_config,
-                self.fdm.clone(),
-            ),
-        );
+    /// Returns a `QueryError::ConfigError` if the encryption keys of the database cannot be loaded.
+    pub fn register_table(&self, table: Table) -> Result<(), QueryError> {
+        let name = table.name.clone();
+        let shard = TableShard::<T>::new(
+            table,
+            self.data_path.clone(),
+            self.scheme.as_str(),
+            TempDataShardConfig {
+                max_offsets: TempOffsetTypes::Custom(Some(
+                    self.database_config.max_rows_per_temp_shard,
+                )),
+                durability: durability_from_config(&self.database_config),
+                keyring: keyring_from_config(&self.database_config)?,
+            },
+            self.helper_tx.clone(),
+            &self.database_config,
+            self.fdm.clone(),
+        )?;
+
+        self.table_names.write().unwrap().push(name.clone());
+        self.tables.insert(name, shard);
+
+        Ok(())
     }
 
     pub fn insert_from_value_map(
@@ -200,6 +214,12 @@ impl<T: Row> SingleQueryManager<T> {
         let mut table_inserts: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
         let mut id = None;
 
+        let _unique_guard = rows
+            .iter()
+            .any(|row| row.get_table().indexes.iter().any(|index| index.unique))
+            .then(|| self.unique_inserts.lock().unwrap());
+        self.check_unique(rows, &HashSet::new(), |_| true)?;
+
         for row in rows.iter_mut() {
             let table_name = row.get_table_name();
 
@@ -219,6 +239,19 @@ impl<T: Row> SingleQueryManager<T> {
                 }
             }
 
+            // Expiry, unless the row sets its own
+            if let Some(ttl) = row.get_table().ttl {
+                let expires_at_col = Table::get_internal_expires_at();
+                if row.get_value(expires_at_col).is_none() {
+                    row.set_value(expires_at_col, DataValue::Number((unix_now() + ttl).into()));
+                }
+            }
+
+            // Large values
+            if let Some(table_shard) = self.tables.get(&table_name) {
+                table_shard.offload_values(row)?;
+            }
+
             let serialized_value = row.to_vec().map_err(|_| QueryError::InvalidSerialization)?;
 
             table_inserts
@@ -242,7 +275,7 @@ impl<T: Row> SingleQueryManager<T> {
                             table_shard.table.clone(),
                             table_shard.indexes.clone(),
                             vec![(
-                                T::from_slice(row, table_shard.table.clone()),
+                                T::from_slice(row, table_shard.table.clone())?,
                                 pointer as u64,
                             )],
                         );
@@ -256,6 +289,191 @@ impl<T: Row> SingleQueryManager<T> {
         Ok(id)
     }
 
+    /// Fails with `QueryError::DuplicateValue` if a row repeats the values of a unique index of its table,
+    /// either the ones of a row already in the table, reconciled or not, or the ones of another row of `rows`.
+    /// Only the unique indexes `is_checked` accepts are checked, and rows at the `replaced` positions are not
+    /// taken into account, as `rows` are about to replace them.
+    fn check_unique(
+        &self,
+        rows: &[T],
+        replaced: &HashSet<u64>,
+        is_checked: impl Fn(&Index) -> bool,
+    ) -> Result<(), QueryError> {
+        let mut seen = HashSet::new();
+
+        for row in rows {
+            let table = row.get_table();
+            for index in table
+                .indexes
+                .iter()
+                .filter(|index| index.unique && is_checked(index))
+            {
+                let values: Vec<DataValue> = index
+                    .members
+                    .iter()
+                    .map(|member| {
+                        table
+                            .get_column(member)
+                            .and_then(|col| row.get_value(col))
+                            .unwrap_or(DataValue::Null)
+                    })
+                    .collect();
+
+                // Like in SQL, nulls are not equal to each other
+                if values.iter().any(|val| val.is_null()) {
+                    continue;
+                }
+
+                let duplicate = || QueryError::DuplicateValue(index.members.join(", "));
+
+                let key: Vec<String> = values.iter().map(|val| val.to_string()).collect();
+                if !seen.insert((table.name.clone(), index.name.clone(), key)) {
+                    return Err(duplicate());
+                }
+
+                let ops = QueryOps::And(
+                    index
+                        .members
+                        .iter()
+                        .cloned()
+                        .zip(values)
+                        .map(|(key, value)| {
+                            QueryOps::Condition(QueryVal {
+                                key,
+                                filter_type: "=".to_string(),
+                                value,
+                            })
+                        })
+                        .collect(),
+                );
+                if self.search_manager.exists(&table.name, &ops, replaced)? {
+                    return Err(duplicate());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes every row of `table_name` matching `ops`, along with the index entries pointing to them.
+    /// Rows still sitting in temporary shards are reconciled first, so they can be matched as well.
+    /// Returns the number of deleted rows.
+    pub fn delete(&self, table_name: &str, ops: &QueryOps) -> Result<usize, QueryError> {
+        let table_shard = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| QueryError::InvalidTable(table_name.to_string()))?;
+
+        table_shard.temps.reconcile_all()?;
+
+        let rows = self.search_manager.find_rows(&table_shard, ops)?;
+
+        {
+            let data = table_shard.data.read();
+            for (pos, row) in rows.iter() {
+                data.delete_element(*pos as usize)?;
+                table_shard.remove_blobs(row)?;
+            }
+        }
+
+        let deleted = rows.len();
+
+        TableShard::<T>::remove_indexes(
+            table_shard.table.clone(),
+            table_shard.indexes.clone(),
+            rows.into_iter().map(|(pos, row)| (row, pos)).collect(),
+        );
+
+        Ok(deleted)
+    }
+
+    /// Applies `patch` (column name to new value) to every row of `table_name` matching `ops`.
+    /// Each matched row is written again as a new version and its old version is tombstoned,
+    /// so the index entries of the row are rewritten to point to the new version.
+    /// Fails with `QueryError::DuplicateValue`, without updating any row, if the patch repeats the values
+    /// of a unique index. Returns the number of updated rows.
+    pub fn update(
+        &self,
+        table_name: &str,
+        ops: &QueryOps,
+        patch: &HashMap<String, DataValue>,
+    ) -> Result<usize, QueryError> {
+        let table_shard = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| QueryError::InvalidTable(table_name.to_string()))?;
+        let table = table_shard.table.clone();
+
+        let changes = patch
+            .iter()
+            .map(|(col_name, val)| {
+                table
+                    .get_column(col_name)
+                    .map(|col| (col, val.clone()))
+                    .ok_or_else(|| QueryError::UnknownColumn(col_name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        table_shard.temps.reconcile_all()?;
+
+        let patches_unique = |index: &Index| {
+            index
+                .members
+                .iter()
+                .any(|member| patch.contains_key(member))
+        };
+        let _unique_guard = table
+            .indexes
+            .iter()
+            .any(|index| index.unique && patches_unique(index))
+            .then(|| self.unique_inserts.lock().unwrap());
+
+        let rows = self.search_manager.find_rows(&table_shard, ops)?;
+        let mut new_rows = Vec::with_capacity(rows.len());
+        for (_, row) in rows.iter() {
+            let mut new_row = T::from_slice(&row.to_vec()?, table.clone())?;
+            for (col, val) in changes.iter() {
+                new_row.set_value(col, val.clone());
+            }
+            new_rows.push(new_row);
+        }
+
+        // The rows being updated no longer hold their old values, but the other rows and the new versions do
+        let replaced: HashSet<u64> = rows.iter().map(|(pos, _)| *pos).collect();
+        self.check_unique(&new_rows, &replaced, patches_unique)?;
+
+        let mut old_versions = Vec::with_capacity(rows.len());
+        let mut new_versions = Vec::with_capacity(rows.len());
+
+        {
+            let mut data = table_shard.data.write();
+            for ((pos, row), mut new_row) in rows.into_iter().zip(new_rows) {
+                table_shard.offload_values(&mut new_row)?;
+
+                // The new version is written before tombstoning the old one, so a failure in between never loses the row.
+                let new_pos = data.insert_rows(&[new_row.to_vec()?.as_slice()]);
+                data.delete_element(pos as usize)?;
+
+                // Blobs of the values that were replaced are no longer referenced by any version
+                for (col, _) in changes.iter() {
+                    if let Some(DataValue::Blob(id)) = row.get_value(col) {
+                        table_shard.blobs.remove(&id)?;
+                    }
+                }
+
+                old_versions.push((row, pos));
+                new_versions.push((new_row, new_pos as u64));
+            }
+        }
+
+        let updated = new_versions.len();
+
+        TableShard::<T>::remove_indexes(table.clone(), table_shard.indexes.clone(), old_versions);
+        TableShard::<T>::insert_indexes(table, table_shard.indexes.clone(), new_versions);
+
+        Ok(updated)
+    }
+
     pub fn get_table(&self, table_name: &str) -> Option<Arc<Table>> {
         self.tables.get(table_name).map(|e| e.table.clone())
     }
@@ -1,28 +1,151 @@
-use crate::row::Row;
+use crate::errors::QueryError;
+use crate::row::{unix_now, Row};
 use chashmap::CHashMap;
 use parking_lot::RwLock;
-use schemajs_config::DatabaseConfig;
+use schemajs_config::{CompressionCodec, DatabaseConfig, DurabilityMode, RowFormat};
+use schemajs_data::blob_store::BlobStore;
+use schemajs_data::compression::Compression;
+use schemajs_data::durability::Durability;
+use schemajs_data::encryption::{EncryptionKey, Keyring};
+use schemajs_data::errors::ShardErrors;
 use schemajs_data::fdm::FileDescriptorManager;
-use schemajs_data::shard::map_shard::MapShard;
+use schemajs_data::reconcile_policy::ReconcilePolicy;
+use schemajs_data::shard::format::replace_file;
+use schemajs_data::shard::map_shard::{MapShard, ScanCursor};
 use schemajs_data::shard::shards::data_shard::config::{DataShardConfig, TempDataShardConfig};
-use schemajs_data::shard::shards::data_shard::shard::DataShard;
+use schemajs_data::shard::shards::memory::config::{MemoryShardConfig, TempMemoryShardConfig};
+use schemajs_data::shard::shards::storage::{
+    StorageShard, StorageShardConfig, TempStorageShardConfig,
+};
 use schemajs_data::shard::temp_collection::TempCollection;
+use schemajs_data::wal::WriteAheadLog;
 use schemajs_dirs::create_schema_js_table;
 use schemajs_helpers::helper::{HelperCall, HelperDbContext};
 use schemajs_index::composite_key::CompositeKey;
+use schemajs_index::implementations::btree::btree_index::BTreeIndex;
 use schemajs_index::implementations::hash::hash_index::HashIndex;
 use schemajs_index::index_keys::IndexKeyType;
 use schemajs_index::index_type::{IndexType, IndexTypeValue};
+use schemajs_index::keys::ordered_key::{OrderedIndexKey, OrderedKeyValue};
 use schemajs_index::types::{Index, IndexKey};
 use schemajs_primitives::column::types::DataValue;
 use schemajs_primitives::table::Table;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::marker::PhantomData;
-use std::path::PathBuf;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
 use tokio::sync::mpsc::Sender;
 
+/// Translates the durability settings of a database into the mode understood by the shards.
+pub fn durability_from_config(db_config: &DatabaseConfig) -> Durability {
+    match db_config.durability {
+        DurabilityMode::None => Durability::None,
+        DurabilityMode::Batch => {
+            Durability::Batch(Duration::from_millis(db_config.durability_sync_interval_ms))
+        }
+        DurabilityMode::Strict => Durability::Strict,
+    }
+}
+
+/// Translates the compression codec of a database into the one understood by the shards.
+pub fn compression_from_config(db_config: &DatabaseConfig) -> Compression {
+    match db_config.compression {
+        CompressionCodec::None => Compression::None,
+        CompressionCodec::Lz4 => Compression::Lz4,
+        CompressionCodec::Zstd => Compression::Zstd,
+    }
+}
+
+// Holds the schema versions of tables storing rows by position, see `load_schema_versions`.
+const SCHEMA_VERSIONS_FILE: &str = "schema_versions.json";
+
+/// Registers the current columns of `table` as a version of its schema, for databases storing rows by position.
+/// Tables kept in files save their versions in the table folder, so rows written before columns were added
+/// can still be read once the process restarts.
+pub fn load_schema_versions(
+    table: &mut Table,
+    table_path: &Path,
+    db_config: &DatabaseConfig,
+) -> Result<(), QueryError> {
+    if db_config.row_format != RowFormat::Binary {
+        return Ok(());
+    }
+
+    let path = table_path.join(SCHEMA_VERSIONS_FILE);
+    if !db_config.memory {
+        if let Ok(contents) = std::fs::read(&path) {
+            table.metadata.schema_versions = serde_json::from_slice(&contents).map_err(|_| {
+                QueryError::ConfigError(format!(
+                    "Failed to read the schema versions {}",
+                    path.display()
+                ))
+            })?;
+        }
+    }
+
+    if table.register_schema_version() && !db_config.memory {
+        let contents = serde_json::to_vec(&table.metadata.schema_versions)
+            .map_err(|_| QueryError::InvalidSerialization)?;
+        replace_file(&path, &contents).map_err(|_| {
+            QueryError::ConfigError(format!(
+                "Failed to save the schema versions {}",
+                path.display()
+            ))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Loads the encryption keys of a database. Encryption is disabled if no key file is set.
+/// A key file that is missing or malformed is reported as a configuration error.
+pub fn keyring_from_config(db_config: &DatabaseConfig) -> Result<Keyring, QueryError> {
+    let load = |path: &PathBuf| {
+        EncryptionKey::from_file(path).map_err(|_| {
+            QueryError::ConfigError(format!("Failed to load encryption key {}", path.display()))
+        })
+    };
+
+    Ok(Keyring::new(
+        db_config
+            .encryption_key_file
+            .as_ref()
+            .map(load)
+            .transpose()?,
+        db_config
+            .previous_encryption_key_files
+            .iter()
+            .map(load)
+            .collect::<Result<Vec<_>, _>>()?,
+    ))
+}
+
+/// Thresholds that trigger the reconciliation of a table, taking the overrides set for it into account.
+pub fn reconcile_policy_from_config(
+    db_config: &DatabaseConfig,
+    table_name: &str,
+) -> ReconcilePolicy {
+    let table_config = db_config.tables.get(table_name);
+
+    ReconcilePolicy {
+        max_rows: table_config
+            .and_then(|t| t.reconcile_max_rows)
+            .unwrap_or(db_config.reconcile_max_rows),
+        max_bytes: table_config
+            .and_then(|t| t.reconcile_max_bytes)
+            .unwrap_or(db_config.reconcile_max_bytes),
+        max_age: Duration::from_millis(
+            table_config
+                .and_then(|t| t.reconcile_max_age_ms)
+                .unwrap_or(db_config.reconcile_max_age_ms),
+        ),
+    }
+}
+
 /// `TableShard` is a structure that manages the sharding of a specific table's data.
 /// It is responsible for storing the table's data in a main shard, handling temporary shards
 /// for efficient insertion, and managing the indexes associated with the table.
@@ -31,12 +154,17 @@ use tokio::sync::mpsc::Sender;
 ///
 /// # Fields:
 /// - `table`: The `Table` structure representing the schema of the table being managed.
-/// - `data`: An `Arc<RwLock<MapShard<DataShard, DataShardConfig>>>` that represents the main shard where the table's data is stored.
+/// - `data`: An `Arc<RwLock<MapShard<StorageShard, StorageShardConfig>>>` that represents the main shard where the table's data is stored.
 ///   This is a thread-safe reference to the shard, which allows concurrent reads and writes to the data.
-/// - `temps`: A `TempCollection<DataShard, DataShardConfig, TempDataShardConfig>` that manages temporary shards for storing data
+///   Its shards are kept in files, or in memory if the database is configured as `memory`.
+/// - `temps`: A `TempCollection<StorageShard, StorageShardConfig, TempStorageShardConfig>` that manages temporary shards for storing data
 ///   before it is reconciled into the main shard. Temporary shards allow for faster writes and efficient sharding operations.
 /// - `indexes`: An `Arc<CHashMap<String, IndexTypeValue>>` that contains the table's indexes, stored in a thread-safe concurrent hash map.
 ///   The key is the index name, and the value is an `IndexTypeValue`, which holds the actual index structure.
+/// - `blobs`: The `BlobStore` holding the values of the table too large to be kept inline in their row.
+/// - `blob_threshold`: Size from which string values are moved to `blobs`, `None` if they are always kept inline.
+/// - `reconcile_policy`: The thresholds deciding when the rows in the temporary shards are reconciled into `data`.
+/// - `_memory_dir`: The temporary folder holding the indexes of a table of a memory database, deleted when the table is dropped.
 ///
 /// - `_marker`: A `PhantomData<T>` used to indicate the generic type `T` in the struct.
 ///   It is a marker used to tell the Rust compiler that this struct works with a specific row type,
@@ -45,11 +173,16 @@ use tokio::sync::mpsc::Sender;
 pub struct TableShard<T: Row> {
     pub table: Arc<Table>,
     pub scheme: String,
-    pub data: Arc<RwLock<MapShard<DataShard, DataShardConfig>>>,
-    pub temps: TempCollection<DataShard, DataShardConfig, TempDataShardConfig>,
+    pub data: Arc<RwLock<MapShard<StorageShard, StorageShardConfig>>>,
+    pub temps: TempCollection<StorageShard, StorageShardConfig, TempStorageShardConfig>,
     pub indexes: Arc<CHashMap<String, IndexTypeValue>>,
+    pub blobs: BlobStore,
+    pub blob_threshold: Option<usize>,
+    pub reconcile_policy: ReconcilePolicy,
     _marker: PhantomData<T>,
     helper_tx: Sender<HelperCall>,
+    // Declared last so the folder is removed once everything kept in it is dropped.
+    _memory_dir: Option<TempDir>,
 }
 
 impl<T: Row> TableShard<T> {
@@ -59,39 +192,91 @@ impl<T: Row> TableShard<T> {
     /// # Parameters:
     /// - `table`: The `Table` object representing the structure of the table to be sharded.
     /// - `base_path`: An optional base path for the table files. If not provided, a default path will be used.
+    ///   Memory databases do not use it, each of their tables gets a new temporary folder instead.
     /// - `scheme`: The database schema that organizes how the table's data and indexes are structured.
     /// - `temp_config`: Configuration for the temporary shard that handles data before being reconciled with the main shard.
     ///
     /// # Returns:
     /// - A `TableShard` instance that handles data storage, sharding, and indexing for the provided table.
+    /// - A `QueryError` if the configuration of the database cannot be loaded or the table cannot be recovered.
     pub fn new(
-        table: Table,
+        mut table: Table,
         base_path: Option<PathBuf>,
         scheme: &str,
         temp_config: TempDataShardConfig,
         helper_tx: Sender<HelperCall>,
         db_config: &Arc<DatabaseConfig>,
         fdm: Arc<FileDescriptorManager>,
-    ) -> Self {
-        let table_path = create_schema_js_table(base_path, scheme, table.name.as_str());
-
-        let map_shard = MapShard::new(
-            table_path.clone(),
-            "data_",
-            DataShardConfig {
-                max_offsets: Some(db_config.max_rows_per_shard),
-            },
-            fdm.clone(),
-        );
+    ) -> Result<Self, QueryError> {
+        // Indexes are only kept in files, so memory databases keep them in a folder of their own,
+        // removed along with the table and never shared with another instance of the database.
+        let memory_dir = db_config
+            .memory
+            .then(|| {
+                tempfile::Builder::new()
+                    .prefix(&format!("{}-{}-", scheme, table.name))
+                    .tempdir()
+            })
+            .transpose()
+            .map_err(|_| {
+                QueryError::ConfigError("Failed to create the folder of a memory table".into())
+            })?;
+        let table_path = match &memory_dir {
+            Some(dir) => dir.path().to_path_buf(),
+            None => create_schema_js_table(base_path, scheme, table.name.as_str()),
+        };
+        load_schema_versions(&mut table, &table_path, db_config)?;
+        let durability = durability_from_config(db_config);
+        let keyring = keyring_from_config(db_config)?;
+
+        // Memory databases keep no write-ahead log, their rows do not outlive the process anyway.
+        let (shard_config, temp_config) = if db_config.memory {
+            (
+                StorageShardConfig::Memory(MemoryShardConfig {
+                    max_offsets: Some(db_config.max_rows_per_shard),
+                }),
+                TempStorageShardConfig::Memory(TempMemoryShardConfig {
+                    max_offsets: temp_config.max_offsets,
+                }),
+            )
+        } else {
+            (
+                StorageShardConfig::File(Box::new(DataShardConfig {
+                    max_offsets: Some(db_config.max_rows_per_shard),
+                    durability,
+                    compression: compression_from_config(db_config),
+                    keyring: keyring.clone(),
+                })),
+                TempStorageShardConfig::File(Box::new(temp_config)),
+            )
+        };
+
+        let map_shard = MapShard::new(table_path.clone(), "data_", shard_config, fdm.clone());
 
         let refs = Arc::new(RwLock::new(map_shard));
 
         let temps_folder = table_path.join("temps");
 
-        if !temps_folder.exists() {
+        if !db_config.memory && !temps_folder.exists() {
             std::fs::create_dir_all(temps_folder.clone()).unwrap();
         }
 
+        let wal = if db_config.memory {
+            None
+        } else {
+            let wal = WriteAheadLog::new(
+                table_path.join("table.wal"),
+                durability,
+                Some(keyring.clone()),
+                fdm.clone(),
+            )
+            .map_err(|_| ShardErrors::WalError)?;
+
+            Some(Arc::new(wal))
+        };
+
+        let blobs = BlobStore::new(table_path.join("blobs"), keyring.clone());
+
         let temp_collection = TempCollection::new(
             refs.clone(),
             db_config.max_temporary_shards,
@@ -99,6 +284,7 @@ impl<T: Row> TableShard<T> {
             "temp_",
             temp_config,
             fdm.clone(),
+            wal,
         );
 
         let mut indexes = CHashMap::new();
@@ -115,6 +301,19 @@ impl<T: Row> TableShard<T> {
                     path,
                     Some(format!("{}", index.name)),
                     Some(db_config.max_records_per_hash_index_shard),
+                    Some(db_config.index_bloom_false_positive_rate),
+                    Some(durability),
+                    Some(keyring.clone()),
+                    fdm.clone(),
+                )),
+                // Ordered indexes use the same shard capacity as hash indexes.
+                IndexType::BTree => IndexTypeValue::BTree(BTreeIndex::new_from_path(
+                    path,
+                    Some(index.name.clone()),
+                    Some(db_config.max_records_per_hash_index_shard),
+                    Some(db_config.index_bloom_false_positive_rate),
+                    Some(durability),
+                    Some(keyring.clone()),
                     fdm.clone(),
                 )),
             };
@@ -122,26 +321,38 @@ impl<T: Row> TableShard<T> {
             indexes.insert(index.name.clone(), index_obj);
         }
 
+        let reconcile_policy = reconcile_policy_from_config(db_config, table.name.as_str());
+
         let mut tbl_shard = Self {
             indexes: Arc::new(indexes),
+            reconcile_policy,
             data: refs.clone(),
             table: Arc::new(table),
             temps: temp_collection,
+            blobs,
+            blob_threshold: match db_config.blob_threshold_bytes {
+                // Blobs are kept in files, so memory databases keep every value inline.
+                _ if db_config.memory => None,
+                0 => None,
+                threshold => Some(threshold as usize),
+            },
             _marker: PhantomData,
             helper_tx,
             scheme: scheme.to_string(),
+            _memory_dir: memory_dir,
         };
 
-        tbl_shard.init();
+        tbl_shard.init()?;
 
-        tbl_shard
+        Ok(tbl_shard)
     }
 
     /// Initializes everything related to the current table context.
     /// Such as loading the indexes
     /// Setting the reconciliation callbacks
+    /// Replaying the write-ahead log of rows that were never reconciled
     /// and potentially future logic related to table loading.
-    pub fn init(&mut self) {
+    pub fn init(&mut self) -> Result<(), QueryError> {
         let indexes = self.indexes.clone();
 
         for temp_shard in self.temps.temps.iter() {
@@ -151,9 +362,14 @@ impl<T: Row> TableShard<T> {
             let helper_tx = self.helper_tx.clone();
 
             temp_shard.write().set_on_reconcile(Box::new(move |rows| {
+                // Rows that cannot be decoded are left out of the indexes, reading them reports the error.
                 let rows: Vec<(T, u64)> = rows
                     .into_iter()
-                    .map(|row| (T::from_slice(&row.data, table.clone()), row.index))
+                    .filter_map(|row| {
+                        T::from_slice(&row.data, table.clone())
+                            .ok()
+                            .map(|decoded| (decoded, row.index))
+                    })
                     .collect();
 
                 {
@@ -181,6 +397,59 @@ impl<T: Row> TableShard<T> {
                 Ok(())
             }))
         }
+
+        self.temps.recover()?;
+
+        Ok(())
+    }
+
+    /// Builds the keys of every index in the table that `row` takes part of.
+    /// Rows whose indexed columns are all null are not indexed, therefore no key is returned for that index.
+    pub fn get_index_keys(
+        table: &Table,
+        indexes: &CHashMap<String, IndexTypeValue>,
+        row: &T,
+    ) -> Vec<(String, IndexKeyType)> {
+        let mut keys = vec![];
+
+        for index in &table.indexes {
+            let mut can_index = false;
+            let mut key_vals: Vec<(String, DataValue)> = vec![];
+
+            for index_col in &index.members {
+                let val = row
+                    .get_value(table.get_column(index_col).unwrap())
+                    .unwrap_or(DataValue::Null);
+
+                if !val.is_null() {
+                    can_index = true;
+                }
+
+                key_vals.push((index_col.clone(), val))
+            }
+
+            if can_index {
+                let real_indx = indexes.get(&index.name).unwrap();
+                let indx = real_indx.as_index();
+                keys.push((index.name.clone(), Self::build_index_key(*indx, key_vals)));
+            }
+        }
+
+        keys
+    }
+
+    /// Builds the key of `indx` for the given column values, in the order of the members of the index.
+    /// Indexes keeping their keys in value order get the typed values, the rest get them as strings.
+    pub fn build_index_key(indx: &dyn Index, key_vals: Vec<(String, DataValue)>) -> IndexKeyType {
+        let ordered_key = OrderedIndexKey(key_vals.iter().map(|(_, val)| val.into()).collect());
+        indx.to_ordered_key(ordered_key).unwrap_or_else(|| {
+            indx.to_key(CompositeKey(
+                key_vals
+                    .into_iter()
+                    .map(|(col, val)| (col, val.to_string()))
+                    .collect(),
+            ))
+        })
     }
 
     /// This method handles automatically indexing the rows that match the index in the Table.
@@ -193,48 +462,233 @@ impl<T: Row> TableShard<T> {
         let mut index_ordered_items: HashMap<String, Vec<(IndexKeyType, u64)>> = HashMap::new();
 
         for (row_t, pos) in data.iter() {
-            for index in &table.indexes {
-                let mut can_index = false;
-                let mut composite_key_vals: Vec<(String, String)> = vec![];
+            for (index_name, key) in Self::get_index_keys(&table, &indexes, row_t) {
+                index_ordered_items
+                    .entry(index_name)
+                    .or_default()
+                    .push((key, *pos));
+            }
+        }
 
-                for index_col in &index.members {
-                    let val = row_t
-                        .get_value(table.get_column(index_col).unwrap())
-                        .unwrap_or(DataValue::Null);
+        for (index, rows) in index_ordered_items {
+            let index = indexes.get_mut(&index).unwrap();
+            let indx = index.as_index();
 
-                    if !val.is_null() {
-                        can_index = true;
-                    }
+            indx.bulk_insert(rows);
+        }
+    }
 
-                    composite_key_vals.push((index_col.clone(), val.to_string()))
+    /// Removes the index entries pointing to the given rows.
+    /// Only the entries pointing to the removed rows are removed, other rows sharing their keys keep theirs.
+    pub fn remove_indexes(
+        table: Arc<Table>,
+        indexes: Arc<CHashMap<String, IndexTypeValue>>,
+        data: Vec<(T, u64)>,
+    ) {
+        for (row_t, pos) in data.iter() {
+            for (index_name, key) in Self::get_index_keys(&table, &indexes, row_t) {
+                let mut index = indexes.get_mut(&index_name).unwrap();
+                index.as_index_mut().remove(&key, *pos);
+            }
+        }
+    }
+
+    /// Lazily walks the reconciled rows of the table from the global position `start` up to `end`
+    /// (exclusive, or the last row if `None`), yielding their position along with their bytes.
+    /// Rows still waiting in temporary shards are not part of the scan.
+    ///
+    /// The data shards are only locked while each row is read, so inserts are not blocked during the scan.
+    pub fn scan(&self, start: usize, end: Option<usize>) -> TableScan {
+        TableScan {
+            data: self.data.clone(),
+            cursor: ScanCursor::new(start, end),
+        }
+    }
+
+    /// Moves the string values of `row` larger than `blob_threshold` to the blob store, leaving a reference
+    /// in their place. Indexed columns are always kept inline, so their values can be looked up.
+    pub fn offload_values(&self, row: &mut T) -> Result<(), ShardErrors> {
+        let threshold = match self.blob_threshold {
+            None => return Ok(()),
+            Some(threshold) => threshold,
+        };
+
+        for col in self.table.columns.values() {
+            let indexed = self
+                .table
+                .indexes
+                .iter()
+                .any(|index| index.members.contains(&col.name));
+            if indexed {
+                continue;
+            }
+
+            if let Some(DataValue::String(val)) = row.get_value(col) {
+                if val.len() > threshold {
+                    let id = self.blobs.put(val.as_bytes())?;
+                    row.set_value(col, DataValue::Blob(id));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads back from the blob store the values of `row` that were moved there by `offload_values`.
+    pub fn load_values(&self, row: &mut T) -> Result<(), ShardErrors> {
+        for col in self.table.columns.values() {
+            if let Some(val @ DataValue::Blob(_)) = row.get_value(col) {
+                row.set_value(col, self.load_value(val)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value a reference to the blob store stands for, any other value is returned as is.
+    pub fn load_value(&self, val: DataValue) -> Result<DataValue, ShardErrors> {
+        match val {
+            DataValue::Blob(id) => {
+                let val =
+                    String::from_utf8(self.blobs.get(&id)?).map_err(|_| ShardErrors::Corrupted)?;
+                Ok(DataValue::String(val))
+            }
+            val => Ok(val),
+        }
+    }
+
+    /// Removes from the blob store the values `row` references, once the row is gone.
+    pub fn remove_blobs(&self, row: &T) -> Result<(), ShardErrors> {
+        for col in self.table.columns.values() {
+            if let Some(DataValue::Blob(id)) = row.get_value(col) {
+                self.blobs.remove(&id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tombstones the reconciled rows whose TTL has run out and removes their index entries.
+    /// Expired rows are looked up through the `_expires_at` index, so no other row is read.
+    /// Returns the number of rows purged.
+    pub fn purge_expired(&self) -> Result<usize, ShardErrors> {
+        if self.table.ttl.is_none() {
+            return Ok(0);
+        }
+
+        let now = unix_now();
+        let positions = {
+            let index = Table::get_internal_expires_at_index();
+            let indx_read = match self.indexes.get(&index.name) {
+                Some(indx) => indx,
+                None => return Ok(0),
+            };
+            let indx = indx_read.as_index();
+            let bound = |val: OrderedKeyValue| indx.to_ordered_key(OrderedIndexKey(vec![val]));
+            match (
+                bound(OrderedKeyValue::Null),
+                bound(OrderedKeyValue::Number(now as f64)),
+            ) {
+                // Rows without an expiry are not indexed, null only bounds the keys before the first one
+                (Some(null), Some(now)) => {
+                    indx.range(Bound::Excluded(&null), Bound::Included(&now))
                 }
+                _ => return Ok(0),
+            }
+        };
 
-                if can_index {
-                    let real_indx = indexes.get(&index.name).unwrap();
-                    let composite_key = CompositeKey(composite_key_vals);
-                    let indx = real_indx.as_index();
-                    let key = indx.to_key(composite_key);
-                    let insertion_value = (key, pos.clone());
-
-                    let index_exists = index_ordered_items.contains_key(&index.name);
-
-                    if index_exists {
-                        index_ordered_items
-                            .get_mut(&index.name)
-                            .unwrap()
-                            .push(insertion_value);
-                    } else {
-                        index_ordered_items.insert(index.name.clone(), vec![insertion_value]);
-                    }
+        let mut expired = vec![];
+        {
+            let data = self.data.read();
+            for pos in positions {
+                let row = match data.get_element(pos as usize) {
+                    Ok(row) => row,
+                    Err(ShardErrors::DeletedItem) => continue,
+                    Err(e) => return Err(e),
+                };
+                let row =
+                    T::from_slice(&row, self.table.clone()).map_err(|_| ShardErrors::Corrupted)?;
+                if row.is_expired(now) {
+                    data.delete_element(pos as usize)?;
+                    self.remove_blobs(&row)?;
+                    expired.push((row, pos));
                 }
             }
         }
 
-        for (index, rows) in index_ordered_items {
-            let index = indexes.get_mut(&index).unwrap();
-            let indx = index.as_index();
+        let purged = expired.len();
+        Self::remove_indexes(self.table.clone(), self.indexes.clone(), expired);
 
-            indx.bulk_insert(rows);
+        Ok(purged)
+    }
+
+    /// Reconciles the temporary shards of the table if any of the thresholds of its `reconcile_policy` was reached,
+    /// returning whether it did.
+    pub fn reconcile_if_due(&self) -> Result<bool, QueryError> {
+        Ok(self.temps.reconcile_if_due(&self.reconcile_policy)?)
+    }
+
+    /// Number of reconciled rows in the table, leaving out the deleted ones.
+    pub fn row_count(&self) -> usize {
+        self.data.read().row_count()
+    }
+
+    /// Reports which shard files under the table folder are damaged, including temporary shards and indexes.
+    pub fn verify(&self) -> Vec<(PathBuf, ShardErrors)> {
+        let mut damaged = self.data.read().verify();
+        damaged.extend(self.temps.verify());
+
+        for index in &self.table.indexes {
+            if let Some(indx) = self.indexes.get(&index.name) {
+                damaged.extend(indx.as_index().verify());
+            }
         }
+
+        damaged
+    }
+
+    /// Rewrites the next shard of the table, temporary shards, blobs and indexes included, that is not encrypted
+    /// with the current key of the database. Returns whether a shard was rewritten.
+    pub fn reencrypt(&self) -> Result<bool, ShardErrors> {
+        if self.data.read().reencrypt()? || self.temps.reencrypt()? || self.blobs.reencrypt()? {
+            return Ok(true);
+        }
+
+        for index in &self.table.indexes {
+            if let Some(indx) = self.indexes.get(&index.name) {
+                if indx.as_index().reencrypt()? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Merges the sorted runs of every index of the table that has too many of them.
+    /// Returns whether any index was merged.
+    pub fn merge_indexes(&self) -> Result<bool, ShardErrors> {
+        let mut merged = false;
+        for index in &self.table.indexes {
+            if let Some(indx) = self.indexes.get(&index.name) {
+                merged |= indx.as_index().merge()?;
+            }
+        }
+
+        Ok(merged)
+    }
+}
+
+/// Iterator returned by `TableShard::scan`.
+pub struct TableScan {
+    data: Arc<RwLock<MapShard<StorageShard, StorageShardConfig>>>,
+    cursor: ScanCursor,
+}
+
+impl Iterator for TableScan {
+    type Item = Result<(usize, Vec<u8>), ShardErrors>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next_in(&self.data.read())
     }
 }
@@ -7,6 +7,15 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds elapsed since the Unix epoch, the unit row expiries are kept in.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
 
 /// The `Row` trait defines the core operations that any row in the database must implement.
 /// It extends the `RowSerializer` trait to ensure that rows can be serialized and deserialized
@@ -38,7 +47,10 @@ pub trait Row {
         })?)
     }
 
-    fn from_slice(slice: &[u8], table: Arc<Table>) -> Self;
+    /// Reads a row back from the bytes it was stored as, whatever encoding they were written with.
+    fn from_slice(slice: &[u8], table: Arc<Table>) -> Result<Self, RowSerializationError>
+    where
+        Self: Sized;
 
     fn from_data(data: Self::RowData, table: Arc<Table>) -> Self;
 
@@ -72,6 +84,18 @@ pub trait Row {
 
     fn set_value(&mut self, column: &Column, value: DataValue);
 
+    /// Whether the row outlived the TTL of its table at `now` (seconds since the Unix epoch).
+    fn is_expired(&self, now: u64) -> bool {
+        if self.get_table().ttl.is_none() {
+            return false;
+        }
+
+        match self.get_value(Table::get_internal_expires_at()) {
+            Some(DataValue::Number(expires_at)) => expires_at.as_u64().is_some_and(|e| e <= now),
+            _ => false,
+        }
+    }
+
     /// Returns the name of the table to which the row belongs.
     ///
     /// # Returns:
@@ -0,0 +1,386 @@
+use super::row::Row;
+use crate::row_json::RowData;
+use crate::RowSerializationError;
+use schemajs_primitives::column::types::DataValue;
+use schemajs_primitives::column::Column;
+use schemajs_primitives::table::Table;
+use serde_json::Number;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+// First byte of every binary row. JSON rows always start with `{`, so rows stored before a database
+// switched to binary rows can still be told apart.
+const BINARY_ROW_MARKER: u8 = 0x01;
+
+const TAG_MISSING: u8 = 0;
+const TAG_NULL: u8 = 1;
+const TAG_UUID: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_BOOLEAN: u8 = 4;
+const TAG_UNSIGNED: u8 = 5;
+const TAG_SIGNED: u8 = 6;
+const TAG_FLOAT: u8 = 7;
+const TAG_BLOB: u8 = 8;
+
+/// Encodes the values of a row following the columns of the current schema version of `table`, sorted by name.
+/// The row starts with the version, and each value is a tag followed by its bytes.
+/// Columns the row has no value for only take their tag.
+fn encode_row(
+    table: &Table,
+    values: &HashMap<String, DataValue>,
+) -> Result<Vec<u8>, RowSerializationError> {
+    let version = table.metadata.schema_version;
+    let columns = table.schema_version_columns(version).ok_or_else(|| {
+        RowSerializationError::SerializationError("Unknown schema version".to_string())
+    })?;
+    let version = u16::try_from(version).map_err(|_| {
+        RowSerializationError::SerializationError("Too many schema versions".to_string())
+    })?;
+
+    if let Some(col_name) = values.keys().find(|col| !columns.contains(col)) {
+        return Err(RowSerializationError::SerializationError(format!(
+            "Unknown column {}",
+            col_name
+        )));
+    }
+
+    let mut bytes = vec![BINARY_ROW_MARKER];
+    bytes.extend_from_slice(&version.to_le_bytes());
+
+    for col_name in columns.iter() {
+        match values.get(col_name) {
+            None => bytes.push(TAG_MISSING),
+            Some(DataValue::Null) => bytes.push(TAG_NULL),
+            Some(DataValue::Uuid(uuid)) => {
+                bytes.push(TAG_UUID);
+                bytes.extend_from_slice(uuid.as_bytes());
+            }
+            Some(DataValue::Blob(id)) => {
+                bytes.push(TAG_BLOB);
+                bytes.extend_from_slice(id.as_bytes());
+            }
+            Some(DataValue::String(val)) => {
+                let len = u32::try_from(val.len()).map_err(|_| {
+                    RowSerializationError::SerializationError(format!(
+                        "Value of column {} is too long",
+                        col_name
+                    ))
+                })?;
+                bytes.push(TAG_STRING);
+                bytes.extend_from_slice(&len.to_le_bytes());
+                bytes.extend_from_slice(val.as_bytes());
+            }
+            Some(DataValue::Boolean(val)) => {
+                bytes.push(TAG_BOOLEAN);
+                bytes.push(*val as u8);
+            }
+            Some(DataValue::Number(val)) => {
+                if let Some(val) = val.as_u64() {
+                    bytes.push(TAG_UNSIGNED);
+                    bytes.extend_from_slice(&val.to_le_bytes());
+                } else if let Some(val) = val.as_i64() {
+                    bytes.push(TAG_SIGNED);
+                    bytes.extend_from_slice(&val.to_le_bytes());
+                } else {
+                    let val = val.as_f64().ok_or_else(|| {
+                        RowSerializationError::SerializationError(format!(
+                            "Invalid number in column {}",
+                            col_name
+                        ))
+                    })?;
+                    bytes.push(TAG_FLOAT);
+                    bytes.extend_from_slice(&val.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn invalid_row() -> RowSerializationError {
+    RowSerializationError::DeserializationError("Invalid binary row".to_string())
+}
+
+/// Splits the first `len` bytes off `rest`.
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], RowSerializationError> {
+    if rest.len() < len {
+        return Err(invalid_row());
+    }
+    let (taken, remaining) = rest.split_at(len);
+    *rest = remaining;
+    Ok(taken)
+}
+
+fn take_array<const N: usize>(rest: &mut &[u8]) -> Result<[u8; N], RowSerializationError> {
+    take(rest, N)?.try_into().map_err(|_| invalid_row())
+}
+
+/// Decodes a row encoded by `encode_row`, following the columns of the schema version it was written with.
+/// Columns added to the table after the row was written are left empty.
+fn decode_row(
+    slice: &[u8],
+    table: &Table,
+) -> Result<HashMap<String, DataValue>, RowSerializationError> {
+    let mut rest = match slice.split_first() {
+        Some((&BINARY_ROW_MARKER, rest)) => rest,
+        _ => return Err(invalid_row()),
+    };
+
+    let version = u16::from_le_bytes(take_array(&mut rest)?);
+    let columns = table
+        .schema_version_columns(version as usize)
+        .ok_or_else(|| {
+            RowSerializationError::DeserializationError(format!(
+                "Unknown schema version {}",
+                version
+            ))
+        })?;
+
+    let mut values = HashMap::new();
+    for col_name in columns.iter() {
+        let value = match take(&mut rest, 1)?[0] {
+            TAG_MISSING => continue,
+            TAG_NULL => DataValue::Null,
+            TAG_UUID => DataValue::Uuid(Uuid::from_bytes(take_array(&mut rest)?)),
+            TAG_BLOB => DataValue::Blob(Uuid::from_bytes(take_array(&mut rest)?)),
+            TAG_STRING => {
+                let len = u32::from_le_bytes(take_array(&mut rest)?);
+                let val = std::str::from_utf8(take(&mut rest, len as usize)?)
+                    .map_err(|_| invalid_row())?;
+                DataValue::String(val.to_string())
+            }
+            TAG_BOOLEAN => DataValue::Boolean(take(&mut rest, 1)?[0] != 0),
+            TAG_UNSIGNED => DataValue::Number(u64::from_le_bytes(take_array(&mut rest)?).into()),
+            TAG_SIGNED => DataValue::Number(i64::from_le_bytes(take_array(&mut rest)?).into()),
+            TAG_FLOAT => DataValue::Number(
+                Number::from_f64(f64::from_le_bytes(take_array(&mut rest)?))
+                    .ok_or_else(invalid_row)?,
+            ),
+            _ => return Err(invalid_row()),
+        };
+
+        values.insert(col_name.clone(), value);
+    }
+
+    if !rest.is_empty() {
+        return Err(invalid_row());
+    }
+
+    Ok(values)
+}
+
+/// `RowBinary` is the compact alternative to `RowJson`. Instead of a JSON object keyed by column name,
+/// it stores the values of the row one after the other following the columns of its table sorted by name,
+/// which makes rows smaller and cheaper to read.
+///
+/// Rows name the schema version of their table they were written with, see `Table::register_schema_version`,
+/// so they keep decoding after columns are added to the table.
+///
+/// # Fields:
+/// - `table`: The table the row belongs to, whose columns drive the layout of the stored values.
+/// - `values`: A `RowData` instance holding the values of the row.
+#[derive(Clone, Debug)]
+pub struct RowBinary {
+    pub table: Arc<Table>,
+    pub values: RowData,
+}
+
+impl Row for RowBinary {
+    type RowData = RowData;
+
+    fn to_data(&self) -> Self::RowData {
+        self.values.clone()
+    }
+
+    fn to_vec(&self) -> Result<Vec<u8>, RowSerializationError> {
+        encode_row(&self.table, &self.values.value)
+    }
+
+    fn to_map(&self) -> Result<HashMap<String, DataValue>, RowSerializationError> {
+        Ok(self.values.value.clone())
+    }
+
+    /// Reads a binary row. Rows stored as JSON, before the database switched to binary rows, are read as well.
+    fn from_slice(slice: &[u8], table: Arc<Table>) -> Result<Self, RowSerializationError> {
+        let values = if slice.first() == Some(&b'{') {
+            serde_json::from_slice(slice).map_err(|_| {
+                RowSerializationError::DeserializationError(
+                    "Row could not be deserialized".to_string(),
+                )
+            })?
+        } else {
+            RowData {
+                value: decode_row(slice, &table)?,
+            }
+        };
+
+        Ok(RowBinary { values, table })
+    }
+
+    fn from_data(data: Self::RowData, table: Arc<Table>) -> Self {
+        RowBinary {
+            table,
+            values: data,
+        }
+    }
+
+    fn from_map(table: Arc<Table>, data: HashMap<String, DataValue>) -> Result<Self, ()> {
+        Ok(RowBinary {
+            table,
+            values: RowData { value: data },
+        })
+    }
+
+    fn get_table(&self) -> Arc<Table> {
+        self.table.clone()
+    }
+
+    fn get_value(&self, column: &Column) -> Option<DataValue> {
+        self.values.value.get(&column.name).cloned()
+    }
+
+    fn set_value(&mut self, column: &Column, value: DataValue) {
+        self.values.value.insert(column.name.clone(), value);
+    }
+
+    fn get_table_name(&self) -> String {
+        self.table.name.clone()
+    }
+
+    /// Rows are only valid if every value belongs to a column of the table, the others could not be encoded.
+    fn validate(&self) -> bool {
+        self.values
+            .value
+            .keys()
+            .all(|col_name| self.table.get_column(col_name).is_some())
+    }
+}
+
+#[cfg(test)]
+mod row_tests {
+    use crate::row::Row;
+    use crate::row_binary::RowBinary;
+    use crate::row_json::RowJson;
+    use schemajs_primitives::column::types::{DataTypes, DataValue};
+    use schemajs_primitives::column::Column;
+    use schemajs_primitives::table::Table;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    pub fn binary_row_round_trip() {
+        let table = Arc::new(
+            Table::new("users")
+                .add_column(Column::new("name", DataTypes::String))
+                .add_column(Column::new("age", DataTypes::Number))
+                .add_column(Column::new("balance", DataTypes::Number))
+                .add_column(Column::new("score", DataTypes::Number))
+                .add_column(Column::new("admin", DataTypes::Boolean))
+                .add_column(Column::new("nickname", DataTypes::String)),
+        );
+        let data = json!({
+            "_uid": "a3b0c1d2-e3f4-4a5b-8c6d-7e8f9a0b1c2d",
+            "name": "Andres",
+            "age": 27,
+            "balance": -150,
+            "score": 9.5,
+            "admin": true
+        });
+
+        let row = RowBinary::from_json(data.clone(), table.clone()).unwrap();
+        let bytes = row.to_vec().unwrap();
+        let decoded = RowBinary::from_slice(&bytes, table.clone()).unwrap();
+        assert_eq!(decoded.to_map().unwrap(), row.to_map().unwrap());
+        assert_eq!(
+            decoded.get_value(table.get_column("nickname").unwrap()),
+            None
+        );
+        assert!(RowBinary::from_slice(&bytes[..bytes.len() - 1], table.clone()).is_err());
+
+        // Rows stored as JSON can still be read
+        let json_row = RowJson::from_json(data, table.clone()).unwrap();
+        let json_bytes = json_row.to_vec().unwrap();
+        let decoded = RowBinary::from_slice(&json_bytes, table.clone()).unwrap();
+        assert_eq!(decoded.to_map().unwrap(), json_row.to_map().unwrap());
+
+        // JSON rows stay JSON
+        assert_eq!(json_bytes.first(), Some(&b'{'));
+        assert!(RowJson::from_slice(&bytes, table.clone()).is_err());
+    }
+
+    #[test]
+    pub fn binary_row_size() {
+        let table = Arc::new(
+            Table::new("events")
+                .add_column(Column::new("kind", DataTypes::String))
+                .add_column(Column::new("count", DataTypes::Number))
+                .add_column(Column::new("processed", DataTypes::Boolean)),
+        );
+        let data = json!({
+            "_uid": "a3b0c1d2-e3f4-4a5b-8c6d-7e8f9a0b1c2d",
+            "kind": "click",
+            "count": 3,
+            "processed": false
+        });
+
+        let json_len = RowJson::from_json(data.clone(), table.clone())
+            .unwrap()
+            .to_vec()
+            .unwrap()
+            .len();
+        let binary_len = RowBinary::from_json(data, table)
+            .unwrap()
+            .to_vec()
+            .unwrap()
+            .len();
+
+        // Marker and version, then a tag per column: the uuid, a length prefixed string, a number and a boolean
+        assert_eq!(binary_len, 3 + (1 + 16) + (1 + 4 + 5) + (1 + 8) + (1 + 1));
+        assert!(binary_len * 2 < json_len);
+    }
+
+    #[test]
+    pub fn binary_row_after_columns_added() {
+        let mut table = Table::new("users")
+            .add_column(Column::new("name", DataTypes::String))
+            .add_column(Column::new("age", DataTypes::Number));
+        table.register_schema_version();
+        let versions = table.metadata.schema_versions.clone();
+
+        let row =
+            RowBinary::from_json(json!({ "name": "Andres", "age": 27 }), Arc::new(table)).unwrap();
+        let bytes = row.to_vec().unwrap();
+
+        // `_expires_at` and `admin` sort before every column the row was written with
+        let mut table = Table::new("users")
+            .add_column(Column::new("name", DataTypes::String))
+            .add_column(Column::new("age", DataTypes::Number))
+            .add_column(Column::new("admin", DataTypes::Boolean))
+            .set_ttl(60);
+        table.metadata.schema_versions = versions;
+        assert!(table.register_schema_version());
+        assert_eq!(table.metadata.schema_version, 1);
+        let table = Arc::new(table);
+
+        let decoded = RowBinary::from_slice(&bytes, table.clone()).unwrap();
+        assert_eq!(
+            decoded.get_value(table.get_column("name").unwrap()),
+            Some(DataValue::String("Andres".to_string()))
+        );
+        assert_eq!(
+            decoded.get_value(table.get_column("age").unwrap()),
+            Some(DataValue::Number(27.into()))
+        );
+        assert_eq!(decoded.get_value(table.get_column("admin").unwrap()), None);
+        assert_eq!(decoded.get_value(Table::get_internal_expires_at()), None);
+        assert!(!decoded.is_expired(u64::MAX));
+
+        // Written again, the row follows the current version
+        let mut updated = decoded.clone();
+        updated.set_value(table.get_column("admin").unwrap(), DataValue::Boolean(true));
+        let decoded = RowBinary::from_slice(&updated.to_vec().unwrap(), table.clone()).unwrap();
+        assert_eq!(decoded.to_map().unwrap(), updated.to_map().unwrap());
+    }
+}
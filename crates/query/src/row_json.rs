@@ -48,11 +48,12 @@ impl Row for RowJson {
         Ok(self.values.value.clone())
     }
 
-    fn from_slice(slice: &[u8], table: Arc<Table>) -> Self {
-        RowJson {
-            table,
-            values: serde_json::from_slice(slice).unwrap(),
-        }
+    fn from_slice(slice: &[u8], table: Arc<Table>) -> Result<Self, RowSerializationError> {
+        let values = serde_json::from_slice(slice).map_err(|_| {
+            RowSerializationError::DeserializationError("Row could not be deserialized".to_string())
+        })?;
+
+        Ok(RowJson { values, table })
     }
 
     fn from_data(data: Self::RowData, table: Arc<Table>) -> Self {
@@ -1,11 +1,15 @@
 use crate::errors::QueryError;
 use crate::managers::single::table_shard::TableShard;
 use crate::ops::query_ops::{QueryOps, QueryVal};
-use crate::row::Row;
+use crate::row::{unix_now, Row};
 use chashmap::CHashMap;
-use schemajs_index::composite_key::CompositeKey;
+use schemajs_data::errors::ShardErrors;
+use schemajs_index::keys::ordered_key::{OrderedIndexKey, OrderedKeyValue};
+use schemajs_primitives::column::types::DataValue;
 use schemajs_primitives::index::Index;
+use std::cmp::Ordering;
 use std::collections::HashSet;
+use std::ops::Bound;
 use std::sync::Arc;
 
 #[derive(Debug)]
@@ -40,46 +44,44 @@ impl<T: Row> QuerySearchManager<T> {
         None
     }
 
-    fn execute_query(&self, tbl: &TableShard<T>, query: &QueryOps) -> Vec<u64> {
+    /// Returns the positions the indexes of the table hold for the rows that may match `query`,
+    /// or `None` if the indexes cannot serve it and every row has to be checked.
+    fn execute_query(&self, tbl: &TableShard<T>, query: &QueryOps) -> Option<Vec<u64>> {
         let indexes = &tbl.table.indexes;
         // Try to find an index that can be used for the entire query
-        if let Some(index_query) = Self::find_index_for_query(query, indexes) {
-            if let Some(indx_manager) = tbl.indexes.get(&index_query.0.name) {
-                let manager = indx_manager.as_index();
-                let key = manager.to_key(index_query.1);
-                // TODO: get_all to return vec in index
-                if let Some(pointer) = manager.get(&key) {
-                    vec![pointer]
-                } else {
-                    Vec::new()
-                }
-            } else {
-                Vec::new()
-            }
+        if let Some((index, key_vals)) = Self::find_index_for_query(query, indexes) {
+            let indx_manager = tbl.indexes.get(&index.name)?;
+            let manager = indx_manager.as_index();
+            let key = TableShard::<T>::build_index_key(*manager, key_vals);
+            Some(manager.get(&key))
         } else {
             // Evaluate recursively
             match query {
-                QueryOps::Condition(cond) => {
-                    return self.evaluate_condition(&tbl, cond, indexes);
-                }
+                QueryOps::Condition(cond) => self.evaluate_condition(tbl, cond, indexes),
                 QueryOps::And(ops) => {
+                    if ops.is_empty() {
+                        return Some(vec![]);
+                    }
+
+                    // Conditions the indexes cannot serve are left to the check of every candidate row
                     let mut results: Option<Vec<u64>> = None;
                     for op in ops {
-                        let res = self.execute_query(tbl, op);
-                        results = match results {
-                            Some(existing) => Some(Self::intersect_indices(existing, res)),
-                            None => Some(res),
-                        };
+                        if let Some(res) = self.execute_query(tbl, op) {
+                            results = match results {
+                                Some(existing) => Some(Self::intersect_indices(existing, res)),
+                                None => Some(res),
+                            };
+                        }
                     }
-                    return results.unwrap_or_else(Vec::new);
+                    results
                 }
                 QueryOps::Or(ops) => {
                     let mut results = Vec::new();
                     for op in ops {
-                        let res = self.execute_query(tbl, op);
+                        let res = self.execute_query(tbl, op)?;
                         results = Self::union_indices(results, res);
                     }
-                    return results;
+                    Some(results)
                 }
             }
         }
@@ -90,31 +92,65 @@ impl<T: Row> QuerySearchManager<T> {
         shard: &TableShard<T>,
         cond: &QueryVal,
         indexes: &Vec<Index>,
-    ) -> Vec<u64> {
-        if cond.filter_type != "=" {
-            // Only "=" is supported
-            return Vec::new();
+    ) -> Option<Vec<u64>> {
+        if cond.filter_type == "=" {
+            if let Some(index) = Self::get_index_for_condition(cond, indexes) {
+                let indx_read = shard.indexes.get(&index.name).unwrap();
+                let indx = indx_read.as_index();
+                let key = TableShard::<T>::build_index_key(
+                    *indx,
+                    vec![(cond.key.clone(), cond.value.clone())],
+                );
+                return Some(indx.get(&key));
+            }
         }
 
-        if let Some(index) = Self::get_index_for_condition(cond, indexes) {
-            let comp_key = CompositeKey(vec![(cond.key.to_string(), (&cond.value).to_string())]);
-
+        // Ordered indexes answer conditions on their first member, whatever other members they have
+        for index in indexes
+            .iter()
+            .filter(|index| index.members.first() == Some(&cond.key))
+        {
             let indx_read = shard.indexes.get(&index.name).unwrap();
             let indx = indx_read.as_index();
-            let key = indx.to_key(comp_key);
-            if let Some(pointer) = indx.get(&key) {
-                return vec![pointer];
+            if !indx
+                .supported_search_operators()
+                .contains(&cond.filter_type)
+            {
+                continue;
             }
+
+            let (key, null) = match (
+                indx.to_ordered_key(OrderedIndexKey(vec![(&cond.value).into()])),
+                indx.to_ordered_key(OrderedIndexKey(vec![OrderedKeyValue::Null])),
+            ) {
+                (Some(key), Some(null)) => (key, null),
+                _ => continue,
+            };
+
+            // Null values come first in the index, but they are never lower than anything
+            return match cond.filter_type.as_str() {
+                "=" => Some(indx.range(Bound::Included(&key), Bound::Included(&key))),
+                ">" => Some(indx.range(Bound::Excluded(&key), Bound::Unbounded)),
+                ">=" => Some(indx.range(Bound::Included(&key), Bound::Unbounded)),
+                "<" => Some(indx.range(Bound::Excluded(&null), Bound::Excluded(&key))),
+                "<=" => Some(indx.range(Bound::Excluded(&null), Bound::Included(&key))),
+                _ => None,
+            };
         }
 
-        vec![]
+        None
     }
 
     fn find_index_for_query(
         query: &QueryOps,
         indexes: &Vec<Index>,
-    ) -> Option<(Index, CompositeKey)> {
+    ) -> Option<(Index, Vec<(String, DataValue)>)> {
         if let Some(conditions) = Self::collect_conditions(query) {
+            // A single key can only be looked up if every condition is an equality
+            if conditions.iter().any(|cond| cond.filter_type != "=") {
+                return None;
+            }
+
             if let Some(index) = Self::find_index_for_conditions(&conditions, indexes) {
                 let key = Self::generate_index_key(&index, &conditions);
                 if let Some(key) = key {
@@ -157,34 +193,195 @@ impl<T: Row> QuerySearchManager<T> {
         }
     }
 
-    fn generate_index_key(index: &Index, conditions: &[QueryVal]) -> Option<CompositeKey> {
+    fn generate_index_key(
+        index: &Index,
+        conditions: &[QueryVal],
+    ) -> Option<Vec<(String, DataValue)>> {
         let mut key_parts = Vec::new();
         for member in &index.members {
             if let Some(cond) = conditions.iter().find(|c| &c.key == member) {
-                key_parts.push((cond.key.to_string(), (&cond.value).to_string()));
+                key_parts.push((cond.key.to_string(), cond.value.clone()));
             } else {
                 // Missing condition for index member
                 return None;
             }
         }
 
-        Some(CompositeKey(key_parts))
+        Some(key_parts)
+    }
+
+    /// Returns the rows matching `ops` along with their position in the table.
+    /// Rows that have been deleted or have expired are skipped.
+    ///
+    /// Rows are decoded straight from where their shard keeps them, without copying them first.
+    /// If the indexes cannot serve `ops`, every row of the table is checked instead.
+    pub(crate) fn find_rows(
+        &self,
+        tbl: &TableShard<T>,
+        ops: &QueryOps,
+    ) -> Result<Vec<(u64, T)>, QueryError> {
+        let pointers = self.execute_query(tbl, ops);
+
+        let mut results = vec![];
+        let tbl_data = tbl.data.read();
+        let now = unix_now();
+
+        let pointers = match pointers {
+            Some(pointers) => pointers,
+            None => {
+                for item in tbl_data.scan(0, None) {
+                    let (pointer, data) = item?;
+                    let row = T::from_slice(&data, tbl.table.clone())?;
+                    if !row.is_expired(now) && Self::row_matches(tbl, &row, ops)? {
+                        results.push((pointer as u64, row));
+                    }
+                }
+
+                return Ok(results);
+            }
+        };
+
+        for pointer in pointers {
+            match tbl_data.get_element_ref(pointer as usize) {
+                Ok(data) => {
+                    let row = T::from_slice(&data, tbl.table.clone())?;
+                    // Conditions of `ops` the indexes did not serve still have to hold, and ordered indexes
+                    // only compare the start of long strings
+                    if !row.is_expired(now) && Self::row_matches(tbl, &row, ops)? {
+                        results.push((pointer, row));
+                    }
+                }
+                Err(ShardErrors::DeletedItem) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Evaluates `query` against a single row, for rows the indexes cannot tell apart.
+    /// Like the indexed lookups, only "=" and range conditions are supported, ranges following the order of `DataValue`.
+    /// Values kept in the blob store are read back before being compared, so they match like inline ones.
+    /// Numbers are compared by value, so `-0.0` equals `0.0` as it does in ordered indexes.
+    fn row_matches(tbl: &TableShard<T>, row: &T, query: &QueryOps) -> Result<bool, QueryError> {
+        match query {
+            QueryOps::Condition(cond) => {
+                let val = match row.get_table().get_column(&cond.key) {
+                    Some(col) => row
+                        .get_value(col)
+                        .map(|val| tbl.load_value(val))
+                        .transpose()?,
+                    None => None,
+                };
+
+                Ok(match (cond.filter_type.as_str(), val) {
+                    ("=", Some(val @ DataValue::Number(_))) if cond.value.is_number() => {
+                        val.cmp(&cond.value) == Ordering::Equal
+                    }
+                    ("=", Some(val)) => val.to_string() == cond.value.to_string(),
+                    (_, None) | (_, Some(DataValue::Null)) => false,
+                    (op, Some(val)) => {
+                        let ord = val.cmp(&cond.value);
+                        match op {
+                            ">" => ord == Ordering::Greater,
+                            ">=" => ord != Ordering::Less,
+                            "<" => ord == Ordering::Less,
+                            "<=" => ord != Ordering::Greater,
+                            _ => false,
+                        }
+                    }
+                })
+            }
+            QueryOps::And(ops) => {
+                for op in ops {
+                    if !Self::row_matches(tbl, row, op)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(!ops.is_empty())
+            }
+            QueryOps::Or(ops) => {
+                for op in ops {
+                    if Self::row_matches(tbl, row, op)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
     }
 
+    /// Returns the rows of `table_name` matching `ops`, including the ones still sitting in temporary shards,
+    /// so every acknowledged insert is visible.
     pub fn search(&self, table_name: &str, ops: &QueryOps) -> Result<Vec<T>, QueryError> {
         let get_table_shard = self
             .table_shards
             .get(table_name)
             .ok_or_else(|| QueryError::InvalidTable(table_name.to_string()))?;
 
-        let pointers = self.execute_query(&get_table_shard, ops);
+        let mut results: Vec<T> = self
+            .search_rows(&get_table_shard, ops)?
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect();
 
-        let mut results = vec![];
+        // Values kept in the blob store are only read for the rows being returned
+        for row in results.iter_mut() {
+            get_table_shard.load_values(row)?;
+        }
 
-        for pointer in pointers {
-            let tbl_data = get_table_shard.data.read();
-            let data = tbl_data.get_element(pointer as usize).unwrap();
-            results.push(T::from_slice(&data, get_table_shard.table.clone()));
+        Ok(results)
+    }
+
+    /// Returns whether any row of `table_name` matches `ops`, including the ones still sitting in temporary shards.
+    /// Reconciled rows at one of the `ignored` positions are left out.
+    pub fn exists(
+        &self,
+        table_name: &str,
+        ops: &QueryOps,
+        ignored: &HashSet<u64>,
+    ) -> Result<bool, QueryError> {
+        let get_table_shard = self
+            .table_shards
+            .get(table_name)
+            .ok_or_else(|| QueryError::InvalidTable(table_name.to_string()))?;
+
+        Ok(self
+            .search_rows(&get_table_shard, ops)?
+            .iter()
+            .any(|(pos, _)| pos.is_none_or(|pos| !ignored.contains(&pos))))
+    }
+
+    /// Returns the rows matching `ops` along with their position in the table, `None` for the rows
+    /// still sitting in temporary shards.
+    fn search_rows(
+        &self,
+        get_table_shard: &TableShard<T>,
+        ops: &QueryOps,
+    ) -> Result<Vec<(Option<u64>, T)>, QueryError> {
+        // Temporary shards stay read-locked during the search so none of their rows is reconciled (and indexed)
+        // halfway through, which would make it show up twice or not at all.
+        let temps: Vec<_> = get_table_shard
+            .temps
+            .temps
+            .iter()
+            .map(|temp| temp.read())
+            .collect();
+
+        let mut results: Vec<(Option<u64>, T)> = self
+            .find_rows(get_table_shard, ops)?
+            .into_iter()
+            .map(|(pos, row)| (Some(pos), row))
+            .collect();
+
+        let now = unix_now();
+        for temp in temps.iter() {
+            for data in temp.unreconciled_rows()? {
+                let row = T::from_slice(&data, get_table_shard.table.clone())?;
+                if !row.is_expired(now) && Self::row_matches(get_table_shard, &row, ops)? {
+                    results.push((None, row));
+                }
+            }
         }
 
         Ok(results)
@@ -193,20 +390,27 @@ impl<T: Row> QuerySearchManager<T> {
 
 #[cfg(test)]
 mod test {
+    use crate::errors::QueryError;
     use crate::managers::single::SingleQueryManager;
     use crate::ops::query_ops::{QueryOps, QueryVal};
-    use crate::row::Row;
+    use crate::row::{unix_now, Row};
+    use crate::row_binary::RowBinary;
     use crate::row_json::{RowData, RowJson};
     use crate::search::search_manager::QuerySearchManager;
-    use schemajs_config::DatabaseConfig;
+    use schemajs_config::{DatabaseConfig, RowFormat};
     use schemajs_data::fdm::FileDescriptorManager;
-    use schemajs_dirs::create_scheme_js_db;
+    use schemajs_data::utils::fs::list_files_with_prefix;
+    use schemajs_dirs::{create_scheme_js_db, get_base_path};
     use schemajs_helpers::create_helper_channel;
+    use schemajs_index::composite_key::CompositeKey;
     use schemajs_index::index_type::IndexType;
+    use schemajs_index::keys::ordered_key::{OrderedIndexKey, OrderedKeyValue};
     use schemajs_primitives::column::types::{DataTypes, DataValue};
     use schemajs_primitives::column::Column;
     use schemajs_primitives::index::Index;
     use schemajs_primitives::table::Table;
+    use std::collections::HashMap;
+    use std::ops::Bound;
     use std::sync::Arc;
     use uuid::Uuid;
 
@@ -236,34 +440,40 @@ mod test {
                 name: "user_id_indx".to_string(),
                 members: vec![String::from("user_id")],
                 index_type: IndexType::Hash,
+                unique: false,
             })
             .add_index(Index {
                 name: "user_email_indx".to_string(),
                 members: vec![String::from("user_email")],
                 index_type: IndexType::Hash,
+                unique: false,
             })
             .add_index(Index {
                 name: "user_country_indx".to_string(),
                 members: vec![String::from("user_country")],
                 index_type: IndexType::Hash,
+                unique: false,
             })
             .add_index(Index {
                 name: "user_age_indx".to_string(),
                 members: vec![String::from("user_age")],
                 index_type: IndexType::Hash,
+                unique: false,
             })
             .add_index(Index {
                 name: "user_name_indx".to_string(),
                 members: vec![String::from("user_name")],
                 index_type: IndexType::Hash,
+                unique: false,
             })
             .add_index(Index {
                 name: "age_country_indx".to_string(),
                 members: vec![String::from("user_age"), String::from("user_country")],
                 index_type: IndexType::Hash,
+                unique: false,
             });
 
-        query_manager.register_table(tbl);
+        query_manager.register_table(tbl).unwrap();
 
         let table = query_manager.get_table("users").unwrap();
 
@@ -375,7 +585,7 @@ mod test {
 
         let tbl = tables.get("users").unwrap();
 
-        tbl.temps.reconcile_all();
+        tbl.temps.reconcile_all().unwrap();
 
         let results = search_manager.search("users", &ops).unwrap();
         let row_0 = &results[0];
@@ -399,6 +609,7 @@ mod test {
                 name: "user_id_indx".to_string(),
                 members: vec![String::from("user_id")],
                 index_type: IndexType::Hash,
+                unique: false,
             })
     }
 
@@ -418,7 +629,7 @@ mod test {
 
             let tbl = get_user_table_for_drop_test();
 
-            query_manager.register_table(tbl);
+            query_manager.register_table(tbl).unwrap();
 
             let table = query_manager.get_table("users").unwrap();
             let row_1 = query_manager
@@ -440,7 +651,7 @@ mod test {
 
             let tbl = tables.get("users").unwrap();
 
-            tbl.temps.reconcile_all();
+            tbl.temps.reconcile_all().unwrap();
 
             let results = search_manager.search("users", &ops).unwrap();
             let row_0 = &results[0];
@@ -464,7 +675,7 @@ mod test {
                 Arc::new(FileDescriptorManager::new(2500)),
             );
             let tbl = get_user_table_for_drop_test();
-            query_manager.register_table(tbl);
+            query_manager.register_table(tbl).unwrap();
             let tables = query_manager.tables.clone();
             let search_manager = QuerySearchManager::new(tables.clone());
             let ops = QueryOps::Or(vec![QueryOps::And(vec![QueryOps::Condition(QueryVal {
@@ -485,4 +696,1211 @@ mod test {
             );
         }
     }
+
+    #[tokio::test]
+    pub async fn test_search_manager_unreconciled_rows() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let user_id_query = |id: &str| {
+            QueryOps::Condition(QueryVal {
+                key: "user_id".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(id.to_string()),
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(get_user_table_for_drop_test())
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        for id in ["1", "2"] {
+            query_manager
+                .insert(create_row(
+                    table.clone(),
+                    serde_json::json!({ "user_id": id }),
+                ))
+                .unwrap();
+        }
+
+        let search = |ops: &QueryOps| query_manager.search_manager.search("users", ops).unwrap();
+        let col = table.get_column("user_id").unwrap();
+
+        // Rows are visible right after being inserted, before the reconcile task runs
+        let results = search(&user_id_query("1"));
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].get_value(col).unwrap(),
+            DataValue::String("1".to_string())
+        );
+        assert_eq!(
+            search(&QueryOps::Or(vec![user_id_query("1"), user_id_query("2")])).len(),
+            2
+        );
+        assert!(search(&QueryOps::And(vec![user_id_query("1"), user_id_query("2")])).is_empty());
+        assert!(search(&user_id_query("3")).is_empty());
+
+        // Once reconciled they are found through the index, and only once
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+        assert_eq!(search(&user_id_query("1")).len(), 1);
+        assert_eq!(
+            search(&QueryOps::Or(vec![user_id_query("1"), user_id_query("2")])).len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_unindexed_columns() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let condition = |key: &str, filter_type: &str, value: DataValue| {
+            QueryOps::Condition(QueryVal {
+                key: key.to_string(),
+                filter_type: filter_type.to_string(),
+                value,
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(
+                get_user_table_for_drop_test()
+                    .add_column(Column::new("user_name", DataTypes::String))
+                    .add_column(Column::new("user_age", DataTypes::Number)),
+            )
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        for (id, name, age) in [("1", "Andres", 27), ("2", "Luis", 19), ("3", "Andres", 40)] {
+            query_manager
+                .insert(create_row(
+                    table.clone(),
+                    serde_json::json!({ "user_id": id, "user_name": name, "user_age": age }),
+                ))
+                .unwrap();
+        }
+
+        let queries = [
+            (condition("user_name", "=", "Andres".into()), 2),
+            (condition("user_age", ">", DataValue::Number(20.into())), 2),
+            (condition("user_age", "<=", DataValue::Number(19.into())), 1),
+            (
+                QueryOps::And(vec![
+                    condition("user_id", "=", "1".into()),
+                    condition("user_name", "=", "Andres".into()),
+                ]),
+                1,
+            ),
+            (
+                QueryOps::And(vec![
+                    condition("user_id", "=", "2".into()),
+                    condition("user_name", "=", "Andres".into()),
+                ]),
+                0,
+            ),
+            (
+                QueryOps::Or(vec![
+                    condition("user_id", "=", "2".into()),
+                    condition("user_age", ">=", DataValue::Number(40.into())),
+                ]),
+                2,
+            ),
+        ];
+
+        let search = |ops: &QueryOps| query_manager.search_manager.search("users", ops).unwrap();
+
+        // Rows match the same queries whether they are still in temporary shards or were reconciled
+        for (ops, expected) in queries.iter() {
+            assert_eq!(search(ops).len(), *expected);
+        }
+
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+        for (ops, expected) in queries.iter() {
+            assert_eq!(search(ops).len(), *expected);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_expired_rows() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let user_id_query = |id: &str| {
+            QueryOps::Condition(QueryVal {
+                key: "user_id".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(id.to_string()),
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(get_user_table_for_drop_test().set_ttl(3600))
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        query_manager
+            .insert(create_row(
+                table.clone(),
+                serde_json::json!({ "user_id": "1" }),
+            ))
+            .unwrap();
+        // Rows can expire before the TTL of their table
+        query_manager
+            .insert(create_row(
+                table.clone(),
+                serde_json::json!({ "user_id": "2", "_expires_at": 1 }),
+            ))
+            .unwrap();
+
+        let search = |id: &str| {
+            query_manager
+                .search_manager
+                .search("users", &user_id_query(id))
+                .unwrap()
+        };
+
+        let live = search("1");
+        assert_eq!(live.len(), 1);
+        assert!(live[0]
+            .get_value(Table::get_internal_expires_at())
+            .is_some());
+        // Not returned while waiting in a temporary shard, nor once reconciled
+        assert!(search("2").is_empty());
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+        assert!(search("2").is_empty());
+
+        let tbl = query_manager.tables.get("users").unwrap();
+        // Expired rows are found through the `_expires_at` index, without reading the live ones
+        let expired_positions = || {
+            let indx = tbl
+                .indexes
+                .get(&Table::get_internal_expires_at_index().name)
+                .unwrap();
+            let indx = indx.as_index();
+            let bound =
+                |val: OrderedKeyValue| indx.to_ordered_key(OrderedIndexKey(vec![val])).unwrap();
+            indx.range(
+                Bound::Excluded(&bound(OrderedKeyValue::Null)),
+                Bound::Included(&bound(OrderedKeyValue::Number(unix_now() as f64))),
+            )
+        };
+        assert_eq!(expired_positions().len(), 1);
+
+        assert_eq!(tbl.purge_expired().unwrap(), 1);
+        assert_eq!(tbl.row_count(), 1);
+        assert_eq!(tbl.purge_expired().unwrap(), 0);
+        assert!(expired_positions().is_empty());
+
+        // The index entry of the expired row is gone as well
+        let indx = tbl.indexes.get("user_id_indx").unwrap();
+        let key = indx
+            .as_index()
+            .to_key(CompositeKey(vec![("user_id".to_string(), "2".to_string())]));
+        assert!(indx.as_index().get(&key).is_empty());
+        assert_eq!(search("1").len(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_binary_rows() {
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let open_db = |table: Table| {
+            let query_manager = SingleQueryManager::<RowBinary>::new(
+                test_db.clone(),
+                create_helper_channel(1).0,
+                Arc::new(DatabaseConfig {
+                    row_format: RowFormat::Binary,
+                    ..Default::default()
+                }),
+                Arc::new(FileDescriptorManager::new(2500)),
+            );
+            query_manager.register_table(table).unwrap();
+            query_manager
+        };
+        let user_query = QueryOps::Condition(QueryVal {
+            key: "user_id".to_string(),
+            filter_type: "=".to_string(),
+            value: DataValue::String("1".to_string()),
+        });
+
+        let query_manager = open_db(get_user_table_for_drop_test());
+        let table = query_manager.get_table("users").unwrap();
+        query_manager
+            .insert(
+                RowBinary::from_json(serde_json::json!({ "user_id": "1" }), table.clone()).unwrap(),
+            )
+            .unwrap();
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+
+        // Stored as a binary row rather than a JSON object
+        let stored = query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .data
+            .read()
+            .get_element(0)
+            .unwrap();
+        assert_ne!(stored.first(), Some(&b'{'));
+
+        let results = query_manager
+            .search_manager
+            .search("users", &user_query)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].get_value(table.get_column("user_id").unwrap()),
+            Some(DataValue::String("1".to_string()))
+        );
+        drop(results);
+        drop(query_manager);
+
+        // Opened again with a new column, which sorts before `user_id`, the row is still read
+        let query_manager = open_db(
+            get_user_table_for_drop_test().add_column(Column::new("nickname", DataTypes::String)),
+        );
+        let table = query_manager.get_table("users").unwrap();
+        assert_eq!(table.metadata.schema_versions.len(), 2);
+        let results = query_manager
+            .search_manager
+            .search("users", &user_query)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].get_value(table.get_column("user_id").unwrap()),
+            Some(DataValue::String("1".to_string()))
+        );
+        assert_eq!(
+            results[0].get_value(table.get_column("nickname").unwrap()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_memory_database() {
+        let test_db = Uuid::new_v4().to_string();
+        let open_db = || {
+            let query_manager = SingleQueryManager::<RowJson>::new(
+                test_db.clone(),
+                create_helper_channel(1).0,
+                Arc::new(DatabaseConfig {
+                    memory: true,
+                    ..Default::default()
+                }),
+                Arc::new(FileDescriptorManager::new(2500)),
+            );
+            query_manager
+                .register_table(get_user_table_for_drop_test())
+                .unwrap();
+            query_manager
+        };
+        let user_query = |user_id: &str| {
+            QueryOps::Condition(QueryVal {
+                key: "user_id".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(user_id.to_string()),
+            })
+        };
+
+        let query_manager = open_db();
+        let table = query_manager.get_table("users").unwrap();
+        for user_id in ["1", "2"] {
+            query_manager
+                .insert(create_row(
+                    table.clone(),
+                    serde_json::json!({ "user_id": user_id }),
+                ))
+                .unwrap();
+        }
+
+        let tbl = query_manager.tables.get("users").unwrap();
+        tbl.temps.reconcile_all().unwrap();
+        assert!(tbl.data.read().current_master_shard.is_memory());
+        assert_eq!(tbl.row_count(), 2);
+
+        let table_path = tbl.data.read().shards_folder.clone();
+        assert!(table_path.starts_with(std::env::temp_dir()));
+        assert!(!table_path.join("temps").exists());
+        assert!(!table_path.join("table.wal").exists());
+        assert!(list_files_with_prefix(&table_path, "data_")
+            .unwrap()
+            .is_empty());
+        drop(tbl);
+
+        // Nothing is kept with the databases stored in files
+        assert!(!get_base_path(None).join("dbs").join(&test_db).exists());
+
+        // Another instance of the same database gets a folder of its own, leaving the first one untouched
+        let other_query_manager = open_db();
+        let other_table_path = other_query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .data
+            .read()
+            .shards_folder
+            .clone();
+        assert_ne!(table_path, other_table_path);
+        assert!(table_path.exists());
+
+        let results = query_manager
+            .search_manager
+            .search("users", &user_query("2"))
+            .unwrap();
+        assert_eq!(results.len(), 1);
+
+        // The folder of the indexes is removed along with the database
+        drop(query_manager);
+        assert!(!table_path.exists());
+
+        // The indexes of the other instance never saw the rows of the first one
+        let query_manager = other_query_manager;
+        {
+            let tbl = query_manager.tables.get("users").unwrap();
+            let indx = tbl.indexes.get("user_id_indx").unwrap();
+            let key = indx
+                .as_index()
+                .to_key(CompositeKey(vec![("user_id".to_string(), "2".to_string())]));
+            assert!(indx.as_index().get(&key).is_empty());
+        }
+        let results = query_manager
+            .search_manager
+            .search("users", &user_query("2"))
+            .unwrap();
+        assert!(results.is_empty());
+
+        let table = query_manager.get_table("users").unwrap();
+        query_manager
+            .insert(create_row(
+                table.clone(),
+                serde_json::json!({ "user_id": "3" }),
+            ))
+            .unwrap();
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+        let results = query_manager
+            .search_manager
+            .search("users", &user_query("3"))
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(query_manager
+            .search_manager
+            .search("users", &user_query("1"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_non_unique_index() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let enabled_query = |enabled: bool| {
+            QueryOps::Condition(QueryVal {
+                key: "enabled".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::Boolean(enabled),
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(
+                get_user_table_for_drop_test()
+                    .add_column(Column::new("enabled", DataTypes::Boolean))
+                    .add_index(Index {
+                        name: "enabled_indx".to_string(),
+                        members: vec![String::from("enabled")],
+                        index_type: IndexType::Hash,
+                        unique: false,
+                    }),
+            )
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        for user_id in 0..5 {
+            query_manager
+                .insert(create_row(
+                    table.clone(),
+                    serde_json::json!({ "user_id": user_id.to_string(), "enabled": user_id % 2 == 0 }),
+                ))
+                .unwrap();
+        }
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+
+        let search =
+            |query: &QueryOps| query_manager.search_manager.search("users", query).unwrap();
+        assert_eq!(search(&enabled_query(true)).len(), 3);
+        assert_eq!(search(&enabled_query(false)).len(), 2);
+
+        let enabled_user = QueryOps::And(vec![
+            enabled_query(true),
+            QueryOps::Condition(QueryVal {
+                key: "user_id".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String("2".to_string()),
+            }),
+        ]);
+        assert_eq!(search(&enabled_user).len(), 1);
+
+        assert_eq!(query_manager.delete("users", &enabled_user).unwrap(), 1);
+        assert_eq!(search(&enabled_query(true)).len(), 2);
+        assert_eq!(search(&enabled_query(false)).len(), 2);
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_btree_index() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let age_query = |filter_type: &str, age: u64| {
+            QueryOps::Condition(QueryVal {
+                key: "age".to_string(),
+                filter_type: filter_type.to_string(),
+                value: DataValue::Number(age.into()),
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(
+                get_user_table_for_drop_test()
+                    .add_column(Column::new("age", DataTypes::Number))
+                    .add_column(Column::new("country", DataTypes::String))
+                    .add_index(Index {
+                        name: "age_country_indx".to_string(),
+                        members: vec![String::from("age"), String::from("country")],
+                        index_type: IndexType::BTree,
+                        unique: false,
+                    }),
+            )
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        let insert = |user_id: &str, age: u64| {
+            query_manager
+                .insert(create_row(
+                    table.clone(),
+                    serde_json::json!({ "user_id": user_id, "age": age, "country": "VE" }),
+                ))
+                .unwrap();
+        };
+        for (user_id, age) in [("1", 9), ("2", 30), ("3", 100), ("4", 30), ("5", 45)] {
+            insert(user_id, age);
+        }
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+        // Still in a temporary shard, so it is matched without the index
+        insert("6", 31);
+
+        let search = |query: &QueryOps| {
+            let mut ids: Vec<String> = query_manager
+                .search_manager
+                .search("users", query)
+                .unwrap()
+                .iter()
+                .map(|row| {
+                    row.get_value(table.get_column("user_id").unwrap())
+                        .unwrap()
+                        .to_string()
+                })
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(search(&age_query(">", 30)), vec!["3", "5", "6"]);
+        assert_eq!(search(&age_query(">=", 30)), vec!["2", "3", "4", "5", "6"]);
+        assert_eq!(search(&age_query("<", 30)), vec!["1"]);
+        assert_eq!(search(&age_query("<=", 30)), vec!["1", "2", "4"]);
+        assert_eq!(search(&age_query("=", 30)), vec!["2", "4"]);
+        assert_eq!(
+            search(&QueryOps::And(vec![age_query(">", 10), age_query("<", 45)])),
+            vec!["2", "4", "6"]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_btree_index_signed_zero() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let score_query = |filter_type: &str, score: f64| {
+            QueryOps::Condition(QueryVal {
+                key: "score".to_string(),
+                filter_type: filter_type.to_string(),
+                value: DataValue::Number(serde_json::Number::from_f64(score).unwrap()),
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(
+                get_user_table_for_drop_test()
+                    .add_column(Column::new("score", DataTypes::Number))
+                    .add_index(Index {
+                        name: "score_indx".to_string(),
+                        members: vec![String::from("score")],
+                        index_type: IndexType::BTree,
+                        unique: false,
+                    }),
+            )
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        for (user_id, score) in [("1", -0.0), ("2", 0.0), ("3", -1.5), ("4", 2.5)] {
+            query_manager
+                .insert(create_row(
+                    table.clone(),
+                    serde_json::json!({ "user_id": user_id, "score": score }),
+                ))
+                .unwrap();
+        }
+
+        let queries = [
+            score_query("=", 0.0),
+            score_query("=", -0.0),
+            score_query(">", -0.0),
+            score_query(">=", 0.0),
+            score_query("<", 0.0),
+            score_query("<=", -0.0),
+        ];
+        let search = |query: &QueryOps| {
+            let mut ids: Vec<String> = query_manager
+                .search_manager
+                .search("users", query)
+                .unwrap()
+                .iter()
+                .map(|row| {
+                    row.get_value(table.get_column("user_id").unwrap())
+                        .unwrap()
+                        .to_string()
+                })
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        // Rows in temporary shards are matched without the index
+        let unindexed: Vec<Vec<String>> = queries.iter().map(search).collect();
+        assert_eq!(unindexed[0], vec!["1", "2"]);
+
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+        let indexed: Vec<Vec<String>> = queries.iter().map(search).collect();
+        assert_eq!(indexed, unindexed);
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_btree_long_strings() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let long_code = |suffix: &str| format!("{}{}", "a".repeat(300), suffix);
+        let code_query = |filter_type: &str, code: String| {
+            QueryOps::Condition(QueryVal {
+                key: "code".to_string(),
+                filter_type: filter_type.to_string(),
+                value: DataValue::String(code),
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(
+                get_user_table_for_drop_test()
+                    .add_column(Column::new("code", DataTypes::String))
+                    .add_index(Index {
+                        name: "code_indx".to_string(),
+                        members: vec![String::from("code")],
+                        index_type: IndexType::BTree,
+                        unique: false,
+                    }),
+            )
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        for (user_id, code) in [
+            ("1", long_code("1")),
+            ("2", long_code("2")),
+            ("3", long_code("3")),
+        ] {
+            query_manager
+                .insert(create_row(
+                    table.clone(),
+                    serde_json::json!({ "user_id": user_id, "code": code }),
+                ))
+                .unwrap();
+        }
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+
+        let search = |query: &QueryOps| {
+            let mut ids: Vec<String> = query_manager
+                .search_manager
+                .search("users", query)
+                .unwrap()
+                .iter()
+                .map(|row| {
+                    row.get_value(table.get_column("user_id").unwrap())
+                        .unwrap()
+                        .to_string()
+                })
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        // The index only keeps the first bytes of the codes, which all of them share
+        assert_eq!(search(&code_query("=", long_code("2"))), vec!["2"]);
+        assert_eq!(search(&code_query(">", long_code("1"))), vec!["2", "3"]);
+        assert_eq!(search(&code_query("<", long_code("3"))), vec!["1", "2"]);
+        assert_eq!(search(&code_query("<=", long_code("1"))), vec!["1"]);
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_blob_values() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let user_id_query = QueryOps::Condition(QueryVal {
+            key: "user_id".to_string(),
+            filter_type: "=".to_string(),
+            value: DataValue::String("1".to_string()),
+        });
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig {
+                blob_threshold_bytes: 16,
+                ..Default::default()
+            }),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+        query_manager
+            .register_table(
+                get_user_table_for_drop_test().add_column(Column::new("bio", DataTypes::String)),
+            )
+            .unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        let bio = "Loves cats, dogs and long walks on the beach".repeat(100);
+        query_manager
+            .insert(create_row(
+                table.clone(),
+                serde_json::json!({ "user_id": "1", "bio": bio }),
+            ))
+            .unwrap();
+
+        let tbl = query_manager.tables.get("users").unwrap();
+        let bio_col = table.get_column("bio").unwrap();
+        let bio_query = |bio: String| {
+            QueryOps::Condition(QueryVal {
+                key: "bio".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(bio),
+            })
+        };
+
+        // Only returned rows read their blobs, whether they were reconciled or not
+        for _ in 0..2 {
+            let results = query_manager
+                .search_manager
+                .search("users", &user_id_query)
+                .unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(
+                results[0].get_value(bio_col),
+                Some(DataValue::String(bio.clone()))
+            );
+            // Conditions compare the value itself, not where it is kept
+            assert_eq!(
+                query_manager
+                    .search_manager
+                    .search("users", &bio_query(bio.clone()))
+                    .unwrap()
+                    .len(),
+                1
+            );
+            tbl.temps.reconcile_all().unwrap();
+        }
+
+        let stored =
+            RowJson::from_slice(&tbl.data.read().get_element(0).unwrap(), table.clone()).unwrap();
+        let blob_id = *stored.get_value(bio_col).unwrap().as_blob().unwrap();
+        // Indexed values are kept inline
+        assert_eq!(
+            stored.get_value(table.get_column("user_id").unwrap()),
+            Some(DataValue::String("1".to_string()))
+        );
+        assert!(tbl.blobs.get(&blob_id).is_ok());
+        assert!(query_manager
+            .search_manager
+            .search("users", &bio_query(blob_id.to_string()))
+            .unwrap()
+            .is_empty());
+        drop(tbl);
+
+        assert_eq!(query_manager.delete("users", &user_id_query).unwrap(), 1);
+        let tbl = query_manager.tables.get("users").unwrap();
+        assert!(tbl.blobs.get(&blob_id).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_with_delete() {
+        let channel = create_helper_channel(1);
+        let db_config: Arc<DatabaseConfig> = Arc::new(Default::default());
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let user_id_query = |id: &str| {
+            QueryOps::Condition(QueryVal {
+                key: "user_id".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(id.to_string()),
+            })
+        };
+
+        {
+            let query_manager = SingleQueryManager::<RowJson>::new(
+                test_db.clone(),
+                channel.0.clone(),
+                db_config.clone(),
+                Arc::new(FileDescriptorManager::new(2500)),
+            );
+            query_manager
+                .register_table(get_user_table_for_drop_test())
+                .unwrap();
+
+            let table = query_manager.get_table("users").unwrap();
+            for id in ["1", "2", "3"] {
+                query_manager
+                    .insert(create_row(
+                        table.clone(),
+                        serde_json::json!({ "user_id": id }),
+                    ))
+                    .unwrap();
+            }
+
+            // Rows that have not been reconciled yet are deleted as well
+            let deleted = query_manager.delete("users", &user_id_query("2")).unwrap();
+            assert_eq!(deleted, 1);
+            assert_eq!(
+                query_manager.delete("users", &user_id_query("2")).unwrap(),
+                0
+            );
+
+            let results = query_manager
+                .search_manager
+                .search("users", &user_id_query("2"))
+                .unwrap();
+            assert!(results.is_empty());
+
+            // The index entry is gone too
+            let tbl = query_manager.tables.get("users").unwrap();
+            let index = tbl.indexes.get("user_id_indx").unwrap();
+            let key = index
+                .as_index()
+                .to_key(CompositeKey(vec![("user_id".to_string(), "2".to_string())]));
+            assert!(index.as_index().get(&key).is_empty());
+
+            let results = query_manager
+                .search_manager
+                .search("users", &user_id_query("3"))
+                .unwrap();
+            assert_eq!(results.len(), 1);
+
+            assert!(query_manager
+                .delete("unknown", &user_id_query("1"))
+                .unwrap_err()
+                .is_invalid_table());
+        }
+
+        {
+            let query_manager = SingleQueryManager::<RowJson>::new(
+                test_db.clone(),
+                channel.0,
+                db_config,
+                Arc::new(FileDescriptorManager::new(2500)),
+            );
+            query_manager
+                .register_table(get_user_table_for_drop_test())
+                .unwrap();
+
+            let search = |id: &str| {
+                query_manager
+                    .search_manager
+                    .search("users", &user_id_query(id))
+                    .unwrap()
+            };
+
+            // Deletions survive a restart
+            assert!(search("2").is_empty());
+            assert_eq!(search("1").len(), 1);
+            assert_eq!(search("3").len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_search_manager_with_update() {
+        let channel = create_helper_channel(1);
+        let db_config: Arc<DatabaseConfig> = Arc::new(Default::default());
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let user_id_query = |id: &str| {
+            QueryOps::Condition(QueryVal {
+                key: "user_id".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(id.to_string()),
+            })
+        };
+        let patch =
+            |id: &str| HashMap::from([("user_id".to_string(), DataValue::String(id.to_string()))]);
+
+        {
+            let query_manager = SingleQueryManager::<RowJson>::new(
+                test_db.clone(),
+                channel.0.clone(),
+                db_config.clone(),
+                Arc::new(FileDescriptorManager::new(2500)),
+            );
+            query_manager
+                .register_table(get_user_table_for_drop_test())
+                .unwrap();
+
+            let table = query_manager.get_table("users").unwrap();
+            for id in ["1", "2", "3"] {
+                query_manager
+                    .insert(create_row(
+                        table.clone(),
+                        serde_json::json!({ "user_id": id }),
+                    ))
+                    .unwrap();
+            }
+
+            let updated = query_manager
+                .update("users", &user_id_query("2"), &patch("20"))
+                .unwrap();
+            assert_eq!(updated, 1);
+
+            let search = |id: &str| {
+                query_manager
+                    .search_manager
+                    .search("users", &user_id_query(id))
+                    .unwrap()
+            };
+            assert!(search("2").is_empty());
+            assert_eq!(search("20").len(), 1);
+
+            // Index entries follow the new version of the row
+            {
+                let tbl = query_manager.tables.get("users").unwrap();
+                let index = tbl.indexes.get("user_id_indx").unwrap();
+                let key = |id: &str| {
+                    index
+                        .as_index()
+                        .to_key(CompositeKey(vec![("user_id".to_string(), id.to_string())]))
+                };
+                assert!(index.as_index().get(&key("2")).is_empty());
+                assert!(!index.as_index().get(&key("20")).is_empty());
+            }
+
+            // Updating without changing indexed columns keeps the row reachable
+            assert_eq!(
+                query_manager
+                    .update("users", &user_id_query("3"), &patch("3"))
+                    .unwrap(),
+                1
+            );
+            assert_eq!(search("3").len(), 1);
+
+            assert!(query_manager
+                .update(
+                    "users",
+                    &user_id_query("1"),
+                    &HashMap::from([("age".to_string(), DataValue::Null)])
+                )
+                .unwrap_err()
+                .is_unknown_column());
+        }
+
+        {
+            let query_manager = SingleQueryManager::<RowJson>::new(
+                test_db.clone(),
+                channel.0,
+                db_config,
+                Arc::new(FileDescriptorManager::new(2500)),
+            );
+            query_manager
+                .register_table(get_user_table_for_drop_test())
+                .unwrap();
+
+            let search = |id: &str| {
+                query_manager
+                    .search_manager
+                    .search("users", &user_id_query(id))
+                    .unwrap()
+            };
+
+            // Updates survive a restart
+            assert!(search("2").is_empty());
+            assert_eq!(search("20").len(), 1);
+            assert_eq!(search("1").len(), 1);
+            assert_eq!(search("3").len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_unique_index() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let _db_folder = create_scheme_js_db(None, test_db.as_str());
+        let email_query = |email: &str| {
+            QueryOps::Condition(QueryVal {
+                key: "email".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(email.to_string()),
+            })
+        };
+
+        let query_manager = SingleQueryManager::<RowJson>::new(
+            test_db.clone(),
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+
+        // Unique columns get an index even without `default_index`
+        let mut tbl = Table::new("users")
+            .add_column(Column::new("user_id", DataTypes::String).set_default_index(false))
+            .add_column(
+                Column::new("email", DataTypes::String)
+                    .set_unique(true)
+                    .set_default_index(false),
+            );
+        tbl.init();
+        assert!(tbl
+            .indexes
+            .iter()
+            .any(|index| index.unique && index.members == vec!["email".to_string()]));
+        query_manager.register_table(tbl).unwrap();
+
+        let table = query_manager.get_table("users").unwrap();
+        let user = |user_id: &str, email: Option<&str>| {
+            create_row(
+                table.clone(),
+                serde_json::json!({ "user_id": user_id, "email": email }),
+            )
+        };
+        fn is_duplicate<R>(result: Result<R, QueryError>) -> bool {
+            matches!(result, Err(QueryError::DuplicateValue(col)) if col == "email")
+        }
+
+        query_manager
+            .insert(user("1", Some("ana@schemajs.com")))
+            .unwrap();
+
+        // Checked against rows that are not reconciled yet, then against the index
+        assert!(is_duplicate(
+            query_manager.insert(user("2", Some("ana@schemajs.com")))
+        ));
+        query_manager
+            .tables
+            .get("users")
+            .unwrap()
+            .temps
+            .reconcile_all()
+            .unwrap();
+        assert!(is_duplicate(
+            query_manager.insert(user("2", Some("ana@schemajs.com")))
+        ));
+
+        // Rows without a value do not clash
+        query_manager.insert(user("3", None)).unwrap();
+        query_manager.insert(user("4", None)).unwrap();
+
+        // Rows of the same insert are checked against each other, nothing is inserted if one clashes
+        assert!(is_duplicate(query_manager.raw_insert(
+            &mut [
+                user("5", Some("luis@schemajs.com")),
+                user("6", Some("luis@schemajs.com"))
+            ],
+            true
+        )));
+        let search =
+            |query: &QueryOps| query_manager.search_manager.search("users", query).unwrap();
+        assert!(search(&email_query("luis@schemajs.com")).is_empty());
+
+        // Values of deleted rows can be used again
+        assert_eq!(
+            query_manager
+                .delete("users", &email_query("ana@schemajs.com"))
+                .unwrap(),
+            1
+        );
+        query_manager
+            .insert(user("7", Some("ana@schemajs.com")))
+            .unwrap();
+        assert_eq!(search(&email_query("ana@schemajs.com")).len(), 1);
+
+        // Updates are checked against the other rows and against each other, but not against the rows they replace
+        let user_id_query = |user_id: &str| {
+            QueryOps::Condition(QueryVal {
+                key: "user_id".to_string(),
+                filter_type: "=".to_string(),
+                value: DataValue::String(user_id.to_string()),
+            })
+        };
+        let email_patch = |email: &str| {
+            HashMap::from([("email".to_string(), DataValue::String(email.to_string()))])
+        };
+        assert!(is_duplicate(query_manager.update(
+            "users",
+            &user_id_query("3"),
+            &email_patch("ana@schemajs.com")
+        )));
+        assert!(is_duplicate(query_manager.update(
+            "users",
+            &QueryOps::Or(vec![user_id_query("3"), user_id_query("4")]),
+            &email_patch("luis@schemajs.com")
+        )));
+        assert!(search(&email_query("luis@schemajs.com")).is_empty());
+        assert_eq!(
+            query_manager
+                .update(
+                    "users",
+                    &user_id_query("7"),
+                    &email_patch("ana@schemajs.com")
+                )
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            query_manager
+                .update(
+                    "users",
+                    &user_id_query("3"),
+                    &email_patch("luis@schemajs.com")
+                )
+                .unwrap(),
+            1
+        );
+        assert_eq!(search(&email_query("ana@schemajs.com")).len(), 1);
+        assert_eq!(search(&email_query("luis@schemajs.com")).len(), 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_register_table_with_invalid_encryption_key() {
+        let channel = create_helper_channel(1);
+        let test_db = Uuid::new_v4().to_string();
+        let db_folder = create_scheme_js_db(None, test_db.as_str());
+
+        let malformed_key = db_folder.join("malformed.key");
+        std::fs::write(&malformed_key, "not-a-key").unwrap();
+
+        for key_file in [db_folder.join("missing.key"), malformed_key] {
+            let query_manager = SingleQueryManager::<RowJson>::new(
+                test_db.clone(),
+                channel.0.clone(),
+                Arc::new(DatabaseConfig {
+                    encryption_key_file: Some(key_file),
+                    ..Default::default()
+                }),
+                Arc::new(FileDescriptorManager::new(2500)),
+            );
+
+            let result = query_manager.register_table(get_user_table_for_drop_test());
+            assert!(matches!(result, Err(QueryError::ConfigError(_))));
+            assert!(query_manager.get_table("users").is_none());
+        }
+    }
 }
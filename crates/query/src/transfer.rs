@@ -0,0 +1,610 @@
+use crate::errors::QueryError;
+use crate::managers::single::SingleQueryManager;
+use crate::row::{unix_now, Row};
+use schemajs_primitives::column::types::{DataTypes, DataValue};
+use schemajs_primitives::column::Column;
+use schemajs_primitives::table::Table;
+use serde_json::{Number, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Rows are handed to `raw_insert` in batches of this size while importing.
+const IMPORT_BATCH_ROWS: usize = 1000;
+
+/// Text formats rows can be exported to and imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFormat {
+    /// One JSON object per line, keyed by column name.
+    Ndjson,
+    /// A header line with the column names followed by one line per row.
+    /// Empty fields are null, while `""` is an empty string.
+    Csv,
+}
+
+/// Line of an import that could not be turned into a row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedLine {
+    /// Line the record starts at, counting from 1 (the CSV header is line 1).
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Outcome of `import_table`.
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub rejected: Vec<RejectedLine>,
+}
+
+/// Writes every live row of `table_name` to `writer` in `format` and returns how many rows were written.
+///
+/// Rows waiting in the temporary shards are reconciled first so they are part of the export.
+/// Internal columns (`_uid` and `_expires_at`) are exported too, so importing the output keeps the rows' ids.
+pub fn export_table<T: Row, W: Write>(
+    query_manager: &SingleQueryManager<T>,
+    table_name: &str,
+    format: TransferFormat,
+    mut writer: W,
+) -> Result<usize, QueryError> {
+    let table_shard = query_manager
+        .tables
+        .get(table_name)
+        .ok_or_else(|| QueryError::InvalidTable(table_name.to_string()))?;
+    table_shard.temps.reconcile_all()?;
+
+    let table = table_shard.table.clone();
+    let mut columns: Vec<&Column> = table.columns.values().collect();
+    columns.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if format == TransferFormat::Csv {
+        let header: Vec<String> = columns.iter().map(|col| csv_field(&col.name)).collect();
+        writeln!(writer, "{}", header.join(",")).map_err(io_error)?;
+    }
+
+    let now = unix_now();
+    let mut exported = 0;
+    for item in table_shard.scan(0, None) {
+        let (_, bytes) = item?;
+        let mut row = T::from_slice(&bytes, table.clone())?;
+        if row.is_expired(now) {
+            continue;
+        }
+        table_shard.load_values(&mut row)?;
+
+        match format {
+            TransferFormat::Ndjson => {
+                serde_json::to_writer(&mut writer, &row.to_json()?)
+                    .map_err(|e| QueryError::TransferError(e.to_string()))?;
+                writeln!(writer).map_err(io_error)?;
+            }
+            TransferFormat::Csv => {
+                let fields: Vec<String> = columns
+                    .iter()
+                    .map(|col| match row.get_value(col) {
+                        None | Some(DataValue::Null) => String::new(),
+                        Some(val) => csv_field(&val.to_string()),
+                    })
+                    .collect();
+                writeln!(writer, "{}", fields.join(",")).map_err(io_error)?;
+            }
+        }
+
+        exported += 1;
+    }
+
+    writer.flush().map_err(io_error)?;
+
+    Ok(exported)
+}
+
+/// Reads rows in `format` from `reader` and inserts them into `table_name` straight into its main shard.
+///
+/// Values are coerced to the type of their column (for example `"42"` into a number column, or `1` into a boolean one).
+/// Lines that cannot be coerced, name unknown columns or miss required values are skipped and listed in the report,
+/// every other row is inserted. Rows repeating the values of a unique index, either the ones of a row already in the table
+/// or the ones of an earlier line, are skipped and listed as well. A CSV header naming an unknown column fails the whole import.
+///
+/// Rows are inserted in batches, so if inserting fails for any other reason the rows of earlier batches stay in the table.
+pub fn import_table<T: Row, R: BufRead>(
+    query_manager: &SingleQueryManager<T>,
+    table_name: &str,
+    format: TransferFormat,
+    mut reader: R,
+) -> Result<ImportReport, QueryError> {
+    let table = query_manager
+        .get_table(table_name)
+        .ok_or_else(|| QueryError::InvalidTable(table_name.to_string()))?;
+
+    let mut report = ImportReport::default();
+    let mut batch: Vec<T> = Vec::with_capacity(IMPORT_BATCH_ROWS);
+    let mut batch_lines: Vec<usize> = Vec::with_capacity(IMPORT_BATCH_ROWS);
+    let mut header: Option<Vec<String>> = None;
+    let mut line_no = 0;
+
+    while let Some((start_line, record)) = read_record(&mut reader, format, &mut line_no)? {
+        if record.trim().is_empty() {
+            continue;
+        }
+
+        let raw_values = match format {
+            TransferFormat::Ndjson => parse_json_record(&record),
+            TransferFormat::Csv => {
+                let fields = parse_csv_record(&record);
+                match &header {
+                    None => {
+                        let names: Vec<String> =
+                            fields.into_iter().map(Option::unwrap_or_default).collect();
+                        if let Some(unknown) = names.iter().find(|n| table.get_column(n).is_none())
+                        {
+                            return Err(QueryError::UnknownColumn(unknown.clone()));
+                        }
+                        header = Some(names);
+                        continue;
+                    }
+                    Some(names) if names.len() != fields.len() => Err(format!(
+                        "Expected {} fields, found {}",
+                        names.len(),
+                        fields.len()
+                    )),
+                    Some(names) => Ok(names
+                        .iter()
+                        .cloned()
+                        .zip(
+                            fields
+                                .into_iter()
+                                .map(|f| f.map_or(Value::Null, Value::String)),
+                        )
+                        .collect()),
+                }
+            }
+        };
+
+        let row = raw_values
+            .and_then(|values| coerce_row(&table.columns, values))
+            .and_then(|values| {
+                T::from_map(table.clone(), values).map_err(|_| "Invalid row".to_string())
+            });
+
+        match row {
+            Ok(row) => {
+                batch.push(row);
+                batch_lines.push(start_line);
+            }
+            Err(reason) => report.rejected.push(RejectedLine {
+                line: start_line,
+                reason,
+            }),
+        }
+
+        if batch.len() >= IMPORT_BATCH_ROWS {
+            flush_batch(query_manager, &mut batch, &mut batch_lines, &mut report)?;
+        }
+    }
+
+    flush_batch(query_manager, &mut batch, &mut batch_lines, &mut report)?;
+    report.rejected.sort_by_key(|rejected| rejected.line);
+
+    Ok(report)
+}
+
+/// Inserts `batch`, whose rows were read at `lines`, and adds them to the report.
+fn flush_batch<T: Row>(
+    query_manager: &SingleQueryManager<T>,
+    batch: &mut Vec<T>,
+    lines: &mut Vec<usize>,
+    report: &mut ImportReport,
+) -> Result<(), QueryError> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    match query_manager.raw_insert(batch, true) {
+        Ok(_) => report.imported += batch.len(),
+        // Unique values are checked before any row of the batch is written, so none was inserted.
+        // Rows are inserted one by one instead, rejecting the ones that repeat a value.
+        Err(QueryError::DuplicateValue(_)) => {
+            for (row, line) in batch.iter_mut().zip(lines.iter()) {
+                match query_manager.raw_insert(std::slice::from_mut(row), true) {
+                    Ok(_) => report.imported += 1,
+                    Err(e @ QueryError::DuplicateValue(_)) => report.rejected.push(RejectedLine {
+                        line: *line,
+                        reason: e.to_string(),
+                    }),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Err(e) => return Err(e),
+    }
+
+    batch.clear();
+    lines.clear();
+
+    Ok(())
+}
+
+fn io_error(e: std::io::Error) -> QueryError {
+    QueryError::TransferError(e.to_string())
+}
+
+/// Reads the next record along with the line it starts at.
+/// CSV records span several lines when a quoted field contains line breaks.
+fn read_record<R: BufRead>(
+    reader: &mut R,
+    format: TransferFormat,
+    line_no: &mut usize,
+) -> Result<Option<(usize, String)>, QueryError> {
+    let mut record = String::new();
+    let start_line = *line_no + 1;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(io_error)? == 0 {
+            return Ok((!record.is_empty()).then_some((start_line, record)));
+        }
+        *line_no += 1;
+        record.push_str(&line);
+
+        let complete =
+            format == TransferFormat::Ndjson || record.matches('"').count().is_multiple_of(2);
+        if complete {
+            let trimmed = record.trim_end_matches(['\n', '\r']).len();
+            record.truncate(trimmed);
+            return Ok(Some((start_line, record)));
+        }
+    }
+}
+
+fn parse_json_record(record: &str) -> Result<Vec<(String, Value)>, String> {
+    match serde_json::from_str::<Value>(record) {
+        Ok(Value::Object(obj)) => Ok(obj.into_iter().collect()),
+        Ok(_) => Err("Expected a JSON object".to_string()),
+        Err(e) => Err(format!("Invalid JSON: {}", e)),
+    }
+}
+
+/// Splits a CSV record into its fields. Empty unquoted fields are `None`.
+fn parse_csv_record(record: &str) -> Vec<Option<String>> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' => {
+                in_quotes = true;
+                quoted = true;
+            }
+            ',' if !in_quotes => {
+                fields.push((quoted || !field.is_empty()).then(|| std::mem::take(&mut field)));
+                quoted = false;
+            }
+            _ => field.push(c),
+        }
+    }
+    fields.push((quoted || !field.is_empty()).then_some(field));
+
+    fields
+}
+
+/// Quotes `val` if it would otherwise not be read back as a single field.
+fn csv_field(val: &str) -> String {
+    if val.is_empty() || val.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", val.replace('"', "\"\""))
+    } else {
+        val.to_string()
+    }
+}
+
+fn coerce_row(
+    columns: &HashMap<String, Column>,
+    raw_values: Vec<(String, Value)>,
+) -> Result<HashMap<String, DataValue>, String> {
+    let mut values = HashMap::new();
+    for (name, raw) in raw_values {
+        let col = columns
+            .get(&name)
+            .ok_or_else(|| format!("Unknown column '{}'", name))?;
+        let val = coerce_value(col, &raw)?;
+        if !val.is_null() {
+            values.insert(name, val);
+        }
+    }
+
+    for col in columns.values() {
+        // Internal columns missing from the line are filled when the row is inserted.
+        let internal = col.name == Table::get_internal_uid().name
+            || col.name == Table::get_internal_expires_at().name;
+        if internal || values.contains_key(&col.name) {
+            continue;
+        }
+
+        match &col.default_value {
+            Some(default) => {
+                values.insert(
+                    col.name.clone(),
+                    coerce_value(col, &Value::String(default.clone()))?,
+                );
+            }
+            None if col.required => {
+                return Err(format!("Required column '{}' has no value", col.name))
+            }
+            None => {}
+        }
+    }
+
+    Ok(values)
+}
+
+/// Converts `raw` into a value of the type of `col`. Nulls are kept as `DataValue::Null`.
+fn coerce_value(col: &Column, raw: &Value) -> Result<DataValue, String> {
+    let invalid = || format!("Invalid value {} for column '{}'", raw, col.name);
+
+    if raw.is_null() {
+        return Ok(DataValue::Null);
+    }
+
+    match col.data_type {
+        DataTypes::Null => Ok(DataValue::Null),
+        DataTypes::String => match raw {
+            Value::String(s) => Ok(DataValue::String(s.clone())),
+            Value::Number(n) => Ok(DataValue::String(n.to_string())),
+            Value::Bool(b) => Ok(DataValue::String(b.to_string())),
+            _ => Err(invalid()),
+        },
+        DataTypes::Number => match raw {
+            Value::Number(n) => Ok(DataValue::Number(n.clone())),
+            Value::String(s) => Number::from_str(s.trim())
+                .map(DataValue::Number)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        },
+        DataTypes::Boolean => match raw {
+            Value::Bool(b) => Ok(DataValue::Boolean(*b)),
+            Value::Number(n) => match n.as_u64() {
+                Some(0) => Ok(DataValue::Boolean(false)),
+                Some(1) => Ok(DataValue::Boolean(true)),
+                _ => Err(invalid()),
+            },
+            Value::String(s) => match s.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" => Ok(DataValue::Boolean(true)),
+                "false" | "0" => Ok(DataValue::Boolean(false)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        },
+        DataTypes::Uuid => match raw {
+            Value::String(s) => Uuid::from_str(s.trim())
+                .map(DataValue::Uuid)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::managers::single::SingleQueryManager;
+    use crate::row::Row;
+    use crate::row_json::RowJson;
+    use crate::transfer::{export_table, import_table, RejectedLine, TransferFormat};
+    use schemajs_config::DatabaseConfig;
+    use schemajs_data::fdm::FileDescriptorManager;
+    use schemajs_dirs::create_scheme_js_db;
+    use schemajs_helpers::create_helper_channel;
+    use schemajs_index::index_type::IndexType;
+    use schemajs_primitives::column::types::{DataTypes, DataValue};
+    use schemajs_primitives::column::Column;
+    use schemajs_primitives::index::Index;
+    use schemajs_primitives::table::Table;
+    use serde_json::json;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn create_manager() -> SingleQueryManager<RowJson> {
+        let test_db = Uuid::new_v4().to_string();
+        create_scheme_js_db(None, test_db.as_str());
+        let channel = create_helper_channel(1);
+        let query_manager = SingleQueryManager::new(
+            test_db,
+            channel.0,
+            Arc::new(DatabaseConfig::default()),
+            Arc::new(FileDescriptorManager::new(2500)),
+        );
+
+        query_manager
+            .register_table(
+                Table::new("users")
+                    .add_column(Column::new("name", DataTypes::String).set_required(true))
+                    .add_column(Column::new("age", DataTypes::Number))
+                    .add_column(Column::new("active", DataTypes::Boolean)),
+            )
+            .unwrap();
+
+        query_manager
+    }
+
+    #[tokio::test]
+    pub async fn test_export_import_round_trip() {
+        let source = create_manager();
+        let table = source.get_table("users").unwrap();
+        for (name, age) in [("Andreas, \"Andy\"", 30), ("Luis\nPirela", 25), ("", 40)] {
+            let row = RowJson::from_json(json!({ "name": name, "age": age }), table.clone());
+            source.insert(row.unwrap()).unwrap();
+        }
+
+        for format in [TransferFormat::Ndjson, TransferFormat::Csv] {
+            let mut exported = vec![];
+            assert_eq!(
+                export_table(&source, "users", format, &mut exported).unwrap(),
+                3
+            );
+
+            let target = create_manager();
+            let report = import_table(&target, "users", format, exported.as_slice()).unwrap();
+            assert_eq!(report.imported, 3);
+            assert!(report.rejected.is_empty());
+
+            let mut reexported = vec![];
+            export_table(&target, "users", format, &mut reexported).unwrap();
+            let mut lines: Vec<&str> = std::str::from_utf8(&exported).unwrap().lines().collect();
+            let mut relines: Vec<&str> =
+                std::str::from_utf8(&reexported).unwrap().lines().collect();
+            lines.sort();
+            relines.sort();
+            assert_eq!(lines, relines);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_import_rejected_lines() {
+        let query_manager = create_manager();
+        let csv =
+            "name,age,active\nAndreas,\"30\",1\n,12,true\nLuis,old,false\nJohn,3\n\nMaria,,FALSE\n";
+        let report =
+            import_table(&query_manager, "users", TransferFormat::Csv, csv.as_bytes()).unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(
+            report.rejected.iter().map(|r| r.line).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+
+        let ndjson =
+            "{\"name\":\"Ana\",\"age\":\"7\"}\n{\"name\":\"Bob\",\"city\":\"X\"}\nnot json\n";
+        let report = import_table(
+            &query_manager,
+            "users",
+            TransferFormat::Ndjson,
+            ndjson.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(
+            report.rejected[0],
+            RejectedLine {
+                line: 2,
+                reason: "Unknown column 'city'".to_string()
+            }
+        );
+        assert_eq!(report.rejected[1].line, 3);
+
+        let mut exported = vec![];
+        export_table(
+            &query_manager,
+            "users",
+            TransferFormat::Ndjson,
+            &mut exported,
+        )
+        .unwrap();
+        let table = query_manager.get_table("users").unwrap();
+        let rows: Vec<RowJson> = std::str::from_utf8(&exported)
+            .unwrap()
+            .lines()
+            .map(|l| RowJson::from_json(serde_json::from_str(l).unwrap(), table.clone()).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 3);
+
+        let andreas = rows
+            .iter()
+            .find(|r| {
+                r.get_value(table.get_column("name").unwrap())
+                    == Some(DataValue::String("Andreas".to_string()))
+            })
+            .unwrap();
+        assert_eq!(
+            andreas.get_value(table.get_column("age").unwrap()),
+            Some(DataValue::Number(30.into()))
+        );
+        assert_eq!(
+            andreas.get_value(table.get_column("active").unwrap()),
+            Some(DataValue::Boolean(true))
+        );
+        assert_eq!(
+            import_table(
+                &query_manager,
+                "users",
+                TransferFormat::Csv,
+                "name,city\n".as_bytes()
+            )
+            .unwrap_err()
+            .into_unknown_column()
+            .unwrap(),
+            "city"
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_import_rejects_duplicate_unique_values() {
+        let query_manager = create_manager();
+        query_manager
+            .register_table(
+                Table::new("members")
+                    .add_column(Column::new("email", DataTypes::String))
+                    .add_index(Index {
+                        name: "email_indx".to_string(),
+                        members: vec!["email".to_string()],
+                        index_type: IndexType::Hash,
+                        unique: true,
+                    }),
+            )
+            .unwrap();
+        let table = query_manager.get_table("members").unwrap();
+        query_manager
+            .insert(RowJson::from_json(json!({ "email": "ana@schemajs.com" }), table).unwrap())
+            .unwrap();
+
+        let csv =
+            "email\nana@schemajs.com\nbob@schemajs.com\nbob@schemajs.com\n,\nluis@schemajs.com\n";
+        let report = import_table(
+            &query_manager,
+            "members",
+            TransferFormat::Csv,
+            csv.as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(
+            report.rejected,
+            vec![
+                RejectedLine {
+                    line: 2,
+                    reason: "Value of column 'email' is already present in table".to_string()
+                },
+                RejectedLine {
+                    line: 4,
+                    reason: "Value of column 'email' is already present in table".to_string()
+                },
+                RejectedLine {
+                    line: 5,
+                    reason: "Expected 1 fields, found 2".to_string()
+                },
+            ]
+        );
+
+        let mut exported = vec![];
+        assert_eq!(
+            export_table(
+                &query_manager,
+                "members",
+                TransferFormat::Csv,
+                &mut exported
+            )
+            .unwrap(),
+            3
+        );
+    }
+}